@@ -2,6 +2,16 @@ use proc_macro::TokenStream;
 use quote::quote;
 use syn::{parse_macro_input, LitInt, Ident};
 
+/// Panics if a component type appears twice, which would hand out two mutable references to the same component.
+fn assert_distinct(generics: &[Ident]) -> proc_macro2::TokenStream {
+    quote! {
+        let types = [ #( std::any::TypeId::of::<#generics>() ),* ];
+        for (i, ty) in types.iter().enumerate() {
+            assert!(!types[..i].contains(ty), "A mutable query must not contain the same component type twice");
+        }
+    }
+}
+
 pub fn generate_queries(input: TokenStream) -> TokenStream {
     let max_n = parse_macro_input!(input as LitInt).base10_parse::<usize>().unwrap();
 
@@ -40,6 +50,7 @@ pub fn generate_queries(input: TokenStream) -> TokenStream {
 
         // Tuple for mutable
         let tuple_refs_mut: Vec<_> = generics.iter().map(|g| quote! { #g }).collect();
+        let distinct = assert_distinct(&generics);
 
         quote! {
             #[auto_enums::auto_enum(Iterator)]
@@ -62,11 +73,14 @@ pub fn generate_queries(input: TokenStream) -> TokenStream {
             pub fn #method_name_mut< #( #generics : Sized + 'static ),* >(
                 &mut self
             ) -> impl Iterator<Item = (EntityId, ( #( &mut #tuple_refs_mut ),* ))> + '_ {
+                #distinct
                 let t1 = std::any::TypeId::of::<C1>();
                 if let Some(blob1) = self.components.get(&t1) {
                     blob1.get_all_mut::<C1>().filter_map(|(idx, C1)| {
                         let en = self.get_entity_from_component_instance::<C1>(idx)?;
                         #rest_gets_mut
+                        let tick = self.change_tick();
+                        #( self.mark_changed::<#generics>(en, tick); )*
                         Some((en, (#( #generics ),* )))
                     })
                 } else {
@@ -104,12 +118,22 @@ pub fn generate_system_impls(input: TokenStream) -> TokenStream {
 
         quote! {
             impl< #( #generics : Sized + 'static ),* > System<#tuple_type> {
+                /// Access for a system that only reads the components of this tuple.
+                pub fn reads(&self) -> Access {
+                    Access::new() #( .read::<#generics>() )*
+                }
+
+                /// Access for a system that writes the components of this tuple.
+                pub fn writes(&self) -> Access {
+                    Access::new() #( .write::<#generics>() )*
+                }
+
                 #[auto_enums::auto_enum(Iterator)]
                 pub fn iter<'a>(&'a self, world: &'a World)
                     -> impl Iterator<Item=(EntityId, ( #( & #generics ),* ))> + 'a {
                     match world {
                         World::SparseSet(ssw) => ssw.storage.#query_fn::<#( #generics ),*>(),
-                        World::ArchetypeWorld(atw) => std::iter::empty(),
+                        World::ArchetypeWorld(atw) => atw.#query_fn::<#( #generics ),*>(),
                     }
                 }
 
@@ -118,7 +142,7 @@ pub fn generate_system_impls(input: TokenStream) -> TokenStream {
                     -> impl Iterator<Item=(EntityId, ( #( &mut #generics ),* ))> + 'a {
                     match world {
                         World::SparseSet(ssw) => ssw.storage.#query_fn_mut::<#( #generics ),*>(),
-                        World::ArchetypeWorld(atw) => std::iter::empty(),
+                        World::ArchetypeWorld(atw) => atw.#query_fn_mut::<#( #generics ),*>(),
                     }
                 }
             }
@@ -128,4 +152,61 @@ pub fn generate_system_impls(input: TokenStream) -> TokenStream {
     TokenStream::from(quote! {
         #( #impls )*
     })
-}
\ No newline at end of file
+}
+pub fn generate_archetype_queries(input: TokenStream) -> TokenStream {
+    let max_n = parse_macro_input!(input as LitInt).base10_parse::<usize>().unwrap();
+
+    let methods = (1..=max_n).map(|n| {
+        // Generics: C1, C2, ...
+        let generics: Vec<Ident> = (1..=n)
+            .map(|i| Ident::new(&format!("C{}", i), proc_macro2::Span::call_site()))
+            .collect();
+
+        // Column bindings: c1, c2, ...
+        let columns: Vec<Ident> = (1..=n)
+            .map(|i| Ident::new(&format!("c{}", i), proc_macro2::Span::call_site()))
+            .collect();
+
+        let method_name = Ident::new(&format!("query{}", n), proc_macro2::Span::call_site());
+        let method_name_mut = Ident::new(&format!("query{}_mut", n), proc_macro2::Span::call_site());
+        let distinct = assert_distinct(&generics);
+
+        quote! {
+            pub fn #method_name< #( #generics : Sized + 'static ),* >(
+                &self
+            ) -> impl Iterator<Item = (EntityId, ( #( & #generics ),* ))> + '_ {
+                self.archetypes.iter().filter_map(|arch| {
+                    #( let #columns = arch.column(std::any::TypeId::of::<#generics>())?; )*
+                    Some(arch.entities.iter().enumerate().map(move |(row, en)| {
+                        // SAFETY: every column of an archetype has exactly one row per entity
+                        unsafe {
+                            (*en, ( #( &*(#columns.get_ptr(row) as *const #generics) ),* ))
+                        }
+                    }))
+                }).flatten()
+            }
+
+            pub fn #method_name_mut< #( #generics : Sized + 'static ),* >(
+                &mut self
+            ) -> impl Iterator<Item = (EntityId, ( #( &mut #generics ),* ))> + '_ {
+                #distinct
+                let tick = self.change_tick();
+                self.archetypes.iter().filter_map(move |arch| {
+                    #( let #columns = arch.column(std::any::TypeId::of::<#generics>())?; )*
+                    Some(arch.entities.iter().enumerate().map(move |(row, en)| {
+                        #( if let Some(ticks) = #columns.get_ticks(row) { ticks.set_changed(tick); } )*
+                        // SAFETY: every column of an archetype has exactly one row per entity, every row is only
+                        // yielded once while self is borrowed mutably, and the columns are distinct
+                        unsafe {
+                            (*en, ( #( &mut *(#columns.get_ptr(row) as *mut #generics) ),* ))
+                        }
+                    }))
+                }).flatten()
+            }
+        }
+    });
+
+    TokenStream::from(quote! {
+        #( #methods )*
+    })
+}
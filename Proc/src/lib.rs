@@ -21,6 +21,11 @@ pub fn generate_system_impls(input: TokenStream) -> TokenStream {
     ecs::generate_system_impls(input)
 }
 
+#[proc_macro]
+pub fn generate_archetype_queries(input: TokenStream) -> TokenStream {
+    ecs::generate_archetype_queries(input)
+}
+
 #[proc_macro]
 pub fn ui(input: TokenStream) -> TokenStream {
     ui::ui(input)
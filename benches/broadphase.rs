@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant};
+use mvengine::game::ecs::entity::Entity;
+use mvengine::game::ecs::world::EcsWorld;
+use mvengine::game::ecs::{Ecs, EcsBackend};
+use mvengine::game::physics::broadphase::{Aabb, BroadPhase, BruteForce, DynamicAabbTree, SpatialHashGrid};
+use mvengine::game::physics::components::{AABBCollider, Transform};
+use mvengine::game::physics::systems::aabb::AabbCollisionSystem;
+use mvengine::math::vec::Vec2;
+
+const FRAMES: u32 = 10;
+
+/// Small xorshift so every run uses the same scene.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+fn scene(count: usize) -> Vec<(u64, Aabb)> {
+    let mut rng = Rng(0x2545F4914F6CDD1D);
+    let size = (count as f32).sqrt() * 4.0;
+    (0..count)
+        .map(|i| {
+            let center = Vec2::new(rng.next() * size, rng.next() * size);
+            let extent = Vec2::new(0.5 + rng.next() * 1.5, 0.5 + rng.next() * 1.5);
+            (i as u64 + 1, Aabb::from_center(center, extent))
+        })
+        .collect()
+}
+
+fn run(broad_phase: &mut dyn BroadPhase, colliders: &mut [(u64, Aabb)]) -> (Duration, Vec<(usize, usize)>) {
+    let mut pairs = Vec::new();
+    let start = Instant::now();
+    for frame in 0..FRAMES {
+        // move everything a bit so persistent structures have to do some work
+        for (_, aabb) in colliders.iter_mut() {
+            let offset = if frame % 2 == 0 { Vec2::new(0.05, 0.0) } else { Vec2::new(-0.05, 0.0) };
+            aabb.min += offset;
+            aabb.max += offset;
+        }
+        pairs.clear();
+        broad_phase.find_pairs(colliders, &mut pairs);
+    }
+    let elapsed = start.elapsed() / FRAMES;
+    pairs.sort_unstable();
+    (elapsed, pairs)
+}
+
+fn main() {
+    for count in [1_000, 2_500, 5_000, 10_000] {
+        let mut colliders = scene(count);
+        let (brute_time, expected) = run(&mut BruteForce, &mut colliders);
+        let (grid_time, grid_pairs) = run(&mut SpatialHashGrid::new(2.0), &mut colliders);
+        let (tree_time, tree_pairs) = run(&mut DynamicAabbTree::new(0.1), &mut colliders);
+
+        assert_eq!(expected, grid_pairs, "Spatial hash grid found different pairs than brute force");
+        assert_eq!(expected, tree_pairs, "Dynamic tree found different pairs than brute force");
+
+        println!(
+            "{count:>6} colliders, {:>5} pairs: brute force {brute_time:>10.2?}, grid {grid_time:>10.2?}, tree {tree_time:>10.2?}",
+            expected.len()
+        );
+    }
+
+    let mut ecs = Ecs::new(EcsBackend::Archetype);
+    let world = ecs.world_mut();
+    for (_, aabb) in scene(10_000) {
+        let id = Entity::<(Transform, AABBCollider)>::create(world);
+        if let Some(t) = world.get_component_mut::<Transform>(id) {
+            t.position = (aabb.min + aabb.max) * 0.5;
+        }
+        if let Some(c) = world.get_component_mut::<AABBCollider>(id) {
+            c.extent = aabb.max - aabb.min;
+        }
+    }
+
+    for (name, mut system) in [
+        ("grid", AabbCollisionSystem::with_broad_phase(SpatialHashGrid::new(2.0))),
+        ("tree", AabbCollisionSystem::with_broad_phase(DynamicAabbTree::new(0.1))),
+    ] {
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            system.iterate(world, 1.0);
+        }
+        println!("AabbCollisionSystem with {name}, 10000 colliders: {:.2?} per frame", start.elapsed() / FRAMES);
+    }
+}
@@ -0,0 +1,118 @@
+use mvengine::net::client::{Client, ClientHandler};
+use mvengine::net::server::{ClientEndpoint, Server, ServerHandler};
+use mvengine::net::DisconnectReason;
+use parking_lot::RwLock;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CLIENTS: usize = 200;
+const PACKETS: u64 = 50;
+
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+static RECEIVED: AtomicU64 = AtomicU64::new(0);
+static LATENCY_MICROS: AtomicU64 = AtomicU64::new(0);
+
+fn micros() -> u64 {
+    EPOCH.get_or_init(Instant::now).elapsed().as_micros() as u64
+}
+
+struct Echo;
+
+impl ServerHandler<u64> for Echo {
+    fn on_server_start(_: &[SocketAddr]) -> Self {
+        Echo
+    }
+
+    fn on_client_connect(&mut self, _: Arc<ClientEndpoint>) {}
+
+    fn on_client_disconnect(&mut self, _: Arc<ClientEndpoint>, _: DisconnectReason) {}
+
+    fn on_packet(&mut self, client: Arc<ClientEndpoint>, packet: u64) {
+        client.send(packet);
+    }
+
+    fn on_server_stop(&mut self, _: &str) {}
+}
+
+struct Measure;
+
+impl ClientHandler<u64> for Measure {
+    fn on_connected(&mut self) {}
+
+    fn on_disconnected(&mut self, _: DisconnectReason) {}
+
+    fn on_packet(&mut self, sent: u64) {
+        LATENCY_MICROS.fetch_add(micros().saturating_sub(sent), Ordering::Relaxed);
+        RECEIVED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// User and system time of this process, only available on linux.
+fn cpu_time() -> Option<Duration> {
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // the command name can contain spaces, the fields after it cannot
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let ticks: u64 = fields.get(11)?.parse::<u64>().ok()? + fields.get(12)?.parse::<u64>().ok()?;
+    // USER_HZ is 100 on practically every system
+    Some(Duration::from_millis(ticks * 10))
+}
+
+fn main() {
+    micros();
+    let mut server = Server::<u64, u64>::new();
+    server.listen::<Echo>("127.0.0.1:0").expect("Could not listen on loopback");
+    let addr = server.local_addrs()[0];
+
+    let start = Instant::now();
+    let mut clients: Vec<Client<u64, u64>> = (0..CLIENTS)
+        .map(|_| Client::connect(addr, Arc::new(RwLock::new(Measure))).expect("Could not connect"))
+        .collect();
+    println!("connecting {CLIENTS} clients: {:?}", start.elapsed());
+
+    thread::sleep(Duration::from_millis(500));
+    let before = cpu_time();
+    thread::sleep(Duration::from_secs(2));
+    match (before, cpu_time()) {
+        (Some(before), Some(after)) => println!("idle cpu time per second: {:?}", (after - before) / 2),
+        _ => println!("idle cpu time per second: not available"),
+    }
+
+    // one packet at a time, so the time is not spent in queues
+    let start = Instant::now();
+    for i in 1..=PACKETS {
+        clients[0].send(micros());
+        while RECEIVED.load(Ordering::Relaxed) < i {
+            assert!(start.elapsed() < Duration::from_secs(60), "Echoes did not arrive");
+            thread::yield_now();
+        }
+    }
+    println!("single round trip: {:?}", start.elapsed() / PACKETS as u32);
+    RECEIVED.store(0, Ordering::Relaxed);
+    LATENCY_MICROS.store(0, Ordering::Relaxed);
+
+    let total = CLIENTS as u64 * PACKETS;
+    let start = Instant::now();
+    for _ in 0..PACKETS {
+        for client in &mut clients {
+            client.send(micros());
+        }
+    }
+    while RECEIVED.load(Ordering::Relaxed) < total {
+        assert!(start.elapsed() < Duration::from_secs(60), "Echoes did not arrive");
+        thread::sleep(Duration::from_millis(1));
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{total} echoes: {elapsed:?}, {:.0} echoes/s, mean round trip {:?}",
+        total as f64 / elapsed.as_secs_f64(),
+        Duration::from_micros(LATENCY_MICROS.load(Ordering::Relaxed) / total)
+    );
+
+    for client in &mut clients {
+        client.disconnect(DisconnectReason::Disconnected);
+    }
+    server.stop("benchmark finished");
+}
@@ -0,0 +1,24 @@
+use crate::audio::decode::{AudioDecoder, decode_compressed};
+use crate::audio::source::Sound;
+use std::sync::Arc;
+use symphonia::default::codecs::FlacDecoder as Codec;
+use symphonia::default::formats::FlacReader;
+
+/// Decodes FLAC files. They are lossless, so the samples are exactly the ones that were encoded.
+pub struct FlacDecoder;
+
+impl FlacDecoder {
+    pub fn try_decode(input: &[u8]) -> Result<Arc<Sound>, String> {
+        decode_compressed::<FlacReader, Codec>(input)
+    }
+}
+
+impl AudioDecoder for FlacDecoder {
+    fn is_compatible(input: &[u8]) -> bool {
+        input.starts_with(b"fLaC")
+    }
+
+    fn decode(&self, input: &[u8]) -> Arc<Sound> {
+        Self::try_decode(input).expect("Not flac data")
+    }
+}
@@ -1,9 +1,191 @@
 use crate::audio::source::Sound;
+use log::warn;
+use std::io::Cursor;
 use std::sync::Arc;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{CodecParameters, Decoder, DecoderOptions};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
 
+pub mod flac;
+pub mod mp3;
+pub mod vorbis;
 pub mod wav;
 
+use flac::FlacDecoder;
+use mp3::Mp3Decoder;
+use vorbis::VorbisDecoder;
+use wav::WavDecoder;
+
 pub trait AudioDecoder {
     fn is_compatible(input: &[u8]) -> bool;
     fn decode(&self, input: &[u8]) -> Arc<Sound>;
 }
+
+/// Decodes `input` with the first decoder that is compatible with it.
+/// Returns `None` if the format is not supported or the data is corrupt. Use the `try_decode` function of a decoder
+/// to get the reason, [`AudioDecoder::decode`] panics instead.
+pub fn decode_any(input: &[u8]) -> Option<Arc<Sound>> {
+    let decoded = if WavDecoder::is_compatible(input) {
+        WavDecoder::try_decode(input)
+    } else if FlacDecoder::is_compatible(input) {
+        FlacDecoder::try_decode(input)
+    } else if VorbisDecoder::is_compatible(input) {
+        VorbisDecoder::try_decode(input)
+    } else if Mp3Decoder::is_compatible(input) {
+        Mp3Decoder::try_decode(input)
+    } else {
+        return None;
+    };
+    decoded.map_err(|e| warn!("Could not decode audio: {e}")).ok()
+}
+
+/// Demuxes `input` with `R` and decodes its default track with `D` into interleaved samples.
+pub(crate) fn decode_compressed<R: FormatReader + 'static, D: Decoder + 'static>(input: &[u8]) -> Result<Arc<Sound>, String> {
+    let source = MediaSourceStream::new(Box::new(Cursor::new(input.to_vec())), Default::default());
+    let reader = R::try_new(source, &PacketDecoder::format_options()).map_err(|e| e.to_string())?;
+    let mut decoder = PacketDecoder::new(Box::new(reader), |params| {
+        Ok(Box::new(D::try_new(params, &DecoderOptions::default())?))
+    })?;
+    let mut samples = Vec::new();
+    while decoder.next(&mut samples)? {}
+    if samples.is_empty() {
+        return Err("No audio decoded".to_string());
+    }
+    Ok(Sound::from_raw(decoder.channels(), decoder.sample_rate(), samples))
+}
+
+/// Decodes the default track of a container packet by packet into interleaved mono or stereo samples.
+/// Packets that fail to decode are skipped, like players do with corrupt frames.
+pub(crate) struct PacketDecoder {
+    reader: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    source_channels: usize,
+    sample_rate: u32,
+    frames: Option<u64>,
+    /// The frame the next decoded samples start at.
+    position: u64,
+    /// Frames still to drop after a seek landed before the requested frame.
+    skip: u64,
+    buffer: Option<SampleBuffer<f32>>,
+}
+
+impl PacketDecoder {
+    pub(crate) fn format_options() -> FormatOptions {
+        FormatOptions {
+            // trims the encoder delay and padding, so the samples line up with the original
+            enable_gapless: true,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn new(
+        reader: Box<dyn FormatReader>,
+        make_decoder: impl FnOnce(&CodecParameters) -> symphonia::core::errors::Result<Box<dyn Decoder>>,
+    ) -> Result<Self, String> {
+        let track = reader.default_track().ok_or("No audio track")?;
+        let params = &track.codec_params;
+        let source_channels = params.channels.ok_or("Unknown channel layout")?.count();
+        let sample_rate = params.sample_rate.ok_or("Unknown sample rate")?;
+        Ok(Self {
+            track_id: track.id,
+            source_channels,
+            sample_rate,
+            frames: params.n_frames,
+            decoder: make_decoder(params).map_err(|e| e.to_string())?,
+            reader,
+            position: 0,
+            skip: 0,
+            buffer: None,
+        })
+    }
+
+    /// Sounds are mono or stereo, so only the front left and right channels of other layouts are kept.
+    pub(crate) fn channels(&self) -> u8 {
+        self.source_channels.min(2) as u8
+    }
+
+    pub(crate) fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// The length in frames, if the container stores it.
+    pub(crate) fn frames(&self) -> Option<u64> {
+        self.frames
+    }
+
+    /// Appends the samples of the next packet to `out`, returns `false` once the track ended.
+    pub(crate) fn next(&mut self, out: &mut Vec<f32>) -> Result<bool, String> {
+        loop {
+            // some encoders pad the last block, the length from the header is the real one
+            if self.frames.is_some_and(|frames| self.position >= frames) {
+                return Ok(false);
+            }
+            let packet = match self.reader.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+                // a chained stream with different parameters starts, only the first one is decoded
+                Err(Error::ResetRequired) => return Ok(false),
+                Err(e) => return Err(e.to_string()),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(Error::DecodeError(e)) => {
+                    warn!("Skipping corrupt audio packet: {e}");
+                    continue;
+                }
+                Err(e) => return Err(e.to_string()),
+            };
+            let spec = *decoded.spec();
+            let channels = spec.channels.count();
+            if self.buffer.as_ref().is_none_or(|b| b.capacity() < decoded.capacity() * channels) {
+                self.buffer = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+            }
+            let buffer = self.buffer.as_mut().expect("buffer was just created");
+            buffer.copy_interleaved_ref(decoded);
+
+            let decoded_frames = buffer.samples().len() / channels;
+            let skip = self.skip.min(decoded_frames as u64) as usize;
+            self.skip -= skip as u64;
+            let mut take = decoded_frames - skip;
+            if let Some(frames) = self.frames {
+                take = take.min(frames.saturating_sub(self.position) as usize);
+            }
+            if take == 0 {
+                continue;
+            }
+            let samples = &buffer.samples()[skip * channels..(skip + take) * channels];
+            if channels <= 2 {
+                out.extend_from_slice(samples);
+            } else {
+                for frame in samples.chunks_exact(channels) {
+                    out.extend_from_slice(&frame[..2]);
+                }
+            }
+            self.position += take as u64;
+            return Ok(true);
+        }
+    }
+
+    /// Continues decoding at `frame`, returns the frame it actually continues at.
+    /// The readers of all supported formats count timestamps in frames.
+    pub(crate) fn seek(&mut self, frame: u64) -> Result<u64, String> {
+        let frame = self.frames.map_or(frame, |frames| frame.min(frames));
+        let seeked = self
+            .reader
+            .seek(SeekMode::Accurate, SeekTo::TimeStamp {
+                ts: frame,
+                track_id: self.track_id,
+            })
+            .map_err(|e| e.to_string())?;
+        self.decoder.reset();
+        self.skip = seeked.required_ts.saturating_sub(seeked.actual_ts);
+        self.position = seeked.required_ts.max(seeked.actual_ts);
+        Ok(self.position)
+    }
+}
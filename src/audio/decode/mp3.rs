@@ -0,0 +1,43 @@
+use crate::audio::decode::{AudioDecoder, decode_compressed};
+use crate::audio::source::Sound;
+use std::sync::Arc;
+use symphonia::default::codecs::MpaDecoder;
+use symphonia::default::formats::MpaReader;
+
+/// Decodes MP3 files. If the encoder wrote a LAME tag, the delay and padding it added are removed.
+pub struct Mp3Decoder;
+
+impl Mp3Decoder {
+    pub fn try_decode(input: &[u8]) -> Result<Arc<Sound>, String> {
+        decode_compressed::<MpaReader, MpaDecoder>(skip_id3(input))
+    }
+}
+
+impl AudioDecoder for Mp3Decoder {
+    fn is_compatible(input: &[u8]) -> bool {
+        if input.starts_with(b"ID3") {
+            return true;
+        }
+        // a layer III frame header: sync word, a valid bitrate and sample rate
+        match input {
+            [0xFF, b, c, ..] => b & 0xE0 == 0xE0 && b & 0x06 == 0x02 && c >> 4 != 0x0F && (c >> 2) & 0x03 != 0x03,
+            _ => false,
+        }
+    }
+
+    fn decode(&self, input: &[u8]) -> Arc<Sound> {
+        Self::try_decode(input).expect("Not mp3 data")
+    }
+}
+
+/// Skips the ID3v2 tag at the start, which could otherwise contain something that looks like a frame header.
+fn skip_id3(input: &[u8]) -> &[u8] {
+    match input {
+        [b'I', b'D', b'3', _, _, flags, size @ ..] if size.len() >= 4 => {
+            let size = size[..4].iter().fold(0usize, |size, &b| (size << 7) | (b & 0x7F) as usize);
+            let footer = if flags & 0x10 != 0 { 10 } else { 0 };
+            input.get(10 + size + footer..).unwrap_or(&[])
+        }
+        _ => input,
+    }
+}
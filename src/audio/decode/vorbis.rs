@@ -0,0 +1,29 @@
+use crate::audio::decode::{AudioDecoder, decode_compressed};
+use crate::audio::source::Sound;
+use std::sync::Arc;
+use symphonia::default::codecs::VorbisDecoder as Codec;
+use symphonia::default::formats::OggReader;
+
+/// Decodes Ogg Vorbis files.
+pub struct VorbisDecoder;
+
+impl VorbisDecoder {
+    pub fn try_decode(input: &[u8]) -> Result<Arc<Sound>, String> {
+        decode_compressed::<OggReader, Codec>(input)
+    }
+}
+
+impl AudioDecoder for VorbisDecoder {
+    fn is_compatible(input: &[u8]) -> bool {
+        // the first page of the ogg container holds the vorbis identification header, right after the segment table
+        if !input.starts_with(b"OggS") || input.len() < 27 {
+            return false;
+        }
+        let header = 27 + input[26] as usize;
+        input.get(header..header + 7) == Some(b"\x01vorbis")
+    }
+
+    fn decode(&self, input: &[u8]) -> Arc<Sound> {
+        Self::try_decode(input).expect("Not ogg vorbis data")
+    }
+}
@@ -12,6 +12,15 @@ use std::sync::Arc;
 
 pub struct WavDecoder;
 
+impl WavDecoder {
+    pub fn try_decode(input: &[u8]) -> Result<Arc<Sound>, String> {
+        let mut buffer = ByteBuffer::from_bytes(input);
+        buffer.set_endian(Endian::LittleEndian);
+        let decoded = WavData::load(&mut buffer)?;
+        Ok(Sound::from_wav(decoded.try_into()?))
+    }
+}
+
 pub type HeaderType4ByteString = [u8; 4];
 
 #[derive(Debug, Savable)]
@@ -43,12 +52,50 @@ impl WavData {
     // NOTE: idk if this works lmao
     // Re: NOTE: i think it does (maybe)
     pub fn validate(&self) -> bool {
-        self.header == *b"RIFF" && self.WAVE == *b"WAVE" && self.fmt_null_byte == *b"fmt\0"
+        self.header == *b"RIFF" && self.WAVE == *b"WAVE" && self.fmt_null_byte == *b"fmt "
+    }
+
+    /// 16 bit PCM of the interleaved `samples`, which are clipped to -1..1.
+    pub fn from_samples(channels: u16, sample_rate: u32, samples: &[f32]) -> Self {
+        let data: Vec<u8> = samples
+            .iter()
+            .flat_map(|s| ((s * 32768.0).round().clamp(-32768.0, 32767.0) as i16).to_le_bytes())
+            .collect();
+        let block_align = channels * 2;
+        Self {
+            header: *b"RIFF",
+            file_size: 36 + data.len() as u32,
+            WAVE: *b"WAVE",
+            fmt_null_byte: *b"fmt ",
+            format_length: 16,
+            format_type: 1,
+            num_channels: channels,
+            sample_rate,
+            weird_calculation_using_data_already_in_this_struct: sample_rate * block_align as u32,
+            even_uselessler_calculation: block_align,
+            bits_per_sample: 16,
+            data_header: *b"data",
+            data_size: data.len() as u32,
+            data,
+        }
+    }
+
+    /// The contents of a .wav file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = ByteBuffer::new_le();
+        self.save(&mut buffer);
+        buffer.into_vec()
     }
 }
 
-impl From<WavData> for ActuallyUsefulWavData {
-    fn from(value: WavData) -> Self {
+impl TryFrom<WavData> for ActuallyUsefulWavData {
+    type Error = String;
+
+    /// Only mono and stereo files with 8 or 16 bit samples are supported.
+    fn try_from(value: WavData) -> Result<Self, String> {
+        if !(1..=2).contains(&value.num_channels) {
+            return Err(format!("Unsupported wav channel count {}", value.num_channels));
+        }
         let mut buffer = ByteBuffer::from_vec_le(value.data);
         let mut samples = Vec::new();
         match value.bits_per_sample {
@@ -62,15 +109,13 @@ impl From<WavData> for ActuallyUsefulWavData {
                     samples.push((value as f32) / 32768.0);
                 }
             }
-            _ => {
-                panic!("Illegal wav format, you are going to jail");
-            }
+            bits => return Err(format!("Unsupported wav sample size of {bits} bits")),
         }
-        ActuallyUsefulWavData {
+        Ok(ActuallyUsefulWavData {
             channels: value.num_channels as u8,
             sample_rate: value.sample_rate,
             samples,
-        }
+        })
     }
 }
 
@@ -88,9 +133,6 @@ impl AudioDecoder for WavDecoder {
     }
 
     fn decode(&self, input: &[u8]) -> Arc<Sound> {
-        let mut buffer = ByteBuffer::from_bytes(input);
-        buffer.set_endian(Endian::LittleEndian);
-        let decoded = WavData::load(&mut buffer).expect("Not wav data");
-        Sound::from_wav(decoded.into())
+        Self::try_decode(input).expect("Not wav data")
     }
 }
@@ -1,8 +1,627 @@
-pub struct DJ {}
+//! The music system.
+//!
+//! The [`DJ`] plays a [`Playlist`] of [`Track`]s, moves from one track to the next with a [`Transition`] and can
+//! hold transitions back until the next beat or bar of the playing track, see [`Quantize`]. Tracks can have stems,
+//! extra layers like drums or a choir that fade in while the intensity of the game is high.
+//!
+//! The DJ is mixed by the [`AudioMixer`](crate::audio::mixer::AudioMixer) like any other sound, which makes it
+//! sample accurate: tracks follow each other without a gap, and synced transitions start exactly on the beat.
+
+use crate::audio::resample::Playhead;
+use crate::audio::source::{Sound, SoundWithAttributes};
+use crate::ui::ease::{Easing, EasingGen, EasingMode};
+use parking_lot::Mutex;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The rhythm of a track, used to sync transitions to it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tempo {
+    pub bpm: f32,
+    pub beats_per_bar: u32,
+    /// Where the first bar starts, e.g. after a pickup or a silent lead-in.
+    pub offset: Duration,
+}
+
+impl Tempo {
+    pub fn new(bpm: f32, beats_per_bar: u32) -> Self {
+        Self {
+            bpm,
+            beats_per_bar,
+            offset: Duration::ZERO,
+        }
+    }
+
+    pub fn with_offset(mut self, offset: Duration) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    fn beat_frames(&self, sample_rate: u32) -> f64 {
+        sample_rate as f64 * 60.0 / self.bpm as f64
+    }
+
+    fn offset_frames(&self, sample_rate: u32) -> f64 {
+        self.offset.as_secs_f64() * sample_rate as f64
+    }
+
+    /// The first beat, or bar if `bar`, at or after `position`.
+    fn next_boundary(&self, position: f64, sample_rate: u32, bar: bool) -> f64 {
+        let offset = self.offset_frames(sample_rate);
+        let mut unit = self.beat_frames(sample_rate);
+        if bar {
+            unit *= self.beats_per_bar.max(1) as f64;
+        }
+        if position <= offset {
+            return offset;
+        }
+        offset + ((position - offset) / unit).ceil() * unit
+    }
+}
+
+/// A layer of a track that plays in sync with it, heard once the intensity reaches `intensity`.
+#[derive(Clone)]
+pub struct Stem {
+    pub sound: Arc<Sound>,
+    pub intensity: f32,
+}
+
+#[derive(Clone)]
+pub struct Track {
+    pub sound: Arc<Sound>,
+    pub tempo: Option<Tempo>,
+    /// Stems should be as long as the track and have the same sample rate.
+    pub stems: Vec<Stem>,
+}
+
+impl Track {
+    pub fn new(sound: Arc<Sound>) -> Self {
+        Self {
+            sound,
+            tempo: None,
+            stems: Vec::new(),
+        }
+    }
+
+    pub fn with_tempo(mut self, tempo: Tempo) -> Self {
+        self.tempo = Some(tempo);
+        self
+    }
+
+    pub fn with_stem(mut self, sound: Arc<Sound>, intensity: f32) -> Self {
+        self.stems.push(Stem { sound, intensity });
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Repeat {
+    /// Stops after the last track.
+    Off,
+    /// Repeats the playing track until it is skipped.
+    One,
+    /// Starts over after the last track, shuffled playlists are shuffled again.
+    All,
+}
+
+pub struct Playlist {
+    tracks: Vec<Track>,
+    repeat: Repeat,
+    shuffle: bool,
+    rng: StdRng,
+    /// The order the tracks play in, shuffled or not.
+    order: Vec<usize>,
+    /// Where in the order the playing track is.
+    cursor: usize,
+}
+
+impl Playlist {
+    pub fn new(tracks: Vec<Track>) -> Self {
+        let order = (0..tracks.len()).collect();
+        Self {
+            tracks,
+            repeat: Repeat::Off,
+            shuffle: false,
+            rng: StdRng::from_rng(&mut rand::rng()),
+            order,
+            cursor: 0,
+        }
+    }
+
+    pub fn with_repeat(mut self, repeat: Repeat) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    pub fn with_shuffle(mut self, shuffle: bool) -> Self {
+        self.shuffle = shuffle;
+        self
+    }
+
+    /// Makes the shuffled order the same every time.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    pub fn repeat(&self) -> Repeat {
+        self.repeat
+    }
+
+    pub fn set_repeat(&mut self, repeat: Repeat) {
+        self.repeat = repeat;
+    }
+
+    fn first(&mut self) -> Option<usize> {
+        self.cursor = 0;
+        if self.shuffle {
+            self.order.shuffle(&mut self.rng);
+        }
+        self.order.first().copied()
+    }
+
+    /// The track after the playing one. Skipping leaves a track that repeats, ending it plays it again.
+    fn next(&mut self, skipped: bool) -> Option<usize> {
+        let playing = *self.order.get(self.cursor)?;
+        if self.repeat == Repeat::One && !skipped {
+            return Some(playing);
+        }
+        self.cursor += 1;
+        if self.cursor < self.order.len() {
+            return Some(self.order[self.cursor]);
+        }
+        if self.repeat == Repeat::Off {
+            self.cursor = self.order.len();
+            return None;
+        }
+        self.cursor = 0;
+        if self.shuffle {
+            self.order.shuffle(&mut self.rng);
+            // the track that just played should not play twice in a row
+            if self.order.len() > 1 && self.order[0] == playing {
+                let last = self.order.len() - 1;
+                self.order.swap(0, last);
+            }
+        }
+        Some(self.order[0])
+    }
+
+    fn jump_to(&mut self, track: usize) -> Option<usize> {
+        self.cursor = self.order.iter().position(|&t| t == track)?;
+        Some(track)
+    }
+}
+
+/// How the DJ moves from one track to the next.
+#[derive(Clone)]
+pub enum Transition {
+    /// The next track starts right where the last one ends, or cuts it off when it is skipped.
+    Cut,
+    /// The tracks overlap for `duration`, the old one fades out while the new one fades in.
+    Crossfade { duration: Duration, ease: EasingGen },
+}
+
+/// When a transition starts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Quantize {
+    Now,
+    /// At the next beat of the playing track. The next track starts at its first bar, so the beats line up.
+    Beat,
+    /// At the next bar of the playing track, the next track starts at its first bar.
+    Bar,
+    /// When the playing track ends, crossfades start early enough to end with it.
+    End,
+}
+
+/// What happens once a transition is due.
+enum Cue {
+    Start(Playlist),
+    /// The next track of the playlist, `skipped` if it was asked for instead of the last track ending.
+    Next {
+        skipped: bool,
+    },
+    Track(usize),
+    Stop,
+}
+
+struct Pending {
+    cue: Cue,
+    /// The frame of the playing track the transition waits for.
+    at: f64,
+    /// If the transition is on a beat or bar.
+    synced: bool,
+}
+
+/// A sound of a deck with its own position, all layers of a deck move in lockstep.
+struct Layer {
+    sound: Arc<SoundWithAttributes>,
+    playhead: Playhead,
+}
+
+impl Layer {
+    fn new(sound: Arc<Sound>, position: f64) -> Self {
+        Self {
+            sound: SoundWithAttributes::new(sound),
+            playhead: Playhead::new(position),
+        }
+    }
+}
+
+/// A volume that eases towards a target, counted in output frames.
+struct Ramp {
+    easing: Option<Easing>,
+    elapsed: f32,
+    target: f32,
+}
+
+impl Ramp {
+    fn new(value: f32) -> Self {
+        Self {
+            easing: None,
+            elapsed: 0.0,
+            target: value,
+        }
+    }
+
+    fn value(&self) -> f32 {
+        self.easing
+            .as_ref()
+            .map_or(self.target, |easing| easing.get(self.elapsed))
+    }
+
+    fn retarget(&mut self, target: f32, frames: f32, ease: EasingGen) {
+        let value = self.value();
+        self.easing = Some(Easing::new(
+            ease,
+            EasingMode::InOut,
+            0.0..frames.max(1.0),
+            value..target,
+        ));
+        self.elapsed = 0.0;
+        self.target = target;
+    }
+
+    fn advance(&mut self) -> f32 {
+        let value = self.value();
+        self.elapsed += 1.0;
+        value
+    }
+}
+
+/// A track that is playing, or fading out after a crossfade.
+struct Deck {
+    track: usize,
+    tempo: Option<Tempo>,
+    main: Layer,
+    stems: Vec<(f32, Layer, Ramp)>,
+    /// The frame of the track it faded out by, after which it is dropped.
+    stop_at: Option<f64>,
+}
+
+impl Deck {
+    fn new(track: &Track, index: usize, position: f64, intensity: f32) -> Self {
+        let stems = track
+            .stems
+            .iter()
+            .map(|stem| {
+                let gain = if intensity >= stem.intensity { 1.0 } else { 0.0 };
+                (
+                    stem.intensity,
+                    Layer::new(stem.sound.clone(), position),
+                    Ramp::new(gain),
+                )
+            })
+            .collect();
+        Self {
+            track: index,
+            tempo: track.tempo,
+            main: Layer::new(track.sound.clone(), position),
+            stems,
+            stop_at: None,
+        }
+    }
+
+    fn layers(&self) -> impl Iterator<Item = &Layer> {
+        std::iter::once(&self.main).chain(self.stems.iter().map(|(_, layer, _)| layer))
+    }
+
+    fn position(&self) -> f64 {
+        self.main.playhead.position
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.main.sound.sound().sample_rate()
+    }
+
+    fn frames(&self) -> f64 {
+        self.main.sound.sound().effective_samples() as f64
+    }
+
+    fn fade_in(&self, ease: &EasingGen, duration: Duration) {
+        let start = self.position() as u64;
+        let frames = (duration.as_secs_f64() * self.sample_rate() as f64).round() as u64;
+        for layer in self.layers() {
+            layer.sound.with_attributes().fade_in_at(ease.clone(), start, frames);
+        }
+    }
+
+    fn fade_out(&mut self, ease: &EasingGen, duration: Duration) {
+        let start = self.position() as u64;
+        let frames = (duration.as_secs_f64() * self.sample_rate() as f64).round() as u64;
+        for layer in self.layers() {
+            layer.sound.with_attributes().fade_out_at(ease.clone(), start, frames);
+        }
+        self.stop_at = Some((start + frames) as f64);
+    }
+
+    fn frame(&mut self, sample_rate: u32, intensity: f32, fade: &(Duration, EasingGen)) -> (f32, f32) {
+        let (mut left, mut right) = self.main.sound.next_frame(&mut self.main.playhead, sample_rate);
+        for (threshold, layer, ramp) in &mut self.stems {
+            let target = if intensity >= *threshold { 1.0 } else { 0.0 };
+            if target != ramp.target {
+                ramp.retarget(
+                    target,
+                    (fade.0.as_secs_f64() * sample_rate as f64) as f32,
+                    fade.1.clone(),
+                );
+            }
+            let gain = ramp.advance();
+            let (l, r) = layer.sound.next_frame(&mut layer.playhead, sample_rate);
+            left += l * gain;
+            right += r * gain;
+        }
+        (left, right)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.main.sound.is_finished(&self.main.playhead) || self.stop_at.is_some_and(|end| self.position() >= end)
+    }
+}
+
+struct State {
+    playlist: Option<Playlist>,
+    transition: Transition,
+    current: Option<Deck>,
+    /// Decks that fade out after a crossfade.
+    fading: Vec<Deck>,
+    pending: Option<Pending>,
+    intensity: f32,
+    stem_fade: (Duration, EasingGen),
+}
+
+impl State {
+    /// Where a transition with `quantize` would start in the playing track, and if it is synced to the beat.
+    fn cue_point(&self, quantize: Quantize) -> (f64, bool) {
+        let Some(deck) = &self.current else {
+            return (0.0, false);
+        };
+        let rate = deck.sample_rate();
+        match (quantize, deck.tempo) {
+            (Quantize::Beat, Some(tempo)) => (tempo.next_boundary(deck.position(), rate, false), true),
+            (Quantize::Bar, Some(tempo)) => (tempo.next_boundary(deck.position(), rate, true), true),
+            (Quantize::End, _) => (deck.frames() - self.overlap(deck), false),
+            _ => (0.0, false),
+        }
+    }
+
+    /// How many frames of the deck a crossfade takes, at most half of it so short tracks still play.
+    fn overlap(&self, deck: &Deck) -> f64 {
+        match &self.transition {
+            Transition::Cut => 0.0,
+            Transition::Crossfade { duration, .. } => {
+                (duration.as_secs_f64() * deck.sample_rate() as f64).min(deck.frames() / 2.0)
+            }
+        }
+    }
+
+    fn schedule(&mut self, cue: Cue, quantize: Quantize) {
+        let (at, synced) = self.cue_point(quantize);
+        self.pending = Some(Pending { cue, at, synced });
+    }
+
+    fn update(&mut self) {
+        let due = match (&self.pending, &self.current) {
+            (Some(_), None) => true,
+            (Some(pending), Some(deck)) => deck.position() >= pending.at || deck.is_finished(),
+            // the playlist moves on by itself
+            (None, Some(deck)) => match self.transition {
+                Transition::Cut => deck.is_finished(),
+                Transition::Crossfade { .. } => deck.position() >= deck.frames() - self.overlap(deck),
+            },
+            (None, None) => false,
+        };
+        if !due {
+            return;
+        }
+
+        let pending = self.pending.take().unwrap_or(Pending {
+            cue: Cue::Next { skipped: false },
+            at: 0.0,
+            synced: false,
+        });
+        // at the end of the playlist, the last track plays out instead of fading
+        let play_out = matches!(pending.cue, Cue::Next { skipped: false });
+        let next = match pending.cue {
+            Cue::Start(mut playlist) => {
+                let first = playlist.first();
+                self.playlist = Some(playlist);
+                first
+            }
+            Cue::Next { skipped } => self.playlist.as_mut().and_then(|playlist| playlist.next(skipped)),
+            Cue::Track(track) => self.playlist.as_mut().and_then(|playlist| playlist.jump_to(track)),
+            Cue::Stop => None,
+        };
+        if next.is_none() && play_out {
+            self.fading.extend(self.current.take());
+            return;
+        }
+        self.switch(next, pending.synced);
+    }
+
+    fn switch(&mut self, next: Option<usize>, synced: bool) {
+        let incoming = next.and_then(|index| {
+            let track = self.playlist.as_ref()?.tracks.get(index)?;
+            // synced transitions start on the first bar of the next track, so its beats fall on the old ones
+            let start = match (synced, track.tempo) {
+                (true, Some(tempo)) => tempo.offset_frames(track.sound.sample_rate()),
+                _ => 0.0,
+            };
+            Some(Deck::new(track, index, start, self.intensity))
+        });
+
+        let outgoing = self.current.take().filter(|deck| !deck.is_finished());
+        if let Transition::Crossfade { ease, .. } = &self.transition {
+            // both decks fade for as long as the tracks overlap, which is shorter than the transition for short tracks
+            let fade = match outgoing.as_ref().or(incoming.as_ref()) {
+                Some(deck) => {
+                    let frames = self.overlap(deck).min(deck.frames() - deck.position()).max(0.0);
+                    Duration::from_secs_f64(frames / deck.sample_rate() as f64)
+                }
+                None => Duration::ZERO,
+            };
+            if let Some(mut deck) = outgoing {
+                deck.fade_out(ease, fade);
+                self.fading.push(deck);
+            }
+            if let Some(deck) = &incoming {
+                deck.fade_in(ease, fade);
+            }
+        }
+        self.current = incoming;
+    }
+
+    fn frame(&mut self, sample_rate: u32) -> (f32, f32) {
+        self.update();
+        let (mut left, mut right) = (0.0, 0.0);
+        let fade = &self.stem_fade;
+        for deck in self.current.iter_mut().chain(self.fading.iter_mut()) {
+            let (l, r) = deck.frame(sample_rate, self.intensity, fade);
+            left += l;
+            right += r;
+        }
+        self.fading.retain(|deck| !deck.is_finished());
+        (left, right)
+    }
+}
+
+/// Plays music, see the [module docs](self). Play it with [`AudioEngine::play_dj`](crate::audio::AudioEngine::play_dj).
+pub struct DJ {
+    state: Mutex<State>,
+}
 
 impl DJ {
-    pub fn new() -> Self {
+    pub fn new() -> Arc<Self> {
         log::info!("Hello, I am Mr. DJ! I am excited to play some music for you!");
-        DJ {}
+        let this = Self {
+            state: Mutex::new(State {
+                playlist: None,
+                transition: Transition::Cut,
+                current: None,
+                fading: Vec::new(),
+                pending: None,
+                intensity: 0.0,
+                stem_fade: (Duration::from_secs(2), EasingGen::sin()),
+            }),
+        };
+        Arc::new(this)
+    }
+
+    /// Switches to `playlist`, starting with its first track.
+    pub fn play(&self, playlist: Playlist, quantize: Quantize) {
+        self.state.lock().schedule(Cue::Start(playlist), quantize);
+    }
+
+    /// Skips to the next track of the playlist.
+    pub fn next(&self, quantize: Quantize) {
+        self.state.lock().schedule(Cue::Next { skipped: true }, quantize);
+    }
+
+    /// Skips to a track of the playlist, by its index in [`Playlist::tracks`].
+    pub fn jump_to(&self, track: usize, quantize: Quantize) {
+        self.state.lock().schedule(Cue::Track(track), quantize);
+    }
+
+    /// Stops the music, fading it out if the transition is a crossfade.
+    pub fn stop(&self, quantize: Quantize) {
+        self.state.lock().schedule(Cue::Stop, quantize);
+    }
+
+    pub fn transition(&self) -> Transition {
+        self.state.lock().transition.clone()
+    }
+
+    /// The transition between tracks, both when the playlist moves on and when tracks are skipped.
+    pub fn set_transition(&self, transition: Transition) {
+        self.state.lock().transition = transition;
+    }
+
+    pub fn set_repeat(&self, repeat: Repeat) {
+        if let Some(playlist) = &mut self.state.lock().playlist {
+            playlist.set_repeat(repeat);
+        }
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.state.lock().intensity
+    }
+
+    /// Fades in the stems whose intensity is at most `intensity`, and fades out the others.
+    pub fn set_intensity(&self, intensity: f32) {
+        self.state.lock().intensity = intensity;
+    }
+
+    /// How stems fade when the intensity changes, 2 seconds along a sine by default.
+    pub fn set_stem_fade(&self, duration: Duration, ease: EasingGen) {
+        self.state.lock().stem_fade = (duration, ease);
+    }
+
+    /// The index of the playing track in [`Playlist::tracks`].
+    pub fn current_track(&self) -> Option<usize> {
+        self.state.lock().current.as_ref().map(|deck| deck.track)
+    }
+
+    pub fn is_playing(&self) -> bool {
+        let state = self.state.lock();
+        state.current.is_some() || !state.fading.is_empty() || state.pending.is_some()
+    }
+
+    /// Where the playing track is.
+    pub fn position(&self) -> Option<Duration> {
+        let state = self.state.lock();
+        let deck = state.current.as_ref()?;
+        Some(Duration::from_secs_f64(deck.position() / deck.sample_rate() as f64))
+    }
+
+    /// The bar and the beat in it the playing track is at, both counted from 0. `None` without a tempo or before the
+    /// first bar.
+    pub fn beat(&self) -> Option<(u64, u32)> {
+        let state = self.state.lock();
+        let deck = state.current.as_ref()?;
+        let tempo = deck.tempo?;
+        let rate = deck.sample_rate();
+        let since = deck.position() - tempo.offset_frames(rate);
+        if since < 0.0 {
+            return None;
+        }
+        let beat = (since / tempo.beat_frames(rate)).floor() as u64;
+        let per_bar = tempo.beats_per_bar.max(1) as u64;
+        Some((beat / per_bar, (beat % per_bar) as u32))
+    }
+
+    /// Adds the next `out.len()` frames at `sample_rate` to `out`.
+    pub(crate) fn mix(&self, out: &mut [(f32, f32)], sample_rate: u32) {
+        let mut state = self.state.lock();
+        for frame in out {
+            let (left, right) = state.frame(sample_rate);
+            frame.0 += left;
+            frame.1 += right;
+        }
     }
 }
@@ -0,0 +1,57 @@
+use crate::audio::effect::AudioEffect;
+use std::time::Duration;
+
+/// Repeats the signal after a fixed time, each echo quieter by `feedback`.
+#[derive(Clone)]
+pub struct Delay {
+    time: Duration,
+    feedback: f32,
+    wet: f32,
+    buffer: Vec<(f32, f32)>,
+    position: usize,
+    sample_rate: u32,
+}
+
+impl Delay {
+    /// `feedback` is how much of each echo is fed back to be repeated again, `wet` how loud the echoes are mixed in.
+    pub fn new(time: Duration, feedback: f32, wet: f32) -> Self {
+        Self {
+            time,
+            feedback,
+            wet,
+            buffer: Vec::new(),
+            position: 0,
+            sample_rate: 0,
+        }
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback;
+    }
+
+    pub fn set_wet(&mut self, wet: f32) {
+        self.wet = wet;
+    }
+}
+
+impl AudioEffect for Delay {
+    fn process(&mut self, frames: &mut [(f32, f32)], sample_rate: u32) {
+        if self.sample_rate != sample_rate {
+            self.sample_rate = sample_rate;
+            let length = (self.time.as_secs_f64() * sample_rate as f64).round().max(1.0) as usize;
+            self.buffer = vec![(0.0, 0.0); length];
+            self.position = 0;
+        }
+        for frame in frames {
+            let echo = self.buffer[self.position];
+            self.buffer[self.position] = (frame.0 + echo.0 * self.feedback, frame.1 + echo.1 * self.feedback);
+            self.position = (self.position + 1) % self.buffer.len();
+            frame.0 += echo.0 * self.wet;
+            frame.1 += echo.1 * self.wet;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.buffer.fill((0.0, 0.0));
+    }
+}
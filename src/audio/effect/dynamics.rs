@@ -0,0 +1,128 @@
+use crate::audio::effect::{AudioEffect, db_to_gain, gain_to_db, time_coefficient};
+use std::time::Duration;
+
+/// Turns down everything louder than the threshold, evening out the volume. Both channels are reduced equally, so
+/// the stereo image does not shift.
+#[derive(Clone)]
+pub struct Compressor {
+    threshold_db: f32,
+    ratio: f32,
+    attack: Duration,
+    release: Duration,
+    makeup_db: f32,
+    envelope: f32,
+}
+
+impl Compressor {
+    /// Levels above `threshold_db` are reduced so that they only rise by 1 dB per `ratio` dB of input.
+    pub fn new(threshold_db: f32, ratio: f32) -> Self {
+        Self {
+            threshold_db,
+            ratio: ratio.max(1.0),
+            attack: Duration::from_millis(5),
+            release: Duration::from_millis(100),
+            makeup_db: 0.0,
+            envelope: 0.0,
+        }
+    }
+
+    /// How fast the compressor reacts to rising and falling levels.
+    pub fn with_times(mut self, attack: Duration, release: Duration) -> Self {
+        self.attack = attack;
+        self.release = release;
+        self
+    }
+
+    /// Gain applied after compressing, to make up for the lost volume.
+    pub fn with_makeup(mut self, makeup_db: f32) -> Self {
+        self.makeup_db = makeup_db;
+        self
+    }
+
+    pub fn set_threshold(&mut self, threshold_db: f32) {
+        self.threshold_db = threshold_db;
+    }
+
+    pub fn set_ratio(&mut self, ratio: f32) {
+        self.ratio = ratio.max(1.0);
+    }
+}
+
+impl AudioEffect for Compressor {
+    fn process(&mut self, frames: &mut [(f32, f32)], sample_rate: u32) {
+        let attack = time_coefficient(self.attack.as_secs_f32(), sample_rate);
+        let release = time_coefficient(self.release.as_secs_f32(), sample_rate);
+        let slope = 1.0 - 1.0 / self.ratio;
+        for frame in frames {
+            let peak = frame.0.abs().max(frame.1.abs());
+            let coefficient = if peak > self.envelope { attack } else { release };
+            self.envelope = peak + (self.envelope - peak) * coefficient;
+            let over = gain_to_db(self.envelope) - self.threshold_db;
+            let gain = db_to_gain(self.makeup_db - over.max(0.0) * slope);
+            frame.0 *= gain;
+            frame.1 *= gain;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.envelope = 0.0;
+    }
+}
+
+/// Keeps the signal below the ceiling. Peaks are caught in the same frame, so nothing ever exceeds it, and the gain
+/// recovers over the release time afterwards.
+#[derive(Clone)]
+pub struct Limiter {
+    ceiling: f32,
+    release: Duration,
+    gain: f32,
+}
+
+impl Limiter {
+    /// `ceiling` is the highest allowed sample value, 1.0 is full scale.
+    pub fn new(ceiling: f32) -> Self {
+        Self {
+            ceiling: ceiling.abs(),
+            release: Duration::from_millis(50),
+            gain: 1.0,
+        }
+    }
+
+    pub fn with_release(mut self, release: Duration) -> Self {
+        self.release = release;
+        self
+    }
+
+    pub fn ceiling(&self) -> f32 {
+        self.ceiling
+    }
+
+    pub fn set_ceiling(&mut self, ceiling: f32) {
+        self.ceiling = ceiling.abs();
+    }
+
+    /// The current gain, below 1.0 while the limiter is reducing the volume.
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+}
+
+impl AudioEffect for Limiter {
+    fn process(&mut self, frames: &mut [(f32, f32)], sample_rate: u32) {
+        let release = time_coefficient(self.release.as_secs_f32(), sample_rate);
+        for frame in frames {
+            self.gain = 1.0 + (self.gain - 1.0) * release;
+            let peak = frame.0.abs().max(frame.1.abs());
+            if peak * self.gain > self.ceiling {
+                self.gain = self.ceiling / peak;
+            }
+            // the division can round up by a bit
+            frame.0 = (frame.0 * self.gain).clamp(-self.ceiling, self.ceiling);
+            frame.1 = (frame.1 * self.gain).clamp(-self.ceiling, self.ceiling);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.gain = 1.0;
+    }
+}
@@ -0,0 +1,147 @@
+use crate::audio::effect::AudioEffect;
+use std::f32::consts::{FRAC_1_SQRT_2, PI};
+
+#[derive(Clone, Copy, Default)]
+struct Coefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+/// A second order filter in transposed direct form II, with separate state for both channels.
+#[derive(Clone, Default)]
+struct Biquad {
+    coefficients: Coefficients,
+    /// The sample rate the coefficients were calculated for, 0 if they are outdated.
+    sample_rate: u32,
+    state: [[f32; 2]; 2],
+}
+
+impl Biquad {
+    /// Coefficients from the Audio EQ Cookbook by Robert Bristow-Johnson.
+    fn update(&mut self, sample_rate: u32, cutoff: f32, q: f32, high_pass: bool) {
+        if self.sample_rate == sample_rate {
+            return;
+        }
+        self.sample_rate = sample_rate;
+        let cutoff = cutoff.clamp(1.0, sample_rate as f32 * 0.49);
+        let w0 = 2.0 * PI * cutoff / sample_rate as f32;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q.max(0.01));
+        let a0 = 1.0 + alpha;
+        let (b0, b1) = if high_pass {
+            ((1.0 + cos) / 2.0, -(1.0 + cos))
+        } else {
+            ((1.0 - cos) / 2.0, 1.0 - cos)
+        };
+        self.coefficients = Coefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b0 / a0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha) / a0,
+        };
+    }
+
+    fn process(&mut self, frames: &mut [(f32, f32)]) {
+        let c = self.coefficients;
+        for frame in frames {
+            for (channel, sample) in [&mut frame.0, &mut frame.1].into_iter().enumerate() {
+                let state = &mut self.state[channel];
+                let input = *sample;
+                let output = c.b0 * input + state[0];
+                state[0] = c.b1 * input - c.a1 * output + state[1];
+                state[1] = c.b2 * input - c.a2 * output;
+                *sample = output;
+            }
+        }
+    }
+}
+
+/// Removes frequencies above the cutoff, making sounds muffled, e.g. behind a wall or under water.
+#[derive(Clone)]
+pub struct LowPass {
+    cutoff: f32,
+    q: f32,
+    filter: Biquad,
+}
+
+impl LowPass {
+    pub fn new(cutoff: f32) -> Self {
+        Self::with_q(cutoff, FRAC_1_SQRT_2)
+    }
+
+    /// Higher `q` values resonate around the cutoff, the default of `1/sqrt(2)` does not.
+    pub fn with_q(cutoff: f32, q: f32) -> Self {
+        Self {
+            cutoff,
+            q,
+            filter: Biquad::default(),
+        }
+    }
+
+    pub fn cutoff(&self) -> f32 {
+        self.cutoff
+    }
+
+    pub fn set_cutoff(&mut self, cutoff: f32) {
+        self.cutoff = cutoff;
+        self.filter.sample_rate = 0;
+    }
+}
+
+impl AudioEffect for LowPass {
+    fn process(&mut self, frames: &mut [(f32, f32)], sample_rate: u32) {
+        self.filter.update(sample_rate, self.cutoff, self.q, false);
+        self.filter.process(frames);
+    }
+
+    fn reset(&mut self) {
+        self.filter.state = Default::default();
+    }
+}
+
+/// Removes frequencies below the cutoff, making sounds thin, e.g. for radio chatter.
+#[derive(Clone)]
+pub struct HighPass {
+    cutoff: f32,
+    q: f32,
+    filter: Biquad,
+}
+
+impl HighPass {
+    pub fn new(cutoff: f32) -> Self {
+        Self::with_q(cutoff, FRAC_1_SQRT_2)
+    }
+
+    /// Higher `q` values resonate around the cutoff, the default of `1/sqrt(2)` does not.
+    pub fn with_q(cutoff: f32, q: f32) -> Self {
+        Self {
+            cutoff,
+            q,
+            filter: Biquad::default(),
+        }
+    }
+
+    pub fn cutoff(&self) -> f32 {
+        self.cutoff
+    }
+
+    pub fn set_cutoff(&mut self, cutoff: f32) {
+        self.cutoff = cutoff;
+        self.filter.sample_rate = 0;
+    }
+}
+
+impl AudioEffect for HighPass {
+    fn process(&mut self, frames: &mut [(f32, f32)], sample_rate: u32) {
+        self.filter.update(sample_rate, self.cutoff, self.q, true);
+        self.filter.process(frames);
+    }
+
+    fn reset(&mut self) {
+        self.filter.state = Default::default();
+    }
+}
@@ -0,0 +1,31 @@
+//! Effects that process the mixed audio of a [`MixerBus`](crate::audio::mixer::MixerBus) block by block.
+
+pub mod delay;
+pub mod dynamics;
+pub mod filter;
+pub mod reverb;
+
+/// Processes blocks of stereo frames in place. Effects keep their state between blocks, so a signal split into blocks
+/// sounds the same as if it was processed at once.
+pub trait AudioEffect: Send {
+    fn process(&mut self, frames: &mut [(f32, f32)], sample_rate: u32);
+
+    /// Clears the state, e.g. the tail of a reverb or delay.
+    fn reset(&mut self);
+}
+
+pub(crate) fn db_to_gain(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+pub(crate) fn gain_to_db(gain: f32) -> f32 {
+    20.0 * gain.max(1e-9).log10()
+}
+
+/// The per frame factor of an exponential envelope that covers about two thirds of the way in `seconds`.
+pub(crate) fn time_coefficient(seconds: f32, sample_rate: u32) -> f32 {
+    if seconds <= 0.0 {
+        return 0.0;
+    }
+    (-1.0 / (seconds * sample_rate as f32)).exp()
+}
@@ -0,0 +1,135 @@
+use crate::audio::effect::AudioEffect;
+
+// the tunings of Freeverb by Jezar at Dreampoint, in frames at 44.1 kHz
+const COMBS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASSES: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+const INPUT_GAIN: f32 = 0.015;
+
+#[derive(Clone)]
+struct Comb {
+    buffer: Vec<f32>,
+    position: usize,
+    filtered: f32,
+}
+
+impl Comb {
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.position];
+        self.filtered = output * (1.0 - damping) + self.filtered * damping;
+        self.buffer[self.position] = input + self.filtered * feedback;
+        self.position = (self.position + 1) % self.buffer.len();
+        output
+    }
+}
+
+#[derive(Clone)]
+struct Allpass {
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+impl Allpass {
+    fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.buffer[self.position];
+        self.buffer[self.position] = input + delayed * 0.5;
+        self.position = (self.position + 1) % self.buffer.len();
+        delayed - input
+    }
+}
+
+/// Simulates the reflections of a room, after the Freeverb algorithm.
+#[derive(Clone)]
+pub struct Reverb {
+    room_size: f32,
+    damping: f32,
+    wet: f32,
+    /// Filters of the left and right channel, the right ones slightly longer to widen the stereo image.
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<Allpass>; 2],
+    sample_rate: u32,
+}
+
+impl Reverb {
+    /// `room_size` and `damping` go from 0 to 1, larger rooms ring longer and more damping absorbs the high
+    /// frequencies faster. `wet` is how loud the reverb is mixed in.
+    pub fn new(room_size: f32, damping: f32, wet: f32) -> Self {
+        Self {
+            room_size: room_size.clamp(0.0, 1.0),
+            damping: damping.clamp(0.0, 1.0),
+            wet,
+            combs: [Vec::new(), Vec::new()],
+            allpasses: [Vec::new(), Vec::new()],
+            sample_rate: 0,
+        }
+    }
+
+    pub fn set_room_size(&mut self, room_size: f32) {
+        self.room_size = room_size.clamp(0.0, 1.0);
+    }
+
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = damping.clamp(0.0, 1.0);
+    }
+
+    pub fn set_wet(&mut self, wet: f32) {
+        self.wet = wet;
+    }
+
+    fn allocate(&mut self, sample_rate: u32) {
+        let scale = |length: usize| ((length as u64 * sample_rate as u64) / 44100).max(1) as usize;
+        for channel in 0..2 {
+            let spread = channel * STEREO_SPREAD;
+            self.combs[channel] = COMBS
+                .iter()
+                .map(|length| Comb {
+                    buffer: vec![0.0; scale(length + spread)],
+                    position: 0,
+                    filtered: 0.0,
+                })
+                .collect();
+            self.allpasses[channel] = ALLPASSES
+                .iter()
+                .map(|length| Allpass {
+                    buffer: vec![0.0; scale(length + spread)],
+                    position: 0,
+                })
+                .collect();
+        }
+    }
+}
+
+impl AudioEffect for Reverb {
+    fn process(&mut self, frames: &mut [(f32, f32)], sample_rate: u32) {
+        if self.sample_rate != sample_rate {
+            self.sample_rate = sample_rate;
+            self.allocate(sample_rate);
+        }
+        let feedback = self.room_size * 0.28 + 0.7;
+        let damping = self.damping * 0.4;
+        for frame in frames {
+            let input = (frame.0 + frame.1) * INPUT_GAIN;
+            let mut output = [0.0; 2];
+            for (channel, output) in output.iter_mut().enumerate() {
+                for comb in &mut self.combs[channel] {
+                    *output += comb.process(input, feedback, damping);
+                }
+                for allpass in &mut self.allpasses[channel] {
+                    *output = allpass.process(*output);
+                }
+            }
+            frame.0 += output[0] * self.wet;
+            frame.1 += output[1] * self.wet;
+        }
+    }
+
+    fn reset(&mut self) {
+        for comb in self.combs.iter_mut().flatten() {
+            comb.buffer.fill(0.0);
+            comb.filtered = 0.0;
+        }
+        for allpass in self.allpasses.iter_mut().flatten() {
+            allpass.buffer.fill(0.0);
+        }
+    }
+}
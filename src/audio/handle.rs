@@ -0,0 +1,188 @@
+//! Control over sounds after they started playing.
+//!
+//! Playing a sound returns a [`PlaybackHandle`] to stop, pause or seek it later. Handles are cheap to clone and can
+//! be dropped, the sound plays on until it ends either way.
+
+use crate::audio::source::SoundWithAttributes;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Why a voice stopped playing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PlaybackEnd {
+    /// The sound played to its end.
+    Finished,
+    /// It was stopped through its handle.
+    Stopped,
+    /// A sound with a higher or equal priority took its voice, see [`VoiceLimit`](crate::audio::mixer::VoiceLimit).
+    Stolen,
+    /// All voices were taken by sounds with a higher priority, so it never played.
+    Rejected,
+}
+
+type FinishCallback = Box<dyn FnOnce(PlaybackEnd) + Send>;
+
+const PLAYING: u8 = 0;
+const PAUSED: u8 = 1;
+const STOPPING: u8 = 2;
+/// No seek is pending, the bits of a NaN that `f64` arithmetic never produces.
+const NO_SEEK: u64 = u64::MAX;
+
+/// State shared between the handles of a voice and the mixer.
+struct Control {
+    sound: Arc<SoundWithAttributes>,
+    state: AtomicU8,
+    /// The frame a seek jumps to, as the bits of an `f64`.
+    seek: AtomicU64,
+    /// The frame the voice was at after the last mixed block, as the bits of an `f64`.
+    position: AtomicU64,
+    /// The callbacks, until the voice ended. The lock makes sure a callback added while the voice ends still runs.
+    finish: Mutex<Result<Vec<FinishCallback>, PlaybackEnd>>,
+}
+
+/// A sound that was started on the mixer, see [`AudioEngine::play_sound`](crate::audio::AudioEngine::play_sound).
+#[derive(Clone)]
+pub struct PlaybackHandle {
+    control: Arc<Control>,
+}
+
+impl PlaybackHandle {
+    pub(crate) fn new(sound: Arc<SoundWithAttributes>) -> Self {
+        Self {
+            control: Arc::new(Control {
+                sound,
+                state: AtomicU8::new(PLAYING),
+                seek: AtomicU64::new(NO_SEEK),
+                position: AtomicU64::new(0.0f64.to_bits()),
+                finish: Mutex::new(Ok(Vec::new())),
+            }),
+        }
+    }
+
+    /// The playing sound. Its attributes like the volume can still be changed, but they also apply to other voices
+    /// that play the same [`SoundWithAttributes`].
+    pub fn sound(&self) -> &Arc<SoundWithAttributes> {
+        &self.control.sound
+    }
+
+    /// Stops the sound with the next block the mixer mixes.
+    pub fn stop(&self) {
+        self.control.state.store(STOPPING, Ordering::Release);
+    }
+
+    /// Keeps the voice but plays silence until it is resumed.
+    pub fn pause(&self) {
+        let _ = self
+            .control
+            .state
+            .compare_exchange(PLAYING, PAUSED, Ordering::AcqRel, Ordering::Acquire);
+    }
+
+    pub fn resume(&self) {
+        let _ = self
+            .control
+            .state
+            .compare_exchange(PAUSED, PLAYING, Ordering::AcqRel, Ordering::Acquire);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.control.state.load(Ordering::Acquire) == PAUSED
+    }
+
+    /// If the voice still plays or is paused.
+    pub fn is_playing(&self) -> bool {
+        self.end().is_none()
+    }
+
+    /// How the voice ended, or `None` if it still plays.
+    pub fn end(&self) -> Option<PlaybackEnd> {
+        self.control.finish.lock().as_ref().err().copied()
+    }
+
+    /// Continues playing at `position` with the next block the mixer mixes.
+    pub fn seek(&self, position: Duration) {
+        let frame = position.as_secs_f64() * self.control.sound.sound().sample_rate() as f64;
+        self.control.seek.store(frame.to_bits(), Ordering::Release);
+    }
+
+    /// Where in the sound the voice was after the last mixed block, wrapped around the length when looping.
+    pub fn position(&self) -> Duration {
+        let frame = f64::from_bits(self.control.position.load(Ordering::Acquire));
+        Duration::from_secs_f64(frame.max(0.0) / self.control.sound.sound().sample_rate() as f64)
+    }
+
+    /// Calls `callback` once the voice ended, right away if it already did.
+    ///
+    /// Callbacks run on the audio thread after the mixer was unlocked, so they can start other sounds, but they
+    /// should be quick to not hold up the audio.
+    pub fn on_finish(&self, callback: impl FnOnce(PlaybackEnd) + Send + 'static) {
+        let mut finish = self.control.finish.lock();
+        match finish.as_mut() {
+            Ok(callbacks) => callbacks.push(Box::new(callback)),
+            Err(end) => {
+                let end = *end;
+                drop(finish);
+                callback(end);
+            }
+        }
+    }
+
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.control.state.load(Ordering::Acquire) == STOPPING
+    }
+
+    /// The pending seek, which is cleared by taking it.
+    pub(crate) fn take_seek(&self) -> Option<f64> {
+        match self.control.seek.swap(NO_SEEK, Ordering::AcqRel) {
+            NO_SEEK => None,
+            bits => Some(f64::from_bits(bits)),
+        }
+    }
+
+    pub(crate) fn set_position(&self, frame: f64) {
+        let frames = self.control.sound.sound().effective_samples() as f64;
+        let frame = if self.control.sound.is_looping() && frames > 0.0 {
+            frame.rem_euclid(frames)
+        } else {
+            frame.min(frames)
+        };
+        self.control.position.store(frame.to_bits(), Ordering::Release);
+    }
+
+    /// Marks the voice as ended and runs its callbacks. Only the first end counts.
+    pub(crate) fn finish(&self, end: PlaybackEnd) {
+        let callbacks = {
+            let mut finish = self.control.finish.lock();
+            match std::mem::replace(&mut *finish, Err(end)) {
+                Ok(callbacks) => callbacks,
+                Err(first) => {
+                    *finish = Err(first);
+                    return;
+                }
+            }
+        };
+        for callback in callbacks {
+            callback(end);
+        }
+    }
+}
+
+/// Voices that ended in the mixer, whose callbacks still have to run. The mixer is locked while it mixes, so the
+/// callbacks run after it was unlocked, see [`AudioMixer::take_finished`](crate::audio::mixer::AudioMixer::take_finished).
+#[derive(Default)]
+pub struct FinishedVoices(pub(crate) Vec<(PlaybackHandle, PlaybackEnd)>);
+
+impl FinishedVoices {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Marks the voices as ended and runs their callbacks.
+    pub fn notify(self) {
+        for (handle, end) in self.0 {
+            handle.finish(end);
+        }
+    }
+}
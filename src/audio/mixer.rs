@@ -1,39 +1,323 @@
+use crate::audio::dj::DJ;
+use crate::audio::effect::AudioEffect;
+use crate::audio::effect::dynamics::Limiter;
+use crate::audio::handle::{FinishedVoices, PlaybackEnd, PlaybackHandle};
+use crate::audio::resample::Playhead;
 use crate::audio::source::SoundWithAttributes;
+use crate::audio::spatial::{AudioListener, SoundEmitter};
+use crate::audio::stream::StreamingSound;
 use std::sync::Arc;
 
+/// The group a sound plays in. Every bus has its own volume and effects, e.g. to let players turn down the music or
+/// to muffle all sound effects while the game is paused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Bus {
+    Music,
+    Sfx,
+    Voice,
+    Ui,
+}
+
+impl Bus {
+    pub const ALL: [Bus; 4] = [Bus::Music, Bus::Sfx, Bus::Voice, Bus::Ui];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// What happens when a sound is played while all voices are taken.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VoiceSteal {
+    /// The new sound is not played.
+    None,
+    /// The oldest of the voices with the lowest priority is stopped for the new sound, unless their priority is
+    /// higher than the new one.
+    Oldest,
+}
+
+/// Caps how many sounds play at once, so e.g. a hundred explosions at the same time don't overload the mixer.
+/// Only sounds count, streams and emitters are not limited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VoiceLimit {
+    pub max_voices: usize,
+    pub steal: VoiceSteal,
+}
+
+impl Default for VoiceLimit {
+    fn default() -> Self {
+        Self {
+            max_voices: 64,
+            steal: VoiceSteal::Oldest,
+        }
+    }
+}
+
+/// A sound playing on the mixer.
+struct Voice {
+    handle: PlaybackHandle,
+    playhead: Playhead,
+    bus: Bus,
+    priority: i32,
+}
+
+pub struct MixerBus {
+    volume: f32,
+    effects: Vec<Box<dyn AudioEffect>>,
+    /// The mix of the current block, kept to avoid allocating while playing.
+    buffer: Vec<(f32, f32)>,
+}
+
+impl MixerBus {
+    fn new() -> Self {
+        Self {
+            volume: 1.0,
+            effects: Vec::new(),
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume;
+    }
+
+    /// Appends an effect to the chain, effects process the bus in the order they were added.
+    pub fn add_effect(&mut self, effect: impl AudioEffect + 'static) -> &mut Self {
+        self.effects.push(Box::new(effect));
+        self
+    }
+
+    pub fn effects_mut(&mut self) -> &mut Vec<Box<dyn AudioEffect>> {
+        &mut self.effects
+    }
+
+    pub fn clear_effects(&mut self) {
+        self.effects.clear();
+    }
+}
+
 pub struct AudioMixer {
     //gt reference
-    playing: Vec<(Arc<SoundWithAttributes>, usize)>,
-    last_idx: usize,
+    /// Oldest first.
+    playing: Vec<Voice>,
+    voice_limit: VoiceLimit,
+    finished: Vec<(PlaybackHandle, PlaybackEnd)>,
+    streams: Vec<(Arc<StreamingSound>, Bus)>,
+    emitters: Vec<(Arc<SoundEmitter>, Bus)>,
+    djs: Vec<(Arc<DJ>, Bus)>,
+    listener: Arc<AudioListener>,
+    buses: [MixerBus; 4],
+    master_volume: f32,
+    limiter: Limiter,
 }
 
 impl AudioMixer {
     pub fn new() -> Self {
         Self {
             playing: vec![],
-            last_idx: 0,
+            voice_limit: VoiceLimit::default(),
+            finished: vec![],
+            streams: vec![],
+            emitters: vec![],
+            djs: vec![],
+            listener: AudioListener::new(),
+            buses: [MixerBus::new(), MixerBus::new(), MixerBus::new(), MixerBus::new()],
+            master_volume: 1.0,
+            limiter: Limiter::new(0.98),
+        }
+    }
+
+    /// Plays the sound on [`Bus::Sfx`].
+    pub fn play(&mut self, sound: Arc<SoundWithAttributes>) -> PlaybackHandle {
+        self.play_on(Bus::Sfx, sound)
+    }
+
+    pub fn play_on(&mut self, bus: Bus, sound: Arc<SoundWithAttributes>) -> PlaybackHandle {
+        self.play_with_priority(bus, sound, 0)
+    }
+
+    /// Plays the sound, if all voices are taken it can only take the voice of a sound with a lower or equal
+    /// `priority`. Otherwise the returned handle already ended with [`PlaybackEnd::Rejected`].
+    pub fn play_with_priority(&mut self, bus: Bus, sound: Arc<SoundWithAttributes>, priority: i32) -> PlaybackHandle {
+        let handle = PlaybackHandle::new(sound);
+        if self.playing.len() >= self.voice_limit.max_voices {
+            let victim = match self.voice_limit.steal {
+                VoiceSteal::None => None,
+                // min_by_key returns the first of equal voices, which is the oldest
+                VoiceSteal::Oldest => self
+                    .playing
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, voice)| voice.priority <= priority)
+                    .min_by_key(|(_, voice)| voice.priority)
+                    .map(|(i, _)| i),
+            };
+            match victim {
+                Some(i) => {
+                    let voice = self.playing.remove(i);
+                    self.finished.push((voice.handle, PlaybackEnd::Stolen));
+                }
+                None => {
+                    handle.finish(PlaybackEnd::Rejected);
+                    return handle;
+                }
+            }
         }
+        self.playing.push(Voice {
+            handle: handle.clone(),
+            playhead: Playhead::default(),
+            bus,
+            priority,
+        });
+        handle
+    }
+
+    /// The number of sounds that are playing or paused.
+    pub fn voices(&self) -> usize {
+        self.playing.len()
+    }
+
+    pub fn voice_limit(&self) -> VoiceLimit {
+        self.voice_limit
+    }
+
+    /// Lowering the limit below the playing voices only applies to new sounds.
+    pub fn set_voice_limit(&mut self, limit: VoiceLimit) {
+        self.voice_limit = limit;
+    }
+
+    /// The voices that ended since the last call, call [`FinishedVoices::notify`] on them once the mixer is unlocked.
+    pub fn take_finished(&mut self) -> FinishedVoices {
+        FinishedVoices(std::mem::take(&mut self.finished))
+    }
+
+    /// Plays the stream on [`Bus::Music`].
+    pub fn play_stream(&mut self, stream: Arc<StreamingSound>) {
+        self.play_stream_on(Bus::Music, stream);
+    }
+
+    pub fn play_stream_on(&mut self, bus: Bus, stream: Arc<StreamingSound>) {
+        self.streams.push((stream, bus));
+    }
+
+    /// Plays the emitter on [`Bus::Sfx`].
+    pub fn play_emitter(&mut self, emitter: Arc<SoundEmitter>) {
+        self.play_emitter_on(Bus::Sfx, emitter);
+    }
+
+    pub fn play_emitter_on(&mut self, bus: Bus, emitter: Arc<SoundEmitter>) {
+        self.emitters.push((emitter, bus));
+    }
+
+    /// Plays the DJ on [`Bus::Music`]. It stays on the mixer until it is removed, and is silent while it has nothing
+    /// to play.
+    pub fn play_dj(&mut self, dj: Arc<DJ>) {
+        self.play_dj_on(Bus::Music, dj);
+    }
+
+    pub fn play_dj_on(&mut self, bus: Bus, dj: Arc<DJ>) {
+        self.djs.push((dj, bus));
     }
 
-    pub fn play(&mut self, sound: Arc<SoundWithAttributes>) {
-        self.playing.push((sound, self.last_idx));
+    pub fn remove_dj(&mut self, dj: &Arc<DJ>) {
+        self.djs.retain(|(playing, _)| !Arc::ptr_eq(playing, dj));
     }
 
-    pub(crate) fn get_current_sample(&mut self, idx: usize, sample_rate: u32) -> (f32, f32) {
-        self.last_idx = idx;
-        let mut mixed = (0.0, 0.0);
-        for (sound, started) in self.playing.iter() {
-            let s = sound.get_sample_mapped(idx - started, sample_rate);
-            mixed.0 += s.0;
-            mixed.1 += s.1;
+    /// The listener all emitters are heard by.
+    pub fn listener(&self) -> Arc<AudioListener> {
+        self.listener.clone()
+    }
+
+    pub fn set_listener(&mut self, listener: Arc<AudioListener>) {
+        self.listener = listener;
+    }
+
+    pub fn bus(&self, bus: Bus) -> &MixerBus {
+        &self.buses[bus.index()]
+    }
+
+    pub fn bus_mut(&mut self, bus: Bus) -> &mut MixerBus {
+        &mut self.buses[bus.index()]
+    }
+
+    pub fn master_volume(&self) -> f32 {
+        self.master_volume
+    }
+
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.master_volume = volume;
+    }
+
+    /// The limiter at the end of the chain that keeps the summed buses from clipping.
+    pub fn limiter_mut(&mut self) -> &mut Limiter {
+        &mut self.limiter
+    }
+
+    /// Mixes the next `out.len()` frames at `sample_rate` into `out`.
+    pub fn mix(&mut self, out: &mut [(f32, f32)], sample_rate: u32) {
+        for bus in &mut self.buses {
+            bus.buffer.clear();
+            bus.buffer.resize(out.len(), (0.0, 0.0));
+        }
+
+        for voice in self.playing.iter_mut() {
+            if let Some(frame) = voice.handle.take_seek() {
+                voice.playhead.seek(frame);
+            }
+            if !voice.handle.is_paused() && !voice.handle.is_stopping() {
+                let sound = voice.handle.sound();
+                for frame in self.buses[voice.bus.index()].buffer.iter_mut() {
+                    let s = sound.next_frame(&mut voice.playhead, sample_rate);
+                    frame.0 += s.0;
+                    frame.1 += s.1;
+                }
+            }
+            voice.handle.set_position(voice.playhead.position);
+        }
+        for (stream, bus) in self.streams.iter() {
+            for frame in self.buses[bus.index()].buffer.iter_mut() {
+                let s = stream.next_sample(sample_rate);
+                frame.0 += s.0;
+                frame.1 += s.1;
+            }
+        }
+        for (emitter, bus) in self.emitters.iter() {
+            emitter.mix(&self.listener, &mut self.buses[bus.index()].buffer, sample_rate);
+        }
+        for (dj, bus) in self.djs.iter() {
+            dj.mix(&mut self.buses[bus.index()].buffer, sample_rate);
         }
 
-        self.playing.retain(|(sound, started)| {
-            sound.is_looping() || idx - started < sound.sound().total_samples()
+        let finished = &mut self.finished;
+        self.playing.retain(|voice| {
+            let end = if voice.handle.is_stopping() {
+                PlaybackEnd::Stopped
+            } else if voice.handle.sound().is_finished(&voice.playhead) {
+                PlaybackEnd::Finished
+            } else {
+                return true;
+            };
+            finished.push((voice.handle.clone(), end));
+            false
         });
+        self.streams.retain(|(stream, _)| !stream.is_finished());
+        self.emitters.retain(|(emitter, _)| !emitter.is_finished());
 
-        // This feels like adding empty sound would just make shit quieter randomly, perhaps just use clamp and sum instead?
-        // (clamp(mixed.0, -1.0, 1.0), clamp(mixed.1, -1.0, 1.0))
-        mixed
+        out.fill((0.0, 0.0));
+        for bus in &mut self.buses {
+            for effect in &mut bus.effects {
+                effect.process(&mut bus.buffer, sample_rate);
+            }
+            let volume = bus.volume * self.master_volume;
+            for (frame, s) in out.iter_mut().zip(&bus.buffer) {
+                frame.0 += s.0 * volume;
+                frame.1 += s.1 * volume;
+            }
+        }
+        self.limiter.process(out, sample_rate);
     }
 }
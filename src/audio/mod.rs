@@ -1,20 +1,33 @@
 pub mod decode;
 pub mod dj;
+pub mod effect;
+pub mod handle;
 pub mod mixer;
+pub mod output;
+pub mod resample;
 pub mod source;
-
-use crate::audio::mixer::AudioMixer;
+pub mod spatial;
+pub mod stream;
+
+use crate::audio::decode::wav::WavData;
+use crate::audio::dj::DJ;
+use crate::audio::handle::PlaybackHandle;
+use crate::audio::mixer::{AudioMixer, Bus};
+use crate::audio::output::{AudioBackend, OfflineBuffer, Output};
 use crate::audio::source::{Sound, SoundWithAttributes};
-use cpal::Stream;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use log::{error, info};
-use parking_lot::Mutex;
+use crate::audio::spatial::{AudioListener, SoundEmitter};
+use crate::audio::stream::StreamingSound;
+use log::error;
+use parking_lot::{Mutex, MutexGuard};
 use std::f32::consts::PI;
+use std::fs;
+use std::io;
+use std::path::Path;
 use std::sync::Arc;
 
 pub struct AudioEngine {
     mixer: Arc<Mutex<AudioMixer>>,
-    _stream: Stream,
+    output: Output,
     sample_rate: u32,
 }
 
@@ -25,58 +38,101 @@ impl AudioEngine {
 }
 
 impl AudioEngine {
+    /// Plays on the default output device, `None` if there is none.
     pub fn setup() -> Option<Self> {
-        let host = cpal::default_host();
-        if let Some(device) = host.default_output_device() {
-            info!("Selected audio device: {:?}", device.name());
-
-            let mut index = 0;
-            let config = device.default_output_config();
-            if let Ok(config) = config {
-                let mixer = Arc::new(Mutex::new(AudioMixer::new()));
-                let cloned_mixer = mixer.clone();
-
-                let sample_rate = config.sample_rate().0;
-
-                let stream = device.build_output_stream(
-                    &config.config(),
-                    move |data: &mut [f32], _| {
-                        for sample in data.chunks_mut(config.channels() as usize) {
-                            let tone = mixer.lock().get_current_sample(index, sample_rate);
-
-                            sample[0] = tone.0;
-                            sample[1] = tone.1;
-
-                            index = index.wrapping_add(1);
-                        }
-                    },
-                    |e| {
-                        error!("Error during audio playback: {e}");
-                    },
-                    None,
-                );
-
-                if let Ok(stream) = stream {
-                    stream.play().ok()?;
-                    return Some(Self {
-                        _stream: stream,
-                        mixer: cloned_mixer,
-                        sample_rate,
-                    });
-                } else {
-                    error!("Error playing stream")
-                }
-            } else {
-                error!("Invalid audio config");
-            }
+        Self::with_backend(AudioBackend::Device)
+    }
+
+    /// `None` if the device could not be opened, or if a sample rate of zero was given.
+    pub fn with_backend(backend: AudioBackend) -> Option<Self> {
+        if let AudioBackend::Null { sample_rate: 0 } | AudioBackend::Offline { sample_rate: 0 } = backend {
+            error!("Audio sample rate must not be zero");
+            return None;
         }
+        let mixer = Arc::new(Mutex::new(AudioMixer::new()));
+        let (output, sample_rate) = match backend {
+            AudioBackend::Device => output::open_device(mixer.clone())?,
+            AudioBackend::Null { sample_rate } => (output::start_null(Arc::downgrade(&mixer), sample_rate), sample_rate),
+            AudioBackend::Offline { sample_rate } => (Output::Offline(Mutex::new(OfflineBuffer::new())), sample_rate),
+        };
+        Some(Self {
+            mixer,
+            output,
+            sample_rate,
+        })
+    }
+
+    /// Mixes the next `out.len()` frames into `out`. Only for [`AudioBackend::Offline`], the other backends pull
+    /// frames themselves.
+    ///
+    /// The mixer works in blocks of 512 frames, sounds played between renders start with the next block.
+    pub fn render_into(&self, out: &mut [(f32, f32)]) {
+        let Output::Offline(buffer) = &self.output else {
+            panic!("Only offline audio engines can be rendered");
+        };
+        buffer.lock().render(&self.mixer, out, self.sample_rate);
+    }
+
+    /// Mixes the next `frames` frames, see [`render_into`](Self::render_into).
+    pub fn render(&self, frames: usize) -> Vec<(f32, f32)> {
+        let mut out = vec![(0.0, 0.0); frames];
+        self.render_into(&mut out);
+        out
+    }
+
+    /// Mixes the next `frames` frames into a 16 bit stereo .wav file.
+    pub fn render_to_wav(&self, path: impl AsRef<Path>, frames: usize) -> io::Result<()> {
+        let samples: Vec<f32> = self.render(frames).into_iter().flat_map(|(l, r)| [l, r]).collect();
+        fs::write(path, WavData::from_samples(2, self.sample_rate, &samples).to_bytes())
+    }
 
-        None
+    /// The mixer, to change the volume and effects of its buses.
+    pub fn mixer(&self) -> MutexGuard<'_, AudioMixer> {
+        self.mixer.lock()
     }
 
-    pub fn play_sound(&self, sound: Arc<SoundWithAttributes>) {
+    /// Plays the sound on [`Bus::Sfx`], the handle can stop, pause or seek it.
+    pub fn play_sound(&self, sound: Arc<SoundWithAttributes>) -> PlaybackHandle {
         self.mixer.lock().play(sound)
     }
+
+    pub fn play_sound_on(&self, bus: Bus, sound: Arc<SoundWithAttributes>) -> PlaybackHandle {
+        self.mixer.lock().play_on(bus, sound)
+    }
+
+    /// See [`AudioMixer::play_with_priority`].
+    pub fn play_sound_with_priority(&self, bus: Bus, sound: Arc<SoundWithAttributes>, priority: i32) -> PlaybackHandle {
+        self.mixer.lock().play_with_priority(bus, sound, priority)
+    }
+
+    pub fn play_dj(&self, dj: Arc<DJ>) {
+        self.mixer.lock().play_dj(dj)
+    }
+
+    pub fn play_dj_on(&self, bus: Bus, dj: Arc<DJ>) {
+        self.mixer.lock().play_dj_on(bus, dj)
+    }
+
+    pub fn play_emitter(&self, emitter: Arc<SoundEmitter>) {
+        self.mixer.lock().play_emitter(emitter)
+    }
+
+    pub fn play_emitter_on(&self, bus: Bus, emitter: Arc<SoundEmitter>) {
+        self.mixer.lock().play_emitter_on(bus, emitter)
+    }
+
+    /// The listener emitters are heard by, move it along with the camera or player.
+    pub fn listener(&self) -> Arc<AudioListener> {
+        self.mixer.lock().listener()
+    }
+
+    pub fn play_stream(&self, stream: Arc<StreamingSound>) {
+        self.mixer.lock().play_stream(stream)
+    }
+
+    pub fn play_stream_on(&self, bus: Bus, stream: Arc<StreamingSound>) {
+        self.mixer.lock().play_stream_on(bus, stream)
+    }
 }
 
 pub fn gen_tone(freq: u32, sample_idx: u32, sample_rate: u32) -> f32 {
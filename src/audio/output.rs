@@ -0,0 +1,141 @@
+//! Where the [`AudioEngine`](crate::audio::AudioEngine) sends the mixed audio.
+
+use crate::audio::mixer::AudioMixer;
+use cpal::Stream;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use log::{error, info};
+use parking_lot::Mutex;
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Frames the null sink mixes at once, 10 ms at 48 kHz.
+const NULL_BLOCK: usize = 480;
+/// Frames an offline engine mixes at once. Always mixing whole blocks makes renders independent of how many frames
+/// are asked for at once.
+const OFFLINE_BLOCK: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AudioBackend {
+    /// The default output device of the system.
+    Device,
+    /// Mixes in real time and throws the audio away, for machines without a sound card like CI runners. Sounds still
+    /// end and run their callbacks like on a device.
+    Null { sample_rate: u32 },
+    /// Only mixes when [`AudioEngine::render`](crate::audio::AudioEngine::render) is called, as fast as possible and
+    /// with the same result every time. For rendering to files and testing.
+    Offline { sample_rate: u32 },
+}
+
+pub(crate) enum Output {
+    Device(Stream),
+    Null,
+    Offline(Mutex<OfflineBuffer>),
+}
+
+/// The last block an offline engine mixed and how much of it was rendered.
+pub(crate) struct OfflineBuffer {
+    block: Vec<(f32, f32)>,
+    read: usize,
+}
+
+impl OfflineBuffer {
+    pub(crate) fn new() -> Self {
+        Self {
+            block: vec![(0.0, 0.0); OFFLINE_BLOCK],
+            read: OFFLINE_BLOCK,
+        }
+    }
+
+    pub(crate) fn render(&mut self, mixer: &Mutex<AudioMixer>, out: &mut [(f32, f32)], sample_rate: u32) {
+        for frame in out {
+            if self.read == self.block.len() {
+                pull(mixer, &mut self.block, sample_rate);
+                self.read = 0;
+            }
+            *frame = self.block[self.read];
+            self.read += 1;
+        }
+    }
+}
+
+/// Mixes the next block, the callbacks of finished voices run after the mixer was unlocked.
+pub(crate) fn pull(mixer: &Mutex<AudioMixer>, block: &mut [(f32, f32)], sample_rate: u32) {
+    let finished = {
+        let mut mixer = mixer.lock();
+        mixer.mix(block, sample_rate);
+        mixer.take_finished()
+    };
+    finished.notify();
+}
+
+/// Opens the default output device, returns the stream and its sample rate.
+pub(crate) fn open_device(mixer: Arc<Mutex<AudioMixer>>) -> Option<(Output, u32)> {
+    let host = cpal::default_host();
+    let Some(device) = host.default_output_device() else {
+        error!("No audio device");
+        return None;
+    };
+    info!("Selected audio device: {:?}", device.name());
+
+    let Ok(config) = device.default_output_config() else {
+        error!("Invalid audio config");
+        return None;
+    };
+    let sample_rate = config.sample_rate().0;
+    let channels = config.channels() as usize;
+    let mut block = Vec::new();
+    let stream = device.build_output_stream(
+        &config.config(),
+        move |data: &mut [f32], _| {
+            block.resize(data.len() / channels, (0.0, 0.0));
+            pull(&mixer, &mut block, sample_rate);
+
+            for (sample, tone) in data.chunks_mut(channels).zip(&block) {
+                match sample {
+                    [mono] => *mono = (tone.0 + tone.1) * 0.5,
+                    [left, right, rest @ ..] => {
+                        *left = tone.0;
+                        *right = tone.1;
+                        rest.fill(0.0);
+                    }
+                    [] => {}
+                }
+            }
+        },
+        |e| {
+            error!("Error during audio playback: {e}");
+        },
+        None,
+    );
+
+    let Ok(stream) = stream else {
+        error!("Error playing stream");
+        return None;
+    };
+    stream.play().ok()?;
+    Some((Output::Device(stream), sample_rate))
+}
+
+/// Mixes on a thread at the pace of a device, until the engine and with it the mixer is dropped.
+pub(crate) fn start_null(mixer: Weak<Mutex<AudioMixer>>, sample_rate: u32) -> Output {
+    thread::Builder::new()
+        .name("null-audio".to_string())
+        .spawn(move || {
+            let mut block = vec![(0.0, 0.0); NULL_BLOCK];
+            let interval = Duration::from_secs_f64(NULL_BLOCK as f64 / sample_rate as f64);
+            let mut next = Instant::now();
+            loop {
+                let Some(mixer) = mixer.upgrade() else {
+                    break;
+                };
+                pull(&mixer, &mut block, sample_rate);
+                drop(mixer);
+
+                next += interval;
+                thread::sleep(next.saturating_duration_since(Instant::now()));
+            }
+        })
+        .expect("Could not start the null audio thread");
+    Output::Null
+}
@@ -0,0 +1,210 @@
+//! Reading sounds at fractional positions, for sample rate conversion, speed changes and time stretching.
+//!
+//! Voices keep a [`Playhead`] into their sound that advances by the ratio of the sound's rate to the device rate,
+//! times the speed. Between frames, the sound is reconstructed with an [`Interpolation`].
+
+use std::f64::consts::PI;
+use std::sync::OnceLock;
+
+/// How a sound is reconstructed between its frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Interpolation {
+    /// The closest frame. The cheapest, but it aliases and adds a lot of noise.
+    Nearest,
+    /// A straight line between the two closest frames. Cheap and fine for most sound effects.
+    #[default]
+    Linear,
+    /// A windowed sinc filter over 16 frames on each side, which also filters out frequencies the output rate cannot
+    /// hold when playing faster. The cleanest, for music and tonal sounds.
+    Sinc,
+}
+
+/// Zero crossings of the sinc on each side.
+const SINC_ZEROS: usize = 16;
+/// Table entries per zero crossing, the kernel is linearly interpolated between them.
+const SINC_RESOLUTION: usize = 256;
+/// The filter gets wider the more frames are skipped per output frame, this caps the cost at very high speeds.
+const MAX_SINC_STRETCH: f64 = 8.0;
+
+impl Interpolation {
+    /// Reads the frame at `position`, `step` is how many frames are advanced per output frame.
+    /// `frame` returns the frame at an index, which can be out of range.
+    pub fn interpolate(self, position: f64, step: f64, frame: impl Fn(i64) -> (f32, f32)) -> (f32, f32) {
+        match self {
+            Interpolation::Nearest => frame(position.round() as i64),
+            Interpolation::Linear => {
+                let index = position.floor();
+                let t = (position - index) as f32;
+                let (a, b) = (frame(index as i64), frame(index as i64 + 1));
+                (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+            }
+            Interpolation::Sinc => {
+                // lowering the cutoff below the source nyquist when skipping frames avoids aliasing
+                let stretch = step.abs().clamp(1.0, MAX_SINC_STRETCH);
+                let cutoff = 1.0 / stretch;
+                let reach = SINC_ZEROS as f64 * stretch;
+                let first = (position - reach).ceil() as i64;
+                let last = (position + reach).floor() as i64;
+                let (mut left, mut right, mut sum) = (0.0, 0.0, 0.0);
+                for index in first..=last {
+                    let weight = sinc_kernel((position - index as f64) * cutoff);
+                    if weight == 0.0 {
+                        continue;
+                    }
+                    let (l, r) = frame(index);
+                    left += l * weight;
+                    right += r * weight;
+                    sum += weight;
+                }
+                // the weights add up to about 1, normalizing removes the error of the table
+                if sum.abs() < f32::EPSILON {
+                    return (0.0, 0.0);
+                }
+                (left / sum, right / sum)
+            }
+        }
+    }
+}
+
+/// The Blackman windowed sinc at `x` zero crossings from the center.
+fn sinc_kernel(x: f64) -> f32 {
+    let table = SINC_TABLE.get_or_init(|| {
+        (0..=SINC_ZEROS * SINC_RESOLUTION + 1)
+            .map(|i| {
+                let x = i as f64 / SINC_RESOLUTION as f64;
+                if x == 0.0 {
+                    return 1.0;
+                }
+                if x >= SINC_ZEROS as f64 {
+                    return 0.0;
+                }
+                let sinc = (PI * x).sin() / (PI * x);
+                let phase = PI * (x / SINC_ZEROS as f64 + 1.0);
+                let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+                (sinc * window) as f32
+            })
+            .collect()
+    });
+    let x = x.abs() * SINC_RESOLUTION as f64;
+    let index = x as usize;
+    if index >= SINC_ZEROS * SINC_RESOLUTION {
+        return 0.0;
+    }
+    let t = (x - index as f64) as f32;
+    table[index] + (table[index + 1] - table[index]) * t
+}
+
+static SINC_TABLE: OnceLock<Vec<f32>> = OnceLock::new();
+
+/// Length of the grains time stretching cuts the sound into, in seconds.
+const GRAIN_SECONDS: f64 = 0.04;
+/// How far a grain can start from where it should, in seconds, to line up with the grain it fades in over.
+const ALIGN_SECONDS: f64 = 0.01;
+/// Frames compared when lining up grains, and the distance between the compared frames.
+const ALIGN_WINDOW: usize = 128;
+const ALIGN_STRIDE: i64 = 4;
+
+/// The position of a voice in its sound.
+///
+/// When time stretching, the sound is played as overlapping grains at the original pitch, and the start of every new
+/// grain jumps ahead or back to where the sound would be at the current speed. The start is moved a little to where
+/// the sound looks most like the continuation of the previous grain, otherwise the grains would cancel each other out
+/// and smear the pitch (WSOLA).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Playhead {
+    /// The frame of the sound that plays next, fractional between frames.
+    pub position: f64,
+    /// Frames played of the newest grain.
+    grain_time: f64,
+    /// The frames the older and the newer grain started at.
+    grains: Option<(f64, f64)>,
+}
+
+impl Playhead {
+    pub fn new(position: f64) -> Self {
+        Self {
+            position,
+            grain_time: 0.0,
+            grains: None,
+        }
+    }
+
+    /// Jumps to `position`, any grains of a time stretch start over.
+    pub fn seek(&mut self, position: f64) {
+        *self = Self::new(position);
+    }
+
+    /// Reads the next output frame and advances by `rate`, the sound frames per output frame, times `speed`.
+    /// `sound_rate` is the sample rate of the sound, the grains of a time stretch are measured in it.
+    ///
+    /// Without `time_stretch`, the speed also changes the pitch like a tape. With it, the sound is read at `rate`
+    /// and only the position moves at the speed.
+    pub fn advance(
+        &mut self,
+        interpolation: Interpolation,
+        sound_rate: u32,
+        rate: f64,
+        speed: f64,
+        time_stretch: bool,
+        frame: impl Fn(i64) -> (f32, f32),
+    ) -> (f32, f32) {
+        if !time_stretch || speed == 1.0 {
+            self.grains = None;
+            self.grain_time = 0.0;
+            let step = rate * speed;
+            let sample = interpolation.interpolate(self.position, step, frame);
+            self.position += step;
+            return sample;
+        }
+
+        // two grains overlap by half, the squared sine windows of both add up to one
+        let grain = (GRAIN_SECONDS * sound_rate as f64).max(4.0);
+        let half = grain * 0.5;
+        let (older, newer) = *self.grains.get_or_insert((self.position - half, self.position));
+        let weight = |t: f64| (PI * t / grain).sin().powi(2) as f32;
+        let a = interpolation.interpolate(older + half + self.grain_time, rate, &frame);
+        let b = interpolation.interpolate(newer + self.grain_time, rate, &frame);
+        let (wa, wb) = (weight(half + self.grain_time), weight(self.grain_time));
+
+        self.position += rate * speed;
+        self.grain_time += rate;
+        if self.grain_time >= half {
+            self.grain_time -= half;
+            let tolerance = (ALIGN_SECONDS * sound_rate as f64) as i64;
+            let start = align_grain(self.position - self.grain_time, newer + half, tolerance, &frame);
+            self.grains = Some((newer, start));
+        }
+        (a.0 * wa + b.0 * wb, a.1 * wa + b.1 * wb)
+    }
+}
+
+/// Finds the start within `tolerance` frames of `ideal`, where the sound matches the one from `natural` on best.
+fn align_grain(ideal: f64, natural: f64, tolerance: i64, frame: &impl Fn(i64) -> (f32, f32)) -> f64 {
+    let mono = |index: i64| {
+        let (left, right) = frame(index);
+        left + right
+    };
+    let natural = natural.round() as i64;
+    let mut reference = [0.0; ALIGN_WINDOW];
+    for (i, sample) in reference.iter_mut().enumerate() {
+        *sample = mono(natural + i as i64 * ALIGN_STRIDE);
+    }
+
+    let center = ideal.round() as i64;
+    let (mut best, mut best_offset) = (f32::MIN, 0i64);
+    for offset in -tolerance..=tolerance {
+        let (mut correlation, mut energy) = (0.0, 0.0);
+        for (i, sample) in reference.iter().enumerate() {
+            let candidate = mono(center + offset + i as i64 * ALIGN_STRIDE);
+            correlation += sample * candidate;
+            energy += candidate * candidate;
+        }
+        let score = correlation / energy.sqrt().max(1e-6);
+        // on ties, e.g. in silence, stay closest to where the grain should start
+        if score > best || (score == best && offset.abs() < best_offset.abs()) {
+            best = score;
+            best_offset = offset;
+        }
+    }
+    ideal + best_offset as f64
+}
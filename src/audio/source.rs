@@ -1,4 +1,5 @@
 use crate::audio::decode::wav::ActuallyUsefulWavData;
+use crate::audio::resample::{Interpolation, Playhead};
 use crate::ui::ease::{Easing, EasingGen, EasingMode};
 use mvutils::unsafe_utils::DangerousCell;
 use std::sync::Arc;
@@ -25,8 +26,85 @@ impl SoundWithAttributes {
             .round() as usize
     }
 
+    /// The frame at `index` frames of the device rate into playback, at a constant speed.
     pub fn get_sample_mapped(&self, index: usize, sample_rate: u32) -> (f32, f32) {
-        self.get_sample(self.map_index(index, sample_rate))
+        let step = self.step(sample_rate) * self.with_attributes.speed.get_val() as f64;
+        self.get_sample_at(index as f64 * step, step)
+    }
+
+    /// The frame at a fractional `position`, reconstructed with the interpolation of the sound. `step` is the
+    /// number of frames advanced per output frame, the sinc interpolation filters out what cannot be played at it.
+    pub fn get_sample_at(&self, position: f64, step: f64) -> (f32, f32) {
+        let interpolation = self.with_attributes.interpolation.get_val();
+        let frame = interpolation.interpolate(position, step, |index| self.raw_frame(index));
+        self.apply_attributes(frame, position)
+    }
+
+    /// The next frame at `sample_rate` from `playhead`, which is advanced by the speed.
+    pub fn next_frame(&self, playhead: &mut Playhead, sample_rate: u32) -> (f32, f32) {
+        self.next_frame_pitched(playhead, sample_rate, 1.0)
+    }
+
+    /// Like [`next_frame`](Self::next_frame), but the pitch and speed are scaled by `pitch`, e.g. for doppler.
+    pub(crate) fn next_frame_pitched(&self, playhead: &mut Playhead, sample_rate: u32, pitch: f64) -> (f32, f32) {
+        if self.sound.samples.is_empty() {
+            return (0.0, 0.0);
+        }
+        let position = playhead.position;
+        let frame = playhead.advance(
+            self.with_attributes.interpolation.get_val(),
+            self.sound.sample_rate,
+            self.step(sample_rate) * pitch,
+            self.with_attributes.speed.get_val() as f64,
+            self.with_attributes.time_stretch.get_val(),
+            |index| self.raw_frame(index),
+        );
+        self.apply_attributes(frame, position)
+    }
+
+    /// If `playhead` is past the end. Looping sounds never end.
+    pub fn is_finished(&self, playhead: &Playhead) -> bool {
+        !self.is_looping() && playhead.position >= self.sound.effective_samples() as f64
+    }
+
+    /// Frames of the sound per frame at `sample_rate`.
+    fn step(&self, sample_rate: u32) -> f64 {
+        self.sound.sample_rate as f64 / sample_rate as f64
+    }
+
+    /// The frame at `index` without any attributes, wrapped around if looping. Mono sounds are in both channels.
+    fn raw_frame(&self, index: i64) -> (f32, f32) {
+        let frames = self.sound.effective_samples() as i64;
+        if frames == 0 {
+            return (0.0, 0.0);
+        }
+        let index = if self.with_attributes.looping.get_val() {
+            index.rem_euclid(frames)
+        } else if index < 0 || index >= frames {
+            return (0.0, 0.0);
+        } else {
+            index
+        };
+        let index = index as usize;
+        match self.sound.channels {
+            2 => (self.sound.samples[index * 2], self.sound.samples[index * 2 + 1]),
+            1 => (self.sound.samples[index], self.sound.samples[index]),
+            _ => {
+                panic!("We do not support futuristic 3+ ear headphones");
+            }
+        }
+    }
+
+    fn apply_attributes(&self, (left, right): (f32, f32), position: f64) -> (f32, f32) {
+        let frames = self.sound.effective_samples();
+        if frames == 0 {
+            return (0.0, 0.0);
+        }
+        let mut index = position.max(0.0) as usize;
+        if self.with_attributes.looping.get_val() {
+            index %= frames;
+        }
+        self.with_attributes.apply((left, right), self.sound.channels, index)
     }
 
     pub fn get_sample(&self, index: usize) -> (f32, f32) {
@@ -107,25 +185,49 @@ impl SoundWithAttributes {
         self.with_attributes.speed.replace(speed);
     }
 
+    pub fn interpolation(&self) -> Interpolation {
+        self.with_attributes.interpolation.get_val()
+    }
+
+    pub fn set_interpolation(&self, interpolation: Interpolation) {
+        self.with_attributes.interpolation.replace(interpolation);
+    }
+
+    pub fn is_time_stretch(&self) -> bool {
+        self.with_attributes.time_stretch.get_val()
+    }
+
+    /// Makes the speed only change the tempo and keep the pitch, instead of changing both like a tape.
+    pub fn set_time_stretch(&self, time_stretch: bool) {
+        self.with_attributes.time_stretch.replace(time_stretch);
+    }
+
     pub fn set_fade_in(&self, ease_gen: EasingGen, duration_ms: u32) {
-        let duration_samples = (duration_ms * self.sound.sample_rate) / 1000;
-        self.with_attributes.fade_in.replace(Some(Easing::new(
-            ease_gen,
-            EasingMode::In,
-            0.0..duration_samples as f32,
-            0.0..1.0,
-        )));
+        self.set_fade_in_at(ease_gen, 0, duration_ms);
     }
 
+    /// Fades out to silence over the last `duration_ms`. Fades longer than the sound start right away.
     pub fn set_fade_out(&self, ease_gen: EasingGen, duration_ms: u32) {
-        let duration_samples = (duration_ms * self.sound.sample_rate) / 1000;
-        let start_sample = self.sound.effective_samples() as u32 - duration_samples;
-        self.with_attributes.fade_out.replace(Some(Easing::new(
-            ease_gen,
-            EasingMode::Out,
-            start_sample as f32..(start_sample + duration_samples) as f32,
-            1.0..0.0,
-        )));
+        let duration_samples = ms_to_frames(duration_ms, self.sound.sample_rate);
+        let start_sample = (self.sound.effective_samples() as u64).saturating_sub(duration_samples);
+        self.with_attributes.fade_out_at(ease_gen, start_sample, duration_samples);
+    }
+
+    /// Fades in from silence, starting at frame `start_sample` of the sound. It is silent before.
+    pub fn set_fade_in_at(&self, ease_gen: EasingGen, start_sample: usize, duration_ms: u32) {
+        let duration_samples = ms_to_frames(duration_ms, self.sound.sample_rate);
+        self.with_attributes.fade_in_at(ease_gen, start_sample as u64, duration_samples);
+    }
+
+    /// Fades out to silence, starting at frame `start_sample` of the sound. It stays silent after.
+    pub fn set_fade_out_at(&self, ease_gen: EasingGen, start_sample: usize, duration_ms: u32) {
+        let duration_samples = ms_to_frames(duration_ms, self.sound.sample_rate);
+        self.with_attributes.fade_out_at(ease_gen, start_sample as u64, duration_samples);
+    }
+
+    pub fn clear_fades(&self) {
+        self.with_attributes.fade_in.replace(None);
+        self.with_attributes.fade_out.replace(None);
     }
 
     pub fn sound(&self) -> Arc<Sound> {
@@ -145,6 +247,11 @@ impl SoundWithAttributes {
     }
 }
 
+/// The number of frames `duration_ms` lasts at `sample_rate`, without overflowing for long fades.
+pub(crate) fn ms_to_frames(duration_ms: u32, sample_rate: u32) -> u64 {
+    duration_ms as u64 * sample_rate as u64 / 1000
+}
+
 unsafe impl Send for SoundWithAttributes {}
 unsafe impl Sync for SoundWithAttributes {}
 
@@ -154,6 +261,8 @@ pub struct WithAttributes {
     volume: DangerousCell<f32>,
     speed: DangerousCell<f32>,
     looping: DangerousCell<bool>,
+    interpolation: DangerousCell<Interpolation>,
+    time_stretch: DangerousCell<bool>,
 
     fade_in: DangerousCell<Option<Easing>>,
     fade_out: DangerousCell<Option<Easing>>,
@@ -166,6 +275,8 @@ impl WithAttributes {
             volume: 1.0.into(),
             speed: 1.0.into(),
             looping: false.into(),
+            interpolation: Interpolation::default().into(),
+            time_stretch: false.into(),
 
             fade_in: None.into(),
             fade_out: None.into(),
@@ -186,6 +297,36 @@ impl WithAttributes {
         fade_in * fade_out
     }
 
+    /// Applies the volume, the fades at frame `index` and, for mono sounds, the balance to a frame.
+    pub(crate) fn apply(&self, (left, right): (f32, f32), channels: u8, index: usize) -> (f32, f32) {
+        let volume = self.volume.get_val() * self.get_easing_multiplier(index);
+        match channels {
+            1 => {
+                let balance = self.mono_balance.get_val();
+                (left * (1.0 - balance) * volume, left * balance * volume)
+            }
+            _ => (left * volume, right * volume),
+        }
+    }
+
+    pub(crate) fn fade_in_at(&self, ease_gen: EasingGen, start_sample: u64, duration_samples: u64) {
+        self.fade_in.replace(Some(Easing::new(
+            ease_gen,
+            EasingMode::In,
+            start_sample as f32..(start_sample + duration_samples) as f32,
+            0.0..1.0,
+        )));
+    }
+
+    pub(crate) fn fade_out_at(&self, ease_gen: EasingGen, start_sample: u64, duration_samples: u64) {
+        self.fade_out.replace(Some(Easing::new(
+            ease_gen,
+            EasingMode::Out,
+            start_sample as f32..(start_sample + duration_samples) as f32,
+            1.0..0.0,
+        )));
+    }
+
     pub fn is_looping(&self) -> bool {
         self.looping.get_val()
     }
@@ -217,6 +358,22 @@ impl WithAttributes {
     pub fn set_speed(&self, speed: f32) {
         self.speed.replace(speed);
     }
+
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation.get_val()
+    }
+
+    pub fn set_interpolation(&self, interpolation: Interpolation) {
+        self.interpolation.replace(interpolation);
+    }
+
+    pub fn is_time_stretch(&self) -> bool {
+        self.time_stretch.get_val()
+    }
+
+    pub fn set_time_stretch(&self, time_stretch: bool) {
+        self.time_stretch.replace(time_stretch);
+    }
 }
 
 impl Clone for WithAttributes {
@@ -226,6 +383,8 @@ impl Clone for WithAttributes {
             volume: self.volume.get_val().into(),
             speed: self.speed.get_val().into(),
             looping: self.looping.get_val().into(),
+            interpolation: self.interpolation.get_val().into(),
+            time_stretch: self.time_stretch.get_val().into(),
             fade_in: self.fade_in.get().clone().into(),
             fade_out: self.fade_out.get().clone().into(),
         }
@@ -0,0 +1,383 @@
+//! Sounds placed in the 2D world, heard by an [`AudioListener`].
+//!
+//! A [`SoundEmitter`] takes its volume from the distance to the listener, following its [`Attenuation`], and is
+//! panned by the direction to it relative to the rotation of the listener. If the emitter or listener move, the
+//! emitter can shift the pitch by the doppler effect. Emitters are positioned by hand, or by the
+//! [`SpatialAudioSystem`] from the transforms of ECS entities with an [`Emitter`] or [`Listener`].
+
+use crate::audio::resample::Playhead;
+use crate::audio::source::SoundWithAttributes;
+use crate::game::ecs::World;
+use crate::game::ecs::command::Commands;
+use crate::game::ecs::entity::EntityId;
+use crate::game::ecs::query::{Query, With};
+use crate::game::ecs::schedule::{Access, ScheduledSystem, SystemWorld};
+use crate::game::physics::components::{GlobalTransform, RigidDynamic, Transform};
+use crate::math::vec::Vec2;
+use crate::ui::geometry::geom;
+use hashbrown::HashMap;
+use mvutils::unsafe_utils::DangerousCell;
+use std::sync::Arc;
+
+/// How the volume falls off between `min_distance` and `max_distance`. Closer sounds play at full volume, sounds
+/// further away are not heard at all.
+#[derive(Clone)]
+pub enum Rolloff {
+    /// Full volume up to `max_distance`.
+    None,
+    /// Falls linearly to silence at `max_distance`.
+    Linear,
+    /// Halves at twice the `min_distance` if the factor is 1, like sound in the real world. Larger factors fall faster.
+    Inverse(f32),
+    /// Falls by the factor every time the distance doubles, e.g. 0.5 halves the volume.
+    Exponential(f32),
+    /// The gain at a distance, which is already clamped to the range.
+    Custom(Arc<dyn Fn(f32) -> f32 + Send + Sync>),
+}
+
+#[derive(Clone)]
+pub struct Attenuation {
+    pub rolloff: Rolloff,
+    pub min_distance: f32,
+    pub max_distance: f32,
+}
+
+impl Attenuation {
+    pub fn new(rolloff: Rolloff, min_distance: f32, max_distance: f32) -> Self {
+        Self {
+            rolloff,
+            min_distance,
+            max_distance,
+        }
+    }
+
+    pub fn gain(&self, distance: f32) -> f32 {
+        if distance > self.max_distance {
+            return 0.0;
+        }
+        let min = self.min_distance.max(f32::EPSILON);
+        let distance = distance.max(min);
+        match &self.rolloff {
+            Rolloff::None => 1.0,
+            Rolloff::Linear => {
+                let range = self.max_distance - min;
+                if range <= 0.0 {
+                    1.0
+                } else {
+                    1.0 - (distance - min) / range
+                }
+            }
+            Rolloff::Inverse(factor) => min / (min + factor * (distance - min)),
+            Rolloff::Exponential(factor) => factor.powf((distance / min).log2()),
+            Rolloff::Custom(curve) => curve(distance).clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl Default for Attenuation {
+    fn default() -> Self {
+        Self::new(Rolloff::Inverse(1.0), 1.0, f32::INFINITY)
+    }
+}
+
+/// Where sounds are heard from, usually the camera or the player.
+pub struct AudioListener {
+    position: DangerousCell<Vec2>,
+    /// The rotation in radians, at 0 the right ear points along the positive x-axis.
+    rotation: DangerousCell<f32>,
+    velocity: DangerousCell<Vec2>,
+    speed_of_sound: DangerousCell<f32>,
+}
+
+unsafe impl Send for AudioListener {}
+unsafe impl Sync for AudioListener {}
+
+impl AudioListener {
+    pub fn new() -> Arc<Self> {
+        let this = Self {
+            position: Vec2::default().into(),
+            rotation: 0.0.into(),
+            velocity: Vec2::default().into(),
+            speed_of_sound: 343.0.into(),
+        };
+        Arc::new(this)
+    }
+
+    pub fn position(&self) -> Vec2 {
+        self.position.get_val()
+    }
+
+    pub fn set_position(&self, position: Vec2) {
+        self.position.replace(position);
+    }
+
+    pub fn rotation(&self) -> f32 {
+        self.rotation.get_val()
+    }
+
+    pub fn set_rotation(&self, rotation: f32) {
+        self.rotation.replace(rotation);
+    }
+
+    pub fn velocity(&self) -> Vec2 {
+        self.velocity.get_val()
+    }
+
+    pub fn set_velocity(&self, velocity: Vec2) {
+        self.velocity.replace(velocity);
+    }
+
+    pub fn speed_of_sound(&self) -> f32 {
+        self.speed_of_sound.get_val()
+    }
+
+    /// In world units per second, used for the doppler effect. Defaults to 343, the speed in air in meters.
+    pub fn set_speed_of_sound(&self, speed: f32) {
+        self.speed_of_sound.replace(speed);
+    }
+}
+
+/// A sound played at a position in the world. Play it with
+/// [`AudioEngine::play_emitter`](crate::audio::AudioEngine::play_emitter).
+///
+/// Stereo sounds are mixed down to mono before panning. The volume, fades and looping of the sound still apply.
+pub struct SoundEmitter {
+    sound: Arc<SoundWithAttributes>,
+    position: DangerousCell<Vec2>,
+    velocity: DangerousCell<Vec2>,
+    attenuation: DangerousCell<Attenuation>,
+    doppler: DangerousCell<f32>,
+
+    // only touched by the mixer
+    playhead: DangerousCell<Playhead>,
+    /// The gains at the end of the last block, the next block fades from them to avoid clicks.
+    gains: DangerousCell<Option<(f32, f32)>>,
+}
+
+unsafe impl Send for SoundEmitter {}
+unsafe impl Sync for SoundEmitter {}
+
+impl SoundEmitter {
+    pub fn new(sound: Arc<SoundWithAttributes>) -> Arc<Self> {
+        let this = Self {
+            sound,
+            position: Vec2::default().into(),
+            velocity: Vec2::default().into(),
+            attenuation: Attenuation::default().into(),
+            doppler: 0.0.into(),
+            playhead: Playhead::default().into(),
+            gains: None.into(),
+        };
+        Arc::new(this)
+    }
+
+    pub fn at(sound: Arc<SoundWithAttributes>, position: Vec2) -> Arc<Self> {
+        let this = Self::new(sound);
+        this.set_position(position);
+        this
+    }
+
+    pub fn sound(&self) -> &Arc<SoundWithAttributes> {
+        &self.sound
+    }
+
+    pub fn position(&self) -> Vec2 {
+        self.position.get_val()
+    }
+
+    pub fn set_position(&self, position: Vec2) {
+        self.position.replace(position);
+    }
+
+    pub fn velocity(&self) -> Vec2 {
+        self.velocity.get_val()
+    }
+
+    /// Only used for the doppler effect, the position is not moved by it.
+    pub fn set_velocity(&self, velocity: Vec2) {
+        self.velocity.replace(velocity);
+    }
+
+    pub fn attenuation(&self) -> Attenuation {
+        self.attenuation.get().clone()
+    }
+
+    pub fn set_attenuation(&self, attenuation: Attenuation) {
+        self.attenuation.replace(attenuation);
+    }
+
+    pub fn doppler(&self) -> f32 {
+        self.doppler.get_val()
+    }
+
+    /// Scales the doppler effect, 1 is physically accurate and 0 turns it off, which is the default.
+    pub fn set_doppler(&self, doppler: f32) {
+        self.doppler.replace(doppler);
+    }
+
+    /// The gains of the left and right channel and the pitch, as heard by `listener`.
+    pub fn spatialize(&self, listener: &AudioListener) -> (f32, f32, f32) {
+        let offset = self.position.get_val() - listener.position();
+        let distance = geom::length(offset);
+        let attenuation = self.attenuation.get();
+        let gain = attenuation.gain(distance);
+        if distance <= f32::EPSILON {
+            return (gain * 0.5, gain * 0.5, 1.0);
+        }
+
+        // closer than the min distance, the sound moves towards the center
+        let local = geom::rotate_vector(offset, -listener.rotation());
+        let pan = (local.x / distance.max(attenuation.min_distance)).clamp(-1.0, 1.0);
+        let balance = (pan + 1.0) * 0.5;
+
+        let mut pitch = 1.0;
+        let doppler = self.doppler.get_val();
+        if doppler > 0.0 {
+            let direction = offset / distance;
+            let speed = listener.speed_of_sound();
+            let towards_source = geom::dot(listener.velocity(), direction) * doppler;
+            let away_from_listener = geom::dot(self.velocity.get_val(), direction) * doppler;
+            pitch = ((speed + towards_source) / (speed + away_from_listener).max(f32::EPSILON)).clamp(0.25, 4.0);
+        }
+        (gain * (1.0 - balance), gain * balance, pitch)
+    }
+
+    /// Adds the next `out.len()` frames at `sample_rate` to `out`.
+    pub(crate) fn mix(&self, listener: &AudioListener, out: &mut [(f32, f32)], sample_rate: u32) {
+        let (left, right, pitch) = self.spatialize(listener);
+        let (start_left, start_right) = self.gains.get_val().unwrap_or((left, right));
+        self.gains.replace(Some((left, right)));
+
+        let mono = self.sound.sound().channels() == 1;
+        let mut playhead = self.playhead.get_val();
+        let blend = 1.0 / out.len().max(1) as f32;
+        for (i, frame) in out.iter_mut().enumerate() {
+            if self.sound.is_finished(&playhead) {
+                break;
+            }
+            let t = (i + 1) as f32 * blend;
+            let (l, r) = self.sound.next_frame_pitched(&mut playhead, sample_rate, pitch as f64);
+            // mono sounds are split between both channels, stereo ones are averaged
+            let sample = if mono { l + r } else { (l + r) * 0.5 };
+            frame.0 += sample * (start_left + (left - start_left) * t);
+            frame.1 += sample * (start_right + (right - start_right) * t);
+        }
+        self.playhead.replace(playhead);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.sound.is_finished(self.playhead.get())
+    }
+}
+
+/// Attaches a [`SoundEmitter`] to an entity, which the [`SpatialAudioSystem`] moves along with it.
+pub struct Emitter(pub Arc<SoundEmitter>);
+
+/// Marks the entity the [`AudioListener`] follows. Only one entity should have it.
+pub struct Listener;
+
+/// Moves emitters and the listener to the [`GlobalTransform`] of their entities, or to the [`Transform`] if there is
+/// none. The velocity for the doppler effect comes from [`RigidDynamic`], or from the movement since the last run.
+/// Entities seen for the first time, and entities that moved faster than the speed of sound, are teleports and get no
+/// velocity from their movement.
+pub struct SpatialAudioSystem {
+    listener: Arc<AudioListener>,
+    /// Where every entity was at the last run.
+    previous: HashMap<EntityId, Vec2>,
+    emitters: Query<(
+        &'static Emitter,
+        Option<&'static Transform>,
+        Option<&'static GlobalTransform>,
+        Option<&'static RigidDynamic>,
+    )>,
+    listeners: Query<
+        (
+            Option<&'static Transform>,
+            Option<&'static GlobalTransform>,
+            Option<&'static RigidDynamic>,
+        ),
+        With<Listener>,
+    >,
+}
+
+impl SpatialAudioSystem {
+    pub fn new(listener: Arc<AudioListener>) -> Self {
+        Self {
+            listener,
+            previous: HashMap::new(),
+            emitters: Query::new(),
+            listeners: Query::new(),
+        }
+    }
+
+    pub fn iterate(&mut self, world: &mut World, dt: f64) {
+        let access = self.access();
+        self.step(&mut SystemWorld::new(world, &access), dt);
+    }
+
+    fn step(&mut self, world: &mut SystemWorld, dt: f64) {
+        let dt = dt as f32;
+        let max_speed = self.listener.speed_of_sound();
+        let previous = std::mem::take(&mut self.previous);
+        let seen = &mut self.previous;
+        let mut velocity = |entity: EntityId, position: Vec2, body: Option<&RigidDynamic>| {
+            seen.insert(entity, position);
+            if let Some(body) = body {
+                return body.velocity;
+            }
+            match previous.get(&entity) {
+                Some(old) if dt > 0.0 => {
+                    let velocity = (position - *old) / dt;
+                    if geom::dot(velocity, velocity) > max_speed * max_speed {
+                        Vec2::default()
+                    } else {
+                        velocity
+                    }
+                }
+                _ => Vec2::default(),
+            }
+        };
+
+        for (entity, (local, global, body)) in world.query(&mut self.listeners) {
+            let Some((position, rotation)) = placement(local, global) else {
+                continue;
+            };
+            let listener = &self.listener;
+            listener.set_velocity(velocity(entity, position, body));
+            listener.set_position(position);
+            listener.set_rotation(rotation);
+        }
+
+        for (entity, (emitter, local, global, body)) in world.query(&mut self.emitters) {
+            let Some((position, _)) = placement(local, global) else {
+                continue;
+            };
+            let emitter = &emitter.0;
+            emitter.set_velocity(velocity(entity, position, body));
+            emitter.set_position(position);
+        }
+    }
+}
+
+fn placement(local: Option<&Transform>, global: Option<&GlobalTransform>) -> Option<(Vec2, f32)> {
+    match (global, local) {
+        (Some(global), _) => Some((global.position, global.rotation as f32)),
+        (None, Some(local)) => Some((local.position, local.rotation as f32)),
+        (None, None) => None,
+    }
+}
+
+impl ScheduledSystem for SpatialAudioSystem {
+    fn access(&self) -> Access {
+        Access::new()
+            .read::<Emitter>()
+            .read::<Listener>()
+            .read::<Transform>()
+            .read::<GlobalTransform>()
+            .read::<RigidDynamic>()
+    }
+
+    fn run(&mut self, world: &mut SystemWorld, _: &mut Commands, dt: f64) {
+        self.step(world, dt);
+    }
+}
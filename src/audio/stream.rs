@@ -0,0 +1,439 @@
+//! Sounds that are decoded while they play, instead of being decoded into memory up front.
+//!
+//! A [`StreamingSound`] decodes on a background thread into a ring buffer of a fixed size, which the
+//! [`AudioMixer`](crate::audio::mixer::AudioMixer) drains. This keeps long music tracks at a few hundred kilobytes
+//! instead of hundreds of megabytes. Seeking flushes the buffer, so the sound is silent until the decode thread
+//! caught up, which usually takes a few milliseconds.
+
+use crate::audio::decode::PacketDecoder;
+use crate::audio::source::{ms_to_frames, WithAttributes};
+use crate::ui::ease::EasingGen;
+use log::error;
+use mvutils::unsafe_utils::DangerousCell;
+use parking_lot::Mutex;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::thread::Thread;
+use std::time::Duration;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+const DEFAULT_BUFFER: Duration = Duration::from_millis(500);
+
+/// A single producer, single consumer queue of stereo frames. Mono frames only use the left sample.
+struct Ring {
+    frames: Box<[DangerousCell<[f32; 2]>]>,
+    /// Total number of frames pushed, only written by the decode thread.
+    written: AtomicUsize,
+    /// Total number of frames popped, only written by the mixer.
+    read: AtomicUsize,
+}
+
+impl Ring {
+    fn new(capacity: usize) -> Self {
+        Self {
+            frames: (0..capacity.max(1)).map(|_| DangerousCell::new([0.0; 2])).collect(),
+            written: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+        }
+    }
+
+    /// Frames before `flushed` are never played, so they can be overwritten even if the mixer did not skip them yet.
+    fn free(&self, flushed: usize) -> usize {
+        let read = self.read.load(Ordering::Acquire).max(flushed);
+        self.frames.len() - (self.written.load(Ordering::Relaxed) - read)
+    }
+
+    /// Only called by the decode thread, after checking [`Ring::free`].
+    fn push(&self, frame: [f32; 2]) {
+        let written = self.written.load(Ordering::Relaxed);
+        self.frames[written % self.frames.len()].replace(frame);
+        self.written.store(written + 1, Ordering::Release);
+    }
+
+    /// Only called by the mixer.
+    fn pop(&self) -> Option<[f32; 2]> {
+        let read = self.read.load(Ordering::Relaxed);
+        if read == self.written.load(Ordering::Acquire) {
+            return None;
+        }
+        let frame = self.frames[read % self.frames.len()].get_val();
+        self.read.store(read + 1, Ordering::Release);
+        Some(frame)
+    }
+}
+
+unsafe impl Send for Ring {}
+unsafe impl Sync for Ring {}
+
+/// State shared between a [`StreamingSound`] and its decode thread.
+struct Shared {
+    ring: Ring,
+    /// Bumped by every seek. The decode thread answers by setting `epoch` to it once it seeked.
+    requested_epoch: AtomicU64,
+    seek_target: Mutex<u64>,
+    epoch: AtomicU64,
+    /// Where the frames of the current epoch start in the ring, and which frame of the sound that is.
+    flush_to: AtomicUsize,
+    flush_frame: AtomicU64,
+    looping: AtomicBool,
+    /// The decode thread reached the end and waits for a seek.
+    ended: AtomicBool,
+    closed: AtomicBool,
+}
+
+/// A sound decoded from a file or reader while it plays. Play it with
+/// [`AudioEngine::play_stream`](crate::audio::AudioEngine::play_stream).
+pub struct StreamingSound {
+    shared: Arc<Shared>,
+    decode_thread: Thread,
+    channels: u8,
+    sample_rate: u32,
+    frames: Option<u64>,
+    /// Looping is kept in [`Shared`] for the decode thread, and the interpolation and time stretch are not used.
+    with_attributes: WithAttributes,
+
+    // only touched by the mixer
+    epoch: DangerousCell<u64>,
+    /// The frame the output is at, fractional because of resampling and speed.
+    cursor: DangerousCell<f64>,
+    /// The frame the next popped frame belongs to.
+    next: DangerousCell<u64>,
+    /// The two frames around the cursor, interpolated linearly. Sinc interpolation would need to look further ahead
+    /// into the ring than it is safe to read.
+    previous: DangerousCell<[f32; 2]>,
+    current: DangerousCell<[f32; 2]>,
+}
+
+unsafe impl Send for StreamingSound {}
+unsafe impl Sync for StreamingSound {}
+
+impl StreamingSound {
+    /// Streams the file at `path`. The format is detected from the contents, the extension is only a hint.
+    pub fn open(path: impl AsRef<Path>) -> Result<Arc<Self>, String> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| format!("Could not open {}: {e}", path.display()))?;
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(extension);
+        }
+        Self::create(Box::new(file), hint, DEFAULT_BUFFER)
+    }
+
+    /// Streams from `reader`, which has to contain a whole Ogg Vorbis, FLAC, MP3 or WAV file.
+    pub fn from_reader<R: Read + Seek + Send + Sync + 'static>(reader: R) -> Result<Arc<Self>, String> {
+        Self::with_buffer(reader, DEFAULT_BUFFER)
+    }
+
+    /// Like [`StreamingSound::from_reader`], but keeps `buffer` worth of audio decoded ahead.
+    /// Larger buffers survive longer hiccups of the decode thread.
+    pub fn with_buffer<R: Read + Seek + Send + Sync + 'static>(mut reader: R, buffer: Duration) -> Result<Arc<Self>, String> {
+        // some formats need the length to seek
+        let start = reader.stream_position().map_err(|e| e.to_string())?;
+        let len = reader.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+        reader.seek(SeekFrom::Start(start)).map_err(|e| e.to_string())?;
+        Self::create(Box::new(SeekableReader { reader, len }), Hint::new(), buffer)
+    }
+
+    fn create(source: Box<dyn MediaSource>, hint: Hint, buffer: Duration) -> Result<Arc<Self>, String> {
+        let source = MediaSourceStream::new(source, Default::default());
+        let probed = symphonia::default::get_probe()
+            .format(&hint, source, &PacketDecoder::format_options(), &MetadataOptions::default())
+            .map_err(|e| e.to_string())?;
+        let decoder = PacketDecoder::new(probed.format, |params| {
+            symphonia::default::get_codecs().make(params, &DecoderOptions::default())
+        })?;
+
+        let channels = decoder.channels();
+        let sample_rate = decoder.sample_rate();
+        let frames = decoder.frames();
+        let capacity = (buffer.as_secs_f64() * sample_rate as f64).ceil() as usize;
+        let shared = Arc::new(Shared {
+            ring: Ring::new(capacity),
+            requested_epoch: AtomicU64::new(0),
+            seek_target: Mutex::new(0),
+            epoch: AtomicU64::new(0),
+            flush_to: AtomicUsize::new(0),
+            flush_frame: AtomicU64::new(0),
+            looping: AtomicBool::new(false),
+            ended: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+        });
+
+        let thread_shared = shared.clone();
+        let handle = thread::spawn(move || decode_thread(&thread_shared, decoder));
+
+        let this = Self {
+            shared,
+            decode_thread: handle.thread().clone(),
+            channels,
+            sample_rate,
+            frames,
+            with_attributes: WithAttributes::new(),
+            epoch: 0.into(),
+            cursor: 0.0.into(),
+            next: 0.into(),
+            previous: [0.0; 2].into(),
+            current: [0.0; 2].into(),
+        };
+        Ok(Arc::new(this))
+    }
+
+    /// Returns the next frame at the output `sample_rate` and advances. While the decode thread did not catch up, the
+    /// output is silent and does not advance.
+    pub fn next_sample(&self, sample_rate: u32) -> (f32, f32) {
+        let requested = self.shared.requested_epoch.load(Ordering::Acquire);
+        if self.epoch.get_val() != requested {
+            if self.shared.epoch.load(Ordering::Acquire) != requested {
+                return (0.0, 0.0);
+            }
+            let frame = self.shared.flush_frame.load(Ordering::Relaxed);
+            self.shared
+                .ring
+                .read
+                .store(self.shared.flush_to.load(Ordering::Relaxed), Ordering::Release);
+            self.epoch.replace(requested);
+            self.next.replace(frame);
+            self.cursor.replace(frame as f64);
+            self.current.replace([0.0; 2]);
+        }
+
+        // the frame at or right after the cursor, reading no further than needed keeps exact positions exact
+        let cursor = self.cursor.get_val();
+        let target = cursor.ceil() as u64;
+        while self.next.get_val() <= target {
+            match self.shared.ring.pop() {
+                Some(frame) => {
+                    self.previous.replace(self.current.get_val());
+                    self.current.replace(frame);
+                    self.next.replace(self.next.get_val() + 1);
+                }
+                None => return (0.0, 0.0),
+            }
+        }
+        let step = self.sample_rate as f64 / sample_rate as f64 * self.with_attributes.speed() as f64;
+        self.cursor.replace(self.cursor.get_val() + step);
+
+        let t = 1.0 - (target as f64 - cursor) as f32;
+        let ([a, b], [c, d]) = (self.previous.get_val(), self.current.get_val());
+        let frame = (a * (1.0 - t) + c * t, b * (1.0 - t) + d * t);
+        self.with_attributes.apply(frame, self.channels, self.position_frames() as usize)
+    }
+
+    /// The frame that was played last, wrapped around the length when looping.
+    fn position_frames(&self) -> u64 {
+        let played = self.next.get_val().saturating_sub(1);
+        match self.frames {
+            Some(frames) if frames > 0 => played % frames,
+            _ => played,
+        }
+    }
+
+    /// If the sound ended and the mixer can drop it. Looping sounds never end.
+    pub fn is_finished(&self) -> bool {
+        let requested = self.shared.requested_epoch.load(Ordering::Acquire);
+        !self.is_looping()
+            && self.epoch.get_val() == requested
+            && self.shared.epoch.load(Ordering::Acquire) == requested
+            && self.shared.ended.load(Ordering::Acquire)
+            && self.shared.ring.read.load(Ordering::Acquire) == self.shared.ring.written.load(Ordering::Acquire)
+    }
+
+    /// How many frames are decoded ahead of the output.
+    pub fn buffered(&self) -> usize {
+        let requested = self.shared.requested_epoch.load(Ordering::Acquire);
+        let written = self.shared.ring.written.load(Ordering::Acquire);
+        if self.epoch.get_val() == requested {
+            written - self.shared.ring.read.load(Ordering::Acquire)
+        } else if self.shared.epoch.load(Ordering::Acquire) == requested {
+            written - self.shared.flush_to.load(Ordering::Relaxed)
+        } else {
+            0
+        }
+    }
+
+    /// Continues playing at `position`. Positions past the end end the sound, unless it loops.
+    pub fn seek(&self, position: Duration) {
+        *self.shared.seek_target.lock() = (position.as_secs_f64() * self.sample_rate as f64).round() as u64;
+        self.shared.requested_epoch.fetch_add(1, Ordering::AcqRel);
+        self.decode_thread.unpark();
+    }
+
+    /// Where in the sound the output currently is.
+    pub fn position(&self) -> Duration {
+        Duration::from_secs_f64(self.position_frames() as f64 / self.sample_rate as f64)
+    }
+
+    /// The length of the sound, if the file stores it.
+    pub fn duration(&self) -> Option<Duration> {
+        self.frames
+            .map(|frames| Duration::from_secs_f64(frames as f64 / self.sample_rate as f64))
+    }
+
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn is_looping(&self) -> bool {
+        self.shared.looping.load(Ordering::Relaxed)
+    }
+
+    pub fn set_looping(&self, looping: bool) {
+        self.shared.looping.store(looping, Ordering::Relaxed);
+        self.decode_thread.unpark();
+    }
+
+    pub fn balance(&self) -> f32 {
+        self.with_attributes.balance()
+    }
+
+    pub fn set_balance(&self, balance: f32) {
+        self.with_attributes.set_balance(balance);
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.with_attributes.volume()
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        self.with_attributes.set_volume(volume);
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.with_attributes.speed()
+    }
+
+    pub fn set_speed(&self, speed: f32) {
+        self.with_attributes.set_speed(speed);
+    }
+
+    pub fn set_fade_in(&self, ease_gen: EasingGen, duration_ms: u32) {
+        let duration_samples = ms_to_frames(duration_ms, self.sample_rate);
+        self.with_attributes.fade_in_at(ease_gen, 0, duration_samples);
+    }
+
+    /// Only works if the length of the sound is known, see [`StreamingSound::duration`].
+    /// Fades longer than the sound start right away.
+    pub fn set_fade_out(&self, ease_gen: EasingGen, duration_ms: u32) {
+        let Some(frames) = self.frames else {
+            error!("Cannot fade out a stream of unknown length");
+            return;
+        };
+        let duration_samples = ms_to_frames(duration_ms, self.sample_rate);
+        let start_sample = frames.saturating_sub(duration_samples);
+        self.with_attributes.fade_out_at(ease_gen, start_sample, duration_samples);
+    }
+}
+
+impl Drop for StreamingSound {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.decode_thread.unpark();
+    }
+}
+
+/// Decodes into the ring until the sound is dropped.
+fn decode_thread(shared: &Shared, mut decoder: PacketDecoder) {
+    let mut epoch = 0;
+    let mut samples = Vec::new();
+    // samples of the current packet that did not fit into the ring yet
+    let mut pending = 0;
+    let channels = decoder.channels() as usize;
+
+    while !shared.closed.load(Ordering::Acquire) {
+        let requested = shared.requested_epoch.load(Ordering::Acquire);
+        if requested != epoch {
+            let target = *shared.seek_target.lock();
+            let frame = decoder.seek(target).unwrap_or_else(|e| {
+                error!("Could not seek audio stream: {e}");
+                decoder.frames().unwrap_or(target)
+            });
+            samples.clear();
+            pending = 0;
+            shared.ended.store(false, Ordering::Release);
+            shared.flush_to.store(shared.ring.written.load(Ordering::Relaxed), Ordering::Relaxed);
+            shared.flush_frame.store(frame, Ordering::Relaxed);
+            shared.epoch.store(requested, Ordering::Release);
+            epoch = requested;
+        }
+
+        if pending == samples.len() {
+            samples.clear();
+            pending = 0;
+            if shared.ended.load(Ordering::Acquire) {
+                // wait for a seek, or for looping to be turned on
+                if !shared.looping.load(Ordering::Relaxed) {
+                    thread::park();
+                    continue;
+                }
+                shared.ended.store(false, Ordering::Release);
+                if let Err(e) = decoder.seek(0) {
+                    error!("Could not loop audio stream: {e}");
+                    shared.looping.store(false, Ordering::Relaxed);
+                    shared.ended.store(true, Ordering::Release);
+                }
+                continue;
+            }
+            match decoder.next(&mut samples) {
+                Ok(true) => {}
+                Ok(false) => {
+                    shared.ended.store(true, Ordering::Release);
+                    continue;
+                }
+                Err(e) => {
+                    error!("Could not decode audio stream: {e}");
+                    shared.ended.store(true, Ordering::Release);
+                    continue;
+                }
+            }
+        }
+
+        let free = shared.ring.free(shared.flush_to.load(Ordering::Relaxed));
+        if free == 0 {
+            thread::park_timeout(Duration::from_millis(5));
+            continue;
+        }
+        for frame in samples[pending..].chunks_exact(channels).take(free) {
+            shared.ring.push([frame[0], frame[channels - 1]]);
+            pending += channels;
+        }
+    }
+}
+
+/// Lets symphonia read from any seekable reader.
+struct SeekableReader<R> {
+    reader: R,
+    len: u64,
+}
+
+impl<R: Read> Read for SeekableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for SeekableReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.reader.seek(pos)
+    }
+}
+
+impl<R: Read + Seek + Send + Sync> MediaSource for SeekableReader<R> {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.len)
+    }
+}
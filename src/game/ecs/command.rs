@@ -0,0 +1,121 @@
+use crate::game::ecs::entity::{next_entity_id, EntityId};
+use crate::game::ecs::world::EcsWorld;
+use crate::game::ecs::World;
+use parking_lot::Mutex;
+
+type CommandFn = Box<dyn FnOnce(&mut World) + Send>;
+
+enum Command {
+    Spawn(EntityId),
+    Despawn(EntityId),
+    Custom(CommandFn),
+}
+
+/// Records structural changes to a world while it is borrowed, e.g. by a system iterator.
+/// Nothing happens until the buffer is applied, at which point every command runs in the order it was recorded.
+pub struct Commands {
+    queue: Vec<Command>,
+}
+
+impl Commands {
+    pub fn new() -> Self {
+        Self { queue: Vec::new() }
+    }
+
+    /// Reserves an id for a new entity right away, so that components can be inserted for it before the buffer is applied.
+    pub fn spawn(&mut self) -> EntityId {
+        let id = next_entity_id();
+        self.queue.push(Command::Spawn(id));
+        id
+    }
+
+    pub fn despawn(&mut self, id: EntityId) {
+        self.queue.push(Command::Despawn(id));
+    }
+
+    pub fn insert<C: Send + 'static>(&mut self, id: EntityId, component: C) {
+        self.add(move |world| world.set_component(id, component));
+    }
+
+    pub fn remove<C: 'static>(&mut self, id: EntityId) {
+        self.add(move |world| world.remove_component::<C>(id));
+    }
+
+    /// Records an arbitrary change to the world.
+    pub fn add<F: FnOnce(&mut World) + Send + 'static>(&mut self, f: F) {
+        self.queue.push(Command::Custom(Box::new(f)));
+    }
+
+    /// Moves every command of `other` to the end of this buffer.
+    pub fn append(&mut self, other: &mut Commands) {
+        self.queue.append(&mut other.queue);
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Runs and removes every recorded command.
+    pub fn apply(&mut self, world: &mut World) {
+        for command in self.queue.drain(..) {
+            match command {
+                Command::Spawn(id) => world.create_entity(id),
+                Command::Despawn(id) => world.destroy_entity(id),
+                Command::Custom(f) => f(world),
+            }
+        }
+    }
+}
+
+impl Default for Commands {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collects command buffers that were recorded on several threads.
+///
+/// Every buffer is submitted with an ordering key and the buffers are applied sorted by that key
+/// (buffers with the same key in submission order), so the result does not depend on which thread finished first.
+pub struct CommandQueue {
+    buffers: Mutex<Vec<(u64, Commands)>>,
+}
+
+impl CommandQueue {
+    pub fn new() -> Self {
+        Self {
+            buffers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn submit(&self, key: u64, commands: Commands) {
+        if !commands.is_empty() {
+            self.buffers.lock().push((key, commands));
+        }
+    }
+
+    /// Merges every submitted buffer into one, ordered by key.
+    pub fn merge(&self) -> Commands {
+        let mut buffers = std::mem::take(&mut *self.buffers.lock());
+        buffers.sort_by_key(|(key, _)| *key);
+        let mut merged = Commands::new();
+        for (_, mut commands) in buffers {
+            merged.append(&mut commands);
+        }
+        merged
+    }
+
+    pub fn apply(&self, world: &mut World) {
+        self.merge().apply(world);
+    }
+}
+
+impl Default for CommandQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,10 +1,29 @@
-use mvutils::utils;
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
 use crate::game::ecs::World;
 use crate::game::ecs::world::EcsWorld;
 
 pub type EntityId = u64;
 
+/// The last entity id that was handed out or reserved.
+static LAST_ENTITY_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Reserves a new unique entity id.
+pub fn next_entity_id() -> EntityId {
+    LAST_ENTITY_ID.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Makes sure that entities created from now on get an id bigger than `id`, e.g. after loading a snapshot.
+pub(crate) fn reserve_ids_through(id: EntityId) {
+    LAST_ENTITY_ID.fetch_max(id, Ordering::Relaxed);
+}
+
+/// Implemented by components that store ids of other entities, so the ids can be translated when the component is
+/// copied into a world where entities have different ids, see [`ComponentRegistry::register_mapped`](crate::game::ecs::save::ComponentRegistry::register_mapped).
+pub trait MapEntities {
+    fn map_entities(&mut self, map: &mut dyn FnMut(EntityId) -> EntityId);
+}
+
 pub struct Entity<C> {
     phantom: PhantomData<C>,
     pub(crate) ty: EntityId,
@@ -14,7 +33,7 @@ impl<C> Entity<C> {
     fn new_internal() -> Self {
         Self {
             phantom: PhantomData::default(),
-            ty: utils::next_id("MVEngine::ecs::entity"),
+            ty: next_entity_id(),
         }
     }
 }
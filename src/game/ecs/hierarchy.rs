@@ -0,0 +1,123 @@
+use crate::game::ecs::command::Commands;
+use crate::game::ecs::entity::{EntityId, MapEntities};
+use crate::game::ecs::world::EcsWorld;
+use crate::game::ecs::World;
+use log::warn;
+use mvutils::Savable;
+
+/// The entity this entity is attached to. Always kept in sync with the [`Children`] of the parent,
+/// so use [`set_parent`] and [`remove_parent`] instead of setting it directly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Savable)]
+pub struct Parent(pub EntityId);
+
+/// The entities attached to this entity, in the order they were attached.
+#[derive(Clone, Default, Debug, PartialEq, Eq, Savable)]
+pub struct Children(pub Vec<EntityId>);
+
+impl MapEntities for Parent {
+    fn map_entities(&mut self, map: &mut dyn FnMut(EntityId) -> EntityId) {
+        self.0 = map(self.0);
+    }
+}
+
+impl MapEntities for Children {
+    fn map_entities(&mut self, map: &mut dyn FnMut(EntityId) -> EntityId) {
+        for child in &mut self.0 {
+            *child = map(*child);
+        }
+    }
+}
+
+impl Children {
+    pub fn iter(&self) -> impl Iterator<Item = EntityId> + '_ {
+        self.0.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Returns true if `ancestor` is `id` itself or one of its parents, grandparents and so on.
+pub fn is_ancestor(world: &World, ancestor: EntityId, id: EntityId) -> bool {
+    let mut current = Some(id);
+    while let Some(c) = current {
+        if c == ancestor {
+            return true;
+        }
+        current = world.get_component::<Parent>(c).map(|p| p.0);
+    }
+    false
+}
+
+/// Attaches `child` to `parent`, detaching it from its previous parent first.
+/// Returns false and changes nothing if this would make an entity its own ancestor.
+pub fn set_parent(world: &mut World, child: EntityId, parent: EntityId) -> bool {
+    if is_ancestor(world, child, parent) {
+        warn!("Cannot attach entity {child} to {parent}, as {child} is already an ancestor of {parent}");
+        return false;
+    }
+
+    remove_parent(world, child);
+    world.set_component(child, Parent(parent));
+    if let Some(children) = world.get_component_mut::<Children>(parent) {
+        children.0.push(child);
+    } else {
+        world.set_component(parent, Children(vec![child]));
+    }
+    true
+}
+
+/// Detaches `child` from its parent, making it a root again.
+pub fn remove_parent(world: &mut World, child: EntityId) {
+    let Some(parent) = world.get_component::<Parent>(child).map(|p| p.0) else {
+        return;
+    };
+    world.remove_component::<Parent>(child);
+
+    let now_empty = if let Some(children) = world.get_component_mut::<Children>(parent) {
+        children.0.retain(|c| *c != child);
+        children.0.is_empty()
+    } else {
+        false
+    };
+    if now_empty {
+        world.remove_component::<Children>(parent);
+    }
+}
+
+/// Destroys `id` and all of its descendants, and detaches it from its parent.
+pub fn despawn_recursive(world: &mut World, id: EntityId) {
+    remove_parent(world, id);
+
+    let mut stack = vec![id];
+    while let Some(current) = stack.pop() {
+        if let Some(children) = world.get_component::<Children>(current) {
+            stack.extend(children.iter());
+        }
+        world.destroy_entity(current);
+    }
+}
+
+impl Commands {
+    /// Deferred version of [`set_parent`].
+    pub fn set_parent(&mut self, child: EntityId, parent: EntityId) {
+        self.add(move |world| {
+            set_parent(world, child, parent);
+        });
+    }
+
+    /// Deferred version of [`remove_parent`].
+    pub fn remove_parent(&mut self, child: EntityId) {
+        self.add(move |world| remove_parent(world, child));
+    }
+
+    /// Deferred version of [`despawn_recursive`].
+    pub fn despawn_recursive(&mut self, id: EntityId) {
+        self.add(move |world| despawn_recursive(world, id));
+    }
+}
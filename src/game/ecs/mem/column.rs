@@ -0,0 +1,217 @@
+use crate::game::ecs::mem::conblob::PHI;
+use crate::game::ecs::tick::{ComponentTicks, Tick};
+use std::alloc::Layout;
+use std::any::TypeId;
+use std::{alloc, ptr};
+
+/// A type erased, densely packed vector of components of one type.
+/// Used as the storage of a single component type inside an archetype table.
+pub struct Column {
+    data: *mut u8,
+    len: usize,
+    capacity: usize,
+    layout: Layout,
+    type_id: TypeId,
+    drop_fn: Option<unsafe fn(*mut u8)>,
+    ticks: Vec<ComponentTicks>,
+}
+
+unsafe fn drop_ptr<T>(ptr: *mut u8) {
+    unsafe { ptr::drop_in_place(ptr as *mut T) }
+}
+
+impl Column {
+    pub fn new<T: Sized + 'static>() -> Self {
+        let drop_fn = if std::mem::needs_drop::<T>() {
+            Some(drop_ptr::<T> as unsafe fn(*mut u8))
+        } else {
+            None
+        };
+        Self::from_raw_parts(Layout::new::<T>(), TypeId::of::<T>(), drop_fn)
+    }
+
+    /// Creates an empty column storing the same type as `other`.
+    pub fn new_like(other: &Column) -> Self {
+        Self::from_raw_parts(other.layout, other.type_id, other.drop_fn)
+    }
+
+    fn from_raw_parts(layout: Layout, type_id: TypeId, drop_fn: Option<unsafe fn(*mut u8)>) -> Self {
+        Self {
+            data: ptr::without_provenance_mut(layout.align()),
+            len: 0,
+            capacity: if layout.size() == 0 { usize::MAX } else { 0 },
+            layout,
+            type_id,
+            drop_fn,
+            ticks: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+
+    fn array_layout(&self, capacity: usize) -> Layout {
+        unsafe { Layout::from_size_align_unchecked(capacity * self.layout.size(), self.layout.align()) }
+    }
+
+    fn reserve_one(&mut self) {
+        if self.len < self.capacity {
+            return;
+        }
+        let new_cap = ((self.capacity as f64 * PHI).ceil() as usize).max(4);
+        let new_layout = self.array_layout(new_cap);
+        unsafe {
+            self.data = if self.capacity == 0 {
+                alloc::alloc(new_layout)
+            } else {
+                alloc::realloc(self.data, self.array_layout(self.capacity), new_layout.size())
+            };
+        }
+        if self.data.is_null() {
+            alloc::handle_alloc_error(new_layout);
+        }
+        self.capacity = new_cap;
+    }
+
+    /// Returns a pointer to the element at `row`. The pointer is only valid until the column is modified.
+    ///
+    /// # Safety
+    /// `row` must be smaller than `self.len()`.
+    pub unsafe fn get_ptr(&self, row: usize) -> *mut u8 {
+        unsafe { self.data.add(row * self.layout.size()) }
+    }
+
+    pub fn push<T: Sized + 'static>(&mut self, t: T, tick: Tick) {
+        debug_assert_eq!(self.type_id, TypeId::of::<T>());
+        self.reserve_one();
+        unsafe {
+            (self.get_ptr(self.len) as *mut T).write(t);
+        }
+        self.ticks.push(ComponentTicks::new(tick));
+        self.len += 1;
+    }
+
+    /// Copies one element from `src` to the end of this column, taking ownership of it.
+    ///
+    /// # Safety
+    /// `src` must point to a valid instance of the type stored in this column, which must not be used or dropped afterward.
+    pub unsafe fn push_raw(&mut self, src: *const u8, ticks: ComponentTicks) {
+        self.reserve_one();
+        unsafe {
+            ptr::copy_nonoverlapping(src, self.get_ptr(self.len), self.layout.size());
+        }
+        self.ticks.push(ticks);
+        self.len += 1;
+    }
+
+    pub fn get<T: Sized + 'static>(&self, row: usize) -> Option<&T> {
+        debug_assert_eq!(self.type_id, TypeId::of::<T>());
+        if row < self.len {
+            unsafe { (self.get_ptr(row) as *const T).as_ref() }
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut<T: Sized + 'static>(&mut self, row: usize) -> Option<&mut T> {
+        debug_assert_eq!(self.type_id, TypeId::of::<T>());
+        if row < self.len {
+            unsafe { (self.get_ptr(row) as *mut T).as_mut() }
+        } else {
+            None
+        }
+    }
+
+    pub fn get_ticks(&self, row: usize) -> Option<&ComponentTicks> {
+        self.ticks.get(row)
+    }
+
+    /// Replaces the element at `row`, dropping the old one.
+    pub fn replace<T: Sized + 'static>(&mut self, row: usize, t: T, tick: Tick) {
+        if let Some(old) = self.get_mut::<T>(row) {
+            *old = t;
+            self.ticks[row].set_changed(tick);
+        }
+    }
+
+    /// Removes the element at `row` by moving the last element in its place and drops it.
+    pub fn swap_remove(&mut self, row: usize) {
+        if row >= self.len {
+            return;
+        }
+        unsafe {
+            let removed = self.get_ptr(row);
+            if let Some(drop_fn) = self.drop_fn {
+                drop_fn(removed);
+            }
+            self.swap_remove_forget(row);
+        }
+    }
+
+    /// Removes the element at `row` by moving the last element in its place without dropping it.
+    ///
+    /// # Safety
+    /// The element at `row` must have been moved out already (e.g. with `push_raw`), otherwise it is leaked.
+    pub unsafe fn swap_remove_forget(&mut self, row: usize) {
+        if row >= self.len {
+            return;
+        }
+        self.len -= 1;
+        self.ticks.swap_remove(row);
+        if row < self.len {
+            unsafe {
+                ptr::copy_nonoverlapping(self.get_ptr(self.len), self.get_ptr(row), self.layout.size());
+            }
+        }
+    }
+
+    /// Moves the element at `row` to the end of `other` and swap removes it from this column.
+    ///
+    /// # Safety
+    /// `other` must store the same type as this column.
+    pub unsafe fn move_to(&mut self, row: usize, other: &mut Column) {
+        debug_assert_eq!(self.type_id, other.type_id);
+        if row >= self.len {
+            return;
+        }
+        unsafe {
+            other.push_raw(self.get_ptr(row), self.ticks[row].clone());
+            self.swap_remove_forget(row);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if let Some(drop_fn) = self.drop_fn {
+            for row in 0..self.len {
+                unsafe {
+                    drop_fn(self.get_ptr(row));
+                }
+            }
+        }
+        self.len = 0;
+        self.ticks.clear();
+    }
+}
+
+impl Drop for Column {
+    fn drop(&mut self) {
+        self.clear();
+        if self.layout.size() != 0 && self.capacity != 0 {
+            unsafe {
+                alloc::dealloc(self.data, self.array_layout(self.capacity));
+            }
+        }
+    }
+}
+
+unsafe impl Send for Column {}
+unsafe impl Sync for Column {}
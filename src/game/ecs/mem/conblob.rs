@@ -20,12 +20,10 @@ pub struct ContinuousBlob {
 
 impl ContinuousBlob {
     pub fn new(layout: Layout) -> Self {
-        let ptr = unsafe { std::alloc::alloc(layout) };
-
         let this = Self {
-            data: ptr,
+            data: ptr::without_provenance_mut(layout.align()),
             len: 0,
-            capacity: layout.size(),
+            capacity: 0,
             layout,
             memmap: BiHashMap::new(),
             next_idx: 0,
@@ -34,41 +32,44 @@ impl ContinuousBlob {
         this
     }
 
-    fn realloc(&mut self) {
-        let old_cap = self.capacity;
-        self.capacity = (self.capacity as f64 * PHI).ceil() as usize;
-        if self.capacity - old_cap < self.layout.size() {
-            self.capacity += self.layout.size();
-        }
-        unsafe {
-            self.data = std::alloc::realloc(
-                self.data,
-                self.layout,
-                self.capacity * self.layout.size(),
-            );
-        }
+    fn array_layout(&self, capacity: usize) -> Layout {
+        unsafe { Layout::from_size_align_unchecked(capacity * self.layout.size(), self.layout.align()) }
     }
 
-    fn maybe_shrink(&mut self) {
-        if (self.len as f64 * PHI).ceil() < self.capacity as f64 {
-            self.capacity = self.len + 2;
+    /// Resizes the allocation to hold `capacity` elements. Zero sized types never allocate.
+    fn resize(&mut self, capacity: usize) {
+        if self.layout.size() != 0 {
+            let new_layout = self.array_layout(capacity);
             unsafe {
-                self.data = std::alloc::realloc(
-                    self.data,
-                    Layout::from_size_align_unchecked(
-                        self.capacity * self.layout.size(),
-                        self.layout.align(),
-                    ),
-                    self.capacity,
-                );
+                self.data = if self.capacity == 0 {
+                    alloc::alloc(new_layout)
+                } else {
+                    alloc::realloc(self.data, self.array_layout(self.capacity), new_layout.size())
+                };
+            }
+            if self.data.is_null() {
+                alloc::handle_alloc_error(new_layout);
             }
         }
+        self.capacity = capacity;
+    }
+
+    fn realloc(&mut self) {
+        let capacity = ((self.capacity as f64 * PHI).ceil() as usize).max(4);
+        self.resize(capacity);
+    }
+
+    fn maybe_shrink(&mut self) {
+        if self.capacity > 4 && ((self.len as f64 * PHI * PHI).ceil() as usize) < self.capacity {
+            let capacity = ((self.len as f64 * PHI).ceil() as usize).max(4);
+            self.resize(capacity);
+        }
     }
 
     pub fn push_next<T: Sized + 'static>(&mut self, t: T) -> Option<ComponentIdx> {
         unsafe {
             if self.layout.equivalent(&Layout::for_value(&t)) {
-                if self.capacity < self.len + self.layout.size() {
+                if self.len >= self.capacity {
                     self.realloc();
                 }
                 let added = self.data.add(self.len * self.layout.size());
@@ -126,11 +127,15 @@ impl ContinuousBlob {
             }
             self.len -= 1;
             if idx < self.len {
+                // move the last element into the gap
                 unsafe {
-                    let src = self.data.add(idx * self.layout.size());
-                    let dst = self.data.add(self.len * self.layout.size());
+                    let src = self.data.add(self.len * self.layout.size());
+                    let dst = self.data.add(idx * self.layout.size());
                     ptr::copy_nonoverlapping(src, dst, self.layout.size());
                 }
+                if let Some((moved, _)) = self.memmap.remove_by_right(&self.len) {
+                    self.memmap.insert(moved, idx);
+                }
             }
             self.maybe_shrink();
         }
@@ -155,9 +160,10 @@ impl ContinuousBlob {
 
 impl Drop for ContinuousBlob {
     fn drop(&mut self) {
-        unsafe {
-            let lay = Layout::from_size_align_unchecked(self.capacity * self.layout.size(), self.layout.align());
-            alloc::dealloc(self.data, lay);
+        if self.layout.size() != 0 && self.capacity != 0 {
+            unsafe {
+                alloc::dealloc(self.data, self.array_layout(self.capacity));
+            }
         }
     }
 }
@@ -208,4 +214,6 @@ impl<'a, T: 'a> Iterator for IterMut<'a, T> {
             typed.as_mut().map(|x| (p, x))
         }
     }
-}
\ No newline at end of file
+}
+unsafe impl Send for ContinuousBlob {}
+unsafe impl Sync for ContinuousBlob {}
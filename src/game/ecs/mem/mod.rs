@@ -1,2 +1,3 @@
+pub mod column;
 pub mod conblob;
 pub mod storage;
@@ -8,6 +8,7 @@ use std::fmt::Debug;
 use itertools::Itertools;
 use mvutils::unsafe_utils::Unsafe;
 use crate::game::ecs::mem::conblob;
+use crate::game::ecs::tick::{ChangeTick, ComponentTicks, Tick};
 
 pub(crate) type ComponentIdx = u64;
 
@@ -21,7 +22,9 @@ pub struct ComponentStorage {
     components: HashMap<TypeId, ContinuousBlob>,
     entity_components:
         HashMap<EntityId, HashMap<TypeId, ComponentIdx, U64IdentityHasher>, U64IdentityHasher>,
-    component_entities: HashMap<ComponentKey, EntityId>
+    component_entities: HashMap<ComponentKey, EntityId>,
+    ticks: HashMap<ComponentKey, ComponentTicks>,
+    tick: ChangeTick,
 }
 
 impl ComponentStorage {
@@ -30,6 +33,34 @@ impl ComponentStorage {
             components: HashMap::new(),
             entity_components: HashMap::with_hasher(U64IdentityHasher::default()),
             component_entities: HashMap::new(),
+            ticks: HashMap::new(),
+            tick: ChangeTick::new(),
+        }
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = EntityId> + '_ {
+        self.entity_components.keys().copied()
+    }
+
+    pub fn change_tick(&self) -> Tick {
+        self.tick.get()
+    }
+
+    pub(crate) fn advance_tick(&self) -> Tick {
+        self.tick.advance()
+    }
+
+    pub fn component_ticks<T: Sized + 'static>(&self, entity: EntityId) -> Option<&ComponentTicks> {
+        let index = *self.entity_components.get(&entity)?.get(&TypeId::of::<T>())?;
+        self.ticks.get(&ComponentKey {
+            type_id: TypeId::of::<T>(),
+            index,
+        })
+    }
+
+    pub(crate) fn mark_changed<T: Sized + 'static>(&self, entity: EntityId, tick: Tick) {
+        if let Some(ticks) = self.component_ticks::<T>(entity) {
+            ticks.set_changed(tick);
         }
     }
 
@@ -45,6 +76,7 @@ impl ComponentStorage {
     }
 
     pub fn get_component_mut<T: Sized + 'static>(&mut self, entity: EntityId) -> Option<&mut T> {
+        self.mark_changed::<T>(entity, self.tick.get());
         if let Some(map) = self.entity_components.get_mut(&entity) {
             if let Some(idx) = map.get_mut(&TypeId::of::<T>()) {
                 if let Some(blob) = self.components.get_mut(&TypeId::of::<T>()) {
@@ -67,6 +99,13 @@ impl ComponentStorage {
     }
 
     pub fn set_component<T: Sized + 'static>(&mut self, entity: EntityId, component: T) {
+        if let Some(existing) = self.get_component_mut::<T>(entity) {
+            *existing = component;
+            return;
+        }
+
+        let tick = self.tick.get();
+
         let blob = if let Some(blob) = self.components.get_mut(&TypeId::of::<T>()) {
             blob
         } else {
@@ -81,13 +120,17 @@ impl ComponentStorage {
             } else {
                 let map = HashMap::with_hasher(U64IdentityHasher::default());
                 self.entity_components.insert(entity, map);
-                self.component_entities.insert(ComponentKey {
-                    type_id: TypeId::of::<T>(),
-                    index: idx,
-                }, entity);
                 self.entity_components.get_mut(&entity).unwrap()
             };
 
+            self.component_entities.insert(ComponentKey {
+                type_id: TypeId::of::<T>(),
+                index: idx,
+            }, entity);
+            self.ticks.insert(ComponentKey {
+                type_id: TypeId::of::<T>(),
+                index: idx,
+            }, ComponentTicks::new(tick));
             map.insert(TypeId::of::<T>(), idx as ComponentIdx);
         }
     }
@@ -95,10 +138,16 @@ impl ComponentStorage {
     pub fn remove_component<T: Sized + 'static>(&mut self, entity: EntityId) {
         let type_id = TypeId::of::<T>();
         if let Some(blob) = self.components.get_mut(&type_id)
-            && let Some(map) = self.entity_components.get(&entity)
-            && let Some(idx) = map.get(&type_id)
+            && let Some(map) = self.entity_components.get_mut(&entity)
+            && let Some(idx) = map.remove(&type_id)
         {
-            blob.remove(*idx);
+            blob.remove(idx);
+            let key = ComponentKey {
+                type_id,
+                index: idx,
+            };
+            self.component_entities.remove(&key);
+            self.ticks.remove(&key);
         }
     }
 
@@ -113,6 +162,7 @@ impl ComponentStorage {
                     index: idx,
                 };
                 self.component_entities.remove(&key);
+                self.ticks.remove(&key);
             }
         }
     }
@@ -3,9 +3,17 @@ use world::sparse::SparseSetWorld;
 use crate::game::ecs::entity::EntityId;
 use crate::game::ecs::world::arch::ArchetypeWorld;
 use crate::game::ecs::world::EcsWorld;
+use crate::game::ecs::tick::{ComponentTicks, Tick};
 
+pub mod command;
+pub mod hierarchy;
 pub mod mem;
+pub(crate) mod pool;
+pub mod query;
+pub mod save;
+pub mod schedule;
 pub mod system;
+pub mod tick;
 pub mod world;
 pub mod entity;
 
@@ -43,7 +51,38 @@ impl World {
     pub(crate) fn new(backend: EcsBackend) -> Self {
         match backend {
             EcsBackend::SparseSet => World::SparseSet(SparseSetWorld::new(ComponentStorage::new())),
-            EcsBackend::Archetype => World::ArchetypeWorld(ArchetypeWorld {})
+            EcsBackend::Archetype => World::ArchetypeWorld(ArchetypeWorld::new())
+        }
+    }
+
+    #[auto_enums::auto_enum(Iterator)]
+    pub fn entities(&self) -> impl Iterator<Item = EntityId> + '_ {
+        match self {
+            World::SparseSet(e) => e.storage.entities(),
+            World::ArchetypeWorld(e) => e.entities(),
+        }
+    }
+
+    /// Advances the world tick, returning the tick before advancing. Called once per query run.
+    pub(crate) fn advance_tick(&self) -> Tick {
+        match self {
+            World::SparseSet(e) => e.storage.advance_tick(),
+            World::ArchetypeWorld(e) => e.advance_tick(),
+        }
+    }
+
+    /// Returns a mutable reference through a shared one and marks the component as changed at `tick`.
+    ///
+    /// # Safety
+    /// The caller has to make sure the component is not borrowed anywhere else.
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn get_component_mut_unchecked<C: 'static>(&self, id: EntityId, tick: Tick) -> Option<&mut C> {
+        match self {
+            World::SparseSet(e) => {
+                e.storage.mark_changed::<C>(id, tick);
+                e.storage.get_component_mut_bruh::<C>(id)
+            }
+            World::ArchetypeWorld(e) => unsafe { e.get_component_mut_unchecked::<C>(id, tick) },
         }
     }
 }
@@ -76,6 +115,13 @@ impl EcsWorld for World {
         world_fn!(self, set_component(id, c));
     }
 
+    fn remove_component<C: 'static>(&mut self, id: EntityId) {
+        match self {
+            World::SparseSet(e) => e.remove_component::<C>(id),
+            World::ArchetypeWorld(e) => e.remove_component::<C>(id),
+        }
+    }
+
     fn get_component<C: 'static>(&self, id: EntityId) -> Option<&C> {
         world_fn!(self, get_component(id))
     }
@@ -83,4 +129,22 @@ impl EcsWorld for World {
     fn get_component_mut<C: 'static>(&mut self, id: EntityId) -> Option<&mut C> {
         world_fn!(self, get_component_mut(id))
     }
-}
\ No newline at end of file
+
+    fn has_component<C: 'static>(&self, id: EntityId) -> bool {
+        match self {
+            World::SparseSet(e) => e.has_component::<C>(id),
+            World::ArchetypeWorld(e) => e.has_component::<C>(id),
+        }
+    }
+
+    fn component_ticks<C: 'static>(&self, id: EntityId) -> Option<&ComponentTicks> {
+        match self {
+            World::SparseSet(e) => e.component_ticks::<C>(id),
+            World::ArchetypeWorld(e) => e.component_ticks::<C>(id),
+        }
+    }
+
+    fn change_tick(&self) -> Tick {
+        world_fn!(self, change_tick())
+    }
+}
@@ -0,0 +1,84 @@
+use crossbeam_channel::Sender;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed set of threads that run the systems of a [`Schedule`](crate::game::ecs::schedule::Schedule) stage.
+/// The threads are started once and live as long as the pool, so running a stage does not spawn anything.
+pub(crate) struct WorkerPool {
+    sender: Option<Sender<Job>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub(crate) fn new(threads: usize) -> Self {
+        let (sender, receiver) = crossbeam_channel::unbounded::<Job>();
+        let threads = (0..threads.max(1))
+            .map(|i| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("ecs-worker-{i}"))
+                    .spawn(move || {
+                        while let Ok(job) = receiver.recv() {
+                            job();
+                        }
+                    })
+                    .expect("Could not start ecs worker thread")
+            })
+            .collect();
+        Self {
+            sender: Some(sender),
+            threads,
+        }
+    }
+
+    pub(crate) fn threads(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Runs every task on the pool and blocks until all of them returned, so the tasks may borrow from the caller.
+    /// If a task panics, the panic is resumed on the calling thread once the other tasks finished.
+    pub(crate) fn scope<'a>(&self, tasks: Vec<Box<dyn FnOnce() + Send + 'a>>) {
+        let count = tasks.len();
+        let (done_sender, done_receiver) = crossbeam_channel::bounded::<Result<(), Box<dyn Any + Send>>>(count);
+        for task in tasks {
+            let done = done_sender.clone();
+            let job: Box<dyn FnOnce() + Send + 'a> = Box::new(move || {
+                let _ = done.send(panic::catch_unwind(AssertUnwindSafe(task)));
+            });
+            // SAFETY: this function does not return before every job reported back through `done`, which only
+            // happens after the task ran, so nothing the task borrows can go away while it runs
+            let job: Job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'a>, Job>(job) };
+            if let Some(sender) = &self.sender
+                && let Err(rejected) = sender.send(job)
+            {
+                // the workers are gone, run it here instead
+                (rejected.into_inner())();
+            }
+        }
+        drop(done_sender);
+
+        let mut panic = None;
+        for _ in 0..count {
+            match done_receiver.recv() {
+                Ok(Ok(())) => {}
+                Ok(Err(payload)) => panic = panic.or(Some(payload)),
+                Err(_) => break,
+            }
+        }
+        if let Some(payload) = panic {
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.sender = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,438 @@
+use crate::game::ecs::entity::EntityId;
+use crate::game::ecs::mem::column::Column;
+use crate::game::ecs::schedule::Access;
+use crate::game::ecs::tick::Tick;
+use crate::game::ecs::world::arch::Archetype;
+use crate::game::ecs::world::EcsWorld;
+use crate::game::ecs::World;
+use std::any::{type_name, TypeId};
+use std::marker::PhantomData;
+
+/// Something a [`Query`] can fetch for a single entity, like `&T`, `&mut T` or `Option<&T>`.
+/// Tuples of query data fetch every element and only match if every element matched.
+pub trait QueryData {
+    type Item<'w>;
+    /// The columns of one archetype the data is fetched from.
+    type Fetch<'w>;
+
+    fn access(access: &mut Access);
+
+    /// Fetches the data of entity `id`, marking mutably fetched components as changed at `tick`.
+    ///
+    /// # Safety
+    /// The caller has to make sure no component handed out mutably is borrowed anywhere else.
+    unsafe fn fetch<'w>(world: &'w World, id: EntityId, tick: Tick) -> Option<Self::Item<'w>>;
+
+    /// Looks up the columns of `archetype`, or returns None if its entities never match.
+    fn init_fetch<'w>(archetype: &'w Archetype) -> Option<Self::Fetch<'w>>;
+
+    /// Fetches the data of the entity at `row` of the archetype `fetch` was created for.
+    ///
+    /// # Safety
+    /// `row` must be a row of that archetype, and no component handed out mutably may be borrowed anywhere else.
+    unsafe fn fetch_row<'w>(fetch: &Self::Fetch<'w>, row: usize, tick: Tick) -> Self::Item<'w>;
+}
+
+/// Query data that only ever hands out shared references.
+///
+/// # Safety
+/// Implementors must not return mutable references from [`QueryData::fetch`].
+pub unsafe trait ReadOnlyQueryData: QueryData {}
+
+impl<T: 'static> QueryData for &T {
+    type Item<'w> = &'w T;
+    type Fetch<'w> = &'w Column;
+
+    fn access(access: &mut Access) {
+        access.add_read(TypeId::of::<T>());
+    }
+
+    unsafe fn fetch<'w>(world: &'w World, id: EntityId, _: Tick) -> Option<Self::Item<'w>> {
+        world.get_component::<T>(id)
+    }
+
+    fn init_fetch<'w>(archetype: &'w Archetype) -> Option<Self::Fetch<'w>> {
+        archetype.column(TypeId::of::<T>())
+    }
+
+    unsafe fn fetch_row<'w>(fetch: &Self::Fetch<'w>, row: usize, _: Tick) -> Self::Item<'w> {
+        unsafe { read_row(fetch, row) }
+    }
+}
+
+unsafe impl<T: 'static> ReadOnlyQueryData for &T {}
+
+impl<T: 'static> QueryData for &mut T {
+    type Item<'w> = &'w mut T;
+    type Fetch<'w> = &'w Column;
+
+    fn access(access: &mut Access) {
+        access.add_write(TypeId::of::<T>());
+    }
+
+    unsafe fn fetch<'w>(world: &'w World, id: EntityId, tick: Tick) -> Option<Self::Item<'w>> {
+        unsafe { world.get_component_mut_unchecked::<T>(id, tick) }
+    }
+
+    fn init_fetch<'w>(archetype: &'w Archetype) -> Option<Self::Fetch<'w>> {
+        archetype.column(TypeId::of::<T>())
+    }
+
+    unsafe fn fetch_row<'w>(fetch: &Self::Fetch<'w>, row: usize, tick: Tick) -> Self::Item<'w> {
+        unsafe { write_row(fetch, row, tick) }
+    }
+}
+
+impl<T: 'static> QueryData for Option<&T> {
+    type Item<'w> = Option<&'w T>;
+    type Fetch<'w> = Option<&'w Column>;
+
+    fn access(access: &mut Access) {
+        access.add_read(TypeId::of::<T>());
+    }
+
+    unsafe fn fetch<'w>(world: &'w World, id: EntityId, _: Tick) -> Option<Self::Item<'w>> {
+        Some(world.get_component::<T>(id))
+    }
+
+    fn init_fetch<'w>(archetype: &'w Archetype) -> Option<Self::Fetch<'w>> {
+        Some(archetype.column(TypeId::of::<T>()))
+    }
+
+    unsafe fn fetch_row<'w>(fetch: &Self::Fetch<'w>, row: usize, _: Tick) -> Self::Item<'w> {
+        fetch.map(|column| unsafe { read_row(column, row) })
+    }
+}
+
+unsafe impl<T: 'static> ReadOnlyQueryData for Option<&T> {}
+
+impl<T: 'static> QueryData for Option<&mut T> {
+    type Item<'w> = Option<&'w mut T>;
+    type Fetch<'w> = Option<&'w Column>;
+
+    fn access(access: &mut Access) {
+        access.add_write(TypeId::of::<T>());
+    }
+
+    unsafe fn fetch<'w>(world: &'w World, id: EntityId, tick: Tick) -> Option<Self::Item<'w>> {
+        Some(unsafe { world.get_component_mut_unchecked::<T>(id, tick) })
+    }
+
+    fn init_fetch<'w>(archetype: &'w Archetype) -> Option<Self::Fetch<'w>> {
+        Some(archetype.column(TypeId::of::<T>()))
+    }
+
+    unsafe fn fetch_row<'w>(fetch: &Self::Fetch<'w>, row: usize, tick: Tick) -> Self::Item<'w> {
+        fetch.map(|column| unsafe { write_row(column, row, tick) })
+    }
+}
+
+/// # Safety
+/// `row` must be in bounds and the column must store `T`.
+unsafe fn read_row<T: 'static>(column: &Column, row: usize) -> &T {
+    unsafe { &*(column.get_ptr(row) as *const T) }
+}
+
+/// Returns the component at `row` mutably and marks it as changed at `tick`.
+///
+/// # Safety
+/// `row` must be in bounds, the column must store `T` and the component must not be borrowed anywhere else.
+#[allow(clippy::mut_from_ref)]
+unsafe fn write_row<T: 'static>(column: &Column, row: usize, tick: Tick) -> &mut T {
+    if let Some(ticks) = column.get_ticks(row) {
+        ticks.set_changed(tick);
+    }
+    unsafe { &mut *(column.get_ptr(row) as *mut T) }
+}
+
+/// Decides which entities a [`Query`] visits, without fetching anything.
+/// Tuples of filters only match if every filter matches.
+pub trait QueryFilter {
+    /// The columns of one archetype the filter looks at.
+    type Fetch<'w>;
+
+    fn access(_access: &mut Access) {}
+
+    fn matches(world: &World, id: EntityId, last_run: Tick, this_run: Tick) -> bool;
+
+    /// Looks up the columns of `archetype`, or returns None if its entities never match.
+    fn init_fetch<'w>(archetype: &'w Archetype) -> Option<Self::Fetch<'w>>;
+
+    fn matches_row(fetch: &Self::Fetch<'_>, row: usize, last_run: Tick, this_run: Tick) -> bool;
+}
+
+impl QueryFilter for () {
+    type Fetch<'w> = ();
+
+    fn matches(_: &World, _: EntityId, _: Tick, _: Tick) -> bool {
+        true
+    }
+
+    fn init_fetch<'w>(_: &'w Archetype) -> Option<Self::Fetch<'w>> {
+        Some(())
+    }
+
+    fn matches_row(_: &Self::Fetch<'_>, _: usize, _: Tick, _: Tick) -> bool {
+        true
+    }
+}
+
+/// Only matches entities that have a `T` component.
+pub struct With<T>(PhantomData<T>);
+
+impl<T: 'static> QueryFilter for With<T> {
+    type Fetch<'w> = ();
+
+    fn matches(world: &World, id: EntityId, _: Tick, _: Tick) -> bool {
+        world.has_component::<T>(id)
+    }
+
+    fn init_fetch<'w>(archetype: &'w Archetype) -> Option<Self::Fetch<'w>> {
+        archetype.has(TypeId::of::<T>()).then_some(())
+    }
+
+    fn matches_row(_: &Self::Fetch<'_>, _: usize, _: Tick, _: Tick) -> bool {
+        true
+    }
+}
+
+/// Only matches entities that do not have a `T` component.
+pub struct Without<T>(PhantomData<T>);
+
+impl<T: 'static> QueryFilter for Without<T> {
+    type Fetch<'w> = ();
+
+    fn matches(world: &World, id: EntityId, _: Tick, _: Tick) -> bool {
+        !world.has_component::<T>(id)
+    }
+
+    fn init_fetch<'w>(archetype: &'w Archetype) -> Option<Self::Fetch<'w>> {
+        (!archetype.has(TypeId::of::<T>())).then_some(())
+    }
+
+    fn matches_row(_: &Self::Fetch<'_>, _: usize, _: Tick, _: Tick) -> bool {
+        true
+    }
+}
+
+/// Only matches entities whose `T` component was added since the query last ran.
+pub struct Added<T>(PhantomData<T>);
+
+impl<T: 'static> QueryFilter for Added<T> {
+    type Fetch<'w> = &'w Column;
+
+    fn access(access: &mut Access) {
+        access.add_read(TypeId::of::<T>());
+    }
+
+    fn matches(world: &World, id: EntityId, last_run: Tick, this_run: Tick) -> bool {
+        world
+            .component_ticks::<T>(id)
+            .is_some_and(|ticks| ticks.is_added(last_run, this_run))
+    }
+
+    fn init_fetch<'w>(archetype: &'w Archetype) -> Option<Self::Fetch<'w>> {
+        archetype.column(TypeId::of::<T>())
+    }
+
+    fn matches_row(fetch: &Self::Fetch<'_>, row: usize, last_run: Tick, this_run: Tick) -> bool {
+        fetch
+            .get_ticks(row)
+            .is_some_and(|ticks| ticks.is_added(last_run, this_run))
+    }
+}
+
+/// Only matches entities whose `T` component was added or mutably accessed since the query last ran.
+pub struct Changed<T>(PhantomData<T>);
+
+impl<T: 'static> QueryFilter for Changed<T> {
+    type Fetch<'w> = &'w Column;
+
+    fn access(access: &mut Access) {
+        access.add_read(TypeId::of::<T>());
+    }
+
+    fn matches(world: &World, id: EntityId, last_run: Tick, this_run: Tick) -> bool {
+        world
+            .component_ticks::<T>(id)
+            .is_some_and(|ticks| ticks.is_changed(last_run, this_run))
+    }
+
+    fn init_fetch<'w>(archetype: &'w Archetype) -> Option<Self::Fetch<'w>> {
+        archetype.column(TypeId::of::<T>())
+    }
+
+    fn matches_row(fetch: &Self::Fetch<'_>, row: usize, last_run: Tick, this_run: Tick) -> bool {
+        fetch
+            .get_ticks(row)
+            .is_some_and(|ticks| ticks.is_changed(last_run, this_run))
+    }
+}
+
+macro_rules! impl_query_tuples {
+    ($first:ident,) => {
+        impl_query_tuples!(@impl $first,);
+    };
+
+    ($first:ident, $($rest:ident, )*) => {
+        impl_query_tuples!($($rest,)*);
+        impl_query_tuples!(@impl $first, $($rest,)*);
+    };
+
+    (@impl $($name:ident, )*) => {
+        #[allow(non_snake_case)]
+        impl<$($name: QueryData),*> QueryData for ($($name,)*) {
+            type Item<'w> = ($($name::Item<'w>,)*);
+            type Fetch<'w> = ($($name::Fetch<'w>,)*);
+
+            fn access(access: &mut Access) {
+                $(
+                    let mut element = Access::new();
+                    $name::access(&mut element);
+                    if access.conflicts_with(&element) {
+                        panic!("Query data {} fetches a component mutably while also fetching it elsewhere", type_name::<Self>());
+                    }
+                    *access = std::mem::take(access).merge(&element);
+                )*
+            }
+
+            unsafe fn fetch<'w>(world: &'w World, id: EntityId, tick: Tick) -> Option<Self::Item<'w>> {
+                unsafe { Some(($( $name::fetch(world, id, tick)?, )*)) }
+            }
+
+            fn init_fetch<'w>(archetype: &'w Archetype) -> Option<Self::Fetch<'w>> {
+                Some(($( $name::init_fetch(archetype)?, )*))
+            }
+
+            unsafe fn fetch_row<'w>(fetch: &Self::Fetch<'w>, row: usize, tick: Tick) -> Self::Item<'w> {
+                let ($($name,)*) = fetch;
+                unsafe { ($( $name::fetch_row($name, row, tick), )*) }
+            }
+        }
+
+        unsafe impl<$($name: ReadOnlyQueryData),*> ReadOnlyQueryData for ($($name,)*) {}
+
+        impl<$($name: QueryFilter),*> QueryFilter for ($($name,)*) {
+            type Fetch<'w> = ($($name::Fetch<'w>,)*);
+
+            fn access(access: &mut Access) {
+                $( $name::access(access); )*
+            }
+
+            fn matches(world: &World, id: EntityId, last_run: Tick, this_run: Tick) -> bool {
+                $( $name::matches(world, id, last_run, this_run) )&&*
+            }
+
+            fn init_fetch<'w>(archetype: &'w Archetype) -> Option<Self::Fetch<'w>> {
+                Some(($( $name::init_fetch(archetype)?, )*))
+            }
+
+            fn matches_row(fetch: &Self::Fetch<'_>, row: usize, last_run: Tick, this_run: Tick) -> bool {
+                let ($($name,)*) = fetch;
+                $( $name::matches_row($name, row, last_run, this_run) )&&*
+            }
+        }
+    };
+}
+
+impl_query_tuples!(C1, C2, C3, C4, C5, C6, C7, C8, C9, C10, C11, C12,);
+
+/// A filtered view of all entities matching `Q` and `F`, e.g.
+/// `Query<(&Transform, &mut AABBCollider, Option<&Static>), Without<Trigger>>`.
+///
+/// A query remembers when it last ran, so [`Added`] and [`Changed`] match everything that happened in between.
+/// In an archetype world, a query run only visits the tables whose components can match,
+/// in a sparse set world it visits all entities.
+pub struct Query<Q: QueryData, F: QueryFilter = ()> {
+    last_run: Tick,
+    access: Access,
+    phantom: PhantomData<fn() -> (Q, F)>,
+}
+
+impl<Q: QueryData, F: QueryFilter> Query<Q, F> {
+    /// # Panics
+    /// If `Q` fetches a component mutably more than once, or both mutably and immutably.
+    pub fn new() -> Self {
+        let mut access = Access::new();
+        Q::access(&mut access);
+        F::access(&mut access);
+        Self {
+            last_run: 0,
+            access,
+            phantom: PhantomData::default(),
+        }
+    }
+
+    /// The components this query reads and writes, for use in [`ScheduledSystem::access`](crate::game::ecs::schedule::ScheduledSystem::access).
+    pub fn access(&self) -> Access {
+        self.access.clone()
+    }
+
+    pub fn last_run(&self) -> Tick {
+        self.last_run
+    }
+
+    pub fn iter<'w>(&mut self, world: &'w World) -> impl Iterator<Item = (EntityId, Q::Item<'w>)> + use<'w, Q, F>
+    where
+        Q: ReadOnlyQueryData,
+    {
+        // SAFETY: read only queries never hand out mutable references
+        unsafe { self.iter_unchecked(world) }
+    }
+
+    pub fn iter_mut<'w>(&mut self, world: &'w mut World) -> impl Iterator<Item = (EntityId, Q::Item<'w>)> + use<'w, Q, F> {
+        // SAFETY: the world is borrowed mutably for as long as the iterator lives
+        unsafe { self.iter_unchecked(world) }
+    }
+
+    /// Fetches a single entity, ignoring change filters relative to the previous run.
+    pub fn get<'w>(&self, world: &'w World, id: EntityId) -> Option<Q::Item<'w>>
+    where
+        Q: ReadOnlyQueryData,
+    {
+        let tick = world.change_tick();
+        if !F::matches(world, id, self.last_run, tick) {
+            return None;
+        }
+        unsafe { Q::fetch(world, id, tick) }
+    }
+
+    /// # Safety
+    /// The caller has to make sure that no component this query writes is borrowed elsewhere while the iterator lives,
+    /// and that `Q` does not fetch the same component mutably twice.
+    #[auto_enums::auto_enum(Iterator)]
+    pub unsafe fn iter_unchecked<'w>(
+        &mut self,
+        world: &'w World,
+    ) -> impl Iterator<Item = (EntityId, Q::Item<'w>)> + use<'w, Q, F> {
+        let last_run = self.last_run;
+        let this_run = world.advance_tick();
+        self.last_run = this_run;
+        match world {
+            World::SparseSet(_) => world.entities().filter_map(move |id| {
+                if !F::matches(world, id, last_run, this_run) {
+                    return None;
+                }
+                unsafe { Q::fetch(world, id, this_run) }.map(|item| (id, item))
+            }),
+            World::ArchetypeWorld(arch) => arch
+                .archetypes()
+                .iter()
+                .filter_map(|archetype| Some((archetype, Q::init_fetch(archetype)?, F::init_fetch(archetype)?)))
+                .flat_map(move |(archetype, fetch, filter)| {
+                    archetype.entities().iter().enumerate().filter_map(move |(row, id)| {
+                        if !F::matches_row(&filter, row, last_run, this_run) {
+                            return None;
+                        }
+                        Some((*id, unsafe { Q::fetch_row(&fetch, row, this_run) }))
+                    })
+                }),
+        }
+    }
+}
+
+impl<Q: QueryData, F: QueryFilter> Default for Query<Q, F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
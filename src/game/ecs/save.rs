@@ -0,0 +1,247 @@
+use crate::game::ecs::entity::{reserve_ids_through, EntityId, MapEntities};
+use crate::game::ecs::world::EcsWorld;
+use crate::game::ecs::{EcsBackend, World};
+use bytebuffer::ByteBuffer;
+use hashbrown::HashMap;
+use itertools::Itertools;
+use log::warn;
+use mvutils::save::{Loader, Savable, Saver};
+use std::any::TypeId;
+
+type SaveFn = fn(&World, EntityId, &mut ByteBuffer) -> bool;
+type LoadFn = fn(&mut World, EntityId, &mut ByteBuffer) -> Result<(), String>;
+type RemoveFn = fn(&mut World, EntityId);
+type MapFn = fn(&mut World, EntityId, &mut dyn FnMut(EntityId) -> EntityId);
+
+struct RegisteredComponent {
+    name: String,
+    type_id: TypeId,
+    save: SaveFn,
+    load: LoadFn,
+    remove: RemoveFn,
+    map: Option<MapFn>,
+}
+
+fn save_component<C: Savable + 'static>(world: &World, id: EntityId, buffer: &mut ByteBuffer) -> bool {
+    if let Some(c) = world.get_component::<C>(id) {
+        c.save(buffer);
+        true
+    } else {
+        false
+    }
+}
+
+fn load_component<C: Savable + 'static>(world: &mut World, id: EntityId, buffer: &mut ByteBuffer) -> Result<(), String> {
+    let c = C::load(buffer)?;
+    world.set_component(id, c);
+    Ok(())
+}
+
+fn remove_registered<C: 'static>(world: &mut World, id: EntityId) {
+    world.remove_component::<C>(id);
+}
+
+fn map_component<C: MapEntities + 'static>(world: &mut World, id: EntityId, map: &mut dyn FnMut(EntityId) -> EntityId) {
+    if let Some(c) = world.get_component_mut::<C>(id) {
+        c.map_entities(map);
+    }
+}
+
+/// The set of component types that are written to and read from world snapshots.
+/// Components are identified by the name they were registered with, so that snapshots stay readable across builds.
+/// Components that are not registered are skipped when saving.
+pub struct ComponentRegistry {
+    components: Vec<RegisteredComponent>,
+    by_name: HashMap<String, usize>,
+}
+
+impl ComponentRegistry {
+    pub fn new() -> Self {
+        Self {
+            components: Vec::new(),
+            by_name: HashMap::new(),
+        }
+    }
+
+    /// Opts the component type `C` into serialization. Registering the same type or name twice replaces the old entry.
+    pub fn register<C: Savable + 'static>(&mut self, name: &str) -> &mut Self {
+        self.insert(RegisteredComponent {
+            name: name.to_string(),
+            type_id: TypeId::of::<C>(),
+            save: save_component::<C>,
+            load: load_component::<C>,
+            remove: remove_registered::<C>,
+            map: None,
+        })
+    }
+
+    /// Like [`ComponentRegistry::register`], for components that refer to other entities.
+    /// Snapshots keep entity ids as they are, but a [`Replica`](crate::net::replication::Replica) translates the
+    /// references to its own ids with [`MapEntities`].
+    pub fn register_mapped<C: Savable + MapEntities + 'static>(&mut self, name: &str) -> &mut Self {
+        self.insert(RegisteredComponent {
+            name: name.to_string(),
+            type_id: TypeId::of::<C>(),
+            save: save_component::<C>,
+            load: load_component::<C>,
+            remove: remove_registered::<C>,
+            map: Some(map_component::<C>),
+        })
+    }
+
+    fn insert(&mut self, component: RegisteredComponent) -> &mut Self {
+        let existing = self
+            .components
+            .iter()
+            .position(|c| c.type_id == component.type_id || c.name == component.name);
+        if let Some(idx) = existing {
+            self.by_name.remove(&self.components[idx].name);
+            self.by_name.insert(component.name.clone(), idx);
+            self.components[idx] = component;
+        } else {
+            self.by_name.insert(component.name.clone(), self.components.len());
+            self.components.push(component);
+        }
+        self
+    }
+
+    pub fn is_registered<C: 'static>(&self) -> bool {
+        self.components.iter().any(|c| c.type_id == TypeId::of::<C>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.components.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    pub(crate) fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.components.iter().map(|c| c.name.as_str())
+    }
+
+    pub(crate) fn index_of(&self, name: &str) -> Option<usize> {
+        self.by_name.get(name).copied()
+    }
+
+    /// Writes component number `idx` of `id` to `buffer`. Returns false if the entity does not have it.
+    pub(crate) fn save(&self, idx: usize, world: &World, id: EntityId, buffer: &mut ByteBuffer) -> bool {
+        (self.components[idx].save)(world, id, buffer)
+    }
+
+    /// Reads component number `idx` from `buffer` and sets it on `id`, translating entity references with `map`.
+    pub(crate) fn load(
+        &self,
+        idx: usize,
+        world: &mut World,
+        id: EntityId,
+        buffer: &mut ByteBuffer,
+        map: &mut dyn FnMut(EntityId) -> EntityId,
+    ) -> Result<(), String> {
+        let component = &self.components[idx];
+        (component.load)(world, id, buffer)
+            .map_err(|e| format!("Could not load component '{}' of entity {id}: {e}", component.name))?;
+        if let Some(map_fn) = component.map {
+            map_fn(world, id, map);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self, idx: usize, world: &mut World, id: EntityId) {
+        (self.components[idx].remove)(world, id);
+    }
+}
+
+impl Default for ComponentRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl World {
+    /// Writes every entity and all of its registered components to `buffer`.
+    ///
+    /// Layout: the registered component names, then every entity id (sorted) followed by its components,
+    /// each one prefixed with its index in the name table and its length in bytes.
+    pub fn save_snapshot(&self, registry: &ComponentRegistry, buffer: &mut ByteBuffer) {
+        buffer.push_u32(registry.components.len() as u32);
+        for component in &registry.components {
+            buffer.push_string(&component.name);
+        }
+
+        let entities = self.entities().sorted_unstable().dedup().collect_vec();
+        buffer.push_u64(entities.len() as u64);
+
+        let mut component_buffer = ByteBuffer::new();
+        component_buffer.set_endian(buffer.endian());
+        for id in entities {
+            let mut saved = Vec::new();
+            for (idx, component) in registry.components.iter().enumerate() {
+                component_buffer.clear();
+                if (component.save)(self, id, &mut component_buffer) {
+                    saved.push((idx as u32, component_buffer.as_bytes().to_vec()));
+                }
+            }
+
+            buffer.push_u64(id);
+            buffer.push_u32(saved.len() as u32);
+            for (idx, bytes) in saved {
+                buffer.push_u32(idx);
+                buffer.push_u32(bytes.len() as u32);
+                buffer.push_bytes(&bytes);
+            }
+        }
+    }
+
+    /// Reads a snapshot written by [`World::save_snapshot`] into this world.
+    /// Entities already in the world keep their other components, but loaded components overwrite existing ones.
+    /// Components whose name is not in `registry` are skipped.
+    pub fn load_snapshot(&mut self, registry: &ComponentRegistry, buffer: &mut ByteBuffer) -> Result<(), String> {
+        let name_count = buffer.pop_u32().ok_or("Snapshot ended before the component table")?;
+        // the count is not trusted, every name takes at least its length prefix
+        let mut table = Vec::with_capacity((name_count as usize).min(buffer.len() / 4));
+        for _ in 0..name_count {
+            let name = buffer.pop_string().ok_or("Snapshot ended inside the component table")?;
+            let registered = registry.by_name.get(&name).map(|idx| &registry.components[*idx]);
+            if registered.is_none() {
+                warn!("Snapshot contains component '{name}' which is not registered, skipping it");
+            }
+            table.push(registered);
+        }
+
+        let entity_count = buffer.pop_u64().ok_or("Snapshot ended before the entity count")?;
+        let mut max_id = 0;
+        for _ in 0..entity_count {
+            let id = buffer.pop_u64().ok_or("Snapshot ended before an entity id")?;
+            max_id = max_id.max(id);
+            self.create_entity(id);
+
+            let component_count = buffer.pop_u32().ok_or("Snapshot ended before a component count")?;
+            for _ in 0..component_count {
+                let idx = buffer.pop_u32().ok_or("Snapshot ended before a component index")? as usize;
+                let len = buffer.pop_u32().ok_or("Snapshot ended before a component length")? as usize;
+                let bytes = buffer.pop_bytes(len).ok_or("Snapshot ended inside a component")?;
+                let registered = *table
+                    .get(idx)
+                    .ok_or_else(|| format!("Component index {idx} is out of the component table"))?;
+                if let Some(registered) = registered {
+                    let mut component_buffer = ByteBuffer::from_vec(bytes);
+                    component_buffer.set_endian(buffer.endian());
+                    (registered.load)(self, id, &mut component_buffer)
+                        .map_err(|e| format!("Could not load component '{}' of entity {id}: {e}", registered.name))?;
+                }
+            }
+        }
+
+        reserve_ids_through(max_id);
+        Ok(())
+    }
+
+    /// Creates a new world with the given backend from a snapshot written by [`World::save_snapshot`].
+    pub fn from_snapshot(backend: EcsBackend, registry: &ComponentRegistry, buffer: &mut ByteBuffer) -> Result<World, String> {
+        let mut world = World::new(backend);
+        world.load_snapshot(registry, buffer)?;
+        Ok(world)
+    }
+}
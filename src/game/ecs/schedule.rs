@@ -0,0 +1,409 @@
+use crate::game::ecs::command::Commands;
+use crate::game::ecs::entity::EntityId;
+use crate::game::ecs::pool::WorkerPool;
+use crate::game::ecs::query::{Query, QueryData, QueryFilter, ReadOnlyQueryData};
+use crate::game::ecs::tick::ComponentTicks;
+use crate::game::ecs::world::EcsWorld;
+use crate::game::ecs::World;
+use hashbrown::HashMap;
+use std::any::{type_name, TypeId};
+
+/// The component types a system reads and writes. Two systems whose accesses do not conflict
+/// can run at the same time.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Access {
+    reads: Vec<TypeId>,
+    writes: Vec<TypeId>,
+}
+
+impl Access {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read<T: 'static>(mut self) -> Self {
+        self.add_read(TypeId::of::<T>());
+        self
+    }
+
+    pub fn write<T: 'static>(mut self) -> Self {
+        self.add_write(TypeId::of::<T>());
+        self
+    }
+
+    pub fn add_read(&mut self, ty: TypeId) {
+        if !self.reads.contains(&ty) && !self.writes.contains(&ty) {
+            self.reads.push(ty);
+        }
+    }
+
+    pub fn add_write(&mut self, ty: TypeId) {
+        self.reads.retain(|t| *t != ty);
+        if !self.writes.contains(&ty) {
+            self.writes.push(ty);
+        }
+    }
+
+    /// Adds everything `other` accesses to this access.
+    pub fn merge(mut self, other: &Access) -> Self {
+        for ty in &other.writes {
+            self.add_write(*ty);
+        }
+        for ty in &other.reads {
+            self.add_read(*ty);
+        }
+        self
+    }
+
+    pub fn reads(&self) -> &[TypeId] {
+        &self.reads
+    }
+
+    pub fn writes(&self) -> &[TypeId] {
+        &self.writes
+    }
+
+    /// Returns true if the component is read or written.
+    pub fn can_read(&self, ty: TypeId) -> bool {
+        self.reads.contains(&ty) || self.writes.contains(&ty)
+    }
+
+    pub fn can_write(&self, ty: TypeId) -> bool {
+        self.writes.contains(&ty)
+    }
+
+    /// Returns true if everything `other` reads or writes is allowed by this access.
+    pub fn covers(&self, other: &Access) -> bool {
+        other.writes.iter().all(|ty| self.can_write(*ty)) && other.reads.iter().all(|ty| self.can_read(*ty))
+    }
+
+    /// Returns true if one of the accesses writes a component the other one reads or writes.
+    pub fn conflicts_with(&self, other: &Access) -> bool {
+        self.writes.iter().any(|ty| other.reads.contains(ty) || other.writes.contains(ty))
+            || other.writes.iter().any(|ty| self.reads.contains(ty))
+    }
+}
+
+/// The world as a running system sees it. Other systems may use the same world on other threads at the same time,
+/// so every component is only handed out if the [`Access`] of the system allows it, and fetching anything else panics.
+/// Entities cannot be created or destroyed through it, that goes through the [`Commands`] of the system.
+pub struct SystemWorld<'w> {
+    world: &'w World,
+    access: &'w Access,
+}
+
+impl<'w> SystemWorld<'w> {
+    /// A view for running a system outside a [`Schedule`], the world is borrowed exclusively.
+    pub fn new(world: &'w mut World, access: &'w Access) -> Self {
+        Self { world, access }
+    }
+
+    /// # Safety
+    /// No other view with an access conflicting with `access` may use the world at the same time,
+    /// and the world must not be changed in any other way while the view lives.
+    unsafe fn shared(world: &'w World, access: &'w Access) -> Self {
+        Self { world, access }
+    }
+
+    pub fn access(&self) -> &Access {
+        self.access
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = EntityId> + '_ {
+        self.world.entities()
+    }
+
+    pub fn has_component<C: 'static>(&self, id: EntityId) -> bool {
+        self.world.has_component::<C>(id)
+    }
+
+    pub fn get_component<C: 'static>(&self, id: EntityId) -> Option<&C> {
+        self.check_read::<C>();
+        self.world.get_component::<C>(id)
+    }
+
+    pub fn get_component_mut<C: 'static>(&mut self, id: EntityId) -> Option<&mut C> {
+        self.check_write::<C>();
+        // SAFETY: no other system of the stage touches C, and the view is borrowed mutably while the result lives
+        unsafe { self.world.get_component_mut_unchecked::<C>(id, self.world.change_tick()) }
+    }
+
+    pub fn component_ticks<C: 'static>(&self, id: EntityId) -> Option<&ComponentTicks> {
+        self.check_read::<C>();
+        self.world.component_ticks::<C>(id)
+    }
+
+    pub fn query<'s, Q: ReadOnlyQueryData, F: QueryFilter>(
+        &'s self,
+        query: &mut Query<Q, F>,
+    ) -> impl Iterator<Item = (EntityId, Q::Item<'s>)> + use<'s, Q, F> {
+        self.check_query(&query.access());
+        // SAFETY: read only queries never hand out mutable references
+        unsafe { query.iter_unchecked(self.world) }
+    }
+
+    pub fn query_mut<'s, Q: QueryData, F: QueryFilter>(
+        &'s mut self,
+        query: &mut Query<Q, F>,
+    ) -> impl Iterator<Item = (EntityId, Q::Item<'s>)> + use<'s, Q, F> {
+        self.check_query(&query.access());
+        // SAFETY: the view is borrowed mutably for as long as the iterator lives
+        unsafe { query.iter_unchecked(self.world) }
+    }
+
+    fn check_read<C: 'static>(&self) {
+        if !self.access.can_read(TypeId::of::<C>()) {
+            panic!("System read {} without declaring it in its access", type_name::<C>());
+        }
+    }
+
+    fn check_write<C: 'static>(&self) {
+        if !self.access.can_write(TypeId::of::<C>()) {
+            panic!("System wrote {} without declaring it in its access", type_name::<C>());
+        }
+    }
+
+    fn check_query(&self, access: &Access) {
+        if !self.access.covers(access) {
+            panic!("System ran a query with an access it did not declare: {access:?}");
+        }
+    }
+}
+
+/// A system that can be driven by a [`Schedule`].
+///
+/// While running, a system may only touch the components it declared in [`ScheduledSystem::access`],
+/// as other systems may be iterating the same world on other threads. The [`SystemWorld`] enforces this.
+/// Structural changes go into `commands` instead, which are applied at the end of the stage the system ran in.
+pub trait ScheduledSystem: Send {
+    fn access(&self) -> Access;
+
+    fn run(&mut self, world: &mut SystemWorld, commands: &mut Commands, dt: f64);
+}
+
+struct FnSystem<F> {
+    access: Access,
+    function: F,
+}
+
+impl<F: FnMut(&mut SystemWorld, &mut Commands, f64) + Send> ScheduledSystem for FnSystem<F> {
+    fn access(&self) -> Access {
+        self.access.clone()
+    }
+
+    fn run(&mut self, world: &mut SystemWorld, commands: &mut Commands, dt: f64) {
+        (self.function)(world, commands, dt);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleError {
+    /// A label was used for more than one system.
+    DuplicateLabel(String),
+    /// An ordering constraint names a system that was never added.
+    UnknownSystem(String),
+    /// The ordering constraints contradict each other. Contains the labels of the systems involved.
+    Cycle(Vec<String>),
+}
+
+struct Node {
+    label: String,
+    system: Box<dyn ScheduledSystem>,
+    /// The access of the system when the schedule was built.
+    access: Access,
+    commands: Commands,
+}
+
+/// Runs a set of systems, executing systems with non conflicting component access in parallel on a pool of worker threads.
+///
+/// Conflicting systems keep the order they were added in, unless reordered by [`Schedule::before`] or [`Schedule::after`].
+/// The systems are grouped into stages, where every stage only depends on stages before it.
+/// The end of every stage is a sync point: the command buffers of its systems are applied in the order the systems were added.
+pub struct Schedule {
+    nodes: Vec<Node>,
+    constraints: Vec<(String, String)>,
+    stages: Option<Vec<Vec<usize>>>,
+    threads: usize,
+    /// Started the first time a stage runs systems in parallel.
+    pool: Option<WorkerPool>,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        Self::with_threads(threads)
+    }
+
+    pub fn with_threads(threads: usize) -> Self {
+        Self {
+            nodes: Vec::new(),
+            constraints: Vec::new(),
+            stages: None,
+            threads: threads.max(1),
+            pool: None,
+        }
+    }
+
+    pub fn add_system<S: ScheduledSystem + 'static>(&mut self, label: &str, system: S) -> &mut Self {
+        self.nodes.push(Node {
+            label: label.to_string(),
+            system: Box::new(system),
+            access: Access::new(),
+            commands: Commands::new(),
+        });
+        self.stages = None;
+        self
+    }
+
+    pub fn add_fn<F: FnMut(&mut SystemWorld, &mut Commands, f64) + Send + 'static>(&mut self, label: &str, access: Access, function: F) -> &mut Self {
+        self.add_system(label, FnSystem { access, function })
+    }
+
+    /// Makes sure the system `first` always finishes before `then` starts.
+    pub fn before(&mut self, first: &str, then: &str) -> &mut Self {
+        self.constraints.push((first.to_string(), then.to_string()));
+        self.stages = None;
+        self
+    }
+
+    /// Makes sure the system `then` only starts after `first` has finished.
+    pub fn after(&mut self, then: &str, first: &str) -> &mut Self {
+        self.before(first, then)
+    }
+
+    /// The labels of the systems in every stage, in execution order.
+    pub fn stages(&mut self) -> Result<Vec<Vec<&str>>, ScheduleError> {
+        self.build()?;
+        let stages = self.stages.as_ref().map(Vec::as_slice).unwrap_or_default();
+        Ok(stages
+            .iter()
+            .map(|stage| stage.iter().map(|i| self.nodes[*i].label.as_str()).collect())
+            .collect())
+    }
+
+    /// Builds the dependency graph and the stages. Called by [`Schedule::run`] whenever the systems changed.
+    pub fn build(&mut self) -> Result<(), ScheduleError> {
+        if self.stages.is_some() {
+            return Ok(());
+        }
+
+        for node in &mut self.nodes {
+            node.access = node.system.access();
+        }
+
+        let n = self.nodes.len();
+        let mut labels = HashMap::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if labels.insert(node.label.as_str(), i).is_some() {
+                return Err(ScheduleError::DuplicateLabel(node.label.clone()));
+            }
+        }
+
+        let accesses: Vec<&Access> = self.nodes.iter().map(|node| &node.access).collect();
+        let mut edges = vec![Vec::new(); n];
+        let mut in_degree = vec![0usize; n];
+
+        let mut explicit = Vec::with_capacity(self.constraints.len());
+        for (first, then) in &self.constraints {
+            let first = *labels.get(first.as_str()).ok_or_else(|| ScheduleError::UnknownSystem(first.clone()))?;
+            let then = *labels.get(then.as_str()).ok_or_else(|| ScheduleError::UnknownSystem(then.clone()))?;
+            if !explicit.contains(&(first, then)) {
+                explicit.push((first, then));
+                edges[first].push(then);
+                in_degree[then] += 1;
+            }
+        }
+
+        for i in 0..n {
+            for j in (i + 1)..n {
+                let ordered = explicit.contains(&(i, j)) || explicit.contains(&(j, i));
+                if !ordered && accesses[i].conflicts_with(accesses[j]) {
+                    edges[i].push(j);
+                    in_degree[j] += 1;
+                }
+            }
+        }
+
+        // Kahn's algorithm, one level of the topological order is one stage
+        let mut stages = Vec::new();
+        let mut current: Vec<usize> = (0..n).filter(|i| in_degree[*i] == 0).collect();
+        let mut visited = 0;
+        while !current.is_empty() {
+            visited += current.len();
+            let mut next = Vec::new();
+            for i in &current {
+                for j in &edges[*i] {
+                    in_degree[*j] -= 1;
+                    if in_degree[*j] == 0 {
+                        next.push(*j);
+                    }
+                }
+            }
+            next.sort_unstable();
+            stages.push(current);
+            current = next;
+        }
+
+        if visited < n {
+            let cycle = (0..n)
+                .filter(|i| in_degree[*i] > 0)
+                .map(|i| self.nodes[i].label.clone())
+                .collect();
+            return Err(ScheduleError::Cycle(cycle));
+        }
+
+        self.stages = Some(stages);
+        Ok(())
+    }
+
+    /// Runs every system once. Stages run one after another, the systems inside a stage are spread over the worker threads.
+    pub fn run(&mut self, world: &mut World, dt: f64) -> Result<(), ScheduleError> {
+        self.build()?;
+        let Some(stages) = &self.stages else {
+            return Ok(());
+        };
+
+        let mut nodes: Vec<Option<&mut Node>> = self.nodes.iter_mut().map(Some).collect();
+
+        for stage in stages {
+            let mut batch: Vec<&mut Node> = stage.iter().filter_map(|i| nodes[*i].take()).collect();
+
+            if batch.len() == 1 || self.threads == 1 {
+                for node in batch.iter_mut() {
+                    let mut view = SystemWorld::new(world, &node.access);
+                    node.system.run(&mut view, &mut node.commands, dt);
+                }
+            } else {
+                let pool = self.pool.get_or_insert_with(|| WorkerPool::new(self.threads));
+                let shared: &World = world;
+                let chunk_size = batch.len().div_ceil(pool.threads());
+                let tasks: Vec<Box<dyn FnOnce() + Send + '_>> = batch
+                    .chunks_mut(chunk_size)
+                    .map(|chunk| {
+                        Box::new(move || {
+                            for node in chunk {
+                                // SAFETY: systems in the same stage have non conflicting access, which the views
+                                // enforce, and the world is not changed otherwise until every system returned
+                                let mut view = unsafe { SystemWorld::shared(shared, &node.access) };
+                                node.system.run(&mut view, &mut node.commands, dt);
+                            }
+                        }) as Box<dyn FnOnce() + Send + '_>
+                    })
+                    .collect();
+                pool.scope(tasks);
+            }
+
+            for node in batch {
+                node.commands.apply(world);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -3,6 +3,7 @@
 
 use std::fmt::Debug;
 use crate::game::ecs::entity::EntityId;
+use crate::game::ecs::schedule::Access;
 use crate::game::ecs::World;
 use std::marker::PhantomData;
 
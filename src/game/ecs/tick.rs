@@ -0,0 +1,80 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A point in time of a world, used for change detection.
+/// Every query run advances the world tick by one, components remember when they were added and last changed.
+pub type Tick = u64;
+
+/// The first tick of a new world. Queries start out with a last run of 0, so everything present counts as added and changed.
+pub const FIRST_TICK: Tick = 1;
+
+#[derive(Debug)]
+pub struct ComponentTicks {
+    added: Tick,
+    changed: AtomicU64,
+}
+
+impl ComponentTicks {
+    pub fn new(tick: Tick) -> Self {
+        Self {
+            added: tick,
+            changed: AtomicU64::new(tick),
+        }
+    }
+
+    pub fn added(&self) -> Tick {
+        self.added
+    }
+
+    pub fn changed(&self) -> Tick {
+        self.changed.load(Ordering::Relaxed)
+    }
+
+    pub fn set_changed(&self, tick: Tick) {
+        self.changed.store(tick, Ordering::Relaxed);
+    }
+
+    /// If the component was added after `last_run` and not after `this_run`.
+    pub fn is_added(&self, last_run: Tick, this_run: Tick) -> bool {
+        self.added > last_run && self.added <= this_run
+    }
+
+    /// If the component was changed (or added) after `last_run` and not after `this_run`.
+    pub fn is_changed(&self, last_run: Tick, this_run: Tick) -> bool {
+        let changed = self.changed();
+        changed > last_run && changed <= this_run
+    }
+}
+
+impl Clone for ComponentTicks {
+    fn clone(&self) -> Self {
+        Self {
+            added: self.added,
+            changed: AtomicU64::new(self.changed()),
+        }
+    }
+}
+
+/// The tick counter of a world.
+#[derive(Debug)]
+pub struct ChangeTick(AtomicU64);
+
+impl ChangeTick {
+    pub fn new() -> Self {
+        Self(AtomicU64::new(FIRST_TICK))
+    }
+
+    pub fn get(&self) -> Tick {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// Advances the counter and returns the tick before advancing.
+    pub fn advance(&self) -> Tick {
+        self.0.fetch_add(1, Ordering::AcqRel)
+    }
+}
+
+impl Default for ChangeTick {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,28 +1,306 @@
 use crate::game::ecs::entity::EntityId;
+use crate::game::ecs::mem::column::Column;
+use crate::game::ecs::tick::{ChangeTick, ComponentTicks, Tick};
 use crate::game::ecs::world::EcsWorld;
+use hashbrown::HashMap;
+use mvutils::hashers::U64IdentityHasher;
+use std::any::TypeId;
+
+pub(crate) type ArchetypeId = usize;
+
+#[derive(Copy, Clone, Debug)]
+struct EntityLocation {
+    archetype: ArchetypeId,
+    row: usize,
+}
+
+/// A table of all entities sharing the exact same set of component types.
+/// Every component type has its own column and the entity at `entities[i]` owns row `i` of every column.
+pub struct Archetype {
+    signature: Vec<TypeId>,
+    columns: HashMap<TypeId, Column>,
+    entities: Vec<EntityId>,
+    add_edges: HashMap<TypeId, ArchetypeId>,
+    remove_edges: HashMap<TypeId, ArchetypeId>,
+}
+
+impl Archetype {
+    fn new(signature: Vec<TypeId>, columns: HashMap<TypeId, Column>) -> Self {
+        Self {
+            signature,
+            columns,
+            entities: Vec::new(),
+            add_edges: HashMap::new(),
+            remove_edges: HashMap::new(),
+        }
+    }
+
+    pub fn signature(&self) -> &[TypeId] {
+        &self.signature
+    }
+
+    pub fn entities(&self) -> &[EntityId] {
+        &self.entities
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn has(&self, ty: TypeId) -> bool {
+        self.columns.contains_key(&ty)
+    }
+
+    pub(crate) fn column(&self, ty: TypeId) -> Option<&Column> {
+        self.columns.get(&ty)
+    }
+
+    /// Swap removes the entity at `row`, returning the entity that was moved into its place if any.
+    fn swap_remove(&mut self, row: usize) -> Option<EntityId> {
+        for column in self.columns.values_mut() {
+            column.swap_remove(row);
+        }
+        self.entities.swap_remove(row);
+        self.entities.get(row).copied()
+    }
+}
 
 pub struct ArchetypeWorld {
+    archetypes: Vec<Archetype>,
+    signatures: HashMap<Vec<TypeId>, ArchetypeId>,
+    locations: HashMap<EntityId, EntityLocation, U64IdentityHasher>,
+    tick: ChangeTick,
+}
+
+impl ArchetypeWorld {
+    pub(crate) fn new() -> Self {
+        let mut this = Self {
+            archetypes: Vec::new(),
+            signatures: HashMap::new(),
+            locations: HashMap::with_hasher(U64IdentityHasher::default()),
+            tick: ChangeTick::new(),
+        };
+        // archetype 0 is always the empty one, new entities start out there
+        this.signatures.insert(Vec::new(), 0);
+        this.archetypes.push(Archetype::new(Vec::new(), HashMap::new()));
+        this
+    }
+
+    pub fn archetypes(&self) -> &[Archetype] {
+        &self.archetypes
+    }
+
+    pub fn contains_entity(&self, id: EntityId) -> bool {
+        self.locations.contains_key(&id)
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = EntityId> + '_ {
+        self.archetypes.iter().flat_map(|arch| arch.entities.iter().copied())
+    }
 
+    fn column_of<C: 'static>(&self, id: EntityId) -> Option<(&Column, usize)> {
+        let loc = self.locations.get(&id)?;
+        let column = self.archetypes[loc.archetype].columns.get(&TypeId::of::<C>())?;
+        Some((column, loc.row))
+    }
+
+    /// Returns a mutable reference through a shared one and marks the component as changed at `tick`.
+    ///
+    /// # Safety
+    /// The caller has to make sure the component is not borrowed anywhere else.
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn get_component_mut_unchecked<C: 'static>(&self, id: EntityId, tick: Tick) -> Option<&mut C> {
+        let (column, row) = self.column_of::<C>(id)?;
+        column.get_ticks(row)?.set_changed(tick);
+        unsafe { (column.get_ptr(row) as *mut C).as_mut() }
+    }
+
+    pub(crate) fn advance_tick(&self) -> Tick {
+        self.tick.advance()
+    }
+
+    fn location_or_create(&mut self, id: EntityId) -> EntityLocation {
+        if let Some(loc) = self.locations.get(&id) {
+            return *loc;
+        }
+        let empty = &mut self.archetypes[0];
+        let loc = EntityLocation {
+            archetype: 0,
+            row: empty.entities.len(),
+        };
+        empty.entities.push(id);
+        self.locations.insert(id, loc);
+        loc
+    }
+
+    /// Finds the archetype reached by adding `ty` to the archetype `from`, creating it if necessary.
+    fn archetype_with<C: Sized + 'static>(&mut self, from: ArchetypeId) -> ArchetypeId {
+        let ty = TypeId::of::<C>();
+        if let Some(to) = self.archetypes[from].add_edges.get(&ty) {
+            return *to;
+        }
+
+        let source = &self.archetypes[from];
+        let mut signature = source.signature.clone();
+        let pos = signature.binary_search(&ty).unwrap_or_else(|e| e);
+        signature.insert(pos, ty);
+
+        let to = if let Some(to) = self.signatures.get(&signature) {
+            *to
+        } else {
+            let mut columns: HashMap<TypeId, Column> = source
+                .columns
+                .iter()
+                .map(|(ty, col)| (*ty, Column::new_like(col)))
+                .collect();
+            columns.insert(ty, Column::new::<C>());
+            let to = self.archetypes.len();
+            self.signatures.insert(signature.clone(), to);
+            self.archetypes.push(Archetype::new(signature, columns));
+            to
+        };
+
+        self.archetypes[from].add_edges.insert(ty, to);
+        to
+    }
+
+    /// Finds the archetype reached by removing `ty` from the archetype `from`, creating it if necessary.
+    fn archetype_without(&mut self, from: ArchetypeId, ty: TypeId) -> ArchetypeId {
+        if let Some(to) = self.archetypes[from].remove_edges.get(&ty) {
+            return *to;
+        }
+
+        let source = &self.archetypes[from];
+        let signature: Vec<TypeId> = source.signature.iter().copied().filter(|t| *t != ty).collect();
+
+        let to = if let Some(to) = self.signatures.get(&signature) {
+            *to
+        } else {
+            let columns: HashMap<TypeId, Column> = source
+                .columns
+                .iter()
+                .filter(|(t, _)| **t != ty)
+                .map(|(t, col)| (*t, Column::new_like(col)))
+                .collect();
+            let to = self.archetypes.len();
+            self.signatures.insert(signature.clone(), to);
+            self.archetypes.push(Archetype::new(signature, columns));
+            to
+        };
+
+        self.archetypes[from].remove_edges.insert(ty, to);
+        to
+    }
+
+    /// Moves the entity at `loc` into archetype `to`. Components the target archetype does not have are dropped.
+    /// Returns the new row of the entity.
+    fn move_entity(&mut self, loc: EntityLocation, to: ArchetypeId) -> usize {
+        let (from, to_arch) = if loc.archetype < to {
+            let (l, r) = self.archetypes.split_at_mut(to);
+            (&mut l[loc.archetype], &mut r[0])
+        } else {
+            let (l, r) = self.archetypes.split_at_mut(loc.archetype);
+            (&mut r[0], &mut l[to])
+        };
+
+        for (ty, column) in from.columns.iter_mut() {
+            if let Some(target) = to_arch.columns.get_mut(ty) {
+                unsafe {
+                    column.move_to(loc.row, target);
+                }
+            } else {
+                column.swap_remove(loc.row);
+            }
+        }
+
+        let id = from.entities.swap_remove(loc.row);
+        let moved = from.entities.get(loc.row).copied();
+        let row = to_arch.entities.len();
+        to_arch.entities.push(id);
+
+        if let Some(moved) = moved
+            && let Some(moved_loc) = self.locations.get_mut(&moved)
+        {
+            moved_loc.row = loc.row;
+        }
+        self.locations.insert(id, EntityLocation { archetype: to, row });
+        row
+    }
+
+    mvengine_proc_macro::generate_archetype_queries!(20);
 }
 
 impl EcsWorld for ArchetypeWorld {
     fn create_entity(&mut self, id: EntityId) {
-        todo!()
+        self.location_or_create(id);
     }
 
     fn destroy_entity(&mut self, id: EntityId) {
-        todo!()
+        if let Some(loc) = self.locations.remove(&id) {
+            let moved = self.archetypes[loc.archetype].swap_remove(loc.row);
+            if let Some(moved) = moved
+                && let Some(moved_loc) = self.locations.get_mut(&moved)
+            {
+                moved_loc.row = loc.row;
+            }
+        }
     }
 
     fn set_component<C: 'static>(&mut self, id: EntityId, c: C) {
-        todo!()
+        let ty = TypeId::of::<C>();
+        let tick = self.tick.get();
+        let loc = self.location_or_create(id);
+
+        if let Some(column) = self.archetypes[loc.archetype].columns.get_mut(&ty) {
+            column.replace(loc.row, c, tick);
+            return;
+        }
+
+        let to = self.archetype_with::<C>(loc.archetype);
+        self.move_entity(loc, to);
+        if let Some(column) = self.archetypes[to].columns.get_mut(&ty) {
+            column.push(c, tick);
+        }
+    }
+
+    fn remove_component<C: 'static>(&mut self, id: EntityId) {
+        let ty = TypeId::of::<C>();
+        if let Some(loc) = self.locations.get(&id).copied()
+            && self.archetypes[loc.archetype].has(ty)
+        {
+            let to = self.archetype_without(loc.archetype, ty);
+            self.move_entity(loc, to);
+        }
     }
 
     fn get_component<C: 'static>(&self, id: EntityId) -> Option<&C> {
-        todo!()
+        let loc = self.locations.get(&id)?;
+        self.archetypes[loc.archetype]
+            .columns
+            .get(&TypeId::of::<C>())?
+            .get(loc.row)
     }
 
     fn get_component_mut<C: 'static>(&mut self, id: EntityId) -> Option<&mut C> {
-        todo!()
+        let tick = self.tick.get();
+        let loc = self.locations.get(&id)?;
+        let column = self.archetypes[loc.archetype]
+            .columns
+            .get_mut(&TypeId::of::<C>())?;
+        column.get_ticks(loc.row)?.set_changed(tick);
+        column.get_mut(loc.row)
+    }
+
+    fn component_ticks<C: 'static>(&self, id: EntityId) -> Option<&ComponentTicks> {
+        let (column, row) = self.column_of::<C>(id)?;
+        column.get_ticks(row)
     }
-}
\ No newline at end of file
+
+    fn change_tick(&self) -> Tick {
+        self.tick.get()
+    }
+}
@@ -2,11 +2,24 @@ pub mod sparse;
 pub mod arch;
 
 use crate::game::ecs::entity::EntityId;
+use crate::game::ecs::tick::{ComponentTicks, Tick};
+
 pub trait EcsWorld {
     fn create_entity(&mut self, id: EntityId);
     fn destroy_entity(&mut self, id: EntityId);
 
     fn set_component<C: 'static>(&mut self, id: EntityId, c: C);
+    fn remove_component<C: 'static>(&mut self, id: EntityId);
     fn get_component<C: 'static>(&self, id: EntityId) -> Option<&C>;
     fn get_component_mut<C: 'static>(&mut self, id: EntityId) -> Option<&mut C>;
-}
\ No newline at end of file
+
+    fn has_component<C: 'static>(&self, id: EntityId) -> bool {
+        self.get_component::<C>(id).is_some()
+    }
+
+    /// When the component was added and last changed.
+    fn component_ticks<C: 'static>(&self, id: EntityId) -> Option<&ComponentTicks>;
+
+    /// The current tick of this world. Component writes outside of queries are stamped with this tick.
+    fn change_tick(&self) -> Tick;
+}
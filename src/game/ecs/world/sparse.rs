@@ -1,5 +1,6 @@
 use crate::game::ecs::entity::EntityId;
 use crate::game::ecs::mem::storage::ComponentStorage;
+use crate::game::ecs::tick::{ComponentTicks, Tick};
 use crate::game::ecs::world::EcsWorld;
 
 pub struct SparseSetWorld {
@@ -32,6 +33,10 @@ impl EcsWorld for SparseSetWorld {
         self.storage.set_component::<C>(id, c);
     }
 
+    fn remove_component<C: 'static>(&mut self, id: EntityId) {
+        self.storage.remove_component::<C>(id);
+    }
+
     fn get_component<C: 'static>(&self, id: EntityId) -> Option<&C> {
         self.storage.get_component::<C>(id)
     }
@@ -39,4 +44,12 @@ impl EcsWorld for SparseSetWorld {
     fn get_component_mut<C: 'static>(&mut self, id: EntityId) -> Option<&mut C> {
         self.storage.get_component_mut::<C>(id)
     }
-}
\ No newline at end of file
+
+    fn component_ticks<C: 'static>(&self, id: EntityId) -> Option<&ComponentTicks> {
+        self.storage.component_ticks::<C>(id)
+    }
+
+    fn change_tick(&self) -> Tick {
+        self.storage.change_tick()
+    }
+}
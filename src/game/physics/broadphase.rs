@@ -0,0 +1,444 @@
+use crate::game::ecs::entity::EntityId;
+use crate::math::vec::Vec2;
+use hashbrown::{HashMap, HashSet};
+
+/// An axis aligned bounding box in world space.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl Aabb {
+    pub fn new(min: Vec2, max: Vec2) -> Self {
+        Self { min, max }
+    }
+
+    pub fn from_center(center: Vec2, extent: Vec2) -> Self {
+        Self {
+            min: center - extent * 0.5,
+            max: center + extent * 0.5,
+        }
+    }
+
+    /// Overlap test that includes touching edges, so that the broad phase never misses a pair the narrow phase would report.
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x && self.max.x >= other.min.x && self.min.y <= other.max.y && self.max.y >= other.min.y
+    }
+
+    pub fn contains(&self, other: &Aabb) -> bool {
+        self.min.x <= other.min.x && self.min.y <= other.min.y && self.max.x >= other.max.x && self.max.y >= other.max.y
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Vec2::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y)),
+            max: Vec2::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y)),
+        }
+    }
+
+    pub fn expanded(&self, margin: f32) -> Aabb {
+        Aabb {
+            min: self.min - Vec2::splat(margin),
+            max: self.max + Vec2::splat(margin),
+        }
+    }
+
+    pub fn perimeter(&self) -> f32 {
+        2.0 * ((self.max.x - self.min.x) + (self.max.y - self.min.y))
+    }
+}
+
+/// Finds the pairs of colliders that might touch, so only those have to go through the narrow phase.
+pub trait BroadPhase: Send {
+    /// Called once per frame with every collider. Pushes the index pairs `(i, j)` with `i < j` of all colliders
+    /// whose boxes overlap into `pairs`, in no particular order. Implementations may keep state between frames keyed by entity.
+    fn find_pairs(&mut self, colliders: &[(EntityId, Aabb)], pairs: &mut Vec<(usize, usize)>);
+}
+
+/// Tests every collider against every other one. Only useful for very few colliders or as a reference.
+#[derive(Default)]
+pub struct BruteForce;
+
+impl BroadPhase for BruteForce {
+    fn find_pairs(&mut self, colliders: &[(EntityId, Aabb)], pairs: &mut Vec<(usize, usize)>) {
+        for i in 0..colliders.len() {
+            for j in (i + 1)..colliders.len() {
+                if colliders[i].1.overlaps(&colliders[j].1) {
+                    pairs.push((i, j));
+                }
+            }
+        }
+    }
+}
+
+/// Sorts colliders into a uniform grid of square cells and only tests colliders sharing a cell.
+/// Works best when the cell size is a bit larger than most colliders.
+pub struct SpatialHashGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl SpatialHashGrid {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size: cell_size.max(f32::EPSILON),
+            cells: HashMap::new(),
+        }
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    fn cell_of(&self, p: Vec2) -> (i32, i32) {
+        ((p.x / self.cell_size).floor() as i32, (p.y / self.cell_size).floor() as i32)
+    }
+}
+
+impl BroadPhase for SpatialHashGrid {
+    fn find_pairs(&mut self, colliders: &[(EntityId, Aabb)], pairs: &mut Vec<(usize, usize)>) {
+        for cell in self.cells.values_mut() {
+            cell.clear();
+        }
+
+        for (i, (_, aabb)) in colliders.iter().enumerate() {
+            let (min_x, min_y) = self.cell_of(aabb.min);
+            let (max_x, max_y) = self.cell_of(aabb.max);
+            for x in min_x..=max_x {
+                for y in min_y..=max_y {
+                    self.cells.entry((x, y)).or_default().push(i);
+                }
+            }
+        }
+
+        for (cell, members) in &self.cells {
+            for a in 0..members.len() {
+                for b in (a + 1)..members.len() {
+                    let (i, j) = (members[a].min(members[b]), members[a].max(members[b]));
+                    let (bi, bj) = (&colliders[i].1, &colliders[j].1);
+                    if !bi.overlaps(bj) {
+                        continue;
+                    }
+                    // a pair can share several cells, only report it from the cell holding the corner of the overlap
+                    let corner = Vec2::new(bi.min.x.max(bj.min.x), bi.min.y.max(bj.min.y));
+                    if self.cell_of(corner) == *cell {
+                        pairs.push((i, j));
+                    }
+                }
+            }
+        }
+
+        self.cells.retain(|_, members| !members.is_empty());
+    }
+}
+
+const NULL_NODE: usize = usize::MAX;
+
+struct TreeNode {
+    aabb: Aabb,
+    parent: usize,
+    left: usize,
+    right: usize,
+    height: i32,
+    /// The index of the collider in the current frame, only meaningful for leaves
+    item: usize,
+}
+
+impl TreeNode {
+    fn is_leaf(&self) -> bool {
+        self.left == NULL_NODE
+    }
+}
+
+/// A bounding volume hierarchy that is kept between frames. Every leaf stores a slightly enlarged box,
+/// so colliders that only move a little do not have to be reinserted.
+pub struct DynamicAabbTree {
+    nodes: Vec<TreeNode>,
+    free: Vec<usize>,
+    root: usize,
+    leaves: HashMap<EntityId, usize>,
+    margin: f32,
+    stack: Vec<(usize, usize)>,
+}
+
+impl DynamicAabbTree {
+    /// `margin` is how far a collider can move before its leaf is reinserted.
+    pub fn new(margin: f32) -> Self {
+        Self {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: NULL_NODE,
+            leaves: HashMap::new(),
+            margin,
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// The height of the tree, 0 for a single leaf.
+    pub fn height(&self) -> i32 {
+        if self.root == NULL_NODE { 0 } else { self.nodes[self.root].height }
+    }
+
+    fn allocate(&mut self, aabb: Aabb, item: usize) -> usize {
+        let node = TreeNode {
+            aabb,
+            parent: NULL_NODE,
+            left: NULL_NODE,
+            right: NULL_NODE,
+            height: 0,
+            item,
+        };
+        if let Some(idx) = self.free.pop() {
+            self.nodes[idx] = node;
+            idx
+        } else {
+            self.nodes.push(node);
+            self.nodes.len() - 1
+        }
+    }
+
+    fn insert_leaf(&mut self, leaf: usize) {
+        if self.root == NULL_NODE {
+            self.root = leaf;
+            self.nodes[leaf].parent = NULL_NODE;
+            return;
+        }
+
+        // find the best sibling by descending into the child that grows the least
+        let leaf_aabb = self.nodes[leaf].aabb;
+        let mut index = self.root;
+        while !self.nodes[index].is_leaf() {
+            let node = &self.nodes[index];
+            let area = node.aabb.perimeter();
+            let combined = node.aabb.union(&leaf_aabb).perimeter();
+            let cost = 2.0 * combined;
+            let inheritance = 2.0 * (combined - area);
+
+            let child_cost = |child: usize| {
+                let child = &self.nodes[child];
+                let union = child.aabb.union(&leaf_aabb).perimeter();
+                if child.is_leaf() {
+                    union + inheritance
+                } else {
+                    union - child.aabb.perimeter() + inheritance
+                }
+            };
+            let cost_left = child_cost(node.left);
+            let cost_right = child_cost(node.right);
+
+            if cost < cost_left && cost < cost_right {
+                break;
+            }
+            index = if cost_left < cost_right { node.left } else { node.right };
+        }
+
+        let sibling = index;
+        let old_parent = self.nodes[sibling].parent;
+        let new_parent = self.allocate(leaf_aabb.union(&self.nodes[sibling].aabb), NULL_NODE);
+        self.nodes[new_parent].parent = old_parent;
+        self.nodes[new_parent].height = self.nodes[sibling].height + 1;
+        self.nodes[new_parent].left = sibling;
+        self.nodes[new_parent].right = leaf;
+        self.nodes[sibling].parent = new_parent;
+        self.nodes[leaf].parent = new_parent;
+
+        if old_parent == NULL_NODE {
+            self.root = new_parent;
+        } else if self.nodes[old_parent].left == sibling {
+            self.nodes[old_parent].left = new_parent;
+        } else {
+            self.nodes[old_parent].right = new_parent;
+        }
+
+        self.refit(new_parent);
+    }
+
+    fn remove_leaf(&mut self, leaf: usize) {
+        if leaf == self.root {
+            self.root = NULL_NODE;
+            return;
+        }
+
+        let parent = self.nodes[leaf].parent;
+        let grand_parent = self.nodes[parent].parent;
+        let sibling = if self.nodes[parent].left == leaf {
+            self.nodes[parent].right
+        } else {
+            self.nodes[parent].left
+        };
+
+        if grand_parent == NULL_NODE {
+            self.root = sibling;
+            self.nodes[sibling].parent = NULL_NODE;
+        } else {
+            if self.nodes[grand_parent].left == parent {
+                self.nodes[grand_parent].left = sibling;
+            } else {
+                self.nodes[grand_parent].right = sibling;
+            }
+            self.nodes[sibling].parent = grand_parent;
+            self.refit(grand_parent);
+        }
+        self.free.push(parent);
+    }
+
+    /// Walks up from `index`, rebalancing and fixing heights and boxes on the way.
+    fn refit(&mut self, mut index: usize) {
+        while index != NULL_NODE {
+            index = self.balance(index);
+            let (left, right) = (self.nodes[index].left, self.nodes[index].right);
+            self.nodes[index].height = 1 + self.nodes[left].height.max(self.nodes[right].height);
+            self.nodes[index].aabb = self.nodes[left].aabb.union(&self.nodes[right].aabb);
+            index = self.nodes[index].parent;
+        }
+    }
+
+    /// Performs a left or right rotation if the subtree at `a` is imbalanced. Returns the new root of the subtree.
+    fn balance(&mut self, a: usize) -> usize {
+        if self.nodes[a].is_leaf() || self.nodes[a].height < 2 {
+            return a;
+        }
+
+        let (b, c) = (self.nodes[a].left, self.nodes[a].right);
+        let balance = self.nodes[c].height - self.nodes[b].height;
+
+        if balance > 1 {
+            self.rotate_up(a, c, b)
+        } else if balance < -1 {
+            self.rotate_up(a, b, c)
+        } else {
+            a
+        }
+    }
+
+    /// Rotates the taller child `up` of `a` above it, `other` being the remaining child of `a`.
+    fn rotate_up(&mut self, a: usize, up: usize, other: usize) -> usize {
+        let (f, g) = (self.nodes[up].left, self.nodes[up].right);
+        let a_parent = self.nodes[a].parent;
+
+        // up takes the place of a
+        self.nodes[up].left = a;
+        self.nodes[up].parent = a_parent;
+        self.nodes[a].parent = up;
+        if a_parent == NULL_NODE {
+            self.root = up;
+        } else if self.nodes[a_parent].left == a {
+            self.nodes[a_parent].left = up;
+        } else {
+            self.nodes[a_parent].right = up;
+        }
+
+        // the taller grandchild stays below up, the smaller one moves to a
+        let (keep, moved) = if self.nodes[f].height > self.nodes[g].height { (f, g) } else { (g, f) };
+        self.nodes[up].right = keep;
+        self.nodes[a].left = other;
+        self.nodes[a].right = moved;
+        self.nodes[moved].parent = a;
+
+        self.nodes[a].aabb = self.nodes[other].aabb.union(&self.nodes[moved].aabb);
+        self.nodes[a].height = 1 + self.nodes[other].height.max(self.nodes[moved].height);
+        self.nodes[up].aabb = self.nodes[a].aabb.union(&self.nodes[keep].aabb);
+        self.nodes[up].height = 1 + self.nodes[a].height.max(self.nodes[keep].height);
+
+        up
+    }
+
+    /// Inserts or moves the leaf of `id`. Returns the leaf node.
+    fn update_leaf(&mut self, id: EntityId, aabb: Aabb, item: usize) -> usize {
+        if let Some(leaf) = self.leaves.get(&id).copied() {
+            self.nodes[leaf].item = item;
+            if self.nodes[leaf].aabb.contains(&aabb) {
+                return leaf;
+            }
+            self.remove_leaf(leaf);
+            self.nodes[leaf].aabb = aabb.expanded(self.margin);
+            self.insert_leaf(leaf);
+            leaf
+        } else {
+            let leaf = self.allocate(aabb.expanded(self.margin), item);
+            self.insert_leaf(leaf);
+            self.leaves.insert(id, leaf);
+            leaf
+        }
+    }
+}
+
+impl BroadPhase for DynamicAabbTree {
+    fn find_pairs(&mut self, colliders: &[(EntityId, Aabb)], pairs: &mut Vec<(usize, usize)>) {
+        let alive: HashSet<EntityId> = colliders.iter().map(|(id, _)| *id).collect();
+        let gone: Vec<(EntityId, usize)> = self
+            .leaves
+            .iter()
+            .filter(|(id, _)| !alive.contains(*id))
+            .map(|(id, leaf)| (*id, *leaf))
+            .collect();
+        for (id, leaf) in gone {
+            self.remove_leaf(leaf);
+            self.free.push(leaf);
+            self.leaves.remove(&id);
+        }
+
+        for (i, (id, aabb)) in colliders.iter().enumerate() {
+            self.update_leaf(*id, *aabb, i);
+        }
+
+        if self.root == NULL_NODE {
+            return;
+        }
+
+        // walk the tree against itself, so every pair of subtrees is only looked at once
+        let mut stack = std::mem::take(&mut self.stack);
+        stack.clear();
+        stack.push((self.root, self.root));
+        while let Some((a, b)) = stack.pop() {
+            let (node_a, node_b) = (&self.nodes[a], &self.nodes[b]);
+            if a == b {
+                if !node_a.is_leaf() {
+                    stack.push((node_a.left, node_a.left));
+                    stack.push((node_a.right, node_a.right));
+                    stack.push((node_a.left, node_a.right));
+                }
+                continue;
+            }
+            if !node_a.aabb.overlaps(&node_b.aabb) {
+                continue;
+            }
+            match (node_a.is_leaf(), node_b.is_leaf()) {
+                (true, true) => {
+                    let (i, j) = (node_a.item.min(node_b.item), node_a.item.max(node_b.item));
+                    if colliders[i].1.overlaps(&colliders[j].1) {
+                        pairs.push((i, j));
+                    }
+                }
+                // descend into the larger subtree
+                (false, true) => {
+                    stack.push((node_a.left, b));
+                    stack.push((node_a.right, b));
+                }
+                (true, false) => {
+                    stack.push((a, node_b.left));
+                    stack.push((a, node_b.right));
+                }
+                (false, false) => {
+                    if node_a.aabb.perimeter() >= node_b.aabb.perimeter() {
+                        stack.push((node_a.left, b));
+                        stack.push((node_a.right, b));
+                    } else {
+                        stack.push((a, node_b.left));
+                        stack.push((a, node_b.right));
+                    }
+                }
+            }
+        }
+        self.stack = stack;
+    }
+}
@@ -1,11 +1,15 @@
 use crate::math::vec::Vec2;
+use crate::ui::geometry::geom;
+use crate::ui::geometry::polygon::Polygon;
+use mvutils::Savable;
 
 pub type Radians = f64;
 pub type Kg = f64;
 
-#[derive(Clone, Default, Debug, PartialOrd, PartialEq)]
+#[derive(Clone, Debug, PartialOrd, PartialEq, Savable)]
 pub struct Transform {
     /// Position relative to center.
+    /// If the entity has a parent, this is relative to the parent's position, rotation and scale.
     pub position: Vec2,
     /// Rotation around center in radiants.
     pub rotation: Radians,
@@ -14,15 +18,83 @@ pub struct Transform {
     pub scale: Vec2,
 }
 
-#[derive(Clone, Default, Debug, PartialOrd, PartialEq)]
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: Vec2::default(),
+            rotation: 0.0,
+            center: Vec2::default(),
+            scale: Vec2::splat(1.0),
+        }
+    }
+}
+
+/// The final transform of an entity after applying the transforms of all its parents.
+/// Written by the `TransformPropagationSystem`, for entities without a parent it equals their [`Transform`].
+#[derive(Clone, Debug, PartialOrd, PartialEq, Savable)]
+pub struct GlobalTransform {
+    pub position: Vec2,
+    pub rotation: Radians,
+    pub scale: Vec2,
+}
+
+impl Default for GlobalTransform {
+    fn default() -> Self {
+        Self {
+            position: Vec2::default(),
+            rotation: 0.0,
+            scale: Vec2::splat(1.0),
+        }
+    }
+}
+
+impl GlobalTransform {
+    pub fn from_transform(transform: &Transform) -> Self {
+        Self {
+            position: transform.position,
+            rotation: transform.rotation,
+            scale: transform.scale,
+        }
+    }
+
+    /// Places the local transform of a child into the space described by this transform.
+    pub fn mul_transform(&self, local: &Transform) -> Self {
+        let offset = geom::rotate_vector(local.position * self.scale, self.rotation as f32);
+        Self {
+            position: self.position + offset,
+            rotation: self.rotation + local.rotation,
+            scale: self.scale * local.scale,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialOrd, PartialEq, Savable)]
 pub struct RigidDynamic {
     pub velocity: Vec2,
     pub circular_velocity: Radians,
     pub gravity: Vec2,
-    pub mass: Kg
+    /// Has to be positive. Bodies that collisions should never move are marked [`Static`] instead.
+    pub mass: Kg,
+    /// How much of the velocity along the contact normal is kept after a collision, 0 is fully inelastic and 1 is perfectly elastic.
+    pub restitution: f32,
+    /// Coulomb friction coefficient, limits the impulse along the contact surface to this factor of the normal impulse.
+    pub friction: f32,
+}
+
+impl Default for RigidDynamic {
+    fn default() -> Self {
+        Self {
+            velocity: Vec2::default(),
+            circular_velocity: 0.0,
+            gravity: Vec2::default(),
+            mass: 1.0,
+            restitution: 0.0,
+            friction: 0.0,
+        }
+    }
 }
 
-#[derive(Clone, Default, Debug, PartialOrd, PartialEq)]
+#[derive(Clone, Default, Debug, PartialOrd, PartialEq, Savable)]
 pub struct AABBCollider {
     /// The extent of this object as a rectangle with Transform::center as the middle.
     pub extent: Vec2,
@@ -32,4 +104,44 @@ pub struct AABBCollider {
     pub collision_point: Vec2,
     /// How much the foreign collider penetrates this collider
     pub overlap: Vec2
-}
\ No newline at end of file
+}
+
+/// A circle around the position of the entity. The radius is scaled by the larger component of [`Transform::scale`].
+#[derive(Clone, Default, Debug, PartialOrd, PartialEq, Savable)]
+pub struct CircleCollider {
+    pub radius: f32,
+}
+
+/// A box that rotates with [`Transform::rotation`], unlike [`AABBCollider`].
+#[derive(Clone, Default, Debug, PartialOrd, PartialEq, Savable)]
+pub struct OBBCollider {
+    /// The extent of this object as a rectangle with the position of the entity as the middle, before rotation and scale.
+    pub extent: Vec2,
+}
+
+/// A convex polygon that is rotated and scaled with the [`Transform`] of the entity.
+#[derive(Clone, Default, Debug, PartialOrd, PartialEq, Savable)]
+pub struct PolygonCollider {
+    /// The vertices relative to the position of the entity, convex and in counter-clockwise order.
+    pub vertices: Vec<Vec2>,
+}
+
+impl PolygonCollider {
+    /// Creates a collider from the convex hull of `vertices`, in any order.
+    pub fn new(vertices: Vec<Vec2>) -> Self {
+        let mut polygon = Polygon { vertices };
+        polygon.sort_vertices_for_convex();
+        Self {
+            vertices: polygon.vertices,
+        }
+    }
+}
+
+/// Marks an entity that never moves. Collisions between two static colliders are never checked,
+/// and static colliders have infinite mass when collisions are resolved.
+#[derive(Clone, Copy, Default, Debug, PartialOrd, PartialEq, Savable)]
+pub struct Static;
+
+/// Marks a collider that only reports collisions through collision events, without being pushed apart from other colliders.
+#[derive(Clone, Copy, Default, Debug, PartialOrd, PartialEq, Savable)]
+pub struct Trigger;
@@ -0,0 +1,15 @@
+use crate::game::ecs::entity::EntityId;
+use crate::math::vec::Vec2;
+
+/// A touching pair of colliders found by the narrow phase.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Contact {
+    pub a: EntityId,
+    pub b: EntityId,
+    /// Unit vector pointing from `a` towards `b`, along which `b` has to move to separate the two.
+    pub normal: Vec2,
+    /// How far the colliders penetrate each other along the normal.
+    pub depth: f32,
+    /// The middle of the overlapping area.
+    pub point: Vec2,
+}
@@ -0,0 +1,93 @@
+use crate::game::ecs::entity::EntityId;
+use crate::game::physics::contact::Contact;
+use hashbrown::HashMap;
+
+/// A change in whether two colliders touch. `a` is always the smaller entity id of the pair.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CollisionEvent {
+    /// The colliders touch for the first time this frame.
+    CollisionStarted(Contact),
+    /// The colliders touched last frame and still do.
+    CollisionOngoing(Contact),
+    /// The colliders no longer touch. Carries the last contact that was seen.
+    CollisionEnded(Contact),
+}
+
+impl CollisionEvent {
+    pub fn contact(&self) -> &Contact {
+        match self {
+            CollisionEvent::CollisionStarted(c) | CollisionEvent::CollisionOngoing(c) | CollisionEvent::CollisionEnded(c) => c,
+        }
+    }
+
+    pub fn entities(&self) -> (EntityId, EntityId) {
+        let contact = self.contact();
+        (contact.a, contact.b)
+    }
+
+    /// Returns the entity this collision is with from the perspective of `id`, if `id` takes part in it.
+    pub fn other(&self, id: EntityId) -> Option<EntityId> {
+        let (a, b) = self.entities();
+        if a == id {
+            Some(b)
+        } else if b == id {
+            Some(a)
+        } else {
+            None
+        }
+    }
+}
+
+/// Turns the contacts of consecutive frames into [`CollisionEvent`]s.
+pub struct CollisionTracker {
+    previous: HashMap<(EntityId, EntityId), Contact>,
+    events: Vec<CollisionEvent>,
+}
+
+impl CollisionTracker {
+    pub fn new() -> Self {
+        Self {
+            previous: HashMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Compares `contacts` to the ones of the last update. The contacts have to have `a < b`.
+    /// Afterwards [`CollisionTracker::events`] holds the started and ongoing collisions in the order of `contacts`,
+    /// followed by the ended ones sorted by entity ids.
+    pub fn update(&mut self, contacts: &[Contact]) {
+        self.events.clear();
+        let mut current = HashMap::with_capacity(contacts.len());
+        for contact in contacts {
+            let key = (contact.a, contact.b);
+            if self.previous.remove(&key).is_some() {
+                self.events.push(CollisionEvent::CollisionOngoing(*contact));
+            } else {
+                self.events.push(CollisionEvent::CollisionStarted(*contact));
+            }
+            current.insert(key, *contact);
+        }
+
+        let mut ended: Vec<Contact> = self.previous.drain().map(|(_, c)| c).collect();
+        ended.sort_unstable_by_key(|c| (c.a, c.b));
+        self.events.extend(ended.into_iter().map(CollisionEvent::CollisionEnded));
+        self.previous = current;
+    }
+
+    /// The events of the last update.
+    pub fn events(&self) -> &[CollisionEvent] {
+        &self.events
+    }
+
+    /// Forgets all current collisions without emitting any events.
+    pub fn clear(&mut self) {
+        self.previous.clear();
+        self.events.clear();
+    }
+}
+
+impl Default for CollisionTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,2 +1,6 @@
+pub mod broadphase;
 pub mod components;
+pub mod contact;
+pub mod events;
+pub mod sat;
 pub mod systems;
\ No newline at end of file
@@ -0,0 +1,152 @@
+use crate::game::physics::broadphase::Aabb;
+use crate::math::vec::Vec2;
+use crate::ui::geometry::geom;
+use crate::ui::geometry::polygon::Polygon;
+
+/// A collider shape in world space, as used by the narrow phase.
+#[derive(Clone, Debug)]
+pub enum ColliderShape {
+    Circle { center: Vec2, radius: f32 },
+    /// A convex polygon with counter-clockwise vertices.
+    Polygon(Polygon),
+}
+
+/// How two shapes overlap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Penetration {
+    /// Unit vector pointing from the first shape towards the second one.
+    pub normal: Vec2,
+    /// How far the shapes have to be moved apart along the normal to only touch.
+    pub depth: f32,
+    /// The middle of the deepest penetration.
+    pub point: Vec2,
+}
+
+impl ColliderShape {
+    pub fn center(&self) -> Vec2 {
+        match self {
+            ColliderShape::Circle { center, .. } => *center,
+            ColliderShape::Polygon(polygon) => polygon.calculate_centroid(),
+        }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        match self {
+            ColliderShape::Circle { center, radius } => Aabb::from_center(*center, Vec2::splat(radius * 2.0)),
+            ColliderShape::Polygon(polygon) => {
+                let first = polygon.vertices.first().copied().unwrap_or_default();
+                polygon
+                    .vertices
+                    .iter()
+                    .fold(Aabb::new(first, first), |b, v| b.union(&Aabb::new(*v, *v)))
+            }
+        }
+    }
+
+    /// Projects the shape onto `axis`, returning the smallest and largest value.
+    fn project(&self, axis: Vec2) -> (f32, f32) {
+        match self {
+            ColliderShape::Circle { center, radius } => {
+                let c = geom::dot(*center, axis);
+                (c - radius, c + radius)
+            }
+            ColliderShape::Polygon(polygon) => polygon.vertices.iter().fold((f32::MAX, f32::MIN), |(min, max), v| {
+                let p = geom::dot(*v, axis);
+                (min.min(p), max.max(p))
+            }),
+        }
+    }
+
+    /// The point of the shape that lies furthest in `direction`.
+    fn support(&self, direction: Vec2) -> Vec2 {
+        match self {
+            ColliderShape::Circle { center, radius } => *center + geom::normalize(direction) * *radius,
+            ColliderShape::Polygon(polygon) => polygon
+                .vertices
+                .iter()
+                .copied()
+                .fold((Vec2::default(), f32::MIN), |(best, best_p), v| {
+                    let p = geom::dot(v, direction);
+                    if p > best_p { (v, p) } else { (best, best_p) }
+                })
+                .0,
+        }
+    }
+
+    /// The separating axes this shape contributes when tested against `other`.
+    fn axes(&self, other: &ColliderShape, axes: &mut Vec<Vec2>) {
+        match self {
+            ColliderShape::Circle { center, .. } => {
+                // the axis towards the closest feature of the other shape
+                let closest = match other {
+                    ColliderShape::Circle { center: other, .. } => *other,
+                    ColliderShape::Polygon(polygon) => polygon
+                        .vertices
+                        .iter()
+                        .copied()
+                        .min_by(|a, b| geom::distance(*a, *center).total_cmp(&geom::distance(*b, *center)))
+                        .unwrap_or(*center),
+                };
+                let axis = geom::normalize(closest - *center);
+                if !geom::is_vec_zero(axis) {
+                    axes.push(axis);
+                }
+            }
+            ColliderShape::Polygon(polygon) => {
+                let n = polygon.vertices.len();
+                for i in 0..n {
+                    let edge = polygon.vertices[(i + 1) % n] - polygon.vertices[i];
+                    let axis = geom::normalize(geom::perpendicular(edge));
+                    if !geom::is_vec_zero(axis) {
+                        axes.push(axis);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Tests two convex shapes using the separating axis theorem. Returns `None` if they do not overlap or only touch.
+pub fn collide(a: &ColliderShape, b: &ColliderShape) -> Option<Penetration> {
+    let mut axes = Vec::new();
+    a.axes(b, &mut axes);
+    b.axes(a, &mut axes);
+
+    let mut normal = Vec2::new(1.0, 0.0);
+    let mut depth = f32::MAX;
+    for axis in axes {
+        let (min_a, max_a) = a.project(axis);
+        let (min_b, max_b) = b.project(axis);
+        let mut overlap = max_a.min(max_b) - min_a.max(min_b);
+        if overlap <= 0.0 {
+            return None;
+        }
+        // if one projection contains the other, the shape also has to be pushed past the nearer end
+        if (min_a <= min_b && max_a >= max_b) || (min_b <= min_a && max_b >= max_a) {
+            overlap += (min_a - min_b).abs().min((max_a - max_b).abs());
+        }
+        if overlap < depth {
+            depth = overlap;
+            normal = axis;
+        }
+    }
+
+    if depth == f32::MAX {
+        // both are circles sharing the same center
+        let ColliderShape::Circle { radius: ra, .. } = a else { return None };
+        let ColliderShape::Circle { radius: rb, .. } = b else { return None };
+        depth = ra + rb;
+    }
+
+    if geom::dot(b.center() - a.center(), normal) < 0.0 {
+        normal = -normal;
+    }
+
+    // the point of b reaching deepest into a, moved halfway back to the surface of a
+    let deepest = b.support(-normal);
+    Some(Penetration {
+        normal,
+        depth,
+        point: deepest + normal * (depth * 0.5),
+    })
+}
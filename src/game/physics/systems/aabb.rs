@@ -1,57 +1,128 @@
-use crate::game::ecs::system::System;
+use crate::game::ecs::command::Commands;
+use crate::game::ecs::entity::EntityId;
+use crate::game::ecs::query::Query;
+use crate::game::ecs::schedule::{Access, ScheduledSystem, SystemWorld};
 use crate::game::ecs::World;
-use crate::game::physics::components::{AABBCollider, Transform};
+use crate::game::physics::broadphase::{Aabb, BroadPhase, DynamicAabbTree};
+use crate::game::physics::components::{AABBCollider, Static, Transform};
+use crate::game::physics::contact::Contact;
 use crate::math::vec::Vec2;
 
 pub struct AabbCollisionSystem {
-    system: System<(Transform, AABBCollider)>,
+    query: Query<(&'static Transform, &'static mut AABBCollider, Option<&'static Static>)>,
+    broad_phase: Box<dyn BroadPhase>,
+    boxes: Vec<(EntityId, Aabb)>,
+    pairs: Vec<(usize, usize)>,
+    contacts: Vec<Contact>,
 }
 
 impl AabbCollisionSystem {
     pub fn new() -> Self {
+        Self::with_broad_phase(DynamicAabbTree::new(0.1))
+    }
+
+    /// Uses `broad_phase` to find the candidate pairs that are passed on to the exact collision check.
+    pub fn with_broad_phase<B: BroadPhase + 'static>(broad_phase: B) -> Self {
         Self {
-            system: System::new(),
+            query: Query::new(),
+            broad_phase: Box::new(broad_phase),
+            boxes: Vec::new(),
+            pairs: Vec::new(),
+            contacts: Vec::new(),
         }
     }
 
+    pub fn set_broad_phase<B: BroadPhase + 'static>(&mut self, broad_phase: B) {
+        self.broad_phase = Box::new(broad_phase);
+    }
+
+    /// Every contact found in the last iteration, with `a < b` and sorted by `(a, b)`.
+    pub fn contacts(&self) -> &[Contact] {
+        &self.contacts
+    }
+
     pub fn iterate(&mut self, world: &mut World, dt: f64) {
-        let mut entities: Vec<_> = self.system.iter_mut(world).collect();
+        let access = self.access();
+        self.step(&mut SystemWorld::new(world, &access));
+    }
+
+    fn step(&mut self, world: &mut SystemWorld) {
+        let mut entities: Vec<_> = world.query_mut(&mut self.query).collect();
 
-        for (_, (_, collider)) in entities.iter_mut() {
+        for (_, (_, collider, _)) in entities.iter_mut() {
             collider.collides = false;
             collider.overlap = Vec2::default();
             collider.collision_point = Vec2::default();
         }
 
-        let mut collisions = vec![];
-        let len = entities.len();
+        self.boxes.clear();
+        self.boxes.extend(
+            entities
+                .iter()
+                .map(|(id, (t, c, _))| (*id, Aabb::from_center(t.position, c.extent))),
+        );
+        self.pairs.clear();
+        self.broad_phase.find_pairs(&self.boxes, &mut self.pairs);
+        // keep the results independent of the order the broad phase found the pairs in
+        self.pairs.sort_unstable();
 
-        // This is like super inefficient and should be replaced by some smart BVH or smth
-        for i in 0..len {
-            let (e1, (t1, c1)) = &entities[i];
-            for j in (i + 1)..len {
-                let (e2, (t2, c2)) = &entities[j];
+        let mut collisions = vec![];
+        self.contacts.clear();
+        for &(i, j) in &self.pairs {
+            let (e1, (t1, c1, s1)) = &entities[i];
+            let (e2, (t2, c2, s2)) = &entities[j];
+            if s1.is_some() && s2.is_some() {
+                continue;
+            }
 
-                if let Some((overlap, mid)) = Self::check_collision(t1, c1, t2, c2) {
-                    collisions.push((i, j, overlap, mid));
+            if let Some((overlap, mid)) = Self::check_collision(t1, c1, t2, c2) {
+                collisions.push((i, j, overlap, mid));
+                let contact = Self::contact(*e1, t1, *e2, t2, overlap, mid);
+                if contact.a < contact.b {
+                    self.contacts.push(contact);
+                } else {
+                    self.contacts.push(Contact {
+                        a: contact.b,
+                        b: contact.a,
+                        normal: -contact.normal,
+                        ..contact
+                    });
                 }
             }
         }
+        self.contacts.sort_unstable_by_key(|c| (c.a, c.b));
 
         for (i, j, overlap, mid) in collisions {
-            let (_, (_, c1)) = &mut entities[i];
+            let (_, (_, c1, _)) = &mut entities[i];
             c1.collides = true;
             c1.overlap = overlap;
             c1.collision_point = mid;
 
-            let (_, (_, c2)) = &mut entities[j];
+            let (_, (_, c2, _)) = &mut entities[j];
             c2.collides = true;
             c2.overlap = overlap;
             c2.collision_point = mid;
         }
     }
 
-    fn check_collision(
+    /// Separates along the axis with the smaller overlap, pointing from `e1` to `e2`.
+    fn contact(e1: EntityId, t1: &Transform, e2: EntityId, t2: &Transform, overlap: Vec2, mid: Vec2) -> Contact {
+        let delta = t2.position - t1.position;
+        let (normal, depth) = if overlap.x < overlap.y {
+            (Vec2::new(if delta.x < 0.0 { -1.0 } else { 1.0 }, 0.0), overlap.x)
+        } else {
+            (Vec2::new(0.0, if delta.y < 0.0 { -1.0 } else { 1.0 }), overlap.y)
+        };
+        Contact {
+            a: e1,
+            b: e2,
+            normal,
+            depth,
+            point: mid,
+        }
+    }
+
+    pub fn check_collision(
         t1: &Transform,
         c1: &AABBCollider,
         t2: &Transform,
@@ -78,4 +149,14 @@ impl AabbCollisionSystem {
             None
         }
     }
-}
\ No newline at end of file
+}
+
+impl ScheduledSystem for AabbCollisionSystem {
+    fn access(&self) -> Access {
+        self.query.access()
+    }
+
+    fn run(&mut self, world: &mut SystemWorld, _: &mut Commands, _: f64) {
+        self.step(world);
+    }
+}
@@ -0,0 +1,65 @@
+use crate::game::ecs::command::Commands;
+use crate::game::ecs::entity::EntityId;
+use crate::game::ecs::hierarchy::{Children, Parent};
+use crate::game::ecs::query::{Query, Without};
+use crate::game::ecs::schedule::{Access, ScheduledSystem, SystemWorld};
+use crate::game::ecs::World;
+use crate::game::physics::components::{GlobalTransform, Transform};
+
+/// Computes the [`GlobalTransform`] of every entity with a [`Transform`] by walking down the hierarchy from the roots.
+pub struct TransformPropagationSystem {
+    roots: Query<&'static Transform, Without<Parent>>,
+}
+
+impl TransformPropagationSystem {
+    pub fn new() -> Self {
+        Self {
+            roots: Query::new(),
+        }
+    }
+
+    pub fn iterate(&mut self, world: &mut World) {
+        let access = self.access();
+        let mut commands = Commands::new();
+        self.propagate(&mut SystemWorld::new(world, &access), &mut commands);
+        commands.apply(world);
+    }
+
+    /// Writes global transforms in place. Entities that do not have a [`GlobalTransform`] yet get one inserted through `commands`.
+    fn propagate(&mut self, world: &mut SystemWorld, commands: &mut Commands) {
+        let mut stack: Vec<(EntityId, GlobalTransform)> = world
+            .query(&mut self.roots)
+            .map(|(id, transform)| (id, GlobalTransform::from_transform(transform)))
+            .collect();
+
+        while let Some((id, global)) = stack.pop() {
+            if let Some(children) = world.get_component::<Children>(id) {
+                for child in children.iter() {
+                    if let Some(local) = world.get_component::<Transform>(child) {
+                        stack.push((child, global.mul_transform(local)));
+                    }
+                }
+            }
+
+            if let Some(existing) = world.get_component_mut::<GlobalTransform>(id) {
+                *existing = global;
+            } else {
+                commands.insert(id, global);
+            }
+        }
+    }
+}
+
+impl ScheduledSystem for TransformPropagationSystem {
+    fn access(&self) -> Access {
+        Access::new()
+            .read::<Transform>()
+            .read::<Parent>()
+            .read::<Children>()
+            .write::<GlobalTransform>()
+    }
+
+    fn run(&mut self, world: &mut SystemWorld, commands: &mut Commands, _: f64) {
+        self.propagate(world, commands);
+    }
+}
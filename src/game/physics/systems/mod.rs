@@ -1,25 +1,74 @@
+use crate::event::EventBus;
 use crate::game::ecs::World;
+use crate::game::physics::contact::Contact;
+use crate::game::physics::events::{CollisionEvent, CollisionTracker};
 use crate::game::physics::systems::aabb::AabbCollisionSystem;
+use crate::game::physics::systems::response::ContactSolver;
 use crate::game::physics::systems::rigid::RigidSystem;
+use crate::game::physics::systems::shape::ShapeCollisionSystem;
 
 pub mod aabb;
+pub mod hierarchy;
+pub mod response;
 pub mod rigid;
+pub mod shape;
 
 pub struct PhysicsSystem {
     aabb_system: AabbCollisionSystem,
-    rigid_system: RigidSystem
+    shape_system: ShapeCollisionSystem,
+    rigid_system: RigidSystem,
+    solver: ContactSolver,
+    contacts: Vec<Contact>,
+    tracker: CollisionTracker,
+    event_bus: EventBus<CollisionEvent, ()>,
 }
 
 impl PhysicsSystem {
     pub fn new() -> Self {
         Self {
             aabb_system: AabbCollisionSystem::new(),
+            shape_system: ShapeCollisionSystem::new(),
             rigid_system: RigidSystem::new(),
+            solver: ContactSolver::new(),
+            contacts: Vec::new(),
+            tracker: CollisionTracker::new(),
+            event_bus: EventBus::new(),
         }
     }
 
+    pub fn solver_mut(&mut self) -> &mut ContactSolver {
+        &mut self.solver
+    }
+
+    /// Every contact found in the last iteration, with `a < b` and sorted by `(a, b)`.
+    pub fn contacts(&self) -> &[Contact] {
+        &self.contacts
+    }
+
+    /// The collision events of the last iteration. They are also dispatched to [`PhysicsSystem::event_bus_mut`].
+    pub fn events(&self) -> &[CollisionEvent] {
+        self.tracker.events()
+    }
+
+    /// Subscribe here to receive every [`CollisionEvent`] right after the iteration that caused it.
+    pub fn event_bus_mut(&mut self) -> &mut EventBus<CollisionEvent, ()> {
+        &mut self.event_bus
+    }
+
     pub fn iterate(&mut self, world: &mut World, dt: f64) {
         self.rigid_system.iterate(world, dt);
         self.aabb_system.iterate(world, dt);
+        self.shape_system.iterate(world);
+
+        self.contacts.clear();
+        self.contacts.extend_from_slice(self.aabb_system.contacts());
+        self.contacts.extend_from_slice(self.shape_system.contacts());
+        self.contacts.sort_unstable_by_key(|c| (c.a, c.b));
+        self.solver.solve(world, &self.contacts);
+
+        self.tracker.update(&self.contacts);
+        for event in self.tracker.events() {
+            self.event_bus.dispatch(&mut event.clone(), ());
+        }
     }
-}
\ No newline at end of file
+}
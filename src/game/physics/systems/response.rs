@@ -0,0 +1,165 @@
+use crate::game::ecs::entity::EntityId;
+use crate::game::ecs::world::EcsWorld;
+use crate::game::ecs::World;
+use crate::game::physics::components::{RigidDynamic, Static, Transform, Trigger};
+use crate::game::physics::contact::Contact;
+use crate::math::vec::Vec2;
+use crate::ui::geometry::geom;
+use hashbrown::HashMap;
+
+#[derive(Clone, Copy)]
+struct Body {
+    inv_mass: f32,
+    velocity: Vec2,
+    /// Restitution and friction, if the body has a [`RigidDynamic`]
+    material: Option<(f32, f32)>,
+}
+
+impl Body {
+    fn load(world: &World, id: EntityId) -> Self {
+        let is_static = world.has_component::<Static>(id);
+        match world.get_component::<RigidDynamic>(id) {
+            Some(rigid) => Self {
+                inv_mass: if is_static { 0.0 } else { (1.0 / rigid.mass) as f32 },
+                velocity: rigid.velocity,
+                material: Some((rigid.restitution, rigid.friction)),
+            },
+            None => Self {
+                inv_mass: 0.0,
+                velocity: Vec2::default(),
+                material: None,
+            },
+        }
+    }
+}
+
+struct SolverContact {
+    contact: Contact,
+    inv_mass_sum: f32,
+    /// The relative velocity along the normal the solver tries to reach, from restitution
+    target_velocity: f32,
+    friction: f32,
+    normal_impulse: f32,
+    tangent_impulse: f32,
+}
+
+/// Resolves contacts found by the collision systems by applying impulses to the velocities of [`RigidDynamic`] bodies
+/// and pushing penetrating bodies apart.
+///
+/// Bodies marked [`Static`] or without a [`RigidDynamic`] have infinite mass and are never moved.
+/// Contacts involving a [`Trigger`] are ignored.
+/// Contacts are solved in the order they are given, so the same contacts always produce the same result.
+pub struct ContactSolver {
+    /// How often all contacts are solved per step. More iterations make stacks and multiple contacts more stable.
+    pub iterations: u32,
+    /// Penetration depth that is tolerated without positional correction, prevents jitter of resting bodies.
+    pub slop: f32,
+    /// How much of the remaining penetration is removed per step, between 0 and 1.
+    pub correction: f32,
+}
+
+impl ContactSolver {
+    pub fn new() -> Self {
+        Self {
+            iterations: 8,
+            slop: 0.01,
+            correction: 0.8,
+        }
+    }
+
+    pub fn solve(&self, world: &mut World, contacts: &[Contact]) {
+        let mut bodies: HashMap<EntityId, Body> = HashMap::new();
+        let mut solver_contacts = Vec::with_capacity(contacts.len());
+
+        for contact in contacts {
+            if world.has_component::<Trigger>(contact.a) || world.has_component::<Trigger>(contact.b) {
+                continue;
+            }
+            let a = *bodies.entry(contact.a).or_insert_with(|| Body::load(world, contact.a));
+            let b = *bodies.entry(contact.b).or_insert_with(|| Body::load(world, contact.b));
+            let inv_mass_sum = a.inv_mass + b.inv_mass;
+            if inv_mass_sum <= 0.0 {
+                continue;
+            }
+
+            let (restitution, friction) = match (a.material, b.material) {
+                (Some((ra, fa)), Some((rb, fb))) => (ra.max(rb), (fa * fb).sqrt()),
+                (Some(m), None) | (None, Some(m)) => m,
+                (None, None) => (0.0, 0.0),
+            };
+            let approach = geom::dot(b.velocity - a.velocity, contact.normal);
+
+            solver_contacts.push(SolverContact {
+                contact: *contact,
+                inv_mass_sum,
+                target_velocity: if approach < 0.0 { -restitution * approach } else { 0.0 },
+                friction,
+                normal_impulse: 0.0,
+                tangent_impulse: 0.0,
+            });
+        }
+
+        for _ in 0..self.iterations {
+            for sc in &mut solver_contacts {
+                let n = sc.contact.normal;
+                let (a, b) = (bodies[&sc.contact.a], bodies[&sc.contact.b]);
+
+                // normal impulse, accumulated impulse is clamped so bodies are only ever pushed apart
+                let vn = geom::dot(b.velocity - a.velocity, n);
+                let lambda = (sc.target_velocity - vn) / sc.inv_mass_sum;
+                let new_impulse = (sc.normal_impulse + lambda).max(0.0);
+                let normal_delta = new_impulse - sc.normal_impulse;
+                sc.normal_impulse = new_impulse;
+                Self::apply(&mut bodies, &sc.contact, n * normal_delta);
+
+                // friction impulse, limited by the normal impulse
+                let (a, b) = (bodies[&sc.contact.a], bodies[&sc.contact.b]);
+                let t = geom::perpendicular(n);
+                let vt = geom::dot(b.velocity - a.velocity, t);
+                let max_friction = sc.friction * sc.normal_impulse;
+                let new_impulse = (sc.tangent_impulse - vt / sc.inv_mass_sum).clamp(-max_friction, max_friction);
+                let tangent_delta = new_impulse - sc.tangent_impulse;
+                sc.tangent_impulse = new_impulse;
+                Self::apply(&mut bodies, &sc.contact, t * tangent_delta);
+            }
+        }
+
+        for (id, body) in &bodies {
+            if body.inv_mass > 0.0 {
+                if let Some(rigid) = world.get_component_mut::<RigidDynamic>(*id) {
+                    rigid.velocity = body.velocity;
+                }
+            }
+        }
+
+        for sc in &solver_contacts {
+            let amount = (sc.contact.depth - self.slop).max(0.0) * self.correction / sc.inv_mass_sum;
+            if amount <= 0.0 {
+                continue;
+            }
+            let correction = sc.contact.normal * amount;
+            let (a, b) = (bodies[&sc.contact.a], bodies[&sc.contact.b]);
+            if let Some(t) = world.get_component_mut::<Transform>(sc.contact.a) {
+                t.position -= correction * a.inv_mass;
+            }
+            if let Some(t) = world.get_component_mut::<Transform>(sc.contact.b) {
+                t.position += correction * b.inv_mass;
+            }
+        }
+    }
+
+    fn apply(bodies: &mut HashMap<EntityId, Body>, contact: &Contact, impulse: Vec2) {
+        if let Some(a) = bodies.get_mut(&contact.a) {
+            a.velocity -= impulse * a.inv_mass;
+        }
+        if let Some(b) = bodies.get_mut(&contact.b) {
+            b.velocity += impulse * b.inv_mass;
+        }
+    }
+}
+
+impl Default for ContactSolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,22 +1,29 @@
-use crate::game::ecs::system::System;
+use crate::game::ecs::command::Commands;
+use crate::game::ecs::query::Query;
+use crate::game::ecs::schedule::{Access, ScheduledSystem, SystemWorld};
 use crate::game::ecs::World;
 use crate::game::physics::components::{RigidDynamic, Transform};
 use crate::math::vec::Vec2;
 use crate::ui::geometry::geom;
 
 pub struct RigidSystem {
-    s: System<(Transform, RigidDynamic)>
+    query: Query<(&'static mut Transform, &'static RigidDynamic)>,
 }
 
 impl RigidSystem {
     pub fn new() -> Self {
         Self {
-            s: System::new(),
+            query: Query::new(),
         }
     }
 
     pub fn iterate(&mut self, world: &mut World, dt: f64) {
-        for (entity, (trns, dy)) in self.s.iter_mut(world) {
+        let access = self.access();
+        self.step(&mut SystemWorld::new(world, &access), dt);
+    }
+
+    fn step(&mut self, world: &mut SystemWorld, dt: f64) {
+        for (_, (trns, dy)) in world.query_mut(&mut self.query) {
             if !geom::is_vec_zero(dy.velocity) {
                 Self::apply_vel(&mut trns.position, dy.velocity, dt);
             }
@@ -33,4 +40,14 @@ impl RigidSystem {
         target.x += vel.x * dt as f32;
         target.y += vel.y * dt as f32;
     }
-}
\ No newline at end of file
+}
+
+impl ScheduledSystem for RigidSystem {
+    fn access(&self) -> Access {
+        self.query.access()
+    }
+
+    fn run(&mut self, world: &mut SystemWorld, _: &mut Commands, dt: f64) {
+        self.step(world, dt);
+    }
+}
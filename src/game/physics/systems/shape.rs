@@ -0,0 +1,153 @@
+use crate::game::ecs::command::Commands;
+use crate::game::ecs::entity::EntityId;
+use crate::game::ecs::query::Query;
+use crate::game::ecs::schedule::{Access, ScheduledSystem, SystemWorld};
+use crate::game::ecs::World;
+use crate::game::physics::broadphase::{Aabb, BroadPhase, DynamicAabbTree};
+use crate::game::physics::components::{AABBCollider, CircleCollider, OBBCollider, PolygonCollider, Static, Transform};
+use crate::game::physics::contact::Contact;
+use crate::game::physics::sat;
+use crate::game::physics::sat::ColliderShape;
+use crate::math::vec::Vec2;
+use crate::ui::geometry::geom;
+use crate::ui::geometry::polygon::Polygon;
+
+type ShapeData = (
+    &'static Transform,
+    Option<&'static CircleCollider>,
+    Option<&'static OBBCollider>,
+    Option<&'static PolygonCollider>,
+    Option<&'static AABBCollider>,
+    Option<&'static Static>,
+);
+type ShapeQuery = Query<ShapeData>;
+
+struct ShapeEntry {
+    id: EntityId,
+    shape: ColliderShape,
+    is_aabb: bool,
+    is_static: bool,
+}
+
+/// Finds collisions involving [`CircleCollider`], [`OBBCollider`] and [`PolygonCollider`], taking rotation into account.
+///
+/// An entity should only have one collider, if it has several the first of circle, oriented box, polygon and aabb is used.
+/// Entities with an [`AABBCollider`] take part as well, but pairs of two aabbs are left to the
+/// [`AabbCollisionSystem`](crate::game::physics::systems::aabb::AabbCollisionSystem).
+pub struct ShapeCollisionSystem {
+    query: ShapeQuery,
+    broad_phase: Box<dyn BroadPhase>,
+    entries: Vec<ShapeEntry>,
+    boxes: Vec<(EntityId, Aabb)>,
+    pairs: Vec<(usize, usize)>,
+    contacts: Vec<Contact>,
+}
+
+impl ShapeCollisionSystem {
+    pub fn new() -> Self {
+        Self::with_broad_phase(DynamicAabbTree::new(0.1))
+    }
+
+    /// Uses `broad_phase` to find the candidate pairs that are passed on to the separating axis test.
+    pub fn with_broad_phase<B: BroadPhase + 'static>(broad_phase: B) -> Self {
+        Self {
+            query: Query::new(),
+            broad_phase: Box::new(broad_phase),
+            entries: Vec::new(),
+            boxes: Vec::new(),
+            pairs: Vec::new(),
+            contacts: Vec::new(),
+        }
+    }
+
+    pub fn set_broad_phase<B: BroadPhase + 'static>(&mut self, broad_phase: B) {
+        self.broad_phase = Box::new(broad_phase);
+    }
+
+    /// Every contact found in the last iteration, with `a < b` and sorted by `(a, b)`.
+    pub fn contacts(&self) -> &[Contact] {
+        &self.contacts
+    }
+
+    pub fn iterate(&mut self, world: &mut World) {
+        let access = self.access();
+        self.step(&mut SystemWorld::new(world, &access));
+    }
+
+    fn step(&mut self, world: &mut SystemWorld) {
+        self.entries.clear();
+        for (id, (t, circle, obb, polygon, aabb, s)) in world.query(&mut self.query) {
+            let (shape, is_aabb) = if let Some(circle) = circle {
+                let scale = t.scale.x.abs().max(t.scale.y.abs());
+                (ColliderShape::Circle { center: t.position, radius: circle.radius * scale }, false)
+            } else if let Some(obb) = obb {
+                let half = obb.extent * 0.5;
+                let corners = [
+                    Vec2::new(-half.x, -half.y),
+                    Vec2::new(half.x, -half.y),
+                    Vec2::new(half.x, half.y),
+                    Vec2::new(-half.x, half.y),
+                ];
+                (Self::to_world(t, &corners), false)
+            } else if let Some(polygon) = polygon {
+                (Self::to_world(t, &polygon.vertices), false)
+            } else if let Some(aabb) = aabb {
+                let min = t.position - aabb.extent * 0.5;
+                let max = t.position + aabb.extent * 0.5;
+                let corners = vec![min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)];
+                (ColliderShape::Polygon(Polygon { vertices: corners }), true)
+            } else {
+                continue;
+            };
+            self.entries.push(ShapeEntry {
+                id,
+                shape,
+                is_aabb,
+                is_static: s.is_some(),
+            });
+        }
+
+        self.boxes.clear();
+        self.boxes.extend(self.entries.iter().map(|e| (e.id, e.shape.bounds())));
+        self.pairs.clear();
+        self.broad_phase.find_pairs(&self.boxes, &mut self.pairs);
+
+        self.contacts.clear();
+        for &(i, j) in &self.pairs {
+            let (e1, e2) = (&self.entries[i], &self.entries[j]);
+            if (e1.is_static && e2.is_static) || (e1.is_aabb && e2.is_aabb) {
+                continue;
+            }
+            // always test with the smaller id first, so the normal does not depend on the iteration order
+            let (e1, e2) = if e1.id < e2.id { (e1, e2) } else { (e2, e1) };
+            if let Some(hit) = sat::collide(&e1.shape, &e2.shape) {
+                self.contacts.push(Contact {
+                    a: e1.id,
+                    b: e2.id,
+                    normal: hit.normal,
+                    depth: hit.depth,
+                    point: hit.point,
+                });
+            }
+        }
+        self.contacts.sort_unstable_by_key(|c| (c.a, c.b));
+    }
+
+    fn to_world(t: &Transform, vertices: &[Vec2]) -> ColliderShape {
+        let vertices = vertices
+            .iter()
+            .map(|v| t.position + geom::rotate_vector(*v * t.scale, t.rotation as f32))
+            .collect();
+        ColliderShape::Polygon(Polygon { vertices })
+    }
+}
+
+impl ScheduledSystem for ShapeCollisionSystem {
+    fn access(&self) -> Access {
+        self.query.access()
+    }
+
+    fn run(&mut self, world: &mut SystemWorld, _: &mut Commands, _: f64) {
+        self.step(world);
+    }
+}
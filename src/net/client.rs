@@ -1,28 +1,53 @@
-use crate::net::{DisconnectReason, ReadPacketError, try_read_packet};
-use bytebuffer::{ByteBuffer, Endian};
-use crossbeam_channel::Sender;
+use crate::net::rpc::{Call, CloseCalls, PendingCalls, Request, RpcError};
+use crate::net::secure::ClientHandshake;
+use crate::net::session::{wait_for_sockets, ConnectionConfig, KeepAlive, Message, SharedRtt};
+use crate::net::tcp::{FrameReader, TcpOut};
+use crate::net::udp::{Connection, Datagram, Delivery, Link, UdpConfig, DISCONNECT_REPEATS};
+use crate::net::{
+    decode_packet, encode_framed, encode_packet, try_read_packet, DisconnectReason, ReadPacketError, RejectReason,
+};
+use crossbeam_channel::{Receiver, Sender};
 use log::{debug, error, info, warn};
 use mvutils::save::Savable;
 use parking_lot::RwLock;
+use polling::{Event, Events, PollMode, Poller};
 use std::io::{ErrorKind, Write};
 use std::marker::PhantomData;
-use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
 use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub struct Client<In: Savable, Out: Savable> {
-    _maker: PhantomData<In>,
+    _maker: PhantomData<(In, Out)>,
     _thread: JoinHandle<()>,
     disconnect_sender: Sender<DisconnectReason>,
-    packet_sender: Sender<Out>,
+    packet_sender: Sender<(Message, Delivery)>,
+    rtt: Arc<SharedRtt>,
+    calls: Arc<PendingCalls>,
+    call_timeout: Duration,
+    /// Wakes up the client thread, which sleeps until the socket is ready.
+    waker: Arc<Poller>,
 }
 
 impl<In: Savable, Out: Savable + Send + 'static> Client<In, Out> {
+    /// Connects to a server started with [`Server::listen`](crate::net::server::Server::listen) using the default [`ConnectionConfig`].
     pub fn connect<Handler: ClientHandler<In> + Sync + 'static>(
         to: impl ToSocketAddrs,
         handler: Arc<RwLock<Handler>>,
+    ) -> Option<Self> {
+        Self::connect_with(to, handler, ConnectionConfig::default())
+    }
+
+    /// Connects to a server started with [`Server::listen`](crate::net::server::Server::listen).
+    /// Blocks until the server answered the handshake or [`ConnectionConfig::handshake_timeout`] passed.
+    /// If the server rejects the client, [`ClientHandler::on_disconnected`] is called with [`DisconnectReason::Rejected`].
+    /// If the client expects a secure server that cannot prove its identity, it is called with [`DisconnectReason::Tampered`].
+    pub fn connect_with<Handler: ClientHandler<In> + Sync + 'static>(
+        to: impl ToSocketAddrs,
+        handler: Arc<RwLock<Handler>>,
+        config: ConnectionConfig,
     ) -> Option<Self> {
         let tcp = TcpStream::connect(to);
         if let Err(e) = tcp {
@@ -30,131 +55,521 @@ impl<In: Savable, Out: Savable + Send + 'static> Client<In, Out> {
             return None;
         }
         let mut tcp = tcp.unwrap();
-        if let Err(_) = tcp.set_nonblocking(true) {
+
+        if let Err(e) = tcp.set_read_timeout(Some(config.handshake_timeout)) {
+            error!("Cannot set read timeout of TcpStream: {e}");
+            return None;
+        }
+        let handshake = config.server_key.as_ref().map(ClientHandshake::new);
+        let hello = match &handshake {
+            Some(handshake) => match handshake.seal_hello(config.protocol_version, &config.hello) {
+                Ok(payload) => Message::Hello {
+                    version: config.protocol_version,
+                    payload,
+                    key: Some(handshake.public()),
+                },
+                Err(e) => {
+                    error!("Could not connect to server, {e}");
+                    return None;
+                }
+            },
+            None => plain_hello(&config),
+        };
+        if let Err(e) = tcp.write_all(&encode_framed(&hello)) {
+            error!("Could not connect to server, {e}");
+            return None;
+        }
+        let keys = match try_read_packet::<Message>(&mut tcp, config.max_frame_size) {
+            Ok(Message::Welcome { key, confirm }) => match (handshake, key) {
+                (Some(handshake), Some(key)) => match handshake.finish(&key, &confirm) {
+                    Ok(keys) => Some(keys),
+                    Err(e) => {
+                        error!("Could not connect to server, {e}");
+                        handler.write().on_disconnected(DisconnectReason::Tampered);
+                        return None;
+                    }
+                },
+                (None, None) => None,
+                _ => {
+                    error!("Could not connect to server, it answered with a different security than requested");
+                    handler.write().on_disconnected(DisconnectReason::Tampered);
+                    return None;
+                }
+            },
+            Ok(Message::Reject(reason)) => {
+                error!("Server rejected the connection: {reason:?}");
+                handler.write().on_disconnected(DisconnectReason::Rejected(reason));
+                return None;
+            }
+            Ok(message) => {
+                error!("Could not connect to server, unexpected handshake answer {message:?}");
+                return None;
+            }
+            Err(ReadPacketError::FromTcp(e)) => {
+                error!("Could not connect to server, {e}");
+                return None;
+            }
+            Err(ReadPacketError::FromSavable(s)) => {
+                error!("Could not connect to server, invalid handshake answer: {s}");
+                return None;
+            }
+        };
+        if tcp.set_read_timeout(None).and_then(|_| tcp.set_nonblocking(true)).is_err() {
             error!("Cannot set TcpStream into non-blocking mode");
             return None;
         }
+        let _ = tcp.set_nodelay(true);
+        let poller = new_poller()?;
+        let out = TcpOut::new(tcp, 0, config.max_queued_bytes);
+        let mut opener = keys.map(|(sealer, opener)| {
+            out.secure(sealer);
+            opener
+        });
+        if let Err(e) = out.register(&poller) {
+            error!("Cannot watch TcpStream: {e}");
+            return None;
+        }
         info!("Connected to server");
 
-        let (disconnect_sen, disconnect_rec) = crossbeam_channel::unbounded();
-        let (packet_sen, packet_rec) = crossbeam_channel::unbounded::<Out>();
+        let waker = poller.clone();
+        Some(Self::start(config.call_timeout, waker, move |client| {
+            let ClientThread {
+                disconnects: disconnect_rec,
+                packets: packet_rec,
+                calls: thread_calls,
+                rtt: cloned_rtt,
+            } = client;
+            let cloned = handler;
+            cloned.write().on_connected();
+            let mut keep_alive = KeepAlive::new(Instant::now());
+            let mut reader = FrameReader::new(config.max_frame_size);
+            let mut events = Events::new();
+            let tick = config.tick();
+            let mut next_tick = Instant::now();
 
-        let cloned_dis_sen = disconnect_sen.clone();
-        let cloned = handler.clone();
+            let close = |reason: Option<DisconnectReason>, handler: &mut Handler| {
+                out.deregister(&poller);
+                if let Err(e) = out.stream().shutdown(Shutdown::Both) {
+                    debug!("Error when shutting down socket connection: {e}");
+                }
+                if let Some(reason) = reason {
+                    handler.on_disconnected(reason);
+                }
+            };
 
-        let handle = thread::spawn(move || {
-            fn write_packet(tcp: &mut TcpStream, vec: &[u8]) {
-                let mut written = 0;
+            loop {
+                wait_for_sockets(&poller, &mut events, next_tick);
+                let now = Instant::now();
+                let mut handler = cloned.write();
 
-                while written < vec.len() {
-                    match tcp.write(&vec[written..]) {
-                        Ok(0) => {
-                            warn!("Socket closed while writing");
-                            break;
+                if let Ok(reason) = disconnect_rec.try_recv() {
+                    debug!("Disconnecting from server, reason: {reason:?}");
+                    close(None, &mut handler);
+                    return;
+                }
+
+                while let Ok((message, _)) = packet_rec.try_recv() {
+                    if !out.send(&encode_packet(&message), &poller) {
+                        warn!("Server does not read fast enough, write queue is full");
+                        close(Some(DisconnectReason::Congested), &mut handler);
+                        return;
+                    }
+                }
+
+                for event in events.iter() {
+                    if event.writable {
+                        if let Err(e) = out.flush(&poller) {
+                            debug!("Error while writing to server: {e}");
+                            close(Some(DisconnectReason::Disconnected), &mut handler);
+                            return;
+                        }
+                    }
+                    if !event.readable {
+                        continue;
+                    }
+                    let open = match reader.read_from(out.stream()) {
+                        Ok(open) => open,
+                        Err(e) if e.kind() == ErrorKind::TimedOut => {
+                            close(Some(DisconnectReason::TimedOut), &mut handler);
+                            return;
                         }
-                        Ok(n) => written += n,
-                        Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
-                            debug!("WouldBlock error, attempting to rewrite packet");
-                            thread::sleep(Duration::from_millis(1));
-                            continue;
+                        Err(e) if e.kind() == ErrorKind::InvalidData => {
+                            warn!("Dropping connection to server: {e}");
+                            close(Some(DisconnectReason::Oversized), &mut handler);
+                            return;
                         }
                         Err(e) => {
-                            warn!("Error when attempting to write packet: {e}");
-                            break;
+                            debug!("Error while reading from server: {e}");
+                            false
+                        }
+                    };
+                    while let Some(mut frame) = reader.next_frame() {
+                        if let Some(opener) = &mut opener {
+                            match opener.open(&frame) {
+                                Ok(plaintext) => frame = plaintext,
+                                Err(e) => {
+                                    warn!("Dropping connection to server: {e}");
+                                    close(Some(DisconnectReason::Tampered), &mut handler);
+                                    return;
+                                }
+                            }
+                        }
+                        keep_alive.received(now);
+                        if let Some(answer) =
+                            receive(&mut *handler, frame, &keep_alive, now, &cloned_rtt, &thread_calls)
+                        {
+                            if !out.send(&encode_packet(&answer), &poller) {
+                                warn!("Server does not read fast enough, write queue is full");
+                                close(Some(DisconnectReason::Congested), &mut handler);
+                                return;
+                            }
                         }
                     }
+                    if !open {
+                        info!("Server closed the connection");
+                        close(Some(DisconnectReason::Disconnected), &mut handler);
+                        return;
+                    }
+                }
+
+                if now >= next_tick {
+                    if keep_alive.timed_out(now, config.idle_timeout) {
+                        info!("Server did not answer for {:?}, disconnecting", config.idle_timeout);
+                        close(Some(DisconnectReason::TimedOut), &mut handler);
+                        return;
+                    }
+                    if let Some(ping) = keep_alive.ping(now, config.keep_alive_interval) {
+                        if !out.send(&encode_packet(&ping), &poller) {
+                            warn!("Server does not read fast enough, write queue is full");
+                            close(Some(DisconnectReason::Congested), &mut handler);
+                            return;
+                        }
+                    }
+                    thread_calls.expire(now);
+                    next_tick = now + tick;
                 }
             }
+        }))
+    }
+
+    /// Connects to a server started with [`Server::listen_udp`](crate::net::server::Server::listen_udp).
+    /// Blocks until the server answered the handshake or [`ConnectionConfig::handshake_timeout`] passed.
+    /// If the server rejects the client, [`ClientHandler::on_disconnected`] is called with [`DisconnectReason::Rejected`].
+    /// Secure connections are only available over TCP, so this fails if [`ConnectionConfig::server_key`] is set.
+    pub fn connect_udp<Handler: ClientHandler<In> + Sync + 'static>(
+        to: impl ToSocketAddrs,
+        handler: Arc<RwLock<Handler>>,
+        config: ConnectionConfig,
+        udp: UdpConfig,
+    ) -> Option<Self> {
+        if config.server_key.is_some() {
+            error!("Could not connect to server, secure connections are only available over TCP");
+            return None;
+        }
+        let addr = match to.to_socket_addrs().map(|mut a| a.next()) {
+            Ok(Some(addr)) => addr,
+            Ok(None) => {
+                error!("Could not connect to server, address resolved to nothing");
+                return None;
+            }
+            Err(e) => {
+                error!("Could not connect to server, {e}");
+                return None;
+            }
+        };
+        let local: SocketAddr = if addr.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = match UdpSocket::bind(local) {
+            Ok(socket) => socket,
+            Err(e) => {
+                error!("Could not bind UDP socket, {e}");
+                return None;
+            }
+        };
+        if let Err(e) = socket.set_nonblocking(true) {
+            error!("Cannot set UdpSocket into non-blocking mode: {e}");
+            return None;
+        }
+        let mut link = Link::new(socket, udp.conditioner.clone());
+
+        let hello = encode_packet(&plain_hello(&config));
+        let start = Instant::now();
+        let mut buffer = vec![0u8; 65536];
+        let mut last_attempt = None;
+        'connect: loop {
+            let now = Instant::now();
+            if now.duration_since(start) > config.handshake_timeout {
+                error!("Could not connect to server, timed out");
+                return None;
+            }
+            if last_attempt.is_none_or(|at| now.duration_since(at) >= udp.resend_timeout) {
+                link.send_to(Datagram::Connect(&hello).encode(), addr, now);
+                last_attempt = Some(now);
+            }
+            link.flush(now);
+            while let Ok((len, from)) = link.socket().recv_from(&mut buffer) {
+                if from != addr {
+                    continue;
+                }
+                match Datagram::parse(&buffer[..len]) {
+                    Some(Datagram::Accept) => break 'connect,
+                    Some(Datagram::Reject(reason)) => match decode_packet::<RejectReason>(reason.to_vec()) {
+                        Ok(reason) => {
+                            error!("Server rejected the connection: {reason:?}");
+                            handler.write().on_disconnected(DisconnectReason::Rejected(reason));
+                            return None;
+                        }
+                        Err(s) => warn!("Could not deserialize reject reason: {s}"),
+                    },
+                    _ => {}
+                }
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        let poller = new_poller()?;
+        // SAFETY: the socket is deleted from the poller before the client thread ends
+        if let Err(e) = unsafe { poller.add_with_mode(link.socket(), Event::readable(0), PollMode::Level) } {
+            error!("Cannot watch UdpSocket: {e}");
+            return None;
+        }
+        info!("Connected to server");
+
+        let waker = poller.clone();
+        Some(Self::start(config.call_timeout, waker, move |client| {
+            let ClientThread {
+                disconnects: disconnect_rec,
+                packets: packet_rec,
+                calls: thread_calls,
+                rtt: cloned_rtt,
+            } = client;
+            let mut connection = Connection::new(&udp);
+            let mut keep_alive = KeepAlive::new(Instant::now());
+            let mut received = Vec::new();
+            let mut events = Events::new();
+            let tick = config.tick();
+            let mut next_tick = Instant::now();
+            handler.write().on_connected();
+
+            let close = |link: &mut Link, now: Instant| {
+                for _ in 0..DISCONNECT_REPEATS {
+                    link.send_to(Datagram::Disconnect.encode(), addr, now);
+                }
+                link.flush(now + Duration::from_secs(3600));
+                let _ = poller.delete(link.socket());
+            };
 
-            let mut handler = cloned.write();
-            handler.on_connected();
-            drop(handler);
             loop {
-                handler = cloned.write();
+                // wake up for incoming datagrams, resends, held back datagrams and keep-alives
+                let mut wake = next_tick;
+                wake = connection.next_poll().map_or(wake, |at| at.min(wake));
+                wake = link.next_flush().map_or(wake, |at| at.min(wake));
+                wait_for_sockets(&poller, &mut events, wake);
+
+                let now = Instant::now();
                 if let Ok(reason) = disconnect_rec.try_recv() {
                     debug!("Disconnecting from server, reason: {reason:?}");
-                    if let Err(e) = tcp.shutdown(Shutdown::Both) {
-                        warn!("Error when shutting down socket connection: {e}");
-                    }
+                    close(&mut link, now);
                     return;
                 }
 
-                while let Ok(packet) = packet_rec.try_recv() {
-                    // Build data (len_u32 + bytes)
-                    let mut buffer = ByteBuffer::new();
-                    buffer.set_endian(Endian::LittleEndian);
-                    packet.save(&mut buffer);
-                    let len = buffer.len() as u32;
-                    let len_bytes: [u8; 4] = len.to_le_bytes();
-                    let mut vec = len_bytes.to_vec();
-                    vec.extend(buffer.into_vec());
-
-                    // Write data
-                    write_packet(&mut tcp, &vec);
-                }
-
-                let mut packet = try_read_packet::<In>(&mut tcp);
-                while let Ok(inner) = packet {
-                    handler.on_packet(inner);
-                    packet = try_read_packet::<In>(&mut tcp);
-                }
-                if let Some(e) = packet.err() {
-                    if let ReadPacketError::FromTcp(tcp_err) = e {
-                        match tcp_err.kind() {
-                            ErrorKind::TimedOut => {
-                                if let Err(e) = disconnect_sen.send(DisconnectReason::TimedOut) {
-                                    warn!(
-                                        "Error when attempting to send disconnect to server thread: {e}"
-                                    );
-                                }
-                            }
-                            ErrorKind::ConnectionReset
-                            | ErrorKind::ConnectionAborted
-                            | ErrorKind::UnexpectedEof
-                            | ErrorKind::BrokenPipe
-                            | ErrorKind::NotConnected => {
-                                if let Err(e) = disconnect_sen.send(DisconnectReason::Disconnected)
-                                {
-                                    warn!(
-                                        "Error when attempting to send disconnect to server thread: {e}"
-                                    );
-                                }
-                            }
-                            _ => {
-                                //WouldBlock
-                            }
+                while let Ok((message, delivery)) = packet_rec.try_recv() {
+                    connection.send(delivery, &encode_packet(&message));
+                }
+
+                let mut handler = handler.write();
+                loop {
+                    let (len, from) = match link.socket().recv_from(&mut buffer) {
+                        Ok(r) => r,
+                        Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(e) => {
+                            debug!("Error while receiving datagram: {e}");
+                            break;
                         }
-                    } else {
-                        if let ReadPacketError::FromSavable(s) = e {
-                            println!("savable error: {s}");
+                    };
+                    if from != addr {
+                        continue;
+                    }
+                    let Some(datagram) = Datagram::parse(&buffer[..len]) else {
+                        continue;
+                    };
+                    keep_alive.received(now);
+                    match datagram {
+                        Datagram::Disconnect => {
+                            info!("Server closed the connection");
+                            let _ = poller.delete(link.socket());
+                            handler.on_disconnected(DisconnectReason::Disconnected);
+                            return;
+                        }
+                        Datagram::Accept | Datagram::Connect(_) | Datagram::Reject(_) => {}
+                        datagram => connection.receive(datagram, now, &mut received),
+                    }
+                    for bytes in received.drain(..) {
+                        if let Some(answer) =
+                            receive(&mut *handler, bytes, &keep_alive, now, &cloned_rtt, &thread_calls)
+                        {
+                            connection.send(Delivery::Unreliable, &encode_packet(&answer));
                         }
                     }
                 }
 
+                if now >= next_tick {
+                    if keep_alive.timed_out(now, config.idle_timeout) {
+                        info!("Server did not answer for {:?}, disconnecting", config.idle_timeout);
+                        close(&mut link, now);
+                        handler.on_disconnected(DisconnectReason::TimedOut);
+                        return;
+                    }
+                    if let Some(ping) = keep_alive.ping(now, config.keep_alive_interval) {
+                        connection.send(Delivery::Unreliable, &encode_packet(&ping));
+                    }
+                    thread_calls.expire(now);
+                    next_tick = now + tick;
+                }
                 drop(handler);
-                thread::sleep(Duration::from_millis(10));
+
+                for datagram in connection.poll(now) {
+                    link.send_to(datagram, addr, now);
+                }
+                link.flush(now);
             }
+        }))
+    }
+
+    /// Starts the client thread of a connected transport. `run` owns the thread and has to return once the connection
+    /// is closed, calls that are still pending then fail with [`RpcError::Disconnected`].
+    fn start(call_timeout: Duration, waker: Arc<Poller>, run: impl FnOnce(ClientThread) + Send + 'static) -> Self {
+        let (disconnect_sender, disconnects) = crossbeam_channel::unbounded();
+        let (packet_sender, packets) = crossbeam_channel::unbounded::<(Message, Delivery)>();
+        let calls = Arc::new(PendingCalls::new());
+        let rtt = Arc::new(SharedRtt::new());
+        let thread = ClientThread {
+            disconnects,
+            packets,
+            calls: calls.clone(),
+            rtt: rtt.clone(),
+        };
+
+        let handle = thread::spawn(move || {
+            let _close_calls = CloseCalls(thread.calls.clone());
+            run(thread);
         });
 
-        Some(Self {
+        Self {
             _maker: PhantomData::default(),
             _thread: handle,
-            disconnect_sender: cloned_dis_sen,
-            packet_sender: packet_sen,
-        })
+            disconnect_sender,
+            packet_sender,
+            rtt,
+            calls,
+            call_timeout,
+            waker,
+        }
     }
 
     pub fn disconnect(&mut self, reason: DisconnectReason) {
         if let Err(e) = self.disconnect_sender.send(reason) {
             warn!("Error when attempting to send disconnect to server thread: {e}");
         }
+        self.wake();
     }
 
+    /// Sends a packet reliable and ordered.
     pub fn send(&mut self, packet: Out) {
-        if let Err(e) = self.packet_sender.send(packet) {
+        self.send_with(packet, Delivery::ReliableOrdered);
+    }
+
+    /// Sends a packet with the given delivery mode. Over TCP, packets are always delivered reliable and ordered.
+    pub fn send_with(&mut self, packet: Out, delivery: Delivery) {
+        if let Err(e) = self.packet_sender.send((Message::Packet(encode_packet(&packet)), delivery)) {
             warn!("Error when sending packet: {e}");
         }
+        self.wake();
+    }
+
+    /// Sends `request` to the handler the server registered with [`Server::on_request`](crate::net::server::Server::on_request).
+    /// The returned [`Call`] fails with [`RpcError::TimedOut`] if no response arrived within [`ConnectionConfig::call_timeout`].
+    pub fn call<R: Request>(&self, request: R) -> Call<R::Response> {
+        self.call_with(request, self.call_timeout)
+    }
+
+    /// Like [`Client::call`], with a different timeout.
+    pub fn call_with<R: Request>(&self, request: R, timeout: Duration) -> Call<R::Response> {
+        let (id, call) = self.calls.start(Instant::now() + timeout);
+        let message = Message::Request {
+            id,
+            name: R::NAME.to_string(),
+            payload: encode_packet(&request),
+        };
+        if self.packet_sender.send((message, Delivery::ReliableOrdered)).is_err() {
+            self.calls.complete(id, Err(RpcError::Disconnected));
+        }
+        self.wake();
+        call
+    }
+
+    /// The round trip time measured by the last keep-alive, or `None` if none was answered yet.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt.get()
+    }
+
+    fn wake(&self) {
+        if let Err(e) = self.waker.notify() {
+            warn!("Error when attempting to wake up client thread: {e}");
+        }
+    }
+}
+
+/// What the thread of a connected client receives from the [`Client`].
+struct ClientThread {
+    disconnects: Receiver<DisconnectReason>,
+    packets: Receiver<(Message, Delivery)>,
+    calls: Arc<PendingCalls>,
+    rtt: Arc<SharedRtt>,
+}
+
+fn new_poller() -> Option<Arc<Poller>> {
+    match Poller::new() {
+        Ok(poller) => Some(Arc::new(poller)),
+        Err(e) => {
+            error!("Could not create poller: {e}");
+            None
+        }
+    }
+}
+
+/// The hello of a connection that is not secure.
+fn plain_hello(config: &ConnectionConfig) -> Message {
+    Message::Hello {
+        version: config.protocol_version,
+        payload: config.hello.clone(),
+        key: None,
+    }
+}
+
+/// Handles a message from the server. Returns the answer that has to be sent back, if any.
+fn receive<In: Savable>(
+    handler: &mut impl ClientHandler<In>,
+    bytes: Vec<u8>,
+    keep_alive: &KeepAlive,
+    now: Instant,
+    rtt: &SharedRtt,
+    calls: &PendingCalls,
+) -> Option<Message> {
+    match decode_packet::<Message>(bytes) {
+        Ok(Message::Packet(bytes)) => match decode_packet::<In>(bytes) {
+            Ok(packet) => handler.on_packet(packet),
+            Err(s) => warn!("Could not deserialize packet: {s}"),
+        },
+        Ok(Message::Ping(sent)) => return Some(Message::Pong(sent)),
+        Ok(Message::Pong(sent)) => keep_alive.pong(sent, now, rtt),
+        Ok(Message::Response { id, answer }) => calls.complete(id, answer),
+        Ok(_) => {}
+        Err(s) => warn!("Could not deserialize message: {s}"),
     }
+    None
 }
 
 pub trait ClientHandler<In: Savable>: Send {
@@ -1,5 +1,11 @@
 pub mod client;
+pub mod replication;
+pub mod rpc;
+pub mod secure;
 pub mod server;
+pub mod session;
+mod tcp;
+pub mod udp;
 
 use bytebuffer::ByteBuffer;
 use log::{debug, info, trace, warn};
@@ -19,6 +25,24 @@ use std::{env, fs, io};
 pub enum DisconnectReason {
     TimedOut,
     Disconnected,
+    /// The server refused the handshake.
+    Rejected(RejectReason),
+    /// The peer did not read its packets fast enough, see [`ConnectionConfig::max_queued_bytes`](session::ConnectionConfig::max_queued_bytes).
+    Congested,
+    /// A frame of a secure connection was altered, replayed or dropped.
+    Tampered,
+    /// The peer announced a frame larger than [`ConnectionConfig::max_frame_size`](session::ConnectionConfig::max_frame_size).
+    Oversized,
+}
+
+/// Why a server refused a client during the handshake.
+#[derive(Clone, Savable, Debug, PartialEq)]
+pub enum RejectReason {
+    VersionMismatch { server: u32, client: u32 },
+    /// Refused by [`ServerHandler::on_hello`](server::ServerHandler::on_hello).
+    Refused(String),
+    /// Only one side uses a secure connection, or the client expects a different server key.
+    Insecure(String),
 }
 
 pub(crate) enum ReadPacketError {
@@ -26,12 +50,15 @@ pub(crate) enum ReadPacketError {
     FromSavable(String),
 }
 
-pub(crate) fn try_read_packet<P: Savable>(stream: &mut TcpStream) -> Result<P, ReadPacketError> {
+pub(crate) fn try_read_packet<P: Savable>(stream: &mut TcpStream, max_len: usize) -> Result<P, ReadPacketError> {
     let mut len = [0u8; 4];
     stream
         .read_exact(&mut len)
         .map_err(ReadPacketError::FromTcp)?;
     let len = u32::from_le_bytes(len);
+    if len as usize > max_len {
+        return Err(ReadPacketError::FromTcp(tcp::oversized(len as usize, max_len)));
+    }
     let mut buffer = vec![0u8; len as usize];
     stream.set_nonblocking(false).unwrap();
     stream
@@ -43,3 +70,22 @@ pub(crate) fn try_read_packet<P: Savable>(stream: &mut TcpStream) -> Result<P, R
 
     P::load(&mut buffer).map_err(ReadPacketError::FromSavable)
 }
+
+pub(crate) fn encode_packet<P: Savable>(packet: &P) -> Vec<u8> {
+    let mut buffer = ByteBuffer::new_le();
+    packet.save(&mut buffer);
+    buffer.into_vec()
+}
+
+/// Encodes a packet prefixed with its length, as sent over TCP.
+pub(crate) fn encode_framed<P: Savable>(packet: &P) -> Vec<u8> {
+    let bytes = encode_packet(packet);
+    let mut vec = (bytes.len() as u32).to_le_bytes().to_vec();
+    vec.extend(bytes);
+    vec
+}
+
+pub(crate) fn decode_packet<P: Savable>(bytes: Vec<u8>) -> Result<P, String> {
+    let mut buffer = ByteBuffer::from_vec_le(bytes);
+    P::load(&mut buffer)
+}
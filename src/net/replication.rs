@@ -0,0 +1,444 @@
+//! Server authoritative replication of ECS entities.
+//!
+//! The server marks entities with [`Replicated`] and calls [`Replicator::replicate`] once per tick. Every connected
+//! client is sent a [`Snapshot`] of what changed since its previous one, limited to the entities it is interested in.
+//! The client hands the snapshots to a [`Replica`] in the order they arrived, which mirrors the entities into its own
+//! world under local ids.
+//!
+//! Snapshots are deltas against the previous snapshot, so they are always sent reliable and ordered. Both sides need a
+//! [`ComponentRegistry`] with the same names, components that only one side registered are skipped.
+
+use crate::game::ecs::entity::{next_entity_id, EntityId};
+use crate::game::ecs::save::ComponentRegistry;
+use crate::game::ecs::world::EcsWorld;
+use crate::game::ecs::World;
+use crate::net::server::{ClientEndpoint, ClientId};
+use crate::net::udp::Delivery;
+use bytebuffer::ByteBuffer;
+use hashbrown::HashMap;
+use log::warn;
+use mvutils::bytebuffer::ByteBufferExtras;
+use mvutils::hashers::U64IdentityHasher;
+use mvutils::save::Savable;
+use mvutils::Savable;
+use std::sync::Arc;
+
+/// Marks an entity to be replicated to clients. Only the components in the registry of the [`Replicator`] are sent.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct Replicated;
+
+/// Unchanged runs shorter than this are sent again instead of starting a new patch.
+const PATCH_GAP: usize = 8;
+
+/// A changed range of a serialized component.
+#[derive(Clone, Debug, PartialEq, Savable)]
+struct Patch {
+    offset: u32,
+    bytes: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Savable)]
+enum ComponentChange {
+    /// The whole component, for new components or when patching would not be smaller.
+    Set { component: u32, bytes: Vec<u8> },
+    /// The byte ranges that differ from the previously sent component of the same length.
+    Patch { component: u32, patches: Vec<Patch> },
+    Remove { component: u32 },
+}
+
+#[derive(Clone, Debug, PartialEq, Savable)]
+struct EntityUpdate {
+    id: EntityId,
+    changes: Vec<ComponentChange>,
+}
+
+/// What changed for one client since the previous snapshot it was sent.
+/// Entities are referred to by their id on the server, components by their index in the registry of the server.
+#[derive(Clone, Debug, PartialEq, Savable)]
+pub struct Snapshot {
+    sequence: u64,
+    /// The registered component names, only sent with the first snapshot.
+    components: Vec<String>,
+    /// Entities that were destroyed or are no longer of interest to the client.
+    despawned: Vec<EntityId>,
+    /// New entities with all of their components, and the changed components of known ones.
+    updated: Vec<EntityUpdate>,
+}
+
+impl Snapshot {
+    /// The number of snapshots sent to this client before this one.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+type Components = Vec<Option<Vec<u8>>>;
+type InterestFn = dyn Fn(&World, ClientId, EntityId) -> bool + Send + Sync;
+
+struct ClientView {
+    endpoint: Arc<ClientEndpoint>,
+    sequence: u64,
+    /// Every component as this client last received it.
+    known: HashMap<EntityId, Components, U64IdentityHasher>,
+}
+
+/// The server side of replication. Keeps track of what every client was sent and diffs the world against it.
+pub struct Replicator {
+    registry: ComponentRegistry,
+    clients: HashMap<ClientId, ClientView, U64IdentityHasher>,
+    interest: Option<Box<InterestFn>>,
+}
+
+impl Replicator {
+    pub fn new(registry: ComponentRegistry) -> Self {
+        Self {
+            registry,
+            clients: HashMap::with_hasher(U64IdentityHasher::default()),
+            interest: None,
+        }
+    }
+
+    /// Only replicates the entities for which `interest` returns true to a client.
+    /// Entities that stop being of interest are despawned on that client, and sent in full again once they are.
+    pub fn set_interest(&mut self, interest: impl Fn(&World, ClientId, EntityId) -> bool + Send + Sync + 'static) {
+        self.interest = Some(Box::new(interest));
+    }
+
+    /// Starts replicating to `client`, usually called from [`ServerHandler::on_client_connect`](crate::net::server::ServerHandler::on_client_connect).
+    /// Its first snapshot contains every replicated entity it is interested in.
+    pub fn add_client(&mut self, client: Arc<ClientEndpoint>) {
+        self.clients.insert(
+            client.id(),
+            ClientView {
+                endpoint: client,
+                sequence: 0,
+                known: HashMap::with_hasher(U64IdentityHasher::default()),
+            },
+        );
+    }
+
+    pub fn remove_client(&mut self, id: ClientId) {
+        self.clients.remove(&id);
+    }
+
+    /// Sends every client a [`Snapshot`] of the changes since the previous call. Clients without changes are sent nothing.
+    pub fn replicate(&mut self, world: &World) {
+        self.replicate_with(world, |snapshot| snapshot);
+    }
+
+    /// Like [`Replicator::replicate`], but wraps the snapshots into the packet type the clients receive.
+    pub fn replicate_with<P: Savable>(&mut self, world: &World, mut wrap: impl FnMut(Snapshot) -> P) {
+        let current = self.serialize(world);
+        for (id, client) in &mut self.clients {
+            let interested = |entity: EntityId| match &self.interest {
+                Some(interest) => interest(world, *id, entity),
+                None => true,
+            };
+            if let Some(snapshot) = diff(&self.registry, client, &current, interested) {
+                client.endpoint.send_with(wrap(snapshot), Delivery::ReliableOrdered);
+            }
+        }
+    }
+
+    /// Serializes the registered components of every replicated entity once for all clients.
+    fn serialize(&self, world: &World) -> HashMap<EntityId, Components, U64IdentityHasher> {
+        let mut current = HashMap::with_hasher(U64IdentityHasher::default());
+        let mut buffer = ByteBuffer::new_le();
+        for id in world.entities() {
+            if !world.has_component::<Replicated>(id) || current.contains_key(&id) {
+                continue;
+            }
+            let components = (0..self.registry.len())
+                .map(|idx| {
+                    buffer.clear();
+                    self.registry
+                        .save(idx, world, id, &mut buffer)
+                        .then(|| buffer.as_bytes().to_vec())
+                })
+                .collect();
+            current.insert(id, components);
+        }
+        current
+    }
+}
+
+fn diff(
+    registry: &ComponentRegistry,
+    client: &mut ClientView,
+    current: &HashMap<EntityId, Components, U64IdentityHasher>,
+    interested: impl Fn(EntityId) -> bool,
+) -> Option<Snapshot> {
+    let mut despawned = Vec::new();
+    client.known.retain(|id, _| {
+        let keep = current.contains_key(id) && interested(*id);
+        if !keep {
+            despawned.push(*id);
+        }
+        keep
+    });
+
+    let mut updated = Vec::new();
+    for (id, components) in current {
+        if !client.known.contains_key(id) && !interested(*id) {
+            continue;
+        }
+        let spawned = !client.known.contains_key(id);
+        let known = client.known.entry(*id).or_insert_with(|| vec![None; components.len()]);
+        let mut changes = Vec::new();
+        for (idx, (old, new)) in known.iter_mut().zip(components).enumerate() {
+            let component = idx as u32;
+            match (old.as_ref(), new) {
+                (None, Some(bytes)) => changes.push(ComponentChange::Set {
+                    component,
+                    bytes: bytes.clone(),
+                }),
+                (Some(old), Some(new)) if old != new => changes.push(change(component, old, new)),
+                (Some(_), None) => changes.push(ComponentChange::Remove { component }),
+                _ => continue,
+            }
+            old.clone_from(new);
+        }
+        if spawned || !changes.is_empty() {
+            updated.push(EntityUpdate { id: *id, changes });
+        }
+    }
+
+    if client.sequence > 0 && despawned.is_empty() && updated.is_empty() {
+        return None;
+    }
+    let components = if client.sequence == 0 {
+        registry.names().map(str::to_string).collect()
+    } else {
+        Vec::new()
+    };
+    let snapshot = Snapshot {
+        sequence: client.sequence,
+        components,
+        despawned,
+        updated,
+    };
+    client.sequence += 1;
+    Some(snapshot)
+}
+
+/// Patches the changed ranges if that is smaller than sending the whole component.
+fn change(component: u32, old: &[u8], new: &[u8]) -> ComponentChange {
+    if old.len() == new.len() {
+        let patches = patches(old, new);
+        // every patch costs its offset and the length of its bytes
+        let size: usize = patches.iter().map(|p| 12 + p.bytes.len()).sum();
+        if size < new.len() {
+            return ComponentChange::Patch { component, patches };
+        }
+    }
+    ComponentChange::Set {
+        component,
+        bytes: new.to_vec(),
+    }
+}
+
+fn patches(old: &[u8], new: &[u8]) -> Vec<Patch> {
+    let mut patches: Vec<Patch> = Vec::new();
+    let mut last_end = 0;
+    for i in 0..new.len() {
+        if old[i] == new[i] {
+            continue;
+        }
+        match patches.last_mut() {
+            Some(patch) if i - last_end < PATCH_GAP => patch.bytes.extend_from_slice(&new[last_end..=i]),
+            _ => patches.push(Patch {
+                offset: i as u32,
+                bytes: vec![new[i]],
+            }),
+        }
+        last_end = i + 1;
+    }
+    patches
+}
+
+/// The client side of replication. Applies the snapshots of a [`Replicator`] to a local world.
+///
+/// Replicated entities get new local ids, and components registered with
+/// [`ComponentRegistry::register_mapped`] have their references translated to them. An entity that is referenced
+/// before it was replicated is assigned its local id right away, so the reference is valid once it arrives later in
+/// the same snapshot. Ids of referenced entities that did not arrive are forgotten again at the end of the snapshot.
+///
+/// A snapshot that fails to apply leaves the world partially updated, so the replica refuses every later snapshot.
+pub struct Replica {
+    registry: ComponentRegistry,
+    /// The local registry index of every component index of the server.
+    table: Vec<Option<usize>>,
+    next_sequence: u64,
+    desynced: bool,
+    /// Server ids to local ids.
+    ids: HashMap<EntityId, EntityId, U64IdentityHasher>,
+    /// Local ids to server ids.
+    locals: HashMap<EntityId, EntityId, U64IdentityHasher>,
+    /// Every component as it was last received, by server id and server component index, to apply patches to.
+    known: HashMap<EntityId, HashMap<u32, Vec<u8>>, U64IdentityHasher>,
+}
+
+impl Replica {
+    pub fn new(registry: ComponentRegistry) -> Self {
+        Self {
+            registry,
+            table: Vec::new(),
+            next_sequence: 0,
+            desynced: false,
+            ids: HashMap::with_hasher(U64IdentityHasher::default()),
+            locals: HashMap::with_hasher(U64IdentityHasher::default()),
+            known: HashMap::with_hasher(U64IdentityHasher::default()),
+        }
+    }
+
+    /// Applies the next snapshot. Snapshots have to be applied in the order they were sent, skipping or repeating one is
+    /// an error. After a snapshot failed to apply, every later one is an error too.
+    pub fn apply(&mut self, world: &mut World, snapshot: Snapshot) -> Result<(), String> {
+        if self.desynced {
+            return Err("Replica is out of sync after a snapshot failed to apply".to_string());
+        }
+        if snapshot.sequence != self.next_sequence {
+            return Err(format!(
+                "Expected snapshot {}, got {}",
+                self.next_sequence, snapshot.sequence
+            ));
+        }
+        let mut referenced = Vec::new();
+        let result = self.apply_changes(world, snapshot, &mut referenced);
+        for server in referenced {
+            if !self.known.contains_key(&server) {
+                if let Some(local) = self.ids.remove(&server) {
+                    self.locals.remove(&local);
+                }
+            }
+        }
+        match result {
+            Ok(()) => self.next_sequence += 1,
+            Err(_) => self.desynced = true,
+        }
+        result
+    }
+
+    /// Applies the changes of `snapshot`, collecting the server ids that were assigned a local id by a reference.
+    fn apply_changes(
+        &mut self,
+        world: &mut World,
+        snapshot: Snapshot,
+        referenced: &mut Vec<EntityId>,
+    ) -> Result<(), String> {
+        if snapshot.sequence == 0 {
+            self.table = snapshot
+                .components
+                .iter()
+                .map(|name| {
+                    let idx = self.registry.index_of(name);
+                    if idx.is_none() {
+                        warn!("Replicated component '{name}' is not registered, skipping it");
+                    }
+                    idx
+                })
+                .collect();
+        }
+
+        for id in snapshot.despawned {
+            self.known.remove(&id);
+            if let Some(local) = self.ids.remove(&id) {
+                self.locals.remove(&local);
+                world.destroy_entity(local);
+            }
+        }
+
+        for update in snapshot.updated {
+            let local = local_id_of(&mut self.ids, &mut self.locals, update.id).0;
+            let known = self.known.entry(update.id).or_insert_with(|| {
+                world.create_entity(local);
+                HashMap::new()
+            });
+            for change in update.changes {
+                let component = match &change {
+                    ComponentChange::Set { component, .. }
+                    | ComponentChange::Patch { component, .. }
+                    | ComponentChange::Remove { component } => *component,
+                };
+                let registered = *self
+                    .table
+                    .get(component as usize)
+                    .ok_or_else(|| format!("Component index {component} is out of the component table"))?;
+
+                let bytes = match change {
+                    ComponentChange::Set { bytes, .. } => bytes,
+                    ComponentChange::Patch { patches, .. } => {
+                        let mut bytes = known
+                            .get(&component)
+                            .ok_or_else(|| format!("Patch for missing component {component} of entity {}", update.id))?
+                            .clone();
+                        for patch in patches {
+                            let start = patch.offset as usize;
+                            let target = bytes
+                                .get_mut(start..start + patch.bytes.len())
+                                .ok_or_else(|| format!("Patch out of bounds for component {component}"))?;
+                            target.copy_from_slice(&patch.bytes);
+                        }
+                        bytes
+                    }
+                    ComponentChange::Remove { .. } => {
+                        known.remove(&component);
+                        if let Some(idx) = registered {
+                            self.registry.remove(idx, world, local);
+                        }
+                        continue;
+                    }
+                };
+
+                if let Some(idx) = registered {
+                    let (ids, locals) = (&mut self.ids, &mut self.locals);
+                    let mut map = |server: EntityId| {
+                        let (local, assigned) = local_id_of(ids, locals, server);
+                        if assigned {
+                            referenced.push(server);
+                        }
+                        local
+                    };
+                    let mut buffer = ByteBuffer::from_vec_le(bytes.clone());
+                    self.registry.load(idx, world, local, &mut buffer, &mut map)?;
+                }
+                known.insert(component, bytes);
+            }
+        }
+        Ok(())
+    }
+
+    /// The local id of the entity with id `server` on the server.
+    pub fn local_id(&self, server: EntityId) -> Option<EntityId> {
+        self.ids.get(&server).copied()
+    }
+
+    /// The id on the server of the local entity `local`.
+    pub fn server_id(&self, local: EntityId) -> Option<EntityId> {
+        self.locals.get(&local).copied()
+    }
+
+    /// The number of replicated entities in the local world.
+    pub fn len(&self) -> usize {
+        self.known.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.known.is_empty()
+    }
+}
+
+/// The local id of `server`, and whether it was assigned just now.
+fn local_id_of(
+    ids: &mut HashMap<EntityId, EntityId, U64IdentityHasher>,
+    locals: &mut HashMap<EntityId, EntityId, U64IdentityHasher>,
+    server: EntityId,
+) -> (EntityId, bool) {
+    if let Some(local) = ids.get(&server) {
+        return (*local, false);
+    }
+    let local = next_entity_id();
+    ids.insert(server, local);
+    locals.insert(local, server);
+    (local, true)
+}
@@ -0,0 +1,199 @@
+//! Typed requests from clients to the server, answered with a response of the matching type.
+//!
+//! A request type implements [`Request`], the server registers one handler per request type with
+//! [`Server::on_request`](crate::net::server::Server::on_request), and the client sends requests with
+//! [`Client::call`](crate::net::client::Client::call). Requests and responses are sent next to the regular packets and
+//! matched up by an id, so they never reach [`ServerHandler::on_packet`](crate::net::server::ServerHandler::on_packet)
+//! or [`ClientHandler::on_packet`](crate::net::client::ClientHandler::on_packet).
+
+use crate::net::server::ClientEndpoint;
+use crate::net::{decode_packet, encode_packet};
+use hashbrown::HashMap;
+use mvutils::hashers::U64IdentityHasher;
+use mvutils::save::Savable;
+use mvutils::Savable;
+use parking_lot::{Condvar, Mutex};
+use std::fmt::{Display, Formatter};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// A request a client can send to the server.
+pub trait Request: Savable + Send + 'static {
+    type Response: Savable + Send + 'static;
+    /// Identifies the request type on the wire, so it has to be the same for client and server and unique among all
+    /// request types.
+    const NAME: &'static str;
+}
+
+/// Why a call did not return a response.
+#[derive(Clone, Debug, PartialEq, Savable)]
+pub enum RpcError {
+    /// No response arrived before the timeout.
+    TimedOut,
+    /// The connection closed before the response arrived.
+    Disconnected,
+    /// The server has no handler for this request type.
+    Unsupported(String),
+    /// The request or the response could not be deserialized.
+    Invalid(String),
+    /// The handler on the server returned an error.
+    Failed(String),
+}
+
+impl Display for RpcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcError::TimedOut => write!(f, "Call timed out"),
+            RpcError::Disconnected => write!(f, "Connection closed before the response arrived"),
+            RpcError::Unsupported(name) => write!(f, "Server does not handle requests of type '{name}'"),
+            RpcError::Invalid(e) => write!(f, "Could not deserialize call: {e}"),
+            RpcError::Failed(e) => write!(f, "Call failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+type Answer = Result<Vec<u8>, RpcError>;
+type ErasedHandler = dyn Fn(&Arc<ClientEndpoint>, Vec<u8>) -> Answer + Send + Sync;
+
+/// The request handlers of a server, by [`Request::NAME`].
+pub(crate) struct RequestHandlers {
+    handlers: HashMap<String, Box<ErasedHandler>>,
+}
+
+impl RequestHandlers {
+    pub(crate) fn new() -> Self {
+        Self {
+            handlers: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn register<R: Request>(
+        &mut self,
+        handler: impl Fn(&Arc<ClientEndpoint>, R) -> Result<R::Response, String> + Send + Sync + 'static,
+    ) {
+        let erased = move |client: &Arc<ClientEndpoint>, payload: Vec<u8>| {
+            let request = decode_packet::<R>(payload).map_err(RpcError::Invalid)?;
+            let response = handler(client, request).map_err(RpcError::Failed)?;
+            Ok(encode_packet(&response))
+        };
+        self.handlers.insert(R::NAME.to_string(), Box::new(erased));
+    }
+
+    pub(crate) fn answer(&self, client: &Arc<ClientEndpoint>, name: &str, payload: Vec<u8>) -> Answer {
+        match self.handlers.get(name) {
+            Some(handler) => handler(client, payload),
+            None => Err(RpcError::Unsupported(name.to_string())),
+        }
+    }
+}
+
+struct Slot {
+    answer: Mutex<Option<Answer>>,
+    ready: Condvar,
+}
+
+/// The calls of a client that are waiting for a response.
+pub(crate) struct PendingCalls {
+    next_id: AtomicU64,
+    calls: Mutex<HashMap<u64, (Instant, Arc<Slot>), U64IdentityHasher>>,
+}
+
+impl PendingCalls {
+    pub(crate) fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            calls: Mutex::new(HashMap::with_hasher(U64IdentityHasher::default())),
+        }
+    }
+
+    /// Starts waiting for the response to a new call, returning its id.
+    pub(crate) fn start<T>(&self, deadline: Instant) -> (u64, Call<T>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let slot = Arc::new(Slot {
+            answer: Mutex::new(None),
+            ready: Condvar::new(),
+        });
+        self.calls.lock().insert(id, (deadline, slot.clone()));
+        let call = Call {
+            slot,
+            deadline,
+            done: false,
+            _marker: PhantomData,
+        };
+        (id, call)
+    }
+
+    /// Hands the answer to the waiting call. Answers to calls that already timed out are dropped.
+    pub(crate) fn complete(&self, id: u64, answer: Answer) {
+        if let Some((_, slot)) = self.calls.lock().remove(&id) {
+            *slot.answer.lock() = Some(answer);
+            slot.ready.notify_all();
+        }
+    }
+
+    /// Forgets the calls whose deadline passed. They time out on their own, this only frees them.
+    pub(crate) fn expire(&self, now: Instant) {
+        self.calls.lock().retain(|_, (deadline, _)| *deadline > now);
+    }
+
+    /// Fails every waiting call, used when the connection closes.
+    pub(crate) fn fail_all(&self, error: &RpcError) {
+        for (_, (_, slot)) in self.calls.lock().drain() {
+            *slot.answer.lock() = Some(Err(error.clone()));
+            slot.ready.notify_all();
+        }
+    }
+}
+
+/// Fails the pending calls of a client with [`RpcError::Disconnected`] when its thread ends.
+pub(crate) struct CloseCalls(pub(crate) Arc<PendingCalls>);
+
+impl Drop for CloseCalls {
+    fn drop(&mut self) {
+        self.0.fail_all(&RpcError::Disconnected);
+    }
+}
+
+/// The pending response of [`Client::call`](crate::net::client::Client::call).
+pub struct Call<T> {
+    slot: Arc<Slot>,
+    deadline: Instant,
+    /// If [`Call::poll`] already returned the result.
+    done: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Savable> Call<T> {
+    /// Blocks until the response arrived, the call timed out or the connection closed.
+    pub fn wait(self) -> Result<T, RpcError> {
+        let mut answer = self.slot.answer.lock();
+        while answer.is_none() {
+            if self.slot.ready.wait_until(&mut answer, self.deadline).timed_out() {
+                break;
+            }
+        }
+        Self::decode(answer.take())
+    }
+
+    /// Returns the result without blocking, or `None` if the response did not arrive yet.
+    /// Once this returned a result, it returns `None` again.
+    pub fn poll(&mut self) -> Option<Result<T, RpcError>> {
+        if self.done {
+            return None;
+        }
+        let answer = self.slot.answer.lock().take();
+        if answer.is_none() && Instant::now() < self.deadline {
+            return None;
+        }
+        self.done = true;
+        Some(Self::decode(answer))
+    }
+
+    fn decode(answer: Option<Answer>) -> Result<T, RpcError> {
+        decode_packet::<T>(answer.unwrap_or(Err(RpcError::TimedOut))?).map_err(RpcError::Invalid)
+    }
+}
@@ -0,0 +1,217 @@
+//! Encrypted and authenticated TCP connections.
+//!
+//! A server with [`ConnectionConfig::server_secret`] only accepts clients that know the matching public key, set as
+//! [`ConnectionConfig::server_key`]. During the handshake both sides exchange fresh X25519 keys, and the session keys are
+//! derived from the ephemeral keys and the long term key of the server, so only the real server can complete it.
+//! The hello payload is already encrypted, every frame after the welcome is encrypted and authenticated with
+//! ChaCha20-Poly1305 and numbered, so that altered, dropped, reordered or replayed frames end the connection.
+//!
+//! [`ConnectionConfig::server_secret`]: crate::net::session::ConnectionConfig::server_secret
+//! [`ConnectionConfig::server_key`]: crate::net::session::ConnectionConfig::server_key
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// A X25519 secret or public key.
+pub type Key = [u8; 32];
+
+const HELLO_INFO: &[u8] = b"MVEngine net hello";
+const SESSION_INFO: &[u8] = b"MVEngine net session";
+const COUNTER_LEN: usize = 8;
+/// The length of the Poly1305 authentication tag.
+const TAG_LEN: usize = 16;
+
+/// Generates a new random secret key for a server.
+pub fn generate_secret() -> Key {
+    rand::random()
+}
+
+/// The public key belonging to `secret`, which clients need to connect to a server using it.
+pub fn public_key(secret: &Key) -> Key {
+    PublicKey::from(&StaticSecret::from(*secret)).to_bytes()
+}
+
+fn diffie_hellman(secret: &StaticSecret, public: &Key) -> Result<Key, String> {
+    let shared = secret.diffie_hellman(&PublicKey::from(*public));
+    // a low order public key would make the shared secret predictable
+    if !shared.was_contributory() {
+        return Err("Peer sent an invalid public key".to_string());
+    }
+    Ok(shared.to_bytes())
+}
+
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    nonce.into()
+}
+
+fn cipher(key: &[u8]) -> ChaCha20Poly1305 {
+    ChaCha20Poly1305::new(key.into())
+}
+
+/// The key the hello payload is encrypted with, known to the client and the owner of the server secret.
+fn hello_cipher(es: &Key, client_key: &Key) -> ChaCha20Poly1305 {
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(client_key), es)
+        .expand(HELLO_INFO, &mut key)
+        .expect("32 bytes is a valid HKDF output length");
+    cipher(&key)
+}
+
+/// Derives the keys for both directions, client to server first.
+fn session_ciphers(es: &Key, ee: &Key, client_key: &Key, server_key: &Key) -> (ChaCha20Poly1305, ChaCha20Poly1305) {
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(client_key);
+    salt[32..].copy_from_slice(server_key);
+    let mut ikm = [0u8; 64];
+    ikm[..32].copy_from_slice(es);
+    ikm[32..].copy_from_slice(ee);
+    let mut keys = [0u8; 64];
+    Hkdf::<Sha256>::new(Some(&salt), &ikm)
+        .expand(SESSION_INFO, &mut keys)
+        .expect("64 bytes is a valid HKDF output length");
+    (cipher(&keys[..32]), cipher(&keys[32..]))
+}
+
+/// Encrypts outgoing frames. Every frame is prefixed with its number, which is also the nonce.
+pub(crate) struct Sealer {
+    cipher: ChaCha20Poly1305,
+    counter: u64,
+}
+
+impl Sealer {
+    fn new(cipher: ChaCha20Poly1305) -> Self {
+        Self { cipher, counter: 0 }
+    }
+
+    /// The length of the frame [`Sealer::seal`] makes from `plaintext_len` bytes.
+    pub(crate) fn sealed_len(plaintext_len: usize) -> usize {
+        COUNTER_LEN + plaintext_len + TAG_LEN
+    }
+
+    pub(crate) fn seal(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let counter = self.counter;
+        self.counter += 1;
+        let sealed = self
+            .cipher
+            .encrypt(&nonce(counter), plaintext)
+            .expect("ChaCha20-Poly1305 encryption cannot fail for frames below 256 GiB");
+        let mut frame = Vec::with_capacity(COUNTER_LEN + sealed.len());
+        frame.extend_from_slice(&counter.to_le_bytes());
+        frame.extend(sealed);
+        frame
+    }
+}
+
+/// Decrypts incoming frames, which have to arrive exactly in the order they were sealed.
+pub(crate) struct Opener {
+    cipher: ChaCha20Poly1305,
+    next: u64,
+}
+
+impl Opener {
+    fn new(cipher: ChaCha20Poly1305) -> Self {
+        Self { cipher, next: 0 }
+    }
+
+    pub(crate) fn open(&mut self, frame: &[u8]) -> Result<Vec<u8>, String> {
+        if frame.len() < COUNTER_LEN {
+            return Err("Frame is too short".to_string());
+        }
+        let (counter, sealed) = frame.split_at(COUNTER_LEN);
+        let counter = u64::from_le_bytes(counter.try_into().expect("split at 8 bytes"));
+        if counter != self.next {
+            return Err(format!("Expected frame {}, got {counter}, it was replayed or dropped", self.next));
+        }
+        let plaintext = self
+            .cipher
+            .decrypt(&nonce(counter), sealed)
+            .map_err(|_| format!("Frame {counter} failed authentication"))?;
+        self.next += 1;
+        Ok(plaintext)
+    }
+}
+
+/// The client side of the key exchange, from sending the hello until the welcome arrived.
+pub(crate) struct ClientHandshake {
+    ephemeral: StaticSecret,
+    public: Key,
+    es: Result<Key, String>,
+}
+
+impl ClientHandshake {
+    pub(crate) fn new(server_key: &Key) -> Self {
+        let ephemeral = StaticSecret::from(generate_secret());
+        let public = PublicKey::from(&ephemeral).to_bytes();
+        let es = diffie_hellman(&ephemeral, server_key);
+        Self { ephemeral, public, es }
+    }
+
+    /// The ephemeral public key sent with the hello.
+    pub(crate) fn public(&self) -> Key {
+        self.public
+    }
+
+    pub(crate) fn seal_hello(&self, version: u32, payload: &[u8]) -> Result<Vec<u8>, String> {
+        let es = self.es.as_ref()?;
+        hello_cipher(es, &self.public)
+            .encrypt(&nonce(0), Payload {
+                msg: payload,
+                aad: &version.to_le_bytes(),
+            })
+            .map_err(|_| "Could not encrypt hello".to_string())
+    }
+
+    /// Derives the session keys from the welcome of the server. Fails if the server does not own the expected key.
+    pub(crate) fn finish(self, server_ephemeral: &Key, confirm: &[u8]) -> Result<(Sealer, Opener), String> {
+        let es = self.es?;
+        let ee = diffie_hellman(&self.ephemeral, server_ephemeral)?;
+        let (to_server, to_client) = session_ciphers(&es, &ee, &self.public, server_ephemeral);
+        let mut opener = Opener::new(to_client);
+        opener
+            .open(confirm)
+            .map_err(|_| "Server could not prove that it owns the server key".to_string())?;
+        Ok((Sealer::new(to_server), opener))
+    }
+}
+
+/// The server side of a completed key exchange.
+pub(crate) struct Accepted {
+    /// The decrypted hello payload.
+    pub(crate) payload: Vec<u8>,
+    /// The ephemeral public key of the server, sent with the welcome.
+    pub(crate) key: Key,
+    /// The first sealed frame, sent with the welcome to prove that the server owns its secret.
+    pub(crate) confirm: Vec<u8>,
+    pub(crate) sealer: Sealer,
+    pub(crate) opener: Opener,
+}
+
+/// Answers the hello of a client that sent `client_key` and the encrypted `payload`.
+pub(crate) fn accept(secret: &Key, version: u32, client_key: &Key, payload: &[u8]) -> Result<Accepted, String> {
+    let es = diffie_hellman(&StaticSecret::from(*secret), client_key)?;
+    let payload = hello_cipher(&es, client_key)
+        .decrypt(&nonce(0), Payload {
+            msg: payload,
+            aad: &version.to_le_bytes(),
+        })
+        .map_err(|_| "Could not decrypt hello, the client uses a different server key".to_string())?;
+
+    let ephemeral = StaticSecret::from(generate_secret());
+    let key = PublicKey::from(&ephemeral).to_bytes();
+    let ee = diffie_hellman(&ephemeral, client_key)?;
+    let (to_server, to_client) = session_ciphers(&es, &ee, client_key, &key);
+    let mut sealer = Sealer::new(to_client);
+    let confirm = sealer.seal(&[]);
+    Ok(Accepted {
+        payload,
+        key,
+        confirm,
+        sealer,
+        opener: Opener::new(to_server),
+    })
+}
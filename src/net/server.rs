@@ -1,20 +1,27 @@
-use crate::net::{DisconnectReason, ReadPacketError, try_read_packet};
-use bytebuffer::{ByteBuffer, Endian};
-use crossbeam_channel::Sender;
+use crate::net::rpc::{Request, RequestHandlers};
+use crate::net::secure;
+use crate::net::secure::{Accepted, Opener};
+use crate::net::session::{wait_for_sockets, ConnectionConfig, KeepAlive, Message, SharedRtt};
+use crate::net::tcp::{FrameReader, TcpOut};
+use crate::net::udp::{Connection, Datagram, Delivery, Link, UdpConfig, DISCONNECT_REPEATS};
+use crate::net::{decode_packet, encode_packet, DisconnectReason, RejectReason};
+use crossbeam_channel::{Receiver, Sender};
 use hashbrown::HashMap;
 use log::{debug, error, info, warn};
 use mvutils::hashers::U64IdentityHasher;
 use mvutils::save::Savable;
-use mvutils::unsafe_utils::DangerousCell;
 use mvutils::utils;
 use parking_lot::{Mutex, RwLock};
-use std::io::{ErrorKind, Write};
+use polling::{Event, Events, PollMode, Poller};
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::ErrorKind;
 use std::marker::PhantomData;
-use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::net::{Shutdown, SocketAddr, TcpListener, ToSocketAddrs, UdpSocket};
 use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub type ClientId = u64;
 
@@ -22,132 +29,558 @@ pub struct Server<In: Savable, Out: Savable> {
     _maker: PhantomData<(In, Out)>,
     thread: Option<JoinHandle<()>>,
     stopper: Option<Sender<String>>,
-    clients: Arc<RwLock<HashMap<ClientId, Arc<ClientEndpoint>, U64IdentityHasher>>>,
+    waker: Option<Arc<Poller>>,
+    clients: Clients,
+    local_addrs: Vec<SocketAddr>,
+    config: ConnectionConfig,
+    requests: Arc<RwLock<RequestHandlers>>,
 }
 
 impl<In: Savable, Out: Savable> Server<In, Out> {
     pub fn new() -> Self {
+        Self::with_config(ConnectionConfig::default())
+    }
+
+    /// Creates a server that uses `config` for the handshake and keep-alives. [`ConnectionConfig::hello`] is not used by servers.
+    pub fn with_config(config: ConnectionConfig) -> Self {
         Self {
             _maker: PhantomData::default(),
             thread: None,
             stopper: None,
+            waker: None,
             clients: Arc::new(RwLock::new(HashMap::with_hasher(
                 U64IdentityHasher::default(),
             ))),
+            local_addrs: Vec::new(),
+            config,
+            requests: Arc::new(RwLock::new(RequestHandlers::new())),
         }
     }
 
+    /// Answers every [`Request`] of type `R` with `handler`. An error is sent back as [`RpcError::Failed`](crate::net::rpc::RpcError::Failed).
+    /// Registering a handler for the same request type again replaces the old one, and handlers can be registered while
+    /// the server is running.
+    ///
+    /// Handlers run on the server thread in the order requests and packets arrived, but without the
+    /// [`ServerHandler`] being locked, so they may lock it themselves.
+    pub fn on_request<R: Request>(
+        &self,
+        handler: impl Fn(&Arc<ClientEndpoint>, R) -> Result<R::Response, String> + Send + Sync + 'static,
+    ) {
+        self.requests.write().register(handler);
+    }
+
+    /// Binds a listener to every address `addrs` resolves to and starts accepting clients on all of them.
+    /// Use `0.0.0.0` or `::` to accept connections from other machines, and a slice of addresses for several listeners.
+    ///
+    /// The server thread sleeps until a socket is ready, so idle servers cost no CPU time.
+    /// Packets are written right away when possible, the rest is queued per client and written once the socket accepts it again.
+    ///
+    /// Fails without starting the server if an address cannot be resolved or bound.
     pub fn listen<Handler: ServerHandler<In> + Send + 'static>(
         &mut self,
-        port: u16,
-    ) -> Arc<Mutex<Handler>> {
-        let (stop_sen, stop_rec) = crossbeam_channel::unbounded::<String>();
-        let (disconnect_sen, disconnect_rec) =
-            crossbeam_channel::unbounded::<(ClientId, DisconnectReason)>();
-        let clients = self.clients.clone();
-
-        let handler = Handler::on_server_start(port);
-        let handler_arc = Arc::new(Mutex::new(handler));
-        let handler_thread = handler_arc.clone();
-
-        let handle = thread::spawn(move || {
-            let addr = SocketAddr::from(([127, 0, 0, 1], port));
-            let socket = TcpListener::bind(addr);
-            if let Err(e) = socket {
-                error!("Could not start server: {e}");
-                return;
-            }
-            let socket = socket.unwrap();
-            if socket.set_nonblocking(true).is_err() {
-                error!("Cannot set TcpListener into non-blocking mode");
-                return;
+        addrs: impl ToSocketAddrs,
+    ) -> Result<Arc<Mutex<Handler>>, ListenError> {
+        let (listeners, poller) = self.bind::<TcpListener>(addrs)?;
+
+        Ok(self.start(poller, move |server: ServerThread<Handler>| {
+            let ServerThread {
+                handler: handler_thread,
+                poller,
+                config,
+                clients,
+                requests,
+                stop: stop_rec,
+                disconnect_sender: disconnect_sen,
+                disconnects: disconnect_rec,
+                local_addrs,
+            } = server;
+            for addr in &local_addrs {
+                info!("Listening on {addr}");
             }
+            // poller keys below listeners.len() are listeners, the others are peers
+            let mut peers: HashMap<usize, Peer> = HashMap::new();
+            let mut keys: HashMap<ClientId, usize, U64IdentityHasher> =
+                HashMap::with_hasher(U64IdentityHasher::default());
+            let mut next_key = listeners.len();
+            let mut events = Events::new();
+            let mut dispatch = Vec::new();
+            let mut closed = Vec::new();
+            let tick = config.tick();
+            let mut next_tick = Instant::now();
 
-            info!("Listening on port {port}");
             loop {
+                wait_for_sockets(&poller, &mut events, next_tick);
+                let now = Instant::now();
+
                 if let Ok(stop_msg) = stop_rec.try_recv() {
                     info!("Server stopped with message: {stop_msg}");
+                    for peer in peers.values() {
+                        peer.out.deregister(&poller);
+                    }
+                    for listener in &listeners {
+                        let _ = poller.delete(listener);
+                    }
                     handler_thread.lock().on_server_stop(&stop_msg);
                     return;
                 }
 
-                if let Ok((id, reason)) = disconnect_rec.try_recv() {
+                while let Ok((id, reason)) = disconnect_rec.try_recv() {
                     debug!("Attempt to disconnect client {id}, reason: {reason:?}");
-                    let mut map = clients.write();
-                    if let Some(client) = map.remove(&id) {
-                        info!("Client {id} disconnected, reason: {reason:?}");
-                        handler_thread.lock().on_client_disconnect(client, reason);
+                    if let Some(key) = keys.get(&id) {
+                        closed.push((*key, Some(reason)));
                     }
                 }
 
-                // Check for new incoming connections
-                loop {
-                    match socket.accept() {
-                        Ok((stream, addr)) => {
-                            info!("Client connected with address {addr:?}");
-                            let id = utils::next_id("MVEngine::net::server::Server::listen");
-                            let endpoint = ClientEndpoint::new(id, stream, disconnect_sen.clone());
-                            let arc = Arc::new(endpoint);
-                            handler_thread.lock().on_client_connect(arc.clone());
-                            let mut map = clients.write();
-                            map.insert(id, arc);
+                for event in events.iter() {
+                    if let Some(listener) = listeners.get(event.key) {
+                        // Check for new incoming connections
+                        loop {
+                            match listener.accept() {
+                                Ok((stream, addr)) => {
+                                    debug!("Accepted connection from {addr:?}, waiting for handshake");
+                                    if let Err(e) = stream.set_nonblocking(true) {
+                                        warn!("Cannot set TcpStream of {addr} into non-blocking mode: {e}");
+                                        continue;
+                                    }
+                                    let _ = stream.set_nodelay(true);
+                                    let out = TcpOut::new(stream, next_key, config.max_queued_bytes);
+                                    if let Err(e) = out.register(&poller) {
+                                        warn!("Cannot watch TcpStream of {addr}: {e}");
+                                        continue;
+                                    }
+                                    peers.insert(
+                                        next_key,
+                                        Peer {
+                                            out: Arc::new(out),
+                                            addr,
+                                            reader: FrameReader::new(config.max_frame_size),
+                                            opener: None,
+                                            state: PeerState::Handshake(now),
+                                        },
+                                    );
+                                    next_key += 1;
+                                }
+                                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                                Err(e) => {
+                                    warn!("Error while accepting connection: {e}");
+                                    break;
+                                }
+                            }
+                        }
+                        continue;
+                    }
+
+                    let Some(peer) = peers.get_mut(&event.key) else {
+                        continue;
+                    };
+                    if event.writable {
+                        if let Err(e) = peer.out.flush(&poller) {
+                            debug!("Error while writing to {:?}: {e}", peer.addr);
+                            closed.push((event.key, Some(DisconnectReason::Disconnected)));
+                            continue;
+                        }
+                    }
+                    if !event.readable {
+                        continue;
+                    }
+
+                    // Read packets from the client
+                    let open = match peer.reader.read_from(peer.out.stream()) {
+                        Ok(open) => open,
+                        Err(e) if e.kind() == ErrorKind::TimedOut => {
+                            closed.push((event.key, Some(DisconnectReason::TimedOut)));
+                            continue;
+                        }
+                        Err(e) if e.kind() == ErrorKind::InvalidData => {
+                            warn!("Dropping connection from {:?}: {e}", peer.addr);
+                            closed.push((event.key, Some(DisconnectReason::Oversized)));
+                            continue;
                         }
-                        Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
                         Err(e) => {
-                            warn!("Error while accepting connection: {e}");
-                            break;
+                            debug!("Error while reading from {:?}: {e}", peer.addr);
+                            false
+                        }
+                    };
+                    while let Some(mut frame) = peer.reader.next_frame() {
+                        if let Some(opener) = &mut peer.opener {
+                            match opener.open(&frame) {
+                                Ok(plaintext) => frame = plaintext,
+                                Err(e) => {
+                                    warn!("Dropping connection from {:?}: {e}", peer.addr);
+                                    closed.push((event.key, Some(DisconnectReason::Tampered)));
+                                    break;
+                                }
+                            }
+                        }
+                        let message = match decode_packet::<Message>(frame) {
+                            Ok(message) => message,
+                            Err(s) => {
+                                warn!("Could not deserialize message: {s}");
+                                continue;
+                            }
+                        };
+                        match &mut peer.state {
+                            PeerState::Handshake(_) => {
+                                match check_hello(&*handler_thread, &config, peer.addr, message) {
+                                    Ok(accepted) => {
+                                        info!("Client connected with address {:?}", peer.addr);
+                                        let id = next_client_id();
+                                        let endpoint = ClientEndpoint::new(
+                                            id,
+                                            peer.out.clone(),
+                                            disconnect_sen.clone(),
+                                            poller.clone(),
+                                        );
+                                        match accepted {
+                                            Some(accepted) => {
+                                                let welcome = Message::Welcome {
+                                                    key: Some(accepted.key),
+                                                    confirm: accepted.confirm,
+                                                };
+                                                endpoint.send_message(&welcome, Delivery::ReliableOrdered);
+                                                peer.out.secure(accepted.sealer);
+                                                peer.opener = Some(accepted.opener);
+                                            }
+                                            None => endpoint.send_message(
+                                                &Message::Welcome {
+                                                    key: None,
+                                                    confirm: Vec::new(),
+                                                },
+                                                Delivery::ReliableOrdered,
+                                            ),
+                                        }
+                                        let arc = accept_client(&clients, endpoint, &mut dispatch);
+                                        keys.insert(id, event.key);
+                                        peer.state = PeerState::Connected(arc, KeepAlive::new(now));
+                                    }
+                                    Err(reason) => {
+                                        info!("Rejected client with address {:?}, reason: {reason:?}", peer.addr);
+                                        peer.out.send(&encode_packet(&Message::Reject(reason)), &poller);
+                                        closed.push((event.key, None));
+                                        break;
+                                    }
+                                }
+                            }
+                            PeerState::Connected(endpoint, keep_alive) => {
+                                keep_alive.received(now);
+                                handle_message(endpoint, keep_alive, message, now, &mut dispatch);
+                            }
                         }
                     }
+                    if !open {
+                        closed.push((event.key, Some(DisconnectReason::Disconnected)));
+                    }
+                }
 
-                    thread::sleep(Duration::from_millis(10));
+                if now >= next_tick {
+                    for (key, peer) in &mut peers {
+                        match &mut peer.state {
+                            PeerState::Handshake(since) => {
+                                if now.duration_since(*since) > config.handshake_timeout {
+                                    debug!("Dropped connection from {:?}, handshake timed out", peer.addr);
+                                    closed.push((*key, None));
+                                }
+                            }
+                            PeerState::Connected(endpoint, keep_alive) => {
+                                if keep_alive.timed_out(now, config.idle_timeout) {
+                                    closed.push((*key, Some(DisconnectReason::TimedOut)));
+                                } else if let Some(ping) = keep_alive.ping(now, config.keep_alive_interval) {
+                                    endpoint.send_message(&ping, Delivery::Unreliable);
+                                }
+                            }
+                        }
+                    }
+                    next_tick = now + tick;
+                }
+
+                for (key, reason) in closed.drain(..) {
+                    let Some(peer) = peers.remove(&key) else {
+                        continue;
+                    };
+                    peer.out.deregister(&poller);
+                    let _ = peer.out.stream().shutdown(Shutdown::Both);
+                    if let PeerState::Connected(endpoint, _) = peer.state {
+                        keys.remove(&endpoint.id);
+                        clients.write().remove(&endpoint.id);
+                        let reason = reason.unwrap_or(DisconnectReason::Disconnected);
+                        info!("Client {} disconnected, reason: {reason:?}", endpoint.id);
+                        dispatch.push(Dispatch::Disconnect(endpoint, reason));
+                    }
                 }
 
-                // Read packets from existing clients
-                let mut map = clients.write();
-                for endpoint in map.values_mut() {
-                    let stream = endpoint.tcp.get_mut();
-                    let mut packet = try_read_packet::<In>(stream);
-                    while let Ok(inner) = packet {
-                        handler_thread.lock().on_packet(endpoint.clone(), inner);
-                        packet = try_read_packet::<In>(stream);
-                    }
-                    if let Some(e) = packet.err() {
-                        match e {
-                            ReadPacketError::FromTcp(tcp_err) => match tcp_err.kind() {
-                                ErrorKind::TimedOut => {
-                                    let _ = disconnect_sen
-                                        .send((endpoint.id, DisconnectReason::TimedOut));
+                run_dispatch(&*handler_thread, &requests, &mut dispatch);
+            }
+        }))
+    }
+
+    /// Like [`Server::listen`], but clients connect over UDP, which allows sending packets with any [`Delivery`].
+    /// Secure connections are only available over TCP, so this fails if [`ConnectionConfig::server_secret`] is set.
+    pub fn listen_udp<Handler: ServerHandler<In> + Send + 'static>(
+        &mut self,
+        addrs: impl ToSocketAddrs,
+        udp: UdpConfig,
+    ) -> Result<Arc<Mutex<Handler>>, ListenError> {
+        if self.config.server_secret.is_some() {
+            return Err(ListenError::SecureUdp);
+        }
+        let (sockets, poller) = self.bind::<UdpSocket>(addrs)?;
+
+        Ok(self.start(poller, move |server: ServerThread<Handler>| {
+            let ServerThread {
+                handler: handler_thread,
+                poller,
+                config,
+                clients,
+                requests,
+                stop: stop_rec,
+                disconnect_sender: disconnect_sen,
+                disconnects: disconnect_rec,
+                local_addrs,
+            } = server;
+            for addr in &local_addrs {
+                info!("Listening for UDP clients on {addr}");
+            }
+            let mut links: Vec<Link> = sockets
+                .into_iter()
+                .map(|s| Link::new(s, udp.conditioner.clone()))
+                .collect();
+            // the listener index and address of every client
+            let mut addresses: HashMap<ClientId, (usize, SocketAddr), U64IdentityHasher> =
+                HashMap::with_hasher(U64IdentityHasher::default());
+            let mut ids: HashMap<(usize, SocketAddr), ClientId> = HashMap::new();
+            let mut keep_alives: HashMap<ClientId, KeepAlive, U64IdentityHasher> =
+                HashMap::with_hasher(U64IdentityHasher::default());
+            let mut buffer = vec![0u8; 65536];
+            let mut received = Vec::new();
+            let mut events = Events::new();
+            let mut dispatch = Vec::new();
+            let tick = config.tick();
+            let mut next_tick = Instant::now();
+
+            loop {
+                // wake up for incoming datagrams, resends, held back datagrams and keep-alives
+                let mut wake = next_tick;
+                for link in &links {
+                    wake = link.next_flush().map_or(wake, |at| at.min(wake));
+                }
+                for endpoint in clients.read().values() {
+                    if let Transport::Udp(connection) = &endpoint.transport {
+                        wake = connection.lock().next_poll().map_or(wake, |at| at.min(wake));
+                    }
+                }
+                wait_for_sockets(&poller, &mut events, wake);
+
+                let now = Instant::now();
+                if let Ok(stop_msg) = stop_rec.try_recv() {
+                    info!("Server stopped with message: {stop_msg}");
+                    for (listener, addr) in addresses.values() {
+                        for _ in 0..DISCONNECT_REPEATS {
+                            links[*listener].send_to(Datagram::Disconnect.encode(), *addr, now);
+                        }
+                    }
+                    for link in &mut links {
+                        link.flush(now + Duration::from_secs(3600));
+                        let _ = poller.delete(link.socket());
+                    }
+                    handler_thread.lock().on_server_stop(&stop_msg);
+                    return;
+                }
+
+                while let Ok((id, reason)) = disconnect_rec.try_recv() {
+                    debug!("Attempt to disconnect client {id}, reason: {reason:?}");
+                    let client = clients.write().remove(&id);
+                    keep_alives.remove(&id);
+                    if let Some((listener, addr)) = addresses.remove(&id) {
+                        ids.remove(&(listener, addr));
+                        for _ in 0..DISCONNECT_REPEATS {
+                            links[listener].send_to(Datagram::Disconnect.encode(), addr, now);
+                        }
+                    }
+                    if let Some(client) = client {
+                        info!("Client {id} disconnected, reason: {reason:?}");
+                        dispatch.push(Dispatch::Disconnect(client, reason));
+                    }
+                }
+
+                for event in events.iter() {
+                    let Some(link) = links.get_mut(event.key) else {
+                        continue;
+                    };
+                    let listener = event.key;
+                    loop {
+                        let (len, addr) = match link.socket().recv_from(&mut buffer) {
+                            Ok(r) => r,
+                            Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                            Err(e) => {
+                                debug!("Error while receiving datagram: {e}");
+                                break;
+                            }
+                        };
+                        let Some(datagram) = Datagram::parse(&buffer[..len]) else {
+                            continue;
+                        };
+                        let known = ids.get(&(listener, addr)).copied();
+                        if let Some(keep_alive) = known.and_then(|id| keep_alives.get_mut(&id)) {
+                            keep_alive.received(now);
+                        }
+                        match (datagram, known) {
+                            (Datagram::Connect(_), Some(_)) => {
+                                // the accept got lost
+                                link.send_to(Datagram::Accept.encode(), addr, now);
+                            }
+                            (Datagram::Connect(hello), None) => {
+                                let checked = decode_packet::<Message>(hello.to_vec())
+                                    .map_err(RejectReason::Refused)
+                                    .and_then(|hello| check_hello(&*handler_thread, &config, addr, hello));
+                                if let Err(reason) = checked {
+                                    info!("Rejected client with address {addr:?}, reason: {reason:?}");
+                                    link.send_to(Datagram::Reject(&encode_packet(&reason)).encode(), addr, now);
+                                    continue;
+                                }
+                                info!("Client connected with address {addr:?}");
+                                let id = next_client_id();
+                                let connection = Connection::new(&udp);
+                                let endpoint =
+                                    ClientEndpoint::new_udp(id, connection, disconnect_sen.clone(), poller.clone());
+                                ids.insert((listener, addr), id);
+                                addresses.insert(id, (listener, addr));
+                                keep_alives.insert(id, KeepAlive::new(now));
+                                link.send_to(Datagram::Accept.encode(), addr, now);
+                                accept_client(&clients, endpoint, &mut dispatch);
+                            }
+                            (Datagram::Disconnect, Some(id)) => {
+                                let _ = disconnect_sen.send((id, DisconnectReason::Disconnected));
+                            }
+                            (datagram, Some(id)) => {
+                                let Some(endpoint) = clients.read().get(&id).cloned() else {
+                                    continue;
+                                };
+                                let Some(keep_alive) = keep_alives.get_mut(&id) else {
+                                    continue;
+                                };
+                                if let Transport::Udp(connection) = &endpoint.transport {
+                                    connection.lock().receive(datagram, now, &mut received);
                                 }
-                                ErrorKind::ConnectionReset
-                                | ErrorKind::ConnectionAborted
-                                | ErrorKind::UnexpectedEof
-                                | ErrorKind::BrokenPipe
-                                | ErrorKind::NotConnected => {
-                                    let _ = disconnect_sen
-                                        .send((endpoint.id, DisconnectReason::Disconnected));
+                                for bytes in received.drain(..) {
+                                    match decode_packet::<Message>(bytes) {
+                                        Ok(message) => handle_message(&endpoint, keep_alive, message, now, &mut dispatch),
+                                        Err(s) => warn!("Could not deserialize message: {s}"),
+                                    }
                                 }
-                                _ => {}
-                            },
-                            ReadPacketError::FromSavable(s) => {
-                                warn!("Could not deserialize packet: {s}");
                             }
+                            _ => {}
                         }
                     }
                 }
+
+                let map = clients.read();
+                for (id, (listener, addr)) in &addresses {
+                    let Some(endpoint) = map.get(id) else {
+                        continue;
+                    };
+                    if now >= next_tick {
+                        if let Some(keep_alive) = keep_alives.get_mut(id) {
+                            if let Some(ping) = keep_alive.ping(now, config.keep_alive_interval) {
+                                endpoint.send_message(&ping, Delivery::Unreliable);
+                            }
+                            if keep_alive.timed_out(now, config.idle_timeout) {
+                                keep_alives.remove(id);
+                                endpoint.disconnect(DisconnectReason::TimedOut);
+                            }
+                        }
+                    }
+                    if let Transport::Udp(connection) = &endpoint.transport {
+                        for datagram in connection.lock().poll(now) {
+                            links[*listener].send_to(datagram, *addr, now);
+                        }
+                    }
+                }
+                drop(map);
+                for link in &mut links {
+                    link.flush(now);
+                }
+                if now >= next_tick {
+                    next_tick = now + tick;
+                }
+
+                run_dispatch(&*handler_thread, &requests, &mut dispatch);
             }
-        });
+        }))
+    }
+
+    /// Binds a nonblocking socket to every address `addrs` resolves to and registers them in a new poller, keyed by
+    /// their index.
+    fn bind<S: ListenSocket>(&mut self, addrs: impl ToSocketAddrs) -> Result<(Vec<S>, Arc<Poller>), ListenError> {
+        if self.thread.is_some() {
+            return Err(ListenError::AlreadyListening);
+        }
+        let addrs: Vec<SocketAddr> = addrs.to_socket_addrs().map_err(ListenError::Resolve)?.collect();
+        if addrs.is_empty() {
+            return Err(ListenError::NoAddress);
+        }
+        let poller = Arc::new(Poller::new().map_err(ListenError::Poll)?);
+
+        let mut sockets = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            let socket = S::bind(addr).map_err(|e| ListenError::Bind(addr, e))?;
+            socket.set_nonblocking().map_err(|e| ListenError::Bind(addr, e))?;
+            sockets.push(socket);
+        }
+        self.local_addrs = sockets
+            .iter()
+            .map(|s| s.local_addr())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(ListenError::Resolve)?;
+        for (key, socket) in sockets.iter().enumerate() {
+            // SAFETY: the sockets are deleted from the poller before the server thread ends
+            unsafe { socket.register(&poller, key) }.map_err(ListenError::Poll)?;
+        }
+        Ok((sockets, poller))
+    }
+
+    /// Creates the handler and starts the server thread, which is owned by `run`.
+    fn start<Handler: ServerHandler<In> + Send + 'static>(
+        &mut self,
+        poller: Arc<Poller>,
+        run: impl FnOnce(ServerThread<Handler>) + Send + 'static,
+    ) -> Arc<Mutex<Handler>> {
+        let (stop_sen, stop) = crossbeam_channel::unbounded::<String>();
+        let (disconnect_sender, disconnects) = crossbeam_channel::unbounded::<(ClientId, DisconnectReason)>();
 
-        self.thread = Some(handle);
+        let handler = Arc::new(Mutex::new(Handler::on_server_start(&self.local_addrs)));
+        let server = ServerThread {
+            handler: handler.clone(),
+            poller: poller.clone(),
+            config: self.config.clone(),
+            clients: self.clients.clone(),
+            requests: self.requests.clone(),
+            stop,
+            disconnect_sender,
+            disconnects,
+            local_addrs: self.local_addrs.clone(),
+        };
+
+        self.thread = Some(thread::spawn(move || run(server)));
         self.stopper = Some(stop_sen);
+        self.waker = Some(poller);
+
+        handler
+    }
 
-        handler_arc
+    /// The addresses the server is listening on, with the actual ports if port 0 was requested.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
     }
 
     pub fn stop(&mut self, message: &str) {
-        if let Some(sender) = &mut self.stopper {
+        if let (Some(sender), Some(waker)) = (&mut self.stopper, &self.waker) {
             if let Err(e) = sender.send(message.to_string()) {
                 error!("Could not send stop message across stopper channel! {e}");
             }
+            if let Err(e) = waker.notify() {
+                error!("Could not wake up server thread! {e}");
+            }
         } else {
             warn!("Tried to stop server that is not started!");
         }
@@ -164,47 +597,302 @@ impl<In: Savable, Out: Savable> Server<In, Out> {
     }
 }
 
+type Clients = Arc<RwLock<HashMap<ClientId, Arc<ClientEndpoint>, U64IdentityHasher>>>;
+
+/// What the server thread of either transport owns.
+struct ServerThread<Handler> {
+    handler: Arc<Mutex<Handler>>,
+    poller: Arc<Poller>,
+    config: ConnectionConfig,
+    clients: Clients,
+    requests: Arc<RwLock<RequestHandlers>>,
+    stop: Receiver<String>,
+    disconnect_sender: Sender<(ClientId, DisconnectReason)>,
+    disconnects: Receiver<(ClientId, DisconnectReason)>,
+    local_addrs: Vec<SocketAddr>,
+}
+
+/// A socket the server accepts clients on.
+trait ListenSocket: Sized + Send + 'static {
+    fn bind(addr: SocketAddr) -> io::Result<Self>;
+
+    fn set_nonblocking(&self) -> io::Result<()>;
+
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Registers the socket for readable events under `key`.
+    ///
+    /// # Safety
+    /// The socket has to be deleted from the poller before it is dropped.
+    unsafe fn register(&self, poller: &Poller, key: usize) -> io::Result<()>;
+}
+
+impl ListenSocket for TcpListener {
+    fn bind(addr: SocketAddr) -> io::Result<Self> {
+        TcpListener::bind(addr)
+    }
+
+    fn set_nonblocking(&self) -> io::Result<()> {
+        TcpListener::set_nonblocking(self, true)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpListener::local_addr(self)
+    }
+
+    unsafe fn register(&self, poller: &Poller, key: usize) -> io::Result<()> {
+        unsafe { poller.add_with_mode(self, Event::readable(key), PollMode::Level) }
+    }
+}
+
+impl ListenSocket for UdpSocket {
+    fn bind(addr: SocketAddr) -> io::Result<Self> {
+        UdpSocket::bind(addr)
+    }
+
+    fn set_nonblocking(&self) -> io::Result<()> {
+        UdpSocket::set_nonblocking(self, true)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    unsafe fn register(&self, poller: &Poller, key: usize) -> io::Result<()> {
+        unsafe { poller.add_with_mode(self, Event::readable(key), PollMode::Level) }
+    }
+}
+
+fn next_client_id() -> ClientId {
+    utils::next_id("MVEngine::net::server::Server::listen")
+}
+
+/// Makes a client that completed the handshake known to the server and the handler.
+fn accept_client<In: Savable>(
+    clients: &Clients,
+    endpoint: ClientEndpoint,
+    dispatch: &mut Vec<Dispatch<In>>,
+) -> Arc<ClientEndpoint> {
+    let arc = Arc::new(endpoint);
+    clients.write().insert(arc.id, arc.clone());
+    dispatch.push(Dispatch::Connect(arc.clone()));
+    arc
+}
+
+#[derive(Debug)]
+pub enum ListenError {
+    /// The server was already started.
+    AlreadyListening,
+    /// The addresses could not be resolved.
+    Resolve(io::Error),
+    /// The addresses resolved to nothing.
+    NoAddress,
+    Bind(SocketAddr, io::Error),
+    /// The sockets could not be registered for readiness notifications.
+    Poll(io::Error),
+    /// [`ConnectionConfig::server_secret`] is set, but secure connections are only available over TCP.
+    SecureUdp,
+}
+
+impl Display for ListenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenError::AlreadyListening => write!(f, "Server is already listening"),
+            ListenError::Resolve(e) => write!(f, "Could not resolve listen address: {e}"),
+            ListenError::NoAddress => write!(f, "Listen address did not resolve to any socket address"),
+            ListenError::Bind(addr, e) => write!(f, "Could not listen on {addr}: {e}"),
+            ListenError::Poll(e) => write!(f, "Could not watch sockets: {e}"),
+            ListenError::SecureUdp => write!(f, "Secure connections are only available over TCP"),
+        }
+    }
+}
+
+impl std::error::Error for ListenError {}
+
 pub trait ServerHandler<In: Savable> {
-    fn on_server_start(port: u16) -> Self;
+    fn on_server_start(addrs: &[SocketAddr]) -> Self;
+    /// Called during the handshake with the [`ConnectionConfig::hello`] of a client whose protocol version matches.
+    /// Returning an error rejects the client with [`RejectReason::Refused`] before it is connected.
+    fn on_hello(&mut self, addr: SocketAddr, hello: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
     fn on_client_connect(&mut self, client: Arc<ClientEndpoint>);
     fn on_client_disconnect(&mut self, client: Arc<ClientEndpoint>, reason: DisconnectReason);
     fn on_packet(&mut self, client: Arc<ClientEndpoint>, packet: In);
     fn on_server_stop(&mut self, message: &str);
 }
 
+/// A TCP connection of the server thread.
+struct Peer {
+    out: Arc<TcpOut>,
+    addr: SocketAddr,
+    reader: FrameReader,
+    /// Decrypts the frames of a secure connection.
+    opener: Option<Opener>,
+    state: PeerState,
+}
+
+enum PeerState {
+    /// Waiting for the hello since the given time.
+    Handshake(Instant),
+    Connected(Arc<ClientEndpoint>, KeepAlive),
+}
+
+/// A handler call collected by the server thread. They are run in order once per wake up, so the handler is locked once
+/// for everything that happened instead of once per packet.
+enum Dispatch<In> {
+    Connect(Arc<ClientEndpoint>),
+    Packet(Arc<ClientEndpoint>, In),
+    Request(Arc<ClientEndpoint>, u64, String, Vec<u8>),
+    Disconnect(Arc<ClientEndpoint>, DisconnectReason),
+}
+
+fn run_dispatch<In: Savable, Handler: ServerHandler<In>>(
+    handler: &Mutex<Handler>,
+    requests: &RwLock<RequestHandlers>,
+    dispatch: &mut Vec<Dispatch<In>>,
+) {
+    let mut locked = None;
+    for call in dispatch.drain(..) {
+        if let Dispatch::Request(client, id, name, payload) = call {
+            // request handlers may lock the server handler themselves
+            locked = None;
+            let answer = requests.read().answer(&client, &name, payload);
+            client.send_message(&Message::Response { id, answer }, Delivery::ReliableOrdered);
+            continue;
+        }
+        let handler = locked.get_or_insert_with(|| handler.lock());
+        match call {
+            Dispatch::Connect(client) => handler.on_client_connect(client),
+            Dispatch::Packet(client, packet) => handler.on_packet(client, packet),
+            Dispatch::Disconnect(client, reason) => handler.on_client_disconnect(client, reason),
+            Dispatch::Request(..) => {}
+        }
+    }
+}
+
+/// Accepts a client if `hello` has the protocol version of the server and the handler agrees.
+/// Returns the session keys if the server uses [`ConnectionConfig::server_secret`].
+fn check_hello<In: Savable, Handler: ServerHandler<In>>(
+    handler: &Mutex<Handler>,
+    config: &ConnectionConfig,
+    addr: SocketAddr,
+    hello: Message,
+) -> Result<Option<Accepted>, RejectReason> {
+    let Message::Hello { version, payload, key } = hello else {
+        return Err(RejectReason::Refused("Expected a hello".to_string()));
+    };
+    if version != config.protocol_version {
+        return Err(RejectReason::VersionMismatch {
+            server: config.protocol_version,
+            client: version,
+        });
+    }
+    let accepted = match (&config.server_secret, key) {
+        (Some(secret), Some(key)) => {
+            Some(secure::accept(secret, version, &key, &payload).map_err(RejectReason::Insecure)?)
+        }
+        (None, None) => None,
+        (Some(_), None) => return Err(RejectReason::Insecure("Server requires a secure connection".to_string())),
+        (None, Some(_)) => return Err(RejectReason::Insecure("Server does not support secure connections".to_string())),
+    };
+    let payload = accepted.as_ref().map_or(&payload, |a| &a.payload);
+    handler.lock().on_hello(addr, payload).map_err(RejectReason::Refused)?;
+    Ok(accepted)
+}
+
+/// Handles a message of a connected client.
+fn handle_message<In: Savable>(
+    endpoint: &Arc<ClientEndpoint>,
+    keep_alive: &KeepAlive,
+    message: Message,
+    now: Instant,
+    dispatch: &mut Vec<Dispatch<In>>,
+) {
+    match message {
+        Message::Packet(bytes) => match decode_packet::<In>(bytes) {
+            Ok(packet) => dispatch.push(Dispatch::Packet(endpoint.clone(), packet)),
+            Err(s) => warn!("Could not deserialize packet: {s}"),
+        },
+        Message::Request { id, name, payload } => dispatch.push(Dispatch::Request(endpoint.clone(), id, name, payload)),
+        Message::Ping(sent) => endpoint.send_message(&Message::Pong(sent), Delivery::Unreliable),
+        Message::Pong(sent) => keep_alive.pong(sent, now, &endpoint.rtt),
+        _ => {}
+    }
+}
+
+pub(crate) enum Transport {
+    Tcp(Arc<TcpOut>),
+    Udp(Mutex<Connection>),
+}
+
 pub struct ClientEndpoint {
     id: ClientId,
-    tcp: DangerousCell<TcpStream>,
+    transport: Transport,
     disconnect_sender: Sender<(ClientId, DisconnectReason)>,
+    rtt: SharedRtt,
+    /// Wakes up the server thread, which sleeps until a socket is ready.
+    waker: Arc<Poller>,
 }
 
 impl ClientEndpoint {
     pub(crate) fn new(
         id: ClientId,
-        stream: TcpStream,
+        out: Arc<TcpOut>,
+        disconnect_sender: Sender<(ClientId, DisconnectReason)>,
+        waker: Arc<Poller>,
+    ) -> Self {
+        Self {
+            id,
+            transport: Transport::Tcp(out),
+            disconnect_sender,
+            rtt: SharedRtt::new(),
+            waker,
+        }
+    }
+
+    pub(crate) fn new_udp(
+        id: ClientId,
+        connection: Connection,
         disconnect_sender: Sender<(ClientId, DisconnectReason)>,
+        waker: Arc<Poller>,
     ) -> Self {
         Self {
             id,
-            tcp: DangerousCell::new(stream),
+            transport: Transport::Udp(Mutex::new(connection)),
             disconnect_sender,
+            rtt: SharedRtt::new(),
+            waker,
         }
     }
 
+    /// Sends a packet reliable and ordered.
     pub fn send<Out: Savable>(&self, packet: Out) {
-        //build data (len_u32+bytes)
-        let mut buffer = ByteBuffer::new();
-        buffer.set_endian(Endian::LittleEndian);
-        packet.save(&mut buffer);
-        let len = buffer.len() as u32;
-        let len_bytes: [u8; 4] = len.to_le_bytes();
-        let mut vec = len_bytes.to_vec();
-        vec.extend(buffer.into_vec());
+        self.send_with(packet, Delivery::ReliableOrdered);
+    }
 
-        //write data
-        let stream = self.tcp.get_mut();
-        if let Err(e) = stream.write_all(&vec) {
-            warn!("Error when attempting to write packet: {e}");
+    /// Sends a packet with the given delivery mode. Clients connected over TCP always get packets reliable and ordered.
+    ///
+    /// Never blocks. If a TCP client does not read fast enough and more than [`ConnectionConfig::max_queued_bytes`] are
+    /// waiting, it is disconnected with [`DisconnectReason::Congested`].
+    pub fn send_with<Out: Savable>(&self, packet: Out, delivery: Delivery) {
+        self.send_message(&Message::Packet(encode_packet(&packet)), delivery);
+    }
+
+    pub(crate) fn send_message(&self, message: &Message, delivery: Delivery) {
+        match &self.transport {
+            Transport::Tcp(out) => {
+                if !out.send(&encode_packet(message), &self.waker) {
+                    warn!("Write queue of client {} is full", self.id);
+                    self.disconnect(DisconnectReason::Congested);
+                }
+            }
+            Transport::Udp(connection) => {
+                connection.lock().send(delivery, &encode_packet(message));
+                if let Err(e) = self.waker.notify() {
+                    warn!("Error when attempting to wake up server thread: {e}");
+                }
+            }
         }
     }
 
@@ -212,15 +900,25 @@ impl ClientEndpoint {
         if let Err(e) = self.disconnect_sender.send((self.id, reason)) {
             warn!("Error when attempting to send disconnect to server thread: {e}");
         }
-        let stream = self.tcp.get_mut();
-        if let Err(e) = stream.shutdown(Shutdown::Both) {
-            warn!("Error when attempting to close socket connection: {e}");
+        if let Err(e) = self.waker.notify() {
+            warn!("Error when attempting to wake up server thread: {e}");
         }
     }
 
     pub fn id(&self) -> ClientId {
         self.id
     }
-}
 
-unsafe impl Sync for ClientEndpoint {}
+    /// The round trip time measured by the last keep-alive, or `None` if none was answered yet.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt.get()
+    }
+
+    /// The number of bytes that were sent but not written to the socket yet, or not acknowledged yet over UDP.
+    pub fn queued_bytes(&self) -> usize {
+        match &self.transport {
+            Transport::Tcp(out) => out.queued(),
+            Transport::Udp(connection) => connection.lock().queued_bytes(),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::net::rpc::RpcError;
+use crate::net::secure::Key;
+use crate::net::RejectReason;
+use bytebuffer::ByteBuffer;
+use log::warn;
+use mvutils::bytebuffer::ByteBufferExtras;
+use mvutils::save::Savable;
+use mvutils::Savable;
+use polling::{Events, Poller};
+use std::fmt::{Debug, Formatter};
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Settings shared by clients and servers on both transports.
+#[derive(Clone)]
+pub struct ConnectionConfig {
+    /// Clients are only accepted if they use the same version as the server. Bump it whenever packets change incompatibly.
+    pub protocol_version: u32,
+    /// Sent to the server during the handshake, e.g. a player name or a session token.
+    /// The server can inspect it in [`ServerHandler::on_hello`](crate::net::server::ServerHandler::on_hello).
+    pub hello: Vec<u8>,
+    /// How long the client waits for the server to answer the handshake.
+    pub handshake_timeout: Duration,
+    /// How often both sides send a keep-alive, which is also used to measure the round trip time.
+    pub keep_alive_interval: Duration,
+    /// Peers that did not send anything for this long are disconnected with [`DisconnectReason::TimedOut`](crate::net::DisconnectReason::TimedOut).
+    pub idle_timeout: Duration,
+    /// How many bytes may wait to be sent to a TCP peer that does not read fast enough.
+    /// Peers exceeding it are disconnected with [`DisconnectReason::Congested`](crate::net::DisconnectReason::Congested).
+    pub max_queued_bytes: usize,
+    /// The largest TCP frame a peer may announce. Peers exceeding it are disconnected with
+    /// [`DisconnectReason::Oversized`](crate::net::DisconnectReason::Oversized) before anything is allocated for it.
+    pub max_frame_size: usize,
+    /// Only used by clients. How long [`Client::call`](crate::net::client::Client::call) waits for a response.
+    pub call_timeout: Duration,
+    /// Only used by servers. If set, clients have to connect securely with the matching [`ConnectionConfig::server_key`],
+    /// see [`secure`](crate::net::secure).
+    pub server_secret: Option<Key>,
+    /// Only used by clients. Connects securely to a server whose [`ConnectionConfig::server_secret`] belongs to this
+    /// public key, see [`secure::public_key`](crate::net::secure::public_key).
+    pub server_key: Option<Key>,
+}
+
+impl ConnectionConfig {
+    /// Uses `hello`, saved as [`Savable`], as the hello payload.
+    pub fn with_hello<H: Savable>(mut self, hello: &H) -> Self {
+        let mut buffer = ByteBuffer::new_le();
+        hello.save(&mut buffer);
+        self.hello = buffer.into_vec();
+        self
+    }
+
+    /// How often the I/O threads wake up to check keep-alives and timeouts while nothing happens.
+    pub(crate) fn tick(&self) -> Duration {
+        let shortest = self.keep_alive_interval.min(self.idle_timeout).min(self.handshake_timeout);
+        (shortest / 4).max(Duration::from_millis(1))
+    }
+}
+
+impl Debug for ConnectionConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionConfig")
+            .field("protocol_version", &self.protocol_version)
+            .field("hello", &self.hello)
+            .field("handshake_timeout", &self.handshake_timeout)
+            .field("keep_alive_interval", &self.keep_alive_interval)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("max_queued_bytes", &self.max_queued_bytes)
+            .field("max_frame_size", &self.max_frame_size)
+            .field("call_timeout", &self.call_timeout)
+            .field("server_secret", &self.server_secret.map(|_| "<redacted>"))
+            .field("server_key", &self.server_key)
+            .finish()
+    }
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            protocol_version: 0,
+            hello: Vec::new(),
+            handshake_timeout: Duration::from_secs(5),
+            keep_alive_interval: Duration::from_secs(1),
+            idle_timeout: Duration::from_secs(10),
+            max_queued_bytes: 16 * 1024 * 1024,
+            max_frame_size: 16 * 1024 * 1024,
+            call_timeout: Duration::from_secs(10),
+            server_secret: None,
+            server_key: None,
+        }
+    }
+}
+
+/// Everything that is sent between client and server. Application packets are wrapped in [`Message::Packet`].
+#[derive(Clone, Debug, Savable)]
+pub(crate) enum Message {
+    /// `key` is the ephemeral public key of a secure client, whose `payload` is then encrypted.
+    Hello { version: u32, payload: Vec<u8>, key: Option<Key> },
+    /// `key` is the ephemeral public key of a secure server, `confirm` its first sealed frame.
+    Welcome { key: Option<Key>, confirm: Vec<u8> },
+    Reject(RejectReason),
+    Ping(u64),
+    Pong(u64),
+    Packet(Vec<u8>),
+    /// A [`Request`](crate::net::rpc::Request) with the given name, answered with a response with the same id.
+    Request { id: u64, name: String, payload: Vec<u8> },
+    Response { id: u64, answer: Result<Vec<u8>, RpcError> },
+}
+
+const NO_RTT: u64 = u64::MAX;
+
+/// A round trip time that can be read from other threads.
+pub(crate) struct SharedRtt(AtomicU64);
+
+impl SharedRtt {
+    pub(crate) fn new() -> Self {
+        Self(AtomicU64::new(NO_RTT))
+    }
+
+    pub(crate) fn get(&self) -> Option<Duration> {
+        match self.0.load(Ordering::Relaxed) {
+            NO_RTT => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+
+    fn set(&self, rtt: Duration) {
+        self.0.store(rtt.as_micros().min(NO_RTT as u128 - 1) as u64, Ordering::Relaxed);
+    }
+}
+
+/// Decides when to send keep-alives and when the peer timed out.
+pub(crate) struct KeepAlive {
+    epoch: Instant,
+    last_ping: Instant,
+    last_received: Instant,
+}
+
+impl KeepAlive {
+    pub(crate) fn new(now: Instant) -> Self {
+        Self {
+            epoch: now,
+            last_ping: now,
+            last_received: now,
+        }
+    }
+
+    pub(crate) fn received(&mut self, now: Instant) {
+        self.last_received = now;
+    }
+
+    /// Returns the ping to send, if one is due.
+    pub(crate) fn ping(&mut self, now: Instant, interval: Duration) -> Option<Message> {
+        if now.duration_since(self.last_ping) < interval {
+            return None;
+        }
+        self.last_ping = now;
+        Some(Message::Ping(now.duration_since(self.epoch).as_micros() as u64))
+    }
+
+    /// Handles the answer to one of our pings.
+    pub(crate) fn pong(&self, sent: u64, now: Instant, rtt: &SharedRtt) {
+        let now = now.duration_since(self.epoch).as_micros() as u64;
+        rtt.set(Duration::from_micros(now.saturating_sub(sent)));
+    }
+
+    pub(crate) fn timed_out(&self, now: Instant, idle_timeout: Duration) -> bool {
+        now.duration_since(self.last_received) > idle_timeout
+    }
+}
+
+/// Waits until a registered socket is ready or `until` is reached.
+pub(crate) fn wait_for_sockets(poller: &Poller, events: &mut Events, until: Instant) {
+    events.clear();
+    if let Err(e) = poller.wait(events, Some(until.saturating_duration_since(Instant::now()))) {
+        if e.kind() != ErrorKind::Interrupted {
+            warn!("Error while waiting for socket: {e}");
+        }
+    }
+}
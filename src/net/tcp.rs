@@ -0,0 +1,246 @@
+use crate::net::secure::Sealer;
+use log::warn;
+use parking_lot::Mutex;
+use polling::{Event, PollMode, Poller};
+use std::collections::VecDeque;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+
+/// Collects the bytes read from a stream and splits them into length prefixed frames, no matter how they were fragmented.
+pub(crate) struct FrameReader {
+    buffer: Vec<u8>,
+    start: usize,
+    /// Where the first length prefix that was not checked yet starts
+    checked: usize,
+    max_frame_size: usize,
+}
+
+impl FrameReader {
+    pub(crate) fn new(max_frame_size: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            start: 0,
+            checked: 0,
+            max_frame_size,
+        }
+    }
+
+    /// Reads everything available without blocking. Returns `Ok(false)` if the peer closed the stream.
+    ///
+    /// Fails with [`ErrorKind::InvalidData`] as soon as a length prefix exceeds the maximum frame size, so nothing is
+    /// buffered for a frame that would be rejected anyway.
+    pub(crate) fn read_from(&mut self, mut stream: &TcpStream) -> io::Result<bool> {
+        let mut chunk = [0u8; 16384];
+        loop {
+            match stream.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(n) => {
+                    self.buffer.extend_from_slice(&chunk[..n]);
+                    self.check_prefixes()?;
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(true),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Returns the next complete frame without its length prefix.
+    pub(crate) fn next_frame(&mut self) -> Option<Vec<u8>> {
+        let available = &self.buffer[self.start..];
+        if available.len() < 4 {
+            self.compact();
+            return None;
+        }
+        let len = u32::from_le_bytes([available[0], available[1], available[2], available[3]]) as usize;
+        if available.len() < 4 + len {
+            self.compact();
+            return None;
+        }
+        let frame = available[4..4 + len].to_vec();
+        self.start += 4 + len;
+        Some(frame)
+    }
+
+    /// Checks every length prefix that was fully read since the last call.
+    fn check_prefixes(&mut self) -> io::Result<()> {
+        while let Some(prefix) = self.buffer.get(self.checked..self.checked + 4) {
+            let len = u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+            if len > self.max_frame_size {
+                return Err(oversized(len, self.max_frame_size));
+            }
+            self.checked += 4 + len;
+        }
+        Ok(())
+    }
+
+    fn compact(&mut self) {
+        if self.start > 0 {
+            self.buffer.drain(..self.start);
+            self.checked -= self.start;
+            self.start = 0;
+        }
+    }
+}
+
+pub(crate) fn oversized(len: usize, max_frame_size: usize) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("Frame of {len} bytes exceeds the maximum frame size of {max_frame_size} bytes"),
+    )
+}
+
+struct WriteQueue {
+    frames: VecDeque<Vec<u8>>,
+    /// How much of the first frame was already written
+    offset: usize,
+    bytes: usize,
+    /// Encrypts frames once the connection is secure. Kept under the same lock as the queue, so frames are numbered
+    /// in the order they are written.
+    sealer: Option<Sealer>,
+}
+
+/// The sending half of a nonblocking stream that is registered in a [`Poller`].
+///
+/// Frames are written right away while the socket accepts them. Whatever does not fit is queued, and the poller is asked
+/// to report the socket as writable until [`TcpOut::flush`] emptied the queue.
+pub(crate) struct TcpOut {
+    stream: TcpStream,
+    key: usize,
+    queue: Mutex<WriteQueue>,
+    max_queued: usize,
+}
+
+impl TcpOut {
+    pub(crate) fn new(stream: TcpStream, key: usize, max_queued: usize) -> Self {
+        Self {
+            stream,
+            key,
+            queue: Mutex::new(WriteQueue {
+                frames: VecDeque::new(),
+                offset: 0,
+                bytes: 0,
+                sealer: None,
+            }),
+            max_queued,
+        }
+    }
+
+    pub(crate) fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    pub(crate) fn key(&self) -> usize {
+        self.key
+    }
+
+    /// Registers the stream for readable events. It has to be removed with [`TcpOut::deregister`] before it is dropped.
+    pub(crate) fn register(&self, poller: &Poller) -> io::Result<()> {
+        // SAFETY: every owner deregisters the stream before dropping it
+        unsafe { poller.add_with_mode(&self.stream, Event::readable(self.key), PollMode::Level) }
+    }
+
+    pub(crate) fn deregister(&self, poller: &Poller) {
+        if let Err(e) = poller.delete(&self.stream) {
+            warn!("Could not remove stream from poller: {e}");
+        }
+    }
+
+    /// Encrypts every frame sent from now on.
+    pub(crate) fn secure(&self, sealer: Sealer) {
+        self.queue.lock().sealer = Some(sealer);
+    }
+
+    /// Sends `message` as a length prefixed frame, queueing what cannot be written yet.
+    /// Returns `false` and drops the frame if the queue is full.
+    pub(crate) fn send(&self, message: &[u8], poller: &Poller) -> bool {
+        let mut queue = self.queue.lock();
+        // checked before sealing, a dropped frame must not use up a counter the peer waits for
+        let len = 4 + match queue.sealer {
+            Some(_) => Sealer::sealed_len(message.len()),
+            None => message.len(),
+        };
+        if !queue.frames.is_empty() && queue.bytes + len > self.max_queued {
+            return false;
+        }
+        let frame = match &mut queue.sealer {
+            Some(sealer) => frame(&sealer.seal(message)),
+            None => frame(message),
+        };
+        if !queue.frames.is_empty() {
+            queue.bytes += frame.len();
+            queue.frames.push_back(frame);
+            return true;
+        }
+
+        let written = match write_nonblocking(&self.stream, &frame) {
+            Ok(n) => n,
+            Err(e) => {
+                // the reading side will notice the broken connection
+                warn!("Error when attempting to write packet: {e}");
+                return true;
+            }
+        };
+        if written < frame.len() {
+            queue.bytes = frame.len() - written;
+            queue.offset = written;
+            queue.frames.push_back(frame);
+            self.interest(poller, true);
+        }
+        true
+    }
+
+    /// Writes queued frames until the socket would block.
+    pub(crate) fn flush(&self, poller: &Poller) -> io::Result<()> {
+        let mut queue = self.queue.lock();
+        while let Some(frame) = queue.frames.front() {
+            let offset = queue.offset;
+            let written = write_nonblocking(&self.stream, &frame[offset..])?;
+            let remaining = frame.len() - offset - written;
+            queue.bytes -= written;
+            if remaining > 0 {
+                queue.offset += written;
+                return Ok(());
+            }
+            queue.frames.pop_front();
+            queue.offset = 0;
+        }
+        self.interest(poller, false);
+        Ok(())
+    }
+
+    /// The number of bytes waiting to be written.
+    pub(crate) fn queued(&self) -> usize {
+        self.queue.lock().bytes
+    }
+
+    fn interest(&self, poller: &Poller, writable: bool) {
+        let event = Event::new(self.key, true, writable);
+        if let Err(e) = poller.modify_with_mode(&self.stream, event, PollMode::Level) {
+            warn!("Could not update poller interest: {e}");
+        }
+    }
+}
+
+fn frame(bytes: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(4 + bytes.len());
+    frame.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    frame.extend_from_slice(bytes);
+    frame
+}
+
+/// Writes as much as the socket accepts without blocking.
+fn write_nonblocking(mut stream: &TcpStream, bytes: &[u8]) -> io::Result<usize> {
+    let mut written = 0;
+    while written < bytes.len() {
+        match stream.write(&bytes[written..]) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(n) => written += n,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(written)
+}
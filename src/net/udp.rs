@@ -0,0 +1,517 @@
+use hashbrown::HashMap;
+use log::warn;
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// How a packet sent over UDP is delivered. Packets sent over TCP are always delivered reliable and ordered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Delivery {
+    /// May be lost, duplicated or arrive out of order.
+    Unreliable,
+    /// May be lost, but packets older than the newest received one are dropped. Useful for state that is sent every tick.
+    UnreliableSequenced,
+    /// Resent until acknowledged and handed out in the order it was sent.
+    ReliableOrdered,
+}
+
+impl Delivery {
+    fn to_byte(self) -> u8 {
+        match self {
+            Delivery::Unreliable => 0,
+            Delivery::UnreliableSequenced => 1,
+            Delivery::ReliableOrdered => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Delivery::Unreliable),
+            1 => Some(Delivery::UnreliableSequenced),
+            2 => Some(Delivery::ReliableOrdered),
+            _ => None,
+        }
+    }
+
+    fn index(self) -> usize {
+        self.to_byte() as usize
+    }
+}
+
+/// Simulates a bad network by dropping and delaying outgoing datagrams. Only meant for testing.
+#[derive(Clone, Debug)]
+pub struct LinkConditioner {
+    /// The chance of a datagram being dropped, from 0 to 1.
+    pub loss: f32,
+    pub latency: Duration,
+    /// Random extra delay up to this duration. Datagrams can be reordered by it.
+    pub jitter: Duration,
+    /// Seed of the random generator, so a test always loses the same datagrams.
+    pub seed: u64,
+}
+
+/// Settings of a UDP connection.
+#[derive(Clone, Debug)]
+pub struct UdpConfig {
+    /// Packets larger than this many bytes are split into several datagrams.
+    pub fragment_size: usize,
+    /// How long to wait for an acknowledgement before sending a reliable datagram again.
+    pub resend_timeout: Duration,
+    /// How many bytes of reliable packets that are incomplete or arrived ahead of order are held at most.
+    /// Fragments beyond it are dropped without acknowledging them, so the peer sends them again later.
+    /// The next packet in order may use the same amount on its own, so larger packets are never delivered.
+    pub reassembly_limit: usize,
+    pub conditioner: Option<LinkConditioner>,
+}
+
+impl Default for UdpConfig {
+    fn default() -> Self {
+        Self {
+            fragment_size: 1024,
+            resend_timeout: Duration::from_millis(100),
+            reassembly_limit: 16 * 1024 * 1024,
+            conditioner: None,
+        }
+    }
+}
+
+const KIND_CONNECT: u8 = 0;
+const KIND_ACCEPT: u8 = 1;
+const KIND_DISCONNECT: u8 = 2;
+const KIND_DATA: u8 = 3;
+const KIND_ACK: u8 = 4;
+const KIND_REJECT: u8 = 5;
+
+const DATA_HEADER: usize = 1 + 1 + 4 + 2 + 2;
+const ACK_ENTRY: usize = 4 + 2;
+/// Fragments of unreliable packets are thrown away if this many newer packets were started since.
+const ASSEMBLY_WINDOW: u32 = 64;
+/// Reliable packets this many sequences or more ahead of the next one to hand out are dropped without acknowledging them.
+const RELIABLE_WINDOW: u32 = 1024;
+/// Disconnects are not acknowledged, so they are sent several times to survive some loss.
+pub(crate) const DISCONNECT_REPEATS: usize = 5;
+/// Every datagram starts with these bytes, anything else on the socket is ignored.
+const MAGIC: [u8; 2] = *b"MV";
+
+pub(crate) enum Datagram<'a> {
+    /// Carries the encoded hello [`Message`](crate::net::session::Message).
+    Connect(&'a [u8]),
+    Accept,
+    /// Carries the encoded [`RejectReason`](crate::net::RejectReason).
+    Reject(&'a [u8]),
+    Disconnect,
+    Data {
+        delivery: Delivery,
+        sequence: u32,
+        index: u16,
+        count: u16,
+        payload: &'a [u8],
+    },
+    Ack(Vec<(u32, u16)>),
+}
+
+impl<'a> Datagram<'a> {
+    pub(crate) fn parse(bytes: &'a [u8]) -> Option<Self> {
+        let bytes = bytes.strip_prefix(&MAGIC)?;
+        let (&kind, rest) = bytes.split_first()?;
+        match kind {
+            KIND_CONNECT => Some(Datagram::Connect(rest)),
+            KIND_ACCEPT => Some(Datagram::Accept),
+            KIND_REJECT => Some(Datagram::Reject(rest)),
+            KIND_DISCONNECT => Some(Datagram::Disconnect),
+            KIND_DATA => {
+                if rest.len() < DATA_HEADER - 1 {
+                    return None;
+                }
+                Some(Datagram::Data {
+                    delivery: Delivery::from_byte(rest[0])?,
+                    sequence: u32::from_le_bytes([rest[1], rest[2], rest[3], rest[4]]),
+                    index: u16::from_le_bytes([rest[5], rest[6]]),
+                    count: u16::from_le_bytes([rest[7], rest[8]]),
+                    payload: &rest[9..],
+                })
+            }
+            KIND_ACK => Some(Datagram::Ack(
+                rest.chunks_exact(ACK_ENTRY)
+                    .map(|c| (u32::from_le_bytes([c[0], c[1], c[2], c[3]]), u16::from_le_bytes([c[4], c[5]])))
+                    .collect(),
+            )),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        match self {
+            Datagram::Connect(hello) => {
+                bytes.push(KIND_CONNECT);
+                bytes.extend_from_slice(hello);
+            }
+            Datagram::Accept => bytes.push(KIND_ACCEPT),
+            Datagram::Reject(reason) => {
+                bytes.push(KIND_REJECT);
+                bytes.extend_from_slice(reason);
+            }
+            Datagram::Disconnect => bytes.push(KIND_DISCONNECT),
+            Datagram::Data {
+                delivery,
+                sequence,
+                index,
+                count,
+                payload,
+            } => {
+                bytes.reserve(DATA_HEADER + payload.len());
+                bytes.push(KIND_DATA);
+                bytes.push(delivery.to_byte());
+                bytes.extend_from_slice(&sequence.to_le_bytes());
+                bytes.extend_from_slice(&index.to_le_bytes());
+                bytes.extend_from_slice(&count.to_le_bytes());
+                bytes.extend_from_slice(payload);
+            }
+            Datagram::Ack(acks) => {
+                bytes.push(KIND_ACK);
+                for (sequence, index) in acks {
+                    bytes.extend_from_slice(&sequence.to_le_bytes());
+                    bytes.extend_from_slice(&index.to_le_bytes());
+                }
+            }
+        }
+        bytes
+    }
+}
+
+/// Returns true if sequence `a` was sent after `b`, assuming they are less than half the sequence space apart.
+fn is_newer(a: u32, b: u32) -> bool {
+    a != b && a.wrapping_sub(b) < u32::MAX / 2
+}
+
+/// The fragments of one packet. Only what arrived is stored, so a bogus fragment count costs nothing.
+struct Assembly {
+    count: u16,
+    fragments: BTreeMap<u16, Vec<u8>>,
+    bytes: usize,
+}
+
+impl Assembly {
+    fn new(count: u16) -> Self {
+        Self {
+            count: count.max(1),
+            fragments: BTreeMap::new(),
+            bytes: 0,
+        }
+    }
+
+    fn contains(&self, index: u16) -> bool {
+        self.fragments.contains_key(&index)
+    }
+
+    /// Stores a fragment, returns true once every fragment arrived.
+    fn insert(&mut self, index: u16, count: u16, payload: &[u8]) -> bool {
+        if count.max(1) != self.count || index >= self.count {
+            return false;
+        }
+        if !self.fragments.contains_key(&index) {
+            self.fragments.insert(index, payload.to_vec());
+            self.bytes += payload.len();
+        }
+        self.fragments.len() == self.count as usize
+    }
+
+    fn take(self) -> Vec<u8> {
+        self.fragments.into_values().flatten().collect()
+    }
+}
+
+struct Pending {
+    datagram: Vec<u8>,
+    /// `None` until the datagram was sent for the first time
+    sent_at: Option<Instant>,
+}
+
+/// The channel state of one UDP connection. Knows nothing about sockets: packets go in with [`Connection::send`]
+/// and datagrams come out of [`Connection::poll`], received datagrams go in with [`Connection::receive`].
+pub(crate) struct Connection {
+    fragment_size: usize,
+    resend_timeout: Duration,
+    reassembly_limit: usize,
+    next_sequence: [u32; 3],
+    outgoing: VecDeque<Vec<u8>>,
+    pending: BTreeMap<(u32, u16), Pending>,
+    acks: Vec<(u32, u16)>,
+    unreliable: HashMap<u32, Assembly>,
+    sequenced: HashMap<u32, Assembly>,
+    newest: [Option<u32>; 2],
+    reliable: BTreeMap<u32, Assembly>,
+    completed: BTreeMap<u32, Vec<u8>>,
+    next_reliable: u32,
+    /// The bytes held in `reliable` and `completed`.
+    held_bytes: usize,
+}
+
+impl Connection {
+    pub(crate) fn new(config: &UdpConfig) -> Self {
+        Self {
+            fragment_size: config.fragment_size.max(1),
+            resend_timeout: config.resend_timeout,
+            reassembly_limit: config.reassembly_limit,
+            next_sequence: [0; 3],
+            outgoing: VecDeque::new(),
+            pending: BTreeMap::new(),
+            acks: Vec::new(),
+            unreliable: HashMap::new(),
+            sequenced: HashMap::new(),
+            newest: [None; 2],
+            reliable: BTreeMap::new(),
+            completed: BTreeMap::new(),
+            next_reliable: 0,
+            held_bytes: 0,
+        }
+    }
+
+    /// Splits `packet` into datagrams and queues them.
+    pub(crate) fn send(&mut self, delivery: Delivery, packet: &[u8]) {
+        let count = packet.len().div_ceil(self.fragment_size).max(1);
+        if count > u16::MAX as usize {
+            warn!("Packet of {} bytes is too large to be sent over UDP", packet.len());
+            return;
+        }
+        let sequence = self.next_sequence[delivery.index()];
+        self.next_sequence[delivery.index()] = sequence.wrapping_add(1);
+
+        for index in 0..count {
+            let start = index * self.fragment_size;
+            let end = (start + self.fragment_size).min(packet.len());
+            let datagram = Datagram::Data {
+                delivery,
+                sequence,
+                index: index as u16,
+                count: count as u16,
+                payload: &packet[start..end],
+            }
+            .encode();
+            if delivery == Delivery::ReliableOrdered {
+                self.pending.insert(
+                    (sequence, index as u16),
+                    Pending {
+                        datagram,
+                        sent_at: None,
+                    },
+                );
+            } else {
+                self.outgoing.push_back(datagram);
+            }
+        }
+    }
+
+    /// Handles a data or ack datagram. Pushes every packet that is complete and may be handed out into `packets`.
+    pub(crate) fn receive(&mut self, datagram: Datagram, now: Instant, packets: &mut Vec<Vec<u8>>) {
+        match datagram {
+            Datagram::Ack(acks) => {
+                for ack in acks {
+                    self.pending.remove(&ack);
+                }
+            }
+            Datagram::Data {
+                delivery: Delivery::ReliableOrdered,
+                sequence,
+                index,
+                count,
+                payload,
+            } => {
+                if index >= count.max(1) {
+                    return;
+                }
+                if is_newer(self.next_reliable, sequence) || self.completed.contains_key(&sequence) {
+                    // handed out already, acknowledge again as the previous ack might have been lost
+                    self.acks.push((sequence, index));
+                    return;
+                }
+                if sequence.wrapping_sub(self.next_reliable) >= RELIABLE_WINDOW {
+                    // too far ahead, the peer resends it once the packets before it arrived
+                    return;
+                }
+
+                let existing = self.reliable.get(&sequence);
+                if !existing.is_some_and(|a| a.contains(index)) {
+                    let size = payload.len();
+                    let next_fits = sequence == self.next_reliable
+                        && existing.map_or(0, |a| a.bytes) + size <= self.reassembly_limit;
+                    if self.held_bytes + size > self.reassembly_limit && !next_fits {
+                        return;
+                    }
+                }
+                self.acks.push((sequence, index));
+
+                let assembly = self.reliable.entry(sequence).or_insert_with(|| Assembly::new(count));
+                let before = assembly.bytes;
+                let complete = assembly.insert(index, count, payload);
+                self.held_bytes += assembly.bytes - before;
+                if complete {
+                    if let Some(assembly) = self.reliable.remove(&sequence) {
+                        self.completed.insert(sequence, assembly.take());
+                    }
+                }
+                while let Some(packet) = self.completed.remove(&self.next_reliable) {
+                    self.held_bytes -= packet.len();
+                    packets.push(packet);
+                    self.next_reliable = self.next_reliable.wrapping_add(1);
+                }
+            }
+            Datagram::Data {
+                delivery,
+                sequence,
+                index,
+                count,
+                payload,
+            } => {
+                let slot = delivery.index();
+                let assemblies = if delivery == Delivery::Unreliable {
+                    &mut self.unreliable
+                } else {
+                    &mut self.sequenced
+                };
+                let newest = self.newest[slot];
+                if delivery == Delivery::UnreliableSequenced && newest.is_some_and(|n| !is_newer(sequence, n)) {
+                    return;
+                }
+
+                let complete = if count <= 1 {
+                    Some(payload.to_vec())
+                } else {
+                    let assembly = assemblies.entry(sequence).or_insert_with(|| Assembly::new(count));
+                    if assembly.insert(index, count, payload) {
+                        assemblies.remove(&sequence).map(Assembly::take)
+                    } else {
+                        None
+                    }
+                };
+                if let Some(packet) = complete {
+                    if delivery == Delivery::UnreliableSequenced {
+                        self.newest[slot] = Some(sequence);
+                        assemblies.retain(|s, _| is_newer(*s, sequence));
+                    }
+                    packets.push(packet);
+                }
+                assemblies.retain(|s, _| sequence.wrapping_sub(*s) < ASSEMBLY_WINDOW || is_newer(*s, sequence));
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the datagrams that should be sent now: new ones, resends of unacknowledged ones and acks.
+    pub(crate) fn poll(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let mut datagrams: Vec<Vec<u8>> = self.outgoing.drain(..).collect();
+
+        for pending in self.pending.values_mut() {
+            if pending.sent_at.is_none_or(|at| now.duration_since(at) >= self.resend_timeout) {
+                pending.sent_at = Some(now);
+                datagrams.push(pending.datagram.clone());
+            }
+        }
+
+        let per_datagram = (self.fragment_size / ACK_ENTRY).max(1);
+        for chunk in self.acks.chunks(per_datagram) {
+            datagrams.push(Datagram::Ack(chunk.to_vec()).encode());
+        }
+        self.acks.clear();
+
+        datagrams
+    }
+
+    /// The number of reliable datagrams that were not acknowledged yet.
+    pub(crate) fn unacknowledged(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// The size of the datagrams that were not sent or not acknowledged yet.
+    pub(crate) fn queued_bytes(&self) -> usize {
+        let outgoing: usize = self.outgoing.iter().map(Vec::len).sum();
+        // reliable datagrams are only kept in pending
+        outgoing + self.pending.values().map(|p| p.datagram.len()).sum::<usize>()
+    }
+
+    /// When [`Connection::poll`] has to be called next to resend datagrams or send acks, if ever.
+    pub(crate) fn next_poll(&self) -> Option<Instant> {
+        if !self.outgoing.is_empty() || !self.acks.is_empty() {
+            return Some(Instant::now());
+        }
+        self.pending
+            .values()
+            .map(|p| p.sent_at.map_or(Instant::now(), |at| at + self.resend_timeout))
+            .min()
+    }
+}
+
+/// A UDP socket that applies a [`LinkConditioner`] to everything it sends.
+pub(crate) struct Link {
+    socket: UdpSocket,
+    conditioner: Option<LinkConditioner>,
+    rng: u64,
+    delayed: Vec<(Instant, SocketAddr, Vec<u8>)>,
+}
+
+impl Link {
+    pub(crate) fn new(socket: UdpSocket, conditioner: Option<LinkConditioner>) -> Self {
+        let rng = conditioner.as_ref().map_or(1, |c| c.seed.max(1));
+        Self {
+            socket,
+            conditioner,
+            rng,
+            delayed: Vec::new(),
+        }
+    }
+
+    pub(crate) fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub(crate) fn send_to(&mut self, datagram: Vec<u8>, addr: SocketAddr, now: Instant) {
+        let Some(conditioner) = self.conditioner.clone() else {
+            Self::send_now(&self.socket, &datagram, addr);
+            return;
+        };
+        if self.random() < conditioner.loss {
+            return;
+        }
+        let delay = conditioner.latency + conditioner.jitter.mul_f32(self.random());
+        if delay.is_zero() {
+            Self::send_now(&self.socket, &datagram, addr);
+        } else {
+            self.delayed.push((now + delay, addr, datagram));
+        }
+    }
+
+    /// Sends the datagrams held back by the conditioner whose time has come.
+    pub(crate) fn flush(&mut self, now: Instant) {
+        let socket = &self.socket;
+        self.delayed.retain(|(at, addr, datagram)| {
+            if *at <= now {
+                Self::send_now(socket, datagram, *addr);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// When [`Link::flush`] has to be called next to send held back datagrams, if ever.
+    pub(crate) fn next_flush(&self) -> Option<Instant> {
+        self.delayed.iter().map(|(at, _, _)| *at).min()
+    }
+
+    fn send_now(socket: &UdpSocket, datagram: &[u8], addr: SocketAddr) {
+        if let Err(e) = socket.send_to(datagram, addr) {
+            if e.kind() != io::ErrorKind::WouldBlock {
+                warn!("Error when attempting to send datagram to {addr}: {e}");
+            }
+        }
+    }
+}
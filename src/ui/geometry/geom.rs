@@ -191,6 +191,10 @@ pub fn normalize(v: Vec2) -> Vec2 {
     }
 }
 
+pub fn dot(a: Vec2, b: Vec2) -> f32 {
+    a.x * b.x + a.y * b.y
+}
+
 pub fn perpendicular(v: Vec2) -> Vec2 {
     Vec2 { x: -v.y, y: v.x }
 }
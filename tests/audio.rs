@@ -0,0 +1,844 @@
+use mvengine::audio::decode::flac::FlacDecoder;
+use mvengine::audio::decode::mp3::Mp3Decoder;
+use mvengine::audio::decode::vorbis::VorbisDecoder;
+use mvengine::audio::decode::wav::{WavData, WavDecoder};
+use mvengine::audio::decode::{AudioDecoder, decode_any};
+use mvengine::audio::dj::{DJ, Playlist, Quantize, Repeat, Tempo, Track, Transition};
+use mvengine::audio::effect::AudioEffect;
+use mvengine::audio::effect::delay::Delay;
+use mvengine::audio::effect::dynamics::{Compressor, Limiter};
+use mvengine::audio::effect::filter::{HighPass, LowPass};
+use mvengine::audio::effect::reverb::Reverb;
+use mvengine::audio::output::AudioBackend;
+use mvengine::audio::{AudioEngine, gen_sin_wave};
+use mvengine::audio::handle::PlaybackEnd;
+use mvengine::audio::mixer::{AudioMixer, Bus, VoiceLimit, VoiceSteal};
+use mvengine::audio::resample::{Interpolation, Playhead};
+use mvengine::audio::source::{Sound, SoundWithAttributes};
+use mvengine::audio::spatial::{Attenuation, AudioListener, Emitter, Listener, Rolloff, SoundEmitter, SpatialAudioSystem};
+use mvengine::audio::stream::StreamingSound;
+use mvengine::game::ecs::entity::next_entity_id;
+use mvengine::game::ecs::world::EcsWorld;
+use mvengine::game::ecs::{Ecs, EcsBackend};
+use mvengine::game::physics::components::Transform;
+use mvengine::math::vec::Vec2;
+use mvengine::ui::ease::EasingGen;
+use std::f32::consts::FRAC_PI_2;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const WAV: &[u8] = include_bytes!("tone.wav");
+const FLAC: &[u8] = include_bytes!("tone.flac");
+const OGG: &[u8] = include_bytes!("tone.ogg");
+const MP3: &[u8] = include_bytes!("tone.mp3");
+
+// tone.* hold the same quarter second of stereo 16 bit audio at 44.1 kHz, 440 Hz left and 660 Hz right
+const FRAMES: usize = 11025;
+
+fn main() {
+    sniffing();
+    decoding();
+    streaming();
+    effects();
+    mixing();
+    spatial();
+    resampling();
+    voices();
+    rendering();
+    dj();
+    println!("end");
+}
+
+fn sniffing() {
+    assert!(WavDecoder::is_compatible(WAV));
+    assert!(FlacDecoder::is_compatible(FLAC));
+    assert!(VorbisDecoder::is_compatible(OGG));
+    assert!(Mp3Decoder::is_compatible(MP3));
+
+    for (i, a) in [WAV, FLAC, OGG, MP3].into_iter().enumerate() {
+        let matches = [
+            WavDecoder::is_compatible(a),
+            FlacDecoder::is_compatible(a),
+            VorbisDecoder::is_compatible(a),
+            Mp3Decoder::is_compatible(a),
+        ];
+        for (j, matched) in matches.into_iter().enumerate() {
+            assert_eq!(matched, i == j, "Fixture {i} matched decoder {j}");
+        }
+    }
+
+    assert!(decode_any(b"definitely not audio").is_none());
+    assert!(decode_any(&[]).is_none());
+
+    // recognized, but corrupt or cut off, is reported instead of panicking
+    let mut garbage = b"fLaC".to_vec();
+    garbage.extend((0..200u32).map(|i| (i * 37) as u8));
+    assert!(decode_any(&garbage).is_none());
+    assert!(FlacDecoder::try_decode(&garbage).is_err());
+    assert!(decode_any(&OGG[..64]).is_none());
+    // well formed, but with 24 bit samples or more than two channels
+    let mut wide = WavData::from_samples(2, 44100, &[0.0; 8]).to_bytes();
+    wide[34..36].copy_from_slice(&24u16.to_le_bytes());
+    assert!(WavDecoder::is_compatible(&wide));
+    assert!(decode_any(&wide).is_none());
+    let mut surround = WavData::from_samples(2, 44100, &[0.0; 8]).to_bytes();
+    surround[22..24].copy_from_slice(&6u16.to_le_bytes());
+    assert!(decode_any(&surround).is_none());
+    assert!(WavDecoder::try_decode(&surround).is_err());
+}
+
+fn decoding() {
+    let reference = decode_any(WAV).expect("Wav is supported");
+    assert_eq!(reference.channels(), 2);
+    assert_eq!(reference.sample_rate(), 44100);
+    assert_eq!(reference.total_samples(), FRAMES * 2);
+    // the 16 bit reference, quantized like it was encoded
+    let (left, right) = reference.get_sample(441);
+    assert!((left - 0.5 * (std::f32::consts::TAU * 4.4).sin()).abs() < 1e-4);
+    assert!((right - 0.4 * (std::f32::consts::TAU * 6.6).sin()).abs() < 1e-4);
+
+    // lossless, so every sample matches
+    let flac = decode_any(FLAC).expect("Flac is supported");
+    assert_eq!(flac.channels(), 2);
+    assert_eq!(flac.sample_rate(), 44100);
+    assert_eq!(samples(&flac), samples(&reference));
+
+    let vorbis = decode_any(OGG).expect("Ogg vorbis is supported");
+    assert_eq!(vorbis.channels(), 2);
+    assert_eq!(vorbis.sample_rate(), 44100);
+    // the granule position of the last page trims the padding
+    assert_eq!(vorbis.total_samples(), FRAMES * 2);
+    let vorbis_snr = snr(&reference, &vorbis);
+    println!("vorbis snr: {vorbis_snr:.1} dB");
+    assert!(vorbis_snr > 35.0);
+
+    let mp3 = decode_any(MP3).expect("Mp3 is supported");
+    assert_eq!(mp3.channels(), 2);
+    assert_eq!(mp3.sample_rate(), 44100);
+    // the LAME tag trims the encoder delay and padding
+    assert_eq!(mp3.total_samples(), FRAMES * 2);
+    let mp3_snr = snr(&reference, &mp3);
+    println!("mp3 snr: {mp3_snr:.1} dB");
+    // 192 kbps keeps the tones well above the noise, a misaligned decode would be far below this
+    assert!(mp3_snr > 25.0);
+
+    // the typed decoders give the same result
+    assert_eq!(samples(&FlacDecoder.decode(FLAC)), samples(&flac));
+    assert_eq!(samples(&Mp3Decoder.decode(MP3)), samples(&mp3));
+}
+
+fn samples(sound: &Arc<Sound>) -> Vec<f32> {
+    (0..sound.total_samples()).map(|i| sound.get_sample_raw(i)).collect()
+}
+
+/// Signal to noise ratio of a lossy decode against the reference, in decibels.
+fn snr(reference: &Arc<Sound>, decoded: &Arc<Sound>) -> f64 {
+    let mut signal = 0.0;
+    let mut noise = 0.0;
+    for (r, d) in samples(reference).into_iter().zip(samples(decoded)) {
+        signal += (r as f64).powi(2);
+        noise += (r as f64 - d as f64).powi(2);
+    }
+    10.0 * (signal / noise).log10()
+}
+
+fn streaming() {
+    let reference = decode_any(FLAC).expect("Flac is supported");
+    let frame = |i: usize| reference.get_sample(i % FRAMES);
+
+    // a 20ms buffer makes the decode thread refill it many times
+    let stream = StreamingSound::with_buffer(Cursor::new(FLAC), Duration::from_millis(20)).expect("Flac streams");
+    assert_eq!(stream.channels(), 2);
+    assert_eq!(stream.sample_rate(), 44100);
+    assert_eq!(stream.duration(), Some(Duration::from_secs_f64(0.25)));
+
+    let played = pull(&stream, FRAMES, 1);
+    assert!(played.iter().enumerate().all(|(i, f)| *f == frame(i)));
+    assert!(wait(|| stream.is_finished()));
+
+    // looping continues at the start
+    stream.set_looping(true);
+    stream.seek(Duration::from_secs_f64(0.2));
+    let played = pull(&stream, 4000, 1);
+    assert!(played.iter().enumerate().all(|(i, f)| *f == frame(8820 + i)));
+    assert!(!stream.is_finished());
+    // 8820 + 3999 wrapped around the length
+    let position = stream.position().as_secs_f64();
+    assert!((position - 1794.0 / 44100.0).abs() < 1e-6);
+
+    // seeking backwards and at double speed, which skips every other frame
+    stream.set_looping(false);
+    stream.seek(Duration::from_millis(50));
+    stream.set_speed(2.0);
+    let played = pull(&stream, 1000, 2);
+    assert!(played.iter().enumerate().all(|(i, f)| *f == frame(2205 + i * 2)));
+    stream.set_speed(1.0);
+
+    // volume and fades apply like for decoded sounds
+    stream.seek(Duration::ZERO);
+    stream.set_volume(0.5);
+    let played = pull(&stream, 100, 1);
+    assert!(played.iter().enumerate().all(|(i, f)| {
+        let (left, right) = frame(i);
+        f.0 == left * 0.5 && f.1 == right * 0.5
+    }));
+    stream.set_volume(1.0);
+
+    // a fade out longer than the sound starts right away instead of overflowing
+    stream.set_fade_out(EasingGen::linear(), 1000);
+    stream.seek(Duration::from_millis(200));
+    let played = pull(&stream, 100, 1);
+    assert!(played.iter().enumerate().all(|(i, f)| {
+        let (left, right) = frame(8820 + i);
+        (f.0.abs() < left.abs() || left == 0.0) && (f.1.abs() < right.abs() || right == 0.0)
+    }));
+    let sound = SoundWithAttributes::new(reference.clone());
+    sound.set_fade_out(EasingGen::linear(), 1000);
+    let (left, _) = sound.get_sample(1000);
+    assert!(left.abs() < frame(1000).0.abs());
+    // fades of several minutes do not overflow
+    sound.set_fade_in_at(EasingGen::linear(), 0, 300_000);
+    assert!(sound.get_sample(1000).0.abs() < left.abs());
+
+    // any reader works, the format is detected from the contents
+    for bytes in [WAV, OGG, MP3] {
+        let stream = StreamingSound::from_reader(Cursor::new(bytes)).expect("Format streams");
+        let decoded = decode_any(bytes).expect("Format decodes");
+        let played = pull(&stream, FRAMES, 1);
+        assert!(played.iter().enumerate().all(|(i, f)| *f == decoded.get_sample(i)));
+        assert!(wait(|| stream.is_finished()));
+    }
+    assert!(StreamingSound::from_reader(Cursor::new(b"definitely not audio")).is_err());
+}
+
+/// Plays `frames` frames at the rate of the fixtures, waiting for the decode thread whenever it fell behind.
+/// At speed `step`, every played frame consumes `step` decoded frames.
+fn pull(stream: &StreamingSound, frames: usize, step: usize) -> Vec<(f32, f32)> {
+    let mut played = Vec::with_capacity(frames);
+    while played.len() < frames {
+        assert!(wait(|| stream.buffered() >= step), "Decode thread stalled");
+        played.push(stream.next_sample(44100));
+    }
+    played
+}
+
+fn wait(condition: impl Fn() -> bool) -> bool {
+    let start = Instant::now();
+    while !condition() {
+        if start.elapsed() > Duration::from_secs(5) {
+            return false;
+        }
+        std::thread::sleep(Duration::from_micros(100));
+    }
+    true
+}
+
+const RATE: u32 = 48000;
+
+/// A mono sine of `amplitude` as stereo frames, one second long.
+fn sine(freq: u32, amplitude: f32) -> Vec<(f32, f32)> {
+    let sound = gen_sin_wave(freq, RATE, 1000);
+    (0..sound.total_samples())
+        .map(|i| (sound.get_sample_raw(i) * amplitude, sound.get_sample_raw(i) * amplitude))
+        .collect()
+}
+
+fn impulse(length: usize) -> Vec<(f32, f32)> {
+    let mut frames = vec![(0.0, 0.0); length];
+    frames[0] = (1.0, 1.0);
+    frames
+}
+
+/// Processes in blocks of 256 frames like the mixer does.
+fn process(effect: &mut impl AudioEffect, mut frames: Vec<(f32, f32)>) -> Vec<(f32, f32)> {
+    for block in frames.chunks_mut(256) {
+        effect.process(block, RATE);
+    }
+    frames
+}
+
+fn rms(frames: &[(f32, f32)]) -> f32 {
+    (frames.iter().map(|f| f.0 * f.0).sum::<f32>() / frames.len() as f32).sqrt()
+}
+
+fn peak(frames: &[(f32, f32)]) -> f32 {
+    frames.iter().map(|f| f.0.abs().max(f.1.abs())).fold(0.0, f32::max)
+}
+
+fn effects() {
+    let sine_rms = rms(&sine(100, 1.0));
+
+    // filters pass one side of the cutoff and remove the other, skipping the settling at the start
+    let low = process(&mut LowPass::new(1000.0), sine(100, 1.0));
+    assert!(rms(&low[4800..]) / sine_rms > 0.95);
+    let low = process(&mut LowPass::new(1000.0), sine(10000, 1.0));
+    assert!(rms(&low[4800..]) / sine_rms < 0.02);
+    let high = process(&mut HighPass::new(1000.0), sine(10000, 1.0));
+    assert!(rms(&high[4800..]) / sine_rms > 0.95);
+    let high = process(&mut HighPass::new(1000.0), sine(100, 1.0));
+    assert!(rms(&high[4800..]) / sine_rms < 0.02);
+    // -3 dB at the cutoff
+    let cutoff = process(&mut LowPass::new(1000.0), sine(1000, 1.0));
+    assert!((rms(&cutoff[4800..]) / sine_rms - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.02);
+
+    // echoes every 100ms, each half as loud
+    let echoes = process(&mut Delay::new(Duration::from_millis(100), 0.5, 1.0), impulse(RATE as usize));
+    assert_eq!(echoes[0], (1.0, 1.0));
+    assert_eq!(echoes[4800], (1.0, 1.0));
+    assert_eq!(echoes[9600], (0.5, 0.5));
+    assert_eq!(echoes[14400], (0.25, 0.25));
+    assert_eq!(echoes.iter().filter(|f| f.0 != 0.0).count(), 10);
+
+    // the reverb tail rings on and decays, without the reverb only the impulse remains
+    let mut reverb = Reverb::new(0.8, 0.5, 1.0);
+    let tail = process(&mut reverb, impulse(RATE as usize * 2));
+    assert_eq!(tail[0], (1.0, 1.0));
+    let early = rms(&tail[2400..12000]);
+    let late = rms(&tail[72000..]);
+    assert!(early > 1e-3);
+    assert!(late < early / 4.0);
+    assert_ne!(tail[24000].0, tail[24000].1, "Reverb should be stereo");
+    reverb.reset();
+    reverb.set_wet(0.0);
+    let dry = process(&mut reverb, impulse(RATE as usize));
+    assert!(dry[1..].iter().all(|f| *f == (0.0, 0.0)));
+
+    // a full scale sine, 20 dB above the threshold, comes out 20 / 4 dB above it
+    let compressed = process(&mut Compressor::new(-20.0, 4.0), sine(440, 1.0));
+    let expected = 10.0f32.powf(-15.0 / 20.0);
+    let level = peak(&compressed[24000..]);
+    assert!((level / expected).log10().abs() * 20.0 < 1.0, "Compressed to {level}, expected {expected}");
+    // quiet signals stay untouched
+    let quiet = process(&mut Compressor::new(-20.0, 4.0), sine(440, 0.05));
+    assert!((peak(&quiet[24000..]) - 0.05).abs() < 1e-3);
+
+    let mut limiter = Limiter::new(0.9);
+    let limited = process(&mut limiter, sine(440, 3.0));
+    assert!(peak(&limited) <= 0.9);
+    assert!(peak(&limited[24000..]) > 0.85);
+    assert!(limiter.gain() < 0.35);
+    // and recovers afterwards
+    let recovered = process(&mut limiter, sine(440, 0.5));
+    assert!((peak(&recovered[24000..]) - 0.5).abs() < 1e-3);
+}
+
+fn mixing() {
+    let mut mixer = AudioMixer::new();
+    let loud = |freq| SoundWithAttributes::new(gen_sin_wave(freq, RATE, 1000));
+    for freq in [220, 330, 440] {
+        mixer.play_on(Bus::Music, loud(freq));
+    }
+    mixer.play(loud(550));
+
+    // three full scale sines on one bus would clip, the limiter keeps them in range
+    let mut out = vec![(0.0, 0.0); 12000];
+    mixer.mix(&mut out[..5000], RATE);
+    mixer.mix(&mut out[5000..], RATE);
+    assert!(peak(&out) <= 0.98);
+    assert!(peak(&out) > 0.9);
+
+    // muting the music bus leaves the sine on the sfx bus, with a low-pass filter on it
+    mixer.bus_mut(Bus::Music).set_volume(0.0);
+    mixer.bus_mut(Bus::Sfx).add_effect(LowPass::new(100.0));
+    let mut out = vec![(0.0, 0.0); 12000];
+    mixer.mix(&mut out, RATE);
+    assert!(peak(&out[4800..]) < 0.05);
+    mixer.bus_mut(Bus::Sfx).clear_effects();
+    mixer.set_master_volume(0.5);
+    mixer.mix(&mut out, RATE);
+    // mono sounds are split between both channels
+    assert!((peak(&out) - 0.25).abs() < 1e-3);
+
+    // the sounds were a second long, so they ended
+    mixer.mix(&mut out, RATE);
+    mixer.mix(&mut out, RATE);
+    assert_eq!(peak(&out), 0.0);
+}
+
+fn spatial() {
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+
+    let inverse = Attenuation::new(Rolloff::Inverse(1.0), 1.0, 100.0);
+    assert_eq!(inverse.gain(0.5), 1.0);
+    assert!(close(inverse.gain(2.0), 0.5));
+    assert!(close(inverse.gain(4.0), 0.25));
+    assert_eq!(inverse.gain(101.0), 0.0);
+    let linear = Attenuation::new(Rolloff::Linear, 0.0, 10.0);
+    assert!(close(linear.gain(5.0), 0.5));
+    let exponential = Attenuation::new(Rolloff::Exponential(0.5), 2.0, 100.0);
+    assert!(close(exponential.gain(8.0), 0.25));
+    let custom = Attenuation::new(Rolloff::Custom(Arc::new(|d| 2.0 / d)), 1.0, 10.0);
+    assert!(close(custom.gain(4.0), 0.5));
+    assert!(close(custom.gain(1.0), 1.0));
+
+    // the listener hears an emitter on its right in the right ear, until it turns around
+    let listener = AudioListener::new();
+    let sound = || SoundWithAttributes::new(gen_sin_wave(440, RATE, 500));
+    let emitter = SoundEmitter::at(sound(), Vec2::new(5.0, 0.0));
+    emitter.set_attenuation(Attenuation::new(Rolloff::None, 1.0, f32::INFINITY));
+    let (left, right, pitch) = emitter.spatialize(&listener);
+    assert!(close(left, 0.0) && close(right, 1.0) && pitch == 1.0);
+    listener.set_rotation(std::f32::consts::PI);
+    let (left, right, _) = emitter.spatialize(&listener);
+    assert!(close(left, 1.0) && close(right, 0.0));
+    listener.set_rotation(FRAC_PI_2);
+    let (left, right, _) = emitter.spatialize(&listener);
+    assert!(close(left, 0.5) && close(right, 0.5));
+    listener.set_rotation(0.0);
+
+    // approaching raises the pitch, receding lowers it
+    emitter.set_doppler(1.0);
+    emitter.set_velocity(Vec2::new(-34.3, 0.0));
+    let (_, _, approaching) = emitter.spatialize(&listener);
+    assert!(close(approaching, 343.0 / 308.7));
+    emitter.set_velocity(Vec2::new(34.3, 0.0));
+    let (_, _, receding) = emitter.spatialize(&listener);
+    assert!(receding < 1.0);
+    emitter.set_doppler(0.0);
+
+    // mixed on its side, quieter further away
+    let mut mixer = AudioMixer::new();
+    let near = SoundEmitter::at(sound(), Vec2::new(-2.0, 0.0));
+    let far = SoundEmitter::at(sound(), Vec2::new(-8.0, 0.0));
+    mixer.play_emitter(near.clone());
+    let mut out = vec![(0.0, 0.0); 4800];
+    mixer.mix(&mut out, RATE);
+    let left = out.iter().map(|f| f.0.abs()).fold(0.0, f32::max);
+    let right = out.iter().map(|f| f.1.abs()).fold(0.0, f32::max);
+    assert!(left > 0.4 && right < 1e-6);
+    let mut quiet = AudioMixer::new();
+    quiet.play_emitter(far);
+    let mut far_out = vec![(0.0, 0.0); 4800];
+    quiet.mix(&mut far_out, RATE);
+    assert!(rms(&far_out) < rms(&out) * 0.3);
+    // the sounds were half a second long
+    mixer.mix(&mut vec![(0.0, 0.0); 24000], RATE);
+    assert!(near.is_finished());
+    mixer.mix(&mut out, RATE);
+    assert_eq!(peak(&out), 0.0);
+
+    // the system moves emitters and the listener along with their entities
+    let mut ecs = Ecs::new(EcsBackend::SparseSet);
+    let world = ecs.world_mut();
+    let ears = next_entity_id();
+    world.create_entity(ears);
+    world.set_component(ears, Transform::default());
+    world.set_component(ears, Listener);
+    let source = next_entity_id();
+    let emitter = SoundEmitter::new(sound());
+    world.create_entity(source);
+    world.set_component(source, Transform {
+        position: Vec2::new(3.0, 4.0),
+        ..Default::default()
+    });
+    world.set_component(source, Emitter(emitter.clone()));
+    let listener = AudioListener::new();
+    let mut system = SpatialAudioSystem::new(listener.clone());
+    system.iterate(world, 0.5);
+    assert_eq!(emitter.position(), Vec2::new(3.0, 4.0));
+    // appearing is not moving
+    assert_eq!(emitter.velocity(), Vec2::default());
+    if let Some(t) = world.get_component_mut::<Transform>(ears) {
+        t.position = Vec2::new(1.0, 0.0);
+        t.rotation = 1.0;
+    }
+    system.iterate(world, 0.5);
+    assert_eq!(listener.position(), Vec2::new(1.0, 0.0));
+    assert_eq!(listener.rotation(), 1.0);
+    assert_eq!(listener.velocity(), Vec2::new(2.0, 0.0));
+    assert_eq!(emitter.velocity(), Vec2::default());
+
+    // moving faster than sound is a teleport, and the velocity comes back with the next regular movement
+    let moves = [
+        (Vec2::new(3.0, 6.0), Vec2::new(0.0, 4.0)),
+        (Vec2::new(500.0, 6.0), Vec2::default()),
+        (Vec2::new(501.0, 6.0), Vec2::new(2.0, 0.0)),
+    ];
+    for (position, velocity) in moves {
+        if let Some(t) = world.get_component_mut::<Transform>(source) {
+            t.position = position;
+        }
+        system.iterate(world, 0.5);
+        assert_eq!(emitter.position(), position);
+        assert_eq!(emitter.velocity(), velocity);
+    }
+}
+
+/// Plays `frames` frames of the sound at `rate` and returns the left channel.
+fn render(sound: &SoundWithAttributes, rate: u32, frames: usize) -> Vec<f32> {
+    let mut playhead = Playhead::default();
+    (0..frames).map(|_| sound.next_frame(&mut playhead, rate).0).collect()
+}
+
+/// The amplitude of `freq` in `signal`, from a hann windowed DFT bin.
+fn magnitude(signal: &[f32], freq: f32, rate: u32) -> f32 {
+    let n = signal.len() as f32;
+    let (mut re, mut im, mut window_sum) = (0.0f64, 0.0f64, 0.0f64);
+    for (i, s) in signal.iter().enumerate() {
+        let window = 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / n).cos();
+        let phase = 2.0 * std::f64::consts::PI * freq as f64 * i as f64 / rate as f64;
+        re += (s * window) as f64 * phase.cos();
+        im -= (s * window) as f64 * phase.sin();
+        window_sum += window as f64;
+    }
+    (2.0 * (re * re + im * im).sqrt() / window_sum) as f32
+}
+
+/// How far `signal` is from `reference`, in decibels.
+fn error_db(signal: &[f32], reference: &[f32]) -> f32 {
+    let power = |s: &mut dyn Iterator<Item = f32>| s.map(|x| x * x).sum::<f32>();
+    let error = power(&mut signal.iter().zip(reference).map(|(a, b)| a - b));
+    10.0 * (power(&mut reference.iter().copied()) / error.max(1e-20)).log10()
+}
+
+fn resampling() {
+    const SOURCE: u32 = 44100;
+    let tone = |freq, interpolation| {
+        let sound = SoundWithAttributes::new(gen_sin_wave(freq, SOURCE, 500));
+        sound.set_interpolation(interpolation);
+        // mono sounds are split between both channels, this plays the left one at full volume
+        sound.set_balance(0.0);
+        sound
+    };
+
+    // a sweep of tones converted from 44.1 to 48 kHz keeps its frequency and level, the better the interpolation
+    // the closer it is to the tone generated at 48 kHz
+    for freq in [100, 1000, 4000, 10000, 16000] {
+        let reference = gen_sin_wave(freq, RATE, 200);
+        let reference = &samples(&reference)[..9000];
+        let errors: Vec<f32> = [Interpolation::Nearest, Interpolation::Linear, Interpolation::Sinc]
+            .into_iter()
+            .map(|interpolation| {
+                let output = render(&tone(freq, interpolation), RATE, 9000);
+                // the start and end are left out, the sinc rings into the silence around the sound
+                error_db(&output[100..8900], &reference[100..8900])
+            })
+            .collect();
+        assert!(errors[0] + 3.0 < errors[1]);
+        assert!(errors[2] > 60.0, "{freq} Hz is {} dB off", errors[2]);
+        let sinc = render(&tone(freq, Interpolation::Sinc), RATE, 9000);
+        assert!((magnitude(&sinc[100..8900], freq as f32, RATE) - 1.0).abs() < 1e-3);
+    }
+
+    // at twice the speed a tone goes up an octave
+    let sound = tone(3000, Interpolation::Sinc);
+    sound.set_speed(2.0);
+    let output = render(&sound, SOURCE, 8000);
+    assert!(magnitude(&output[100..7900], 6000.0, SOURCE) > 0.999);
+    assert!(magnitude(&output[100..7900], 3000.0, SOURCE) < 1e-3);
+    // and one that would be above the nyquist frequency is filtered out, instead of folding back down
+    let aliased = |interpolation| {
+        let sound = tone(15000, interpolation);
+        sound.set_speed(2.0);
+        let output = render(&sound, SOURCE, 8000);
+        magnitude(&output[100..7900], 44100.0 - 30000.0, SOURCE)
+    };
+    assert!(aliased(Interpolation::Linear) > 0.9);
+    assert!(aliased(Interpolation::Sinc) < 1e-3);
+
+    // time stretching keeps the pitch and only changes the tempo
+    for speed in [1.5, 0.75] {
+        let sound = tone(440, Interpolation::Linear);
+        sound.set_speed(speed);
+        sound.set_time_stretch(true);
+        let mut playhead = Playhead::default();
+        let mut output = vec![];
+        while !sound.is_finished(&playhead) {
+            output.push(sound.next_frame(&mut playhead, SOURCE).0);
+        }
+        assert_eq!(output.len(), (22050.0 / speed).ceil() as usize);
+        let output = &output[..12000];
+        assert!(magnitude(output, 440.0, SOURCE) > 0.99, "{speed}x");
+        assert!(magnitude(output, 440.0 * speed as f32, SOURCE) < 1e-3, "{speed}x");
+    }
+    // while without it the pitch moves with the speed, like a tape
+    let sound = tone(440, Interpolation::Linear);
+    sound.set_speed(1.5);
+    let output = render(&sound, SOURCE, 12000);
+    assert!(magnitude(&output, 440.0, SOURCE) < 1e-3);
+    assert!(magnitude(&output, 660.0, SOURCE) > 0.99);
+}
+
+fn voices() {
+    let mut mixer = AudioMixer::new();
+    let tone = || SoundWithAttributes::new(gen_sin_wave(440, RATE, 1000));
+    let ends = Arc::new(Mutex::new(vec![]));
+    let record = |ends: &Arc<Mutex<Vec<PlaybackEnd>>>| {
+        let ends = ends.clone();
+        move |end| ends.lock().expect("Ends poisoned").push(end)
+    };
+    let mut out = vec![(0.0, 0.0); 4800];
+
+    let handle = mixer.play(tone());
+    handle.on_finish(record(&ends));
+    mixer.mix(&mut out, RATE);
+    assert_eq!(handle.position(), Duration::from_millis(100));
+    assert!(peak(&out) > 0.4);
+
+    // paused voices keep their place and play silence
+    handle.pause();
+    mixer.mix(&mut out, RATE);
+    assert_eq!(peak(&out), 0.0);
+    assert_eq!(handle.position(), Duration::from_millis(100));
+    assert!(handle.is_paused() && handle.is_playing());
+    handle.resume();
+    handle.seek(Duration::from_millis(500));
+    mixer.mix(&mut out, RATE);
+    assert!(peak(&out) > 0.4);
+    assert_eq!(handle.position(), Duration::from_millis(600));
+
+    // stopping ends the voice with the next block, the callbacks run once the mixer hands them out
+    handle.stop();
+    mixer.mix(&mut out, RATE);
+    assert_eq!(peak(&out), 0.0);
+    assert_eq!(mixer.voices(), 0);
+    assert!(handle.is_playing());
+    mixer.take_finished().notify();
+    assert_eq!(handle.end(), Some(PlaybackEnd::Stopped));
+    // callbacks added afterwards run right away
+    handle.on_finish(record(&ends));
+    assert_eq!(*ends.lock().expect("Ends poisoned"), [PlaybackEnd::Stopped, PlaybackEnd::Stopped]);
+
+    let handle = mixer.play(tone());
+    for _ in 0..9 {
+        mixer.mix(&mut out, RATE);
+    }
+    assert!(mixer.take_finished().is_empty());
+    mixer.mix(&mut out, RATE);
+    mixer.take_finished().notify();
+    assert_eq!(handle.end(), Some(PlaybackEnd::Finished));
+    assert_eq!(handle.position(), Duration::from_secs(1));
+
+    // the oldest voice of the lowest priority makes way for new sounds
+    mixer.set_voice_limit(VoiceLimit {
+        max_voices: 2,
+        steal: VoiceSteal::Oldest,
+    });
+    let first = mixer.play(tone());
+    let second = mixer.play(tone());
+    let third = mixer.play(tone());
+    assert_eq!(mixer.voices(), 2);
+    let rejected = mixer.play_with_priority(Bus::Sfx, tone(), -1);
+    assert_eq!(rejected.end(), Some(PlaybackEnd::Rejected));
+    let important = mixer.play_with_priority(Bus::Sfx, tone(), 5);
+    let unimportant = mixer.play_with_priority(Bus::Sfx, tone(), 0);
+    mixer.take_finished().notify();
+    assert_eq!(first.end(), Some(PlaybackEnd::Stolen));
+    assert_eq!(second.end(), Some(PlaybackEnd::Stolen));
+    assert_eq!(third.end(), Some(PlaybackEnd::Stolen));
+    assert!(important.is_playing() && unimportant.is_playing());
+
+    mixer.set_voice_limit(VoiceLimit {
+        max_voices: 2,
+        steal: VoiceSteal::None,
+    });
+    assert_eq!(mixer.play(tone()).end(), Some(PlaybackEnd::Rejected));
+}
+
+/// Half a second of music with fades and reverb, an emitter to the left and, after `split` frames, a sound effect
+/// with an echo. The frames are rendered in `chunks`.
+fn render_scene(split: usize, chunks: &[usize]) -> Vec<(f32, f32)> {
+    let engine = AudioEngine::with_backend(AudioBackend::Offline { sample_rate: RATE }).expect("Offline engine");
+    {
+        let mut mixer = engine.mixer();
+        mixer.bus_mut(Bus::Music).add_effect(Reverb::new(0.5, 0.5, 0.3));
+        mixer.bus_mut(Bus::Sfx).add_effect(Delay::new(Duration::from_millis(50), 0.4, 0.5));
+    }
+    let music = SoundWithAttributes::new(gen_sin_wave(220, RATE, 500));
+    music.set_fade_in(EasingGen::linear(), 100);
+    music.set_fade_out(EasingGen::sin(), 150);
+    engine.play_sound_on(Bus::Music, music);
+    engine.play_emitter(SoundEmitter::at(
+        SoundWithAttributes::new(gen_sin_wave(330, RATE, 250)),
+        Vec2::new(-3.0, 1.0),
+    ));
+
+    let mut out = engine.render(split);
+    engine.play_sound(SoundWithAttributes::new(gen_sin_wave(880, RATE, 50)));
+    for frames in chunks {
+        out.extend(engine.render(*frames));
+    }
+    out
+}
+
+fn interleave(frames: &[(f32, f32)]) -> Vec<f32> {
+    frames.iter().flat_map(|(l, r)| [*l, *r]).collect()
+}
+
+fn rendering() {
+    // renders are the same every time, no matter how many frames are asked for at once
+    let rendered = render_scene(12000, &[12000]);
+    assert_eq!(rendered.len(), 24000);
+    assert_eq!(rendered, render_scene(12000, &[1, 999, 0, 11000]));
+    assert!(peak(&rendered) > 0.3);
+
+    // and match the golden file, up to rounding to 16 bits
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/render.wav");
+    let quantized = WavData::from_samples(2, RATE, &interleave(&rendered)).to_bytes();
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(path, &quantized).expect("Could not write the golden file");
+    }
+    let golden = std::fs::read(path).expect("Golden file is missing, run with UPDATE_GOLDEN=1 to create it");
+    let golden = samples(&WavDecoder.decode(&golden));
+    assert_eq!(golden.len(), rendered.len() * 2);
+    let off = golden.iter().zip(interleave(&rendered)).map(|(a, b)| (a - b).abs()).fold(0.0, f32::max);
+    assert!(off <= 1.0 / 32768.0, "Render is {off} off the golden file");
+
+    // render_to_wav writes the same file
+    let engine = AudioEngine::with_backend(AudioBackend::Offline { sample_rate: RATE }).expect("Offline engine");
+    engine.play_sound(SoundWithAttributes::new(gen_sin_wave(440, RATE, 100)));
+    let file = std::env::temp_dir().join(format!("mvengine-render-{}.wav", std::process::id()));
+    engine.render_to_wav(&file, 4800).expect("Could not write the render");
+    let written = WavDecoder.decode(&std::fs::read(&file).expect("Render was not written"));
+    let _ = std::fs::remove_file(&file);
+    assert_eq!((written.channels(), written.sample_rate(), written.effective_samples()), (2, RATE, 4800));
+    let tone = samples(&gen_sin_wave(440, RATE, 100));
+    assert!(samples(&written).chunks(2).zip(&tone).all(|(s, t)| (s[0] - t * 0.5).abs() <= 1.0 / 32768.0));
+
+    // the null sink plays in real time without a device, sounds still end
+    let engine = AudioEngine::with_backend(AudioBackend::Null { sample_rate: RATE }).expect("Null engine");
+    let ended = Arc::new(Mutex::new(None));
+    let start = Instant::now();
+    let handle = engine.play_sound(SoundWithAttributes::new(gen_sin_wave(440, RATE, 100)));
+    let sink = ended.clone();
+    handle.on_finish(move |end| *sink.lock().expect("End poisoned") = Some(end));
+    assert!(wait(|| ended.lock().expect("End poisoned").is_some()));
+    assert!(start.elapsed() >= Duration::from_millis(90));
+    assert_eq!(*ended.lock().expect("End poisoned"), Some(PlaybackEnd::Finished));
+
+    // a sample rate of zero is refused instead of panicking on the mixing thread
+    assert!(AudioEngine::with_backend(AudioBackend::Null { sample_rate: 0 }).is_none());
+    assert!(AudioEngine::with_backend(AudioBackend::Offline { sample_rate: 0 }).is_none());
+}
+
+/// A mono track at `level` that plays at half of it on each channel.
+fn flat(level: f32, frames: usize) -> Track {
+    Track::new(Sound::from_raw(1, RATE, vec![level; frames]))
+}
+
+/// Mixes `frames` frames in odd sized blocks and returns the left channel.
+fn left(mixer: &mut AudioMixer, frames: usize) -> Vec<f32> {
+    let mut out = vec![(0.0, 0.0); frames];
+    for block in out.chunks_mut(300) {
+        mixer.mix(block, RATE);
+    }
+    out.into_iter().map(|f| f.0).collect()
+}
+
+fn dj() {
+    // tracks follow each other without a gap, and the playlist stops after the last one
+    let mut mixer = AudioMixer::new();
+    let dj = DJ::new();
+    mixer.play_dj(dj.clone());
+    dj.play(Playlist::new(vec![flat(0.2, 1000), flat(0.4, 1500)]), Quantize::Now);
+    let played = left(&mut mixer, 3000);
+    assert!(played[..1000].iter().all(|s| *s == 0.1));
+    assert!(played[1000..2500].iter().all(|s| *s == 0.2));
+    assert!(played[2500..].iter().all(|s| *s == 0.0));
+    assert!(!dj.is_playing());
+
+    // repeating one track plays it until it is skipped
+    dj.play(
+        Playlist::new(vec![flat(0.2, 1000), flat(0.4, 1000)]).with_repeat(Repeat::One),
+        Quantize::Now,
+    );
+    let played = left(&mut mixer, 3000);
+    assert!(played.iter().all(|s| *s == 0.1));
+    dj.next(Quantize::Now);
+    assert_eq!(left(&mut mixer, 1)[0], 0.2);
+    assert_eq!(dj.current_track(), Some(1));
+
+    // shuffled playlists play every track once per round, never the same one twice in a row
+    let tracks = (0..4).map(|_| flat(0.2, 1000)).collect();
+    dj.play(
+        Playlist::new(tracks)
+            .with_repeat(Repeat::All)
+            .with_shuffle(true)
+            .with_seed(7),
+        Quantize::Now,
+    );
+    let mut order = vec![];
+    for _ in 0..12 {
+        left(&mut mixer, 1000);
+        order.push(dj.current_track().expect("Playlist repeats"));
+    }
+    for round in order.chunks(4) {
+        let mut sorted = round.to_vec();
+        sorted.sort();
+        assert_eq!(sorted, [0, 1, 2, 3]);
+    }
+    assert!(order.windows(2).all(|w| w[0] != w[1]));
+    assert_ne!(order[..4], order[4..8]);
+
+    // crossfades overlap the end of a track with the start of the next one
+    dj.set_transition(Transition::Crossfade {
+        duration: Duration::from_millis(100),
+        ease: EasingGen::linear(),
+    });
+    dj.play(Playlist::new(vec![flat(0.2, 48000), flat(0.4, 48000)]), Quantize::Now);
+    let played = left(&mut mixer, 96000);
+    // the first track fades in, as nothing played before it
+    assert!(played[0] < 0.001 && played[2400] > 0.04 && played[2400] < 0.06);
+    assert!(played[4800..43200].iter().all(|s| (*s - 0.1).abs() < 1e-6));
+    assert!((played[45600] - 0.15).abs() < 1e-3);
+    // the second track started 100 ms early, and as the last one it plays out without fading
+    assert!(played[48000..91200].iter().all(|s| (*s - 0.2).abs() < 1e-6));
+    assert!(played[91200..].iter().all(|s| *s == 0.0));
+
+    // tracks shorter than two crossfades overlap by half their length, and both fades last exactly that long
+    dj.set_transition(Transition::Crossfade {
+        duration: Duration::from_secs(1),
+        ease: EasingGen::linear(),
+    });
+    dj.play(Playlist::new(vec![flat(0.2, 4800), flat(0.4, 48000)]), Quantize::Now);
+    let played = left(&mut mixer, 10000);
+    assert!(played.windows(2).all(|w| (w[1] - w[0]).abs() < 1e-3), "Crossfade was cut off");
+    assert!(played[4800..].iter().all(|s| (*s - 0.2).abs() < 1e-6));
+    dj.stop(Quantize::Now);
+    left(&mut mixer, 48000);
+
+    // stems fade in and out with the intensity
+    dj.set_transition(Transition::Cut);
+    dj.set_stem_fade(Duration::from_millis(10), EasingGen::linear());
+    let track = flat(0.2, 48000).with_stem(Sound::from_raw(1, RATE, vec![0.4; 48000]), 0.5);
+    dj.play(Playlist::new(vec![track]), Quantize::Now);
+    assert!(left(&mut mixer, 1000).iter().all(|s| *s == 0.1));
+    dj.set_intensity(0.8);
+    let played = left(&mut mixer, 1000);
+    assert!(played[0] < 0.101 && (played[240] - 0.2).abs() < 1e-3 && played[480..].iter().all(|s| *s == 0.3));
+    dj.set_intensity(0.2);
+    let played = left(&mut mixer, 1000);
+    assert!(played[480..].iter().all(|s| *s == 0.1));
+
+    // synced transitions wait for the beat or bar, the next track starts at its first bar
+    let tempo = Tempo::new(120.0, 4);
+    let beat = 24000;
+    let ramp = |frames: usize| Sound::from_raw(1, RATE, (0..frames).map(|i| i as f32 / frames as f32).collect());
+    let tracks = vec![
+        Track::new(ramp(8 * beat)).with_tempo(tempo),
+        Track::new(ramp(8 * beat)).with_tempo(tempo.with_offset(Duration::from_millis(250))),
+    ];
+    dj.play(Playlist::new(tracks).with_repeat(Repeat::All), Quantize::Now);
+    left(&mut mixer, beat + 100);
+    assert_eq!(dj.beat(), Some((0, 1)));
+    dj.next(Quantize::Beat);
+    let played = left(&mut mixer, beat);
+    assert_eq!(dj.current_track(), Some(1));
+    // the old track plays up to the second beat, then the new one starts 250 ms, or 12000 frames, in
+    let before = beat - 101;
+    assert!((played[before] - (2 * beat - 1) as f32 / (16 * beat) as f32).abs() < 1e-6);
+    assert!((played[before + 1] - 12000.0 / (16 * beat) as f32).abs() < 1e-6);
+    assert_eq!(dj.beat(), Some((0, 0)));
+    dj.next(Quantize::Bar);
+    let played = left(&mut mixer, 4 * beat);
+    // the second track played 100 frames past its offset, its second bar starts 4 beats after the offset
+    let bar = 12000 + 4 * beat;
+    let switch = bar - 12100;
+    assert!((played[switch - 1] - (bar - 1) as f32 / (16 * beat) as f32).abs() < 1e-6);
+    assert!(played[switch].abs() < 1e-6);
+
+    dj.stop(Quantize::Now);
+    assert!(left(&mut mixer, 10).iter().all(|s| *s == 0.0));
+    assert!(!dj.is_playing());
+}
@@ -1,12 +1,52 @@
 use std::sync::Arc;
-use mvengine::game::ecs::entity::Entity;
+use bytebuffer::ByteBuffer;
+use mvutils::bytebuffer::ByteBufferExtras;
+use mvutils::save::Saver;
+use mvengine::game::ecs::entity::{next_entity_id, Entity};
+use mvengine::game::ecs::command::{CommandQueue, Commands};
+use mvengine::game::ecs::hierarchy::{despawn_recursive, is_ancestor, remove_parent, set_parent, Children, Parent};
+use mvengine::game::ecs::mem::column::Column;
+use mvengine::game::ecs::mem::conblob::ContinuousBlob;
+use mvengine::game::ecs::query::{Added, Changed, Query, With, Without};
+use mvengine::game::ecs::schedule::{Access, Schedule, ScheduleError};
+use mvengine::game::ecs::system::System;
 use mvengine::game::ecs::world::EcsWorld;
-use mvengine::game::ecs::{Ecs, EcsBackend};
-use mvengine::game::physics::components::{AABBCollider, RigidDynamic, Transform};
+use mvengine::game::ecs::save::ComponentRegistry;
+use mvengine::game::ecs::world::arch::ArchetypeWorld;
+use mvengine::game::ecs::{Ecs, EcsBackend, World};
+use mvengine::game::physics::components::{AABBCollider, GlobalTransform, RigidDynamic, Transform};
+use mvengine::game::physics::systems::aabb::AabbCollisionSystem;
+use mvengine::game::physics::systems::hierarchy::TransformPropagationSystem;
+use mvengine::game::physics::systems::rigid::RigidSystem;
 use mvengine::game::physics::systems::PhysicsSystem;
 use mvengine::math::vec::Vec2;
+use std::alloc::Layout;
+use std::collections::HashSet;
+use std::any::TypeId;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+
+#[derive(Debug, PartialEq)]
+struct A(u64);
+#[derive(Debug, PartialEq)]
+struct B(u64);
+struct Tag;
 
 fn main() {
+    snapshot();
+    blob();
+    column();
+    archetypes();
+    schedule();
+    query();
+    commands();
+    hierarchy();
+    println!("end");
+}
+
+fn snapshot() {
     let mut ecs = Ecs::new(EcsBackend::SparseSet);
     let world = ecs.world_mut();
     let en1 = Entity::<(Transform, AABBCollider)>::create(world);
@@ -19,5 +59,629 @@ fn main() {
 
     let mut physics = PhysicsSystem::new();
     physics.iterate(world, 1.0);
-    println!("end");
-}
\ No newline at end of file
+
+    let mut registry = ComponentRegistry::new();
+    registry
+        .register::<Transform>("transform")
+        .register::<AABBCollider>("aabb")
+        .register::<RigidDynamic>("rigid");
+
+    let mut buffer = ByteBuffer::new_le();
+    world.save_snapshot(&registry, &mut buffer);
+    let bytes = buffer.into_vec();
+
+    let mut buffer = ByteBuffer::from_vec_le(bytes.clone());
+    let loaded = World::from_snapshot(EcsBackend::Archetype, &registry, &mut buffer).expect("Could not load snapshot");
+    let mut buffer = ByteBuffer::new_le();
+    loaded.save_snapshot(&registry, &mut buffer);
+    assert_eq!(bytes, buffer.into_vec(), "Snapshot round trip is not byte exact");
+
+    // corrupt headers are errors instead of huge allocations
+    let mut buffer = ByteBuffer::new_le();
+    buffer.push_u32(u32::MAX);
+    assert!(World::from_snapshot(EcsBackend::SparseSet, &registry, &mut buffer).is_err());
+
+    // large ids are reserved in one step
+    let far = 1u64 << 48;
+    let mut buffer = ByteBuffer::new_le();
+    buffer.push_u32(0);
+    buffer.push_u64(1);
+    buffer.push_u64(far);
+    buffer.push_u32(0);
+    World::from_snapshot(EcsBackend::SparseSet, &registry, &mut buffer).expect("Could not load snapshot");
+    assert!(next_entity_id() > far);
+}
+
+fn blob() {
+    let mut blob = ContinuousBlob::new(Layout::new::<(u64, u32)>());
+    let indices: Vec<_> = (0..100u64)
+        .map(|i| blob.push_next((i, i as u32 * 3)).expect("Layout matches"))
+        .collect();
+    assert!(blob.push_next(1u8).is_none());
+    // growing keeps every element
+    for (i, idx) in indices.iter().enumerate() {
+        assert_eq!(blob.get::<(u64, u32)>(*idx), Some(&(i as u64, i as u32 * 3)));
+    }
+
+    // removing moves the last element into the gap, its index has to follow it
+    blob.remove(indices[0]);
+    assert!(blob.get::<(u64, u32)>(indices[0]).is_none());
+    assert_eq!(blob.get::<(u64, u32)>(indices[99]), Some(&(99, 297)));
+    assert_eq!(blob.get::<(u64, u32)>(indices[1]), Some(&(1, 3)));
+
+    // removing most elements shrinks the allocation, the rest stays intact
+    for idx in &indices[1..95] {
+        blob.remove(*idx);
+    }
+    for (i, idx) in indices.iter().enumerate().skip(95) {
+        assert_eq!(blob.get::<(u64, u32)>(*idx), Some(&(i as u64, i as u32 * 3)));
+    }
+    let mut left: Vec<_> = blob.get_all::<(u64, u32)>().map(|(idx, c)| (idx, c.0)).collect();
+    left.sort();
+    assert_eq!(left, indices[95..].iter().zip(95..100).map(|(idx, i)| (*idx, i)).collect::<Vec<_>>());
+
+    // and grows again after shrinking
+    for (_, c) in blob.get_all_mut::<(u64, u32)>() {
+        c.1 += 1;
+    }
+    let more: Vec<_> = (0..50u64).map(|i| blob.push_next((i + 1000, 0u32)).expect("Layout matches")).collect();
+    for (i, idx) in more.iter().enumerate() {
+        assert_eq!(blob.get::<(u64, u32)>(*idx), Some(&(i as u64 + 1000, 0)));
+    }
+    assert_eq!(blob.get::<(u64, u32)>(indices[97]), Some(&(97, 292)));
+    assert_eq!(blob.get_all::<(u64, u32)>().count(), 55);
+
+    let mut empty = ContinuousBlob::new(Layout::new::<()>());
+    let a = empty.push_next(()).expect("Layout matches");
+    let b = empty.push_next(()).expect("Layout matches");
+    empty.remove(a);
+    assert!(empty.get::<()>(a).is_none());
+    assert!(empty.get::<()>(b).is_some());
+}
+fn column() {
+    let mut column = Column::new::<(u64, u32)>();
+    for i in 0..10u64 {
+        column.push((i, i as u32 * 2), i);
+    }
+    assert_eq!(column.len(), 10);
+    assert_eq!(column.get::<(u64, u32)>(3), Some(&(3, 6)));
+    assert_eq!(column.get_ticks(3).map(|t| t.added()), Some(3));
+
+    // the last row moves into the removed one, ticks included
+    column.swap_remove(3);
+    assert_eq!(column.len(), 9);
+    assert_eq!(column.get::<(u64, u32)>(3), Some(&(9, 18)));
+    assert_eq!(column.get_ticks(3).map(|t| t.added()), Some(9));
+    column.swap_remove(8);
+    assert_eq!(column.len(), 8);
+    assert_eq!(column.get::<(u64, u32)>(8), None);
+    column.swap_remove(100);
+    assert_eq!(column.len(), 8);
+    assert_eq!(column.get::<(u64, u32)>(7), Some(&(7, 14)));
+
+    column.replace(0, (100u64, 0u32), 50);
+    assert_eq!(column.get::<(u64, u32)>(0), Some(&(100, 0)));
+    assert_eq!(column.get_ticks(0).map(|t| (t.added(), t.changed())), Some((0, 50)));
+
+    // removed, replaced and remaining elements are all dropped exactly once
+    let rc = Rc::new(());
+    let mut column = Column::new::<Rc<()>>();
+    for _ in 0..20 {
+        column.push(rc.clone(), 0);
+    }
+    assert_eq!(Rc::strong_count(&rc), 21);
+    column.swap_remove(0);
+    column.swap_remove(10);
+    column.replace(0, rc.clone(), 1);
+    assert_eq!(Rc::strong_count(&rc), 19);
+
+    let mut other = Column::new_like(&column);
+    unsafe {
+        column.move_to(0, &mut other);
+    }
+    assert_eq!((column.len(), other.len()), (17, 1));
+    assert_eq!(Rc::strong_count(&rc), 19);
+    column.clear();
+    assert!(column.is_empty());
+    assert_eq!(Rc::strong_count(&rc), 2);
+    drop(other);
+    assert_eq!(Rc::strong_count(&rc), 1);
+
+    let mut zst = Column::new::<()>();
+    for i in 0..100 {
+        zst.push((), i);
+    }
+    zst.swap_remove(50);
+    assert_eq!(zst.len(), 99);
+    assert_eq!(zst.get_ticks(50).map(|t| t.added()), Some(99));
+}
+
+fn archetype_of(world: &ArchetypeWorld, id: u64) -> Vec<TypeId> {
+    let archetype = world
+        .archetypes()
+        .iter()
+        .find(|a| a.entities().contains(&id))
+        .expect("Entity is in an archetype");
+    let mut signature = archetype.signature().to_vec();
+    signature.sort();
+    signature
+}
+
+fn signature(mut types: Vec<TypeId>) -> Vec<TypeId> {
+    types.sort();
+    types
+}
+
+fn archetypes() {
+    let mut ecs = Ecs::new(EcsBackend::Archetype);
+    let world = ecs.world_mut();
+    let rc = Rc::new(());
+    for i in 0..3u64 {
+        world.create_entity(i);
+        world.set_component(i, A(i));
+        world.set_component(i, rc.clone());
+    }
+    let World::ArchetypeWorld(arch) = &*world else {
+        panic!("Not an archetype world");
+    };
+    let a_rc = signature(vec![TypeId::of::<A>(), TypeId::of::<Rc<()>>()]);
+    assert_eq!(archetype_of(arch, 1), a_rc);
+
+    // adding a component moves the entity and keeps the others intact
+    world.set_component(1, B(10));
+    let World::ArchetypeWorld(arch) = &*world else {
+        panic!("Not an archetype world");
+    };
+    let a_b_rc = signature(vec![TypeId::of::<A>(), TypeId::of::<B>(), TypeId::of::<Rc<()>>()]);
+    assert_eq!(archetype_of(arch, 1), a_b_rc);
+    assert_eq!(archetype_of(arch, 0), a_rc);
+    assert_eq!(archetype_of(arch, 2), a_rc);
+    for i in 0..3 {
+        assert_eq!(world.get_component::<A>(i), Some(&A(i)));
+    }
+    assert_eq!(world.get_component::<B>(1), Some(&B(10)));
+    assert_eq!(world.get_component::<B>(0), None);
+    assert_eq!(Rc::strong_count(&rc), 4);
+
+    // replacing a component does not move it, removing it moves the entity back
+    world.set_component(1, B(20));
+    assert_eq!(world.get_component::<B>(1), Some(&B(20)));
+    world.set_component(0, B(0));
+    world.remove_component::<B>(1);
+    assert_eq!(world.get_component::<B>(1), None);
+    let World::ArchetypeWorld(arch) = &*world else {
+        panic!("Not an archetype world");
+    };
+    assert_eq!(archetype_of(arch, 1), a_rc);
+    assert_eq!(archetype_of(arch, 0), a_b_rc);
+    // the same signature always resolves to the same table
+    let tables = arch.archetypes().iter().filter(|a| signature(a.signature().to_vec()) == a_rc).count();
+    assert_eq!(tables, 1);
+    assert_eq!(world.get_component::<A>(1), Some(&A(1)));
+
+    world.remove_component::<Rc<()>>(2);
+    assert_eq!(Rc::strong_count(&rc), 3);
+    world.destroy_entity(0);
+    assert_eq!(Rc::strong_count(&rc), 2);
+    assert_eq!(world.get_component::<A>(0), None);
+    assert_eq!(world.get_component::<A>(2), Some(&A(2)));
+    assert!(!world.has_component::<A>(0));
+    let World::ArchetypeWorld(arch) = &*world else {
+        panic!("Not an archetype world");
+    };
+    assert!(!arch.contains_entity(0));
+    assert_eq!(archetype_of(arch, 2), vec![TypeId::of::<A>()]);
+    drop(ecs);
+    assert_eq!(Rc::strong_count(&rc), 1);
+}
+
+fn schedule() {
+    // writes conflict with reads and writes of the same component, reads never conflict
+    let read_a = Access::new().read::<A>();
+    let write_a = Access::new().write::<A>();
+    let write_b = Access::new().write::<B>().read::<Transform>();
+    assert!(!read_a.conflicts_with(&read_a));
+    assert!(read_a.conflicts_with(&write_a) && write_a.conflicts_with(&read_a));
+    assert!(write_a.conflicts_with(&write_a));
+    assert!(!write_a.conflicts_with(&write_b));
+    assert!(write_a.clone().merge(&read_a).covers(&read_a));
+    assert!(!read_a.covers(&write_a));
+
+    for backend in [EcsBackend::SparseSet, EcsBackend::Archetype] {
+        let mut ecs = Ecs::new(backend);
+        let world = ecs.world_mut();
+        for i in 0..1000u64 {
+            world.create_entity(i);
+            world.set_component(i, A(i));
+            world.set_component(i, B(i));
+        }
+
+        let mut schedule = Schedule::with_threads(4);
+        let mut increment = Query::<&mut A>::new();
+        let mut double = Query::<&mut B>::new();
+        let mut sum = Query::<(&A, &mut B)>::new();
+        schedule
+            .add_fn("a", increment.access(), move |world, _, _| {
+                for (_, a) in world.query_mut(&mut increment) {
+                    a.0 += 1;
+                }
+            })
+            .add_fn("b", double.access(), move |world, _, _| {
+                for (_, b) in world.query_mut(&mut double) {
+                    b.0 *= 2;
+                }
+            })
+            .add_fn("ab", sum.access(), move |world, _, _| {
+                for (_, (a, b)) in world.query_mut(&mut sum) {
+                    b.0 += a.0;
+                }
+            })
+            .add_system("rigid", RigidSystem::new())
+            .add_system("aabb", AabbCollisionSystem::new());
+        // "ab" conflicts with "a" and "b", "aabb" reads the transforms "rigid" writes
+        assert_eq!(schedule.stages(), Ok(vec![vec!["a", "b", "rigid"], vec!["ab", "aabb"]]));
+        schedule.run(world, 1.0).expect("Schedule is valid");
+        assert_eq!(world.get_component::<B>(5), Some(&B(16)));
+
+        schedule.before("ab", "b");
+        assert_eq!(schedule.stages(), Ok(vec![vec!["a", "rigid"], vec!["ab", "aabb"], vec!["b"]]));
+        schedule.before("b", "a").before("a", "ab");
+        let Err(ScheduleError::Cycle(mut cycle)) = schedule.stages() else {
+            panic!("Contradicting constraints are not a cycle");
+        };
+        cycle.sort();
+        assert_eq!(cycle, ["a", "ab", "b"]);
+
+        let mut schedule = Schedule::with_threads(1);
+        schedule.add_fn("a", Access::new(), |_, _, _| {}).add_fn("a", Access::new(), |_, _, _| {});
+        assert_eq!(schedule.stages(), Err(ScheduleError::DuplicateLabel("a".to_string())));
+        let mut schedule = Schedule::with_threads(1);
+        schedule.add_fn("a", Access::new(), |_, _, _| {}).after("a", "missing");
+        assert_eq!(schedule.stages(), Err(ScheduleError::UnknownSystem("missing".to_string())));
+    }
+
+    // stages run on the same worker threads every time
+    let mut ecs = Ecs::new(EcsBackend::Archetype);
+    let world = ecs.world_mut();
+    let workers = Arc::new(Mutex::new(HashSet::<ThreadId>::new()));
+    let mut schedule = Schedule::with_threads(2);
+    for label in ["a", "b", "c", "d"] {
+        let workers = workers.clone();
+        schedule.add_fn(label, Access::new(), move |_, _, _| {
+            workers.lock().expect("Workers poisoned").insert(thread::current().id());
+        });
+    }
+    for _ in 0..20 {
+        schedule.run(world, 1.0).expect("Schedule is valid");
+    }
+    let workers = workers.lock().expect("Workers poisoned");
+    assert!(!workers.is_empty() && workers.len() <= 2);
+    assert!(!workers.contains(&thread::current().id()));
+
+    // systems can only touch what they declared, also on the worker threads
+    world.create_entity(0);
+    world.set_component(0, A(0));
+    world.set_component(0, B(0));
+    for threads in [1, 2] {
+        let mut schedule = Schedule::with_threads(threads);
+        schedule
+            .add_fn("honest", Access::new().write::<B>(), |world, _, _| {
+                if let Some(b) = world.get_component_mut::<B>(0) {
+                    b.0 += 1;
+                }
+            })
+            .add_fn("sneaky", Access::new().read::<A>(), |world, _, _| {
+                let _ = world.get_component_mut::<A>(0);
+            });
+        let result = panic::catch_unwind(AssertUnwindSafe(|| schedule.run(world, 1.0)));
+        assert!(result.is_err(), "Undeclared write was not caught");
+
+        let mut schedule = Schedule::with_threads(threads);
+        let mut query = Query::<&mut A>::new();
+        schedule.add_fn("sneaky", Access::new().read::<A>(), move |world, _, _| {
+            let _ = world.query_mut(&mut query).count();
+        });
+        let result = panic::catch_unwind(AssertUnwindSafe(|| schedule.run(world, 1.0)));
+        assert!(result.is_err(), "Undeclared query was not caught");
+    }
+    assert_eq!(world.get_component::<A>(0), Some(&A(0)));
+}
+
+fn sorted<I: Iterator<Item = u64>>(ids: I) -> Vec<u64> {
+    let mut ids: Vec<_> = ids.collect();
+    ids.sort();
+    ids
+}
+
+fn query() {
+    for backend in [EcsBackend::SparseSet, EcsBackend::Archetype] {
+        let mut ecs = Ecs::new(backend);
+        let world = ecs.world_mut();
+        for i in 0..10u64 {
+            world.create_entity(i);
+            world.set_component(i, A(i));
+            if i % 2 == 0 {
+                world.set_component(i, B(i));
+            }
+            if i % 3 == 0 {
+                world.set_component(i, Tag);
+            }
+        }
+
+        let mut optional = Query::<(&A, Option<&B>), Without<Tag>>::new();
+        let mut items: Vec<_> = optional.iter(world).map(|(_, (a, b))| (a.0, b.map(|b| b.0))).collect();
+        items.sort();
+        assert_eq!(items, [(1, None), (2, Some(2)), (4, Some(4)), (5, None), (7, None), (8, Some(8))]);
+        let mut tagged = Query::<&B, With<Tag>>::new();
+        assert_eq!(sorted(tagged.iter(world).map(|(id, _)| id)), [0, 6]);
+        let mut both = Query::<&A, (With<A>, Without<B>)>::new();
+        assert_eq!(sorted(both.iter(world).map(|(id, _)| id)), [1, 3, 5, 7, 9]);
+
+        // the first run sees everything, later runs only what happened since
+        let mut changed = Query::<&A, Changed<A>>::new();
+        let mut added = Query::<&B, Added<B>>::new();
+        assert_eq!(changed.iter(world).count(), 10);
+        assert_eq!(added.iter(world).count(), 5);
+        assert!(changed.last_run() > 0);
+        assert_eq!(changed.iter(world).count(), 0);
+        assert_eq!(added.iter(world).count(), 0);
+
+        let before = world.change_tick();
+        if let Some(a) = world.get_component_mut::<A>(3) {
+            a.0 = 100;
+        }
+        world.set_component(1, B(1));
+        let ticks = world.component_ticks::<A>(3).map(|t| (t.added(), t.changed()));
+        assert!(ticks.is_some_and(|(added, changed)| added < changed && changed >= before));
+        assert_eq!(sorted(changed.iter(world).map(|(id, _)| id)), [3]);
+        assert_eq!(sorted(added.iter(world).map(|(id, _)| id)), [1]);
+
+        // replacing a component changes it without adding it again
+        world.set_component(2, B(20));
+        assert_eq!(added.iter(world).count(), 0);
+        let mut changed_b = Query::<&B, Changed<B>>::new();
+        changed_b.iter(world).for_each(drop);
+        world.set_component(4, B(40));
+        assert_eq!(sorted(changed_b.iter(world).map(|(id, _)| id)), [4]);
+
+        // mutable queries mark what they hand out as changed, and only that
+        let mut increment = Query::<&mut A, With<Tag>>::new();
+        for (_, a) in increment.iter_mut(world) {
+            a.0 += 1;
+        }
+        assert_eq!(sorted(changed.iter(world).map(|(id, _)| id)), [0, 3, 6, 9]);
+        assert_eq!(world.get_component::<A>(3), Some(&A(101)));
+        let mut optional_mut = Query::<(&A, Option<&mut B>)>::new();
+        let mut seen = 0;
+        for (_, (a, b)) in optional_mut.iter_mut(world) {
+            seen += 1;
+            if let Some(b) = b {
+                b.0 = a.0;
+            }
+        }
+        assert_eq!(seen, 10);
+        assert_eq!(sorted(changed_b.iter(world).map(|(id, _)| id)), [0, 1, 2, 4, 6, 8]);
+        assert_eq!(changed.iter(world).count(), 0);
+        assert_eq!(world.get_component::<B>(6), Some(&B(7)));
+        assert_eq!(tagged.get(world, 6), Some(&B(7)));
+        assert_eq!(tagged.get(world, 2), None);
+
+        // entities leaving and entering the matched set are picked up
+        world.remove_component::<Tag>(0);
+        world.set_component(2, Tag);
+        world.destroy_entity(6);
+        assert_eq!(sorted(tagged.iter(world).map(|(id, _)| id)), [2]);
+    }
+
+    // a query must not alias its own components
+    let aliasing = [
+        panic::catch_unwind(|| Query::<(&mut A, &mut A)>::new()).is_err(),
+        panic::catch_unwind(|| Query::<(&A, &mut A)>::new()).is_err(),
+        panic::catch_unwind(|| Query::<(&mut A, (&B, Option<&A>))>::new()).is_err(),
+        panic::catch_unwind(|| Query::<(Option<&mut A>, &A)>::new()).is_err(),
+    ];
+    assert_eq!(aliasing, [true; 4]);
+    // neither may the mutable queries of the worlds, which would hand out the same component twice
+    for backend in [EcsBackend::SparseSet, EcsBackend::Archetype] {
+        let mut ecs = Ecs::new(backend);
+        let world = ecs.world_mut();
+        world.create_entity(0);
+        world.set_component(0, A(0));
+        world.set_component(0, B(0));
+        let aliased = panic::catch_unwind(AssertUnwindSafe(|| System::<(A, B, A)>::new().iter_mut(world).count()));
+        assert!(aliased.is_err());
+        assert_eq!(System::<(A, B)>::new().iter_mut(world).count(), 1);
+    }
+    let access = Query::<(&A, &A, &mut B), Changed<B>>::new().access();
+    assert_eq!((access.reads(), access.writes()), (&[TypeId::of::<A>()][..], &[TypeId::of::<B>()][..]));
+}
+
+fn commands() {
+    for backend in [EcsBackend::SparseSet, EcsBackend::Archetype] {
+        let mut ecs = Ecs::new(backend);
+        let world = ecs.world_mut();
+        for i in 0..4u64 {
+            world.create_entity(i);
+            world.set_component(i, A(i));
+        }
+
+        // nothing happens until the buffer is applied, then everything runs in recording order
+        let mut commands = Commands::new();
+        let spawned = commands.spawn();
+        commands.insert(spawned, A(1));
+        commands.insert(spawned, A(2));
+        commands.add(move |world| {
+            let a = world.get_component::<A>(spawned).map_or(0, |a| a.0);
+            world.set_component(spawned, B(a * 10));
+        });
+        commands.remove::<A>(1);
+        commands.despawn(2);
+        commands.insert(3, B(3));
+        commands.despawn(3);
+        assert_eq!(commands.len(), 8);
+        assert!(!world.has_component::<A>(spawned));
+        assert!(world.has_component::<A>(1));
+
+        let mut other = Commands::new();
+        other.insert(spawned, B(0));
+        commands.append(&mut other);
+        assert!(other.is_empty());
+        commands.apply(world);
+        assert!(commands.is_empty());
+        assert_eq!(world.get_component::<A>(spawned), Some(&A(2)));
+        assert_eq!(world.get_component::<B>(spawned), Some(&B(0)));
+        assert_eq!(world.get_component::<A>(1), None);
+        assert!(world.entities().all(|id| id != 2 && id != 3));
+        assert_eq!(world.get_component::<A>(0), Some(&A(0)));
+
+        // buffers from several threads are applied by key, equal keys in submission order
+        let append_digit = |digit: u64| {
+            let mut commands = Commands::new();
+            commands.add(move |world| {
+                let a = world.get_component::<A>(0).map_or(0, |a| a.0);
+                world.set_component(0, A(a * 10 + digit));
+            });
+            commands
+        };
+        let queue = CommandQueue::new();
+        thread::scope(|scope| {
+            for key in [3u64, 1, 2, 0] {
+                let queue = &queue;
+                scope.spawn(move || queue.submit(key, append_digit(key)));
+            }
+        });
+        queue.submit(4, append_digit(5));
+        queue.submit(4, Commands::new());
+        queue.submit(4, append_digit(6));
+        assert_eq!(world.get_component::<A>(0), Some(&A(0)));
+        queue.apply(world);
+        assert_eq!(world.get_component::<A>(0), Some(&A(12356)));
+        assert!(queue.merge().is_empty());
+
+        // commands of a stage are applied before the next stage starts, in the order the systems were added
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut schedule = Schedule::with_threads(4);
+        let mut count = Query::<&B>::new();
+        let mut count_later = Query::<&B>::new();
+        let (first, second, same, later) = (log.clone(), log.clone(), log.clone(), log.clone());
+        schedule
+            .add_fn("first", Access::new(), move |_, commands, _| {
+                let id = commands.spawn();
+                commands.insert(id, B(100));
+                let log = first.clone();
+                commands.add(move |_| log.lock().expect("Log poisoned").push("first"));
+            })
+            .add_fn("second", Access::new(), move |_, commands, _| {
+                let log = second.clone();
+                commands.add(move |_| log.lock().expect("Log poisoned").push("second"));
+            })
+            .add_fn("same", count.access(), move |world, _, _| {
+                let found = world.query(&mut count).filter(|(_, b)| b.0 == 100).count();
+                same.lock().expect("Log poisoned").push(if found == 0 { "same: 0" } else { "same: 1" });
+            })
+            .add_fn("later", count_later.access(), move |world, _, _| {
+                let found = world.query(&mut count_later).filter(|(_, b)| b.0 == 100).count();
+                later.lock().expect("Log poisoned").push(if found == 0 { "later: 0" } else { "later: 1" });
+            })
+            .after("later", "first");
+        assert_eq!(schedule.stages(), Ok(vec![vec!["first", "second", "same"], vec!["later"]]));
+        for _ in 0..10 {
+            let old: Vec<_> = world.entities().filter(|id| world.get_component::<B>(*id) == Some(&B(100))).collect();
+            old.into_iter().for_each(|id| world.destroy_entity(id));
+            log.lock().expect("Log poisoned").clear();
+            schedule.run(world, 1.0).expect("Schedule is valid");
+            assert_eq!(*log.lock().expect("Log poisoned"), ["same: 0", "first", "second", "later: 1"]);
+        }
+    }
+}
+
+fn assert_global(world: &World, id: u64, x: f32, y: f32, scale: f32) {
+    let global = world.get_component::<GlobalTransform>(id).expect("Global transform was propagated");
+    assert!(
+        (global.position.x - x).abs() < 1e-4 && (global.position.y - y).abs() < 1e-4,
+        "{id} is at {:?}, expected ({x}, {y})",
+        global.position
+    );
+    assert!((global.scale.x - scale).abs() < 1e-4, "{id} has scale {:?}", global.scale);
+}
+
+fn hierarchy() {
+    for backend in [EcsBackend::SparseSet, EcsBackend::Archetype] {
+        let mut ecs = Ecs::new(backend);
+        let world = ecs.world_mut();
+        let transforms = [
+            (1, Vec2::new(10.0, 0.0), std::f64::consts::FRAC_PI_2, 2.0),
+            (2, Vec2::new(1.0, 0.0), 0.0, 1.0),
+            (3, Vec2::new(1.0, 0.0), 0.0, 0.5),
+            (4, Vec2::new(0.0, 1.0), 0.0, 1.0),
+            (5, Vec2::new(-5.0, -5.0), 0.0, 1.0),
+        ];
+        for (id, position, rotation, scale) in transforms {
+            world.create_entity(id);
+            world.set_component(
+                id,
+                Transform {
+                    position,
+                    rotation,
+                    scale: Vec2::splat(scale),
+                    ..Transform::default()
+                },
+            );
+        }
+        assert!(set_parent(world, 2, 1));
+        assert!(set_parent(world, 3, 2));
+        assert!(set_parent(world, 4, 3));
+        assert!(is_ancestor(world, 1, 4));
+        assert!(!is_ancestor(world, 4, 1));
+        // an entity can never end up below itself
+        assert!(!set_parent(world, 1, 4));
+        assert!(!set_parent(world, 2, 2));
+        assert_eq!(world.get_component::<Parent>(1), None);
+
+        // every level is placed in the rotated and scaled space of its parent
+        let mut propagation = TransformPropagationSystem::new();
+        propagation.iterate(world);
+        assert_global(world, 1, 10.0, 0.0, 2.0);
+        assert_global(world, 2, 10.0, 2.0, 2.0);
+        assert_global(world, 3, 10.0, 4.0, 1.0);
+        assert_global(world, 4, 9.0, 4.0, 1.0);
+        assert_global(world, 5, -5.0, -5.0, 1.0);
+
+        // re-parenting moves the entity between the children lists and takes its subtree along
+        assert!(set_parent(world, 3, 5));
+        assert_eq!(world.get_component::<Parent>(3), Some(&Parent(5)));
+        assert_eq!(world.get_component::<Children>(5), Some(&Children(vec![3])));
+        assert_eq!(world.get_component::<Children>(2), None);
+        assert_eq!(world.get_component::<Children>(1), Some(&Children(vec![2])));
+        assert!(set_parent(world, 2, 5));
+        assert_eq!(world.get_component::<Children>(5), Some(&Children(vec![3, 2])));
+        assert_eq!(world.get_component::<Children>(1), None);
+        if let Some(transform) = world.get_component_mut::<Transform>(5) {
+            transform.position = Vec2::new(-4.0, -5.0);
+        }
+        propagation.iterate(world);
+        assert_global(world, 3, -3.0, -5.0, 0.5);
+        assert_global(world, 4, -3.0, -4.5, 0.5);
+        assert_global(world, 2, -3.0, -5.0, 1.0);
+
+        remove_parent(world, 2);
+        assert_eq!(world.get_component::<Parent>(2), None);
+        assert_eq!(world.get_component::<Children>(5), Some(&Children(vec![3])));
+        propagation.iterate(world);
+        assert_global(world, 2, 1.0, 0.0, 1.0);
+
+        // despawning takes every descendant along and detaches the entity from its parent
+        assert!(set_parent(world, 5, 1));
+        let mut commands = Commands::new();
+        commands.despawn_recursive(3);
+        assert!(world.has_component::<Transform>(4));
+        commands.apply(world);
+        for id in [3, 4] {
+            assert!(world.entities().all(|e| e != id));
+            assert!(!world.has_component::<Transform>(id));
+        }
+        assert_eq!(world.get_component::<Children>(5), None);
+        despawn_recursive(world, 1);
+        assert_eq!(world.entities().collect::<Vec<_>>(), [2]);
+    }
+}
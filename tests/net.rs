@@ -0,0 +1,899 @@
+use bytebuffer::ByteBuffer;
+use mvengine::game::ecs::entity::next_entity_id;
+use mvengine::game::ecs::hierarchy::{remove_parent, set_parent, Children, Parent};
+use mvengine::game::ecs::save::ComponentRegistry;
+use mvengine::game::ecs::world::EcsWorld;
+use mvengine::game::ecs::{Ecs, EcsBackend, World};
+use mvengine::game::physics::components::Transform;
+use mvengine::math::vec::Vec2;
+use mvengine::net::client::{Client, ClientHandler};
+use mvengine::net::replication::{Replica, Replicated, Replicator, Snapshot};
+use mvengine::net::rpc::{Request, RpcError};
+use mvengine::net::secure;
+use mvengine::net::server::{ClientEndpoint, ListenError, Server, ServerHandler};
+use mvengine::net::session::ConnectionConfig;
+use mvengine::net::udp::{Delivery, LinkConditioner, UdpConfig};
+use mvengine::net::{DisconnectReason, RejectReason};
+use mvutils::bytebuffer::ByteBufferExtras;
+use mvutils::save::Savable;
+use mvutils::Savable;
+use parking_lot::{Mutex, RwLock};
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct RecordingServer {
+    addrs: Vec<SocketAddr>,
+    connected: usize,
+    disconnected: usize,
+    packets: Vec<String>,
+    hellos: Vec<Vec<u8>>,
+    clients: Vec<Arc<ClientEndpoint>>,
+    reasons: Vec<DisconnectReason>,
+}
+
+impl ServerHandler<String> for RecordingServer {
+    fn on_server_start(addrs: &[SocketAddr]) -> Self {
+        Self {
+            addrs: addrs.to_vec(),
+            ..Self::default()
+        }
+    }
+
+    fn on_hello(&mut self, _: SocketAddr, hello: &[u8]) -> Result<(), String> {
+        self.hellos.push(hello.to_vec());
+        if hello == b"banned" {
+            return Err("You are banned".to_string());
+        }
+        Ok(())
+    }
+
+    fn on_client_connect(&mut self, client: Arc<ClientEndpoint>) {
+        self.connected += 1;
+        self.clients.push(client);
+    }
+
+    fn on_client_disconnect(&mut self, _: Arc<ClientEndpoint>, reason: DisconnectReason) {
+        self.disconnected += 1;
+        self.reasons.push(reason);
+    }
+
+    fn on_packet(&mut self, _: Arc<ClientEndpoint>, packet: String) {
+        self.packets.push(packet);
+    }
+
+    fn on_server_stop(&mut self, _: &str) {}
+}
+
+#[derive(Default)]
+struct RecordingClient {
+    packets: Vec<String>,
+    reasons: Vec<DisconnectReason>,
+}
+
+impl ClientHandler<String> for RecordingClient {
+    fn on_connected(&mut self) {}
+
+    fn on_disconnected(&mut self, reason: DisconnectReason) {
+        self.reasons.push(reason);
+    }
+
+    fn on_packet(&mut self, packet: String) {
+        self.packets.push(packet);
+    }
+}
+
+fn lossy(seed: u64) -> UdpConfig {
+    UdpConfig {
+        resend_timeout: Duration::from_millis(50),
+        conditioner: Some(LinkConditioner {
+            loss: 0.25,
+            latency: Duration::from_millis(10),
+            jitter: Duration::from_millis(20),
+            seed,
+        }),
+        ..UdpConfig::default()
+    }
+}
+
+fn quick(version: u32) -> ConnectionConfig {
+    ConnectionConfig {
+        protocol_version: version,
+        keep_alive_interval: Duration::from_millis(20),
+        idle_timeout: Duration::from_millis(300),
+        ..ConnectionConfig::default()
+    }
+}
+
+fn recording_client() -> Arc<RwLock<RecordingClient>> {
+    Arc::new(RwLock::new(RecordingClient::default()))
+}
+
+#[derive(Default)]
+struct SnapshotClient {
+    snapshots: Vec<Snapshot>,
+}
+
+impl ClientHandler<Snapshot> for SnapshotClient {
+    fn on_connected(&mut self) {}
+
+    fn on_disconnected(&mut self, _: DisconnectReason) {}
+
+    fn on_packet(&mut self, packet: Snapshot) {
+        self.snapshots.push(packet);
+    }
+}
+
+struct SilentClient;
+
+impl ClientHandler<String> for SilentClient {
+    fn on_connected(&mut self) {}
+
+    fn on_disconnected(&mut self, _: DisconnectReason) {}
+
+    fn on_packet(&mut self, _: String) {}
+}
+
+fn wait_until(mut condition: impl FnMut() -> bool) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(5) {
+        if condition() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+fn main() {
+    // one server listening on IPv4 and IPv6 at the same time, on ports chosen by the os
+    let addrs: [SocketAddr; 2] = ["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()];
+    let mut server = Server::<String, String>::new();
+    let handler = server
+        .listen::<RecordingServer>(&addrs[..])
+        .expect("Could not listen on loopback addresses");
+    let bound = server.local_addrs().to_vec();
+    assert_eq!(bound.len(), 2);
+    assert!(bound[0].is_ipv4() && bound[1].is_ipv6());
+    assert!(bound.iter().all(|a| a.port() != 0));
+    assert_eq!(handler.lock().addrs, bound);
+
+    let mut clients = Vec::new();
+    for addr in &bound {
+        let mut client = Client::<String, String>::connect(*addr, Arc::new(RwLock::new(SilentClient)))
+            .expect("Could not connect to server");
+        client.send(format!("hello from {}", if addr.is_ipv4() { "v4" } else { "v6" }));
+        clients.push(client);
+    }
+    assert!(wait_until(|| handler.lock().packets.len() == 2), "Server did not receive both packets");
+    let mut packets = handler.lock().packets.clone();
+    packets.sort();
+    assert_eq!(packets, ["hello from v4", "hello from v6"]);
+    assert_eq!(handler.lock().connected, 2);
+
+    // errors are reported instead of silently ending the server thread
+    assert!(matches!(server.listen::<RecordingServer>("127.0.0.1:0"), Err(ListenError::AlreadyListening)));
+    let mut second = Server::<String, String>::new();
+    match second.listen::<RecordingServer>(bound[0]) {
+        Err(ListenError::Bind(addr, _)) => assert_eq!(addr, bound[0]),
+        _ => panic!("Binding an address in use should fail"),
+    }
+    assert!(matches!(second.listen::<RecordingServer>(&[][..] as &[SocketAddr]), Err(ListenError::NoAddress)));
+    assert!(second.listen::<RecordingServer>("0.0.0.0:0").is_ok());
+
+    for client in &mut clients {
+        client.disconnect(DisconnectReason::Disconnected);
+    }
+    server.stop("test finished");
+    second.stop("test finished");
+
+    // UDP over a link that loses a quarter of all datagrams and reorders them
+    let mut server = Server::<String, String>::new();
+    let handler = server
+        .listen_udp::<RecordingServer>("127.0.0.1:0", lossy(7))
+        .expect("Could not listen on UDP");
+    let addr = server.local_addrs()[0];
+    let client_handler = Arc::new(RwLock::new(RecordingClient::default()));
+    let config = ConnectionConfig::default();
+    let mut client = Client::<String, String>::connect_udp(addr, client_handler.clone(), config, lossy(11))
+        .expect("Could not connect over UDP");
+    assert!(wait_until(|| handler.lock().connected == 1), "Server did not accept the UDP client");
+
+    let large = "x".repeat(10_000);
+    for i in 0..50 {
+        client.send(format!("r{i}"));
+        client.send_with(format!("s{i}"), Delivery::UnreliableSequenced);
+        client.send_with(format!("u{i}"), Delivery::Unreliable);
+        if i == 25 {
+            client.send(large.clone());
+        }
+        thread::sleep(Duration::from_millis(2));
+    }
+    assert!(
+        wait_until(|| handler.lock().packets.iter().filter(|p| p.starts_with('r')).count() == 50),
+        "Not every reliable packet arrived"
+    );
+
+    let packets = handler.lock().packets.clone();
+    let reliable: Vec<&String> = packets.iter().filter(|p| p.starts_with('r') || p.starts_with('x')).collect();
+    let mut expected: Vec<String> = (0..50).map(|i| format!("r{i}")).collect();
+    expected.insert(26, large.clone());
+    assert_eq!(reliable, expected.iter().collect::<Vec<_>>(), "Reliable packets arrived out of order");
+
+    let sequenced: Vec<u32> = packets
+        .iter()
+        .filter_map(|p| p.strip_prefix('s'))
+        .map(|n| n.parse().unwrap())
+        .collect();
+    assert!(sequenced.windows(2).all(|w| w[0] < w[1]), "Sequenced packets went backwards: {sequenced:?}");
+    assert!(sequenced.len() < 50, "Nothing was lost, the conditioner did not work");
+    let unreliable = packets.iter().filter(|p| p.starts_with('u')).count();
+    assert!(unreliable > 0 && unreliable < 50);
+
+    // and back from the server to the client
+    server.send_to_all_clients(large.clone());
+    assert!(wait_until(|| client_handler.read().packets.len() == 1), "Client did not receive the large packet");
+    assert_eq!(client_handler.read().packets[0], large);
+
+    client.disconnect(DisconnectReason::Disconnected);
+    assert!(wait_until(|| handler.lock().disconnected == 1), "Server did not notice the disconnect");
+    server.stop("test finished");
+
+    handshake();
+    backpressure();
+    oversized();
+    replication();
+    security();
+    reassembly();
+    rpc();
+    println!("end");
+}
+
+fn backpressure() {
+    let config = ConnectionConfig {
+        max_queued_bytes: 1024 * 1024,
+        ..ConnectionConfig::default()
+    };
+    let mut server = Server::<String, String>::with_config(config.clone());
+    let handler = server.listen::<RecordingServer>("127.0.0.1:0").expect("Could not listen on TCP");
+    let addr = server.local_addrs()[0];
+
+    // packets larger than the socket buffers are queued and arrive complete and in order
+    let client_handler = recording_client();
+    let _client = Client::<String, String>::connect_with(addr, client_handler.clone(), config.clone())
+        .expect("Could not connect");
+    assert!(wait_until(|| handler.lock().connected == 1));
+    let endpoint = handler.lock().clients[0].clone();
+    let expected: Vec<String> = (0..20).map(|i| i.to_string().repeat(40_000)).collect();
+    for packet in &expected {
+        endpoint.send(packet.clone());
+    }
+    assert!(wait_until(|| client_handler.read().packets.len() == expected.len()), "Large packets did not arrive");
+    assert_eq!(client_handler.read().packets, expected);
+    assert!(wait_until(|| endpoint.queued_bytes() == 0));
+
+    // a client that stops reading is disconnected once its queue is full, instead of blocking the sender
+    let frozen_handler = recording_client();
+    let _frozen = Client::<String, String>::connect_with(addr, frozen_handler.clone(), config)
+        .expect("Could not connect");
+    assert!(wait_until(|| handler.lock().connected == 2));
+    let endpoint = handler.lock().clients[1].clone();
+    let frozen = frozen_handler.write();
+    let chunk = "x".repeat(64 * 1024);
+    let start = Instant::now();
+    while handler.lock().disconnected == 0 {
+        assert!(start.elapsed() < Duration::from_secs(10), "Client was not disconnected");
+        endpoint.send(chunk.clone());
+    }
+    drop(frozen);
+    assert!(matches!(handler.lock().reasons[..], [DisconnectReason::Congested]));
+    server.stop("test finished");
+}
+
+fn oversized() {
+    let small = ConnectionConfig {
+        max_frame_size: 1024,
+        ..ConnectionConfig::default()
+    };
+    let mut server = Server::<String, String>::with_config(small.clone());
+    let handler = server.listen::<RecordingServer>("127.0.0.1:0").expect("Could not listen on TCP");
+    let addr = server.local_addrs()[0];
+
+    // a huge length prefix is refused right away, without waiting for the frame or even the handshake
+    let mut raw = TcpStream::connect(addr).expect("Could not connect");
+    raw.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    raw.write_all(&u32::MAX.to_le_bytes()).unwrap();
+    let mut answer = Vec::new();
+    let _ = raw.read_to_end(&mut answer);
+    assert!(answer.is_empty(), "Server answered a peer announcing an oversized frame");
+    assert_eq!(handler.lock().connected, 0);
+
+    // frames up to the limit pass, a client sending a larger one is disconnected
+    let mut client = Client::<String, String>::connect_with(addr, recording_client(), ConnectionConfig::default())
+        .expect("Could not connect");
+    assert!(wait_until(|| handler.lock().connected == 1));
+    client.send("x".repeat(1000));
+    assert!(wait_until(|| handler.lock().packets.len() == 1), "Frame below the limit did not arrive");
+    client.send("x".repeat(2000));
+    assert!(wait_until(|| handler.lock().disconnected == 1), "Client sending an oversized frame was not disconnected");
+    assert!(matches!(handler.lock().reasons[..], [DisconnectReason::Oversized]));
+
+    // and the other way around
+    let client_handler = recording_client();
+    let _client = Client::<String, String>::connect_with(addr, client_handler.clone(), small)
+        .expect("Could not connect");
+    assert!(wait_until(|| handler.lock().connected == 2));
+    let endpoint = handler.lock().clients[1].clone();
+    endpoint.send("x".repeat(2000));
+    assert!(wait_until(|| !client_handler.read().reasons.is_empty()), "Client accepted an oversized frame");
+    assert!(matches!(client_handler.read().reasons[..], [DisconnectReason::Oversized]));
+    assert!(client_handler.read().packets.is_empty());
+    server.stop("test finished");
+}
+
+fn handshake() {
+    // TCP: the hello arrives, mismatched versions and refused hellos are rejected before connecting
+    let mut server = Server::<String, String>::with_config(quick(2));
+    let handler = server.listen::<RecordingServer>("127.0.0.1:0").expect("Could not listen on TCP");
+    let addr = server.local_addrs()[0];
+
+    let rejected = recording_client();
+    assert!(Client::<String, String>::connect_with(addr, rejected.clone(), quick(1)).is_none());
+    assert!(matches!(
+        rejected.read().reasons[..],
+        [DisconnectReason::Rejected(RejectReason::VersionMismatch { server: 2, client: 1 })]
+    ));
+
+    let banned = ConnectionConfig {
+        hello: b"banned".to_vec(),
+        ..quick(2)
+    };
+    let rejected = recording_client();
+    assert!(Client::<String, String>::connect_with(addr, rejected.clone(), banned).is_none());
+    match &rejected.read().reasons[..] {
+        [DisconnectReason::Rejected(RejectReason::Refused(reason))] => assert_eq!(reason, "You are banned"),
+        reasons => panic!("Expected a refusal, got {reasons:?}"),
+    }
+
+    let config = quick(2).with_hello(&"player".to_string());
+    let client_handler = recording_client();
+    let client = Client::<String, String>::connect_with(addr, client_handler.clone(), config.clone())
+        .expect("Could not connect with matching version");
+    assert!(wait_until(|| handler.lock().connected == 1));
+    assert_eq!(handler.lock().hellos.len(), 2, "Version mismatches must not reach on_hello");
+    assert_eq!(handler.lock().hellos[1], config.hello);
+
+    // keep-alives measure the round trip time on both sides
+    assert!(wait_until(|| client.rtt().is_some()), "Client did not measure a round trip time");
+    assert!(wait_until(|| handler.lock().clients[0].rtt().is_some()), "Server did not measure a round trip time");
+    assert!(client.rtt().unwrap() < Duration::from_secs(1));
+
+    // a server that stops answering is detected by the idle timeout
+    thread::sleep(Duration::from_millis(500));
+    assert!(client_handler.read().reasons.is_empty(), "Keep-alives did not keep the connection open");
+    server.stop("test finished");
+    assert!(wait_until(|| !client_handler.read().reasons.is_empty()), "Client did not time out");
+    assert!(matches!(client_handler.read().reasons[..], [DisconnectReason::TimedOut]));
+
+    // UDP: same handshake
+    let mut server = Server::<String, String>::with_config(quick(2));
+    let handler = server
+        .listen_udp::<RecordingServer>("127.0.0.1:0", UdpConfig::default())
+        .expect("Could not listen on UDP");
+    let addr = server.local_addrs()[0];
+
+    let rejected = recording_client();
+    assert!(Client::<String, String>::connect_udp(addr, rejected.clone(), quick(3), UdpConfig::default()).is_none());
+    assert!(matches!(
+        rejected.read().reasons[..],
+        [DisconnectReason::Rejected(RejectReason::VersionMismatch { server: 2, client: 3 })]
+    ));
+
+    let client_handler = recording_client();
+    let client = Client::<String, String>::connect_udp(addr, client_handler.clone(), config.clone(), UdpConfig::default())
+        .expect("Could not connect over UDP with matching version");
+    assert!(wait_until(|| handler.lock().connected == 1));
+    assert_eq!(handler.lock().hellos, [config.hello.clone()]);
+    assert!(wait_until(|| client.rtt().is_some()), "UDP client did not measure a round trip time");
+    assert!(wait_until(|| handler.lock().clients[0].rtt().is_some()), "UDP server did not measure a round trip time");
+
+    // a client that stops answering is timed out by the server, its thread blocks while the handler is locked
+    thread::sleep(Duration::from_millis(500));
+    assert!(client_handler.read().reasons.is_empty(), "Keep-alives did not keep the connection open");
+    let frozen = client_handler.write();
+    assert!(wait_until(|| handler.lock().disconnected == 1), "Server did not time out the frozen client");
+    drop(frozen);
+    assert!(matches!(handler.lock().reasons[..], [DisconnectReason::TimedOut]));
+    assert!(wait_until(|| !client_handler.read().reasons.is_empty()), "Client was not told about the disconnect");
+    server.stop("test finished");
+}
+
+fn replication() {
+    let mut server = Server::<String, Snapshot>::new();
+    let handler = server.listen::<RecordingServer>("127.0.0.1:0").expect("Could not listen on TCP");
+    let addr = server.local_addrs()[0];
+
+    let registry = || {
+        let mut registry = ComponentRegistry::new();
+        registry
+            .register::<Transform>("transform")
+            .register_mapped::<Parent>("parent")
+            .register_mapped::<Children>("children");
+        registry
+    };
+    let mut replicator = Replicator::new(registry());
+    // only entities left of x = 500 are relevant to clients
+    replicator.set_interest(|world, _, id| {
+        world
+            .get_component::<Transform>(id)
+            .is_some_and(|t| t.position.x < 500.0)
+    });
+
+    let mut ecs = Ecs::new(EcsBackend::SparseSet);
+    let world = ecs.world_mut();
+    let spawn = |world: &mut World, x: f32, replicated: bool| {
+        let id = next_entity_id();
+        world.create_entity(id);
+        world.set_component(id, Transform {
+            position: Vec2::new(x, 0.0),
+            ..Transform::default()
+        });
+        if replicated {
+            world.set_component(id, Replicated);
+        }
+        id
+    };
+    let parent = spawn(world, 1.0, true);
+    let child = spawn(world, 2.0, true);
+    let private = spawn(world, 3.0, false);
+    let far = spawn(world, 1000.0, true);
+    set_parent(world, child, parent);
+
+    let client_handler = Arc::new(RwLock::new(SnapshotClient::default()));
+    let _client = Client::<Snapshot, String>::connect(addr, client_handler.clone()).expect("Could not connect");
+    assert!(wait_until(|| handler.lock().connected == 1));
+    replicator.add_client(handler.lock().clients[0].clone());
+
+    let mut client_ecs = Ecs::new(EcsBackend::Archetype);
+    let mut replica = Replica::new(registry());
+    let mut received = 0;
+    // replicates once and applies the snapshot on the client, returning its size
+    let mut sync = |replicator: &mut Replicator, world: &World, replica: &mut Replica, client_world: &mut World| {
+        replicator.replicate(world);
+        assert!(wait_until(|| client_handler.read().snapshots.len() > received), "Snapshot did not arrive");
+        let snapshot = client_handler.read().snapshots[received].clone();
+        received += 1;
+        let mut buffer = ByteBuffer::new_le();
+        snapshot.save(&mut buffer);
+        replica.apply(client_world, snapshot).expect("Could not apply snapshot");
+        buffer.len()
+    };
+
+    // the first snapshot contains the replicated entities of interest, under new ids and with mapped references
+    let full = sync(&mut replicator, world, &mut replica, client_ecs.world_mut());
+    assert_eq!(replica.len(), 2);
+    assert!(replica.local_id(private).is_none() && replica.local_id(far).is_none());
+    let local_parent = replica.local_id(parent).expect("Parent was not replicated");
+    let local_child = replica.local_id(child).expect("Child was not replicated");
+    assert_ne!(local_parent, parent);
+    assert_eq!(replica.server_id(local_child), Some(child));
+    let client_world = client_ecs.world();
+    assert_eq!(client_world.get_component::<Transform>(local_child), world.get_component::<Transform>(child));
+    assert_eq!(client_world.get_component::<Parent>(local_child), Some(&Parent(local_parent)));
+    assert_eq!(client_world.get_component::<Children>(local_parent), Some(&Children(vec![local_child])));
+    assert!(!client_world.has_component::<Replicated>(local_parent));
+
+    // changes are sent as small deltas, and nothing is sent without changes
+    world.get_component_mut::<Transform>(parent).unwrap().position.y = 7.0;
+    let delta = sync(&mut replicator, world, &mut replica, client_ecs.world_mut());
+    assert!(delta < full / 2, "Delta of {delta} bytes is not much smaller than the full snapshot of {full} bytes");
+    assert_eq!(client_ecs.world().get_component::<Transform>(local_parent), world.get_component::<Transform>(parent));
+    let sent = client_handler.read().snapshots.len();
+    replicator.replicate(world);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(client_handler.read().snapshots.len(), sent, "Snapshot without changes was sent");
+
+    // entities entering and leaving the area of interest are spawned and despawned, removed components are removed
+    world.get_component_mut::<Transform>(far).unwrap().position.x = 4.0;
+    world.get_component_mut::<Transform>(child).unwrap().position.x = 600.0;
+    remove_parent(world, child);
+    sync(&mut replicator, world, &mut replica, client_ecs.world_mut());
+    assert_eq!(replica.len(), 2);
+    let local_far = replica.local_id(far).expect("Entity entering the area was not replicated");
+    assert!(replica.local_id(child).is_none());
+    let client_world = client_ecs.world();
+    assert!(client_world.get_component::<Transform>(local_child).is_none());
+    assert!(client_world.get_component::<Children>(local_parent).is_none());
+    assert_eq!(client_world.get_component::<Transform>(local_far), world.get_component::<Transform>(far));
+
+    world.destroy_entity(far);
+    sync(&mut replicator, world, &mut replica, client_ecs.world_mut());
+    assert_eq!(replica.len(), 1);
+    assert!(client_ecs.world().get_component::<Transform>(local_far).is_none());
+
+    // references to entities that are not replicated do not keep an id around
+    let orphan = spawn(world, 5.0, true);
+    set_parent(world, orphan, private);
+    sync(&mut replicator, world, &mut replica, client_ecs.world_mut());
+    let local_orphan = replica.local_id(orphan).expect("Orphan was not replicated");
+    assert_eq!(replica.server_id(local_orphan), Some(orphan));
+    assert!(replica.local_id(private).is_none());
+
+    // snapshots have to be applied in order
+    assert!(replica.apply(client_ecs.world_mut(), client_handler.read().snapshots[0].clone()).is_err());
+
+    // a snapshot that fails halfway leaves the replica unusable, the delta is patching a component it never received
+    let mut buffer = ByteBuffer::new_le();
+    client_handler.read().snapshots[1].save(&mut buffer);
+    let mut bytes = buffer.into_vec();
+    bytes[..8].copy_from_slice(&0u64.to_le_bytes());
+    let corrupt = Snapshot::load(&mut ByteBuffer::from_vec_le(bytes)).expect("Could not load snapshot");
+    let mut fresh_ecs = Ecs::new(EcsBackend::SparseSet);
+    let mut fresh = Replica::new(registry());
+    assert!(fresh.apply(fresh_ecs.world_mut(), corrupt).is_err());
+    assert!(fresh.apply(fresh_ecs.world_mut(), client_handler.read().snapshots[0].clone()).is_err());
+    server.stop("test finished");
+}
+
+const PASS: u8 = 0;
+const FLIP: u8 = 1;
+const REPLAY: u8 = 2;
+
+/// Sits between one client and the server, recording and tampering with what the client sends.
+struct Proxy {
+    addr: SocketAddr,
+    /// What to do with the next chunk the client sends, reset to [`PASS`] afterwards.
+    mode: Arc<AtomicU8>,
+    seen: Arc<Mutex<Vec<u8>>>,
+}
+
+fn proxy(to: SocketAddr) -> Proxy {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mode = Arc::new(AtomicU8::new(PASS));
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let (thread_mode, thread_seen) = (mode.clone(), seen.clone());
+    thread::spawn(move || {
+        let (mut client, _) = listener.accept().unwrap();
+        let mut server = TcpStream::connect(to).unwrap();
+        let (mut from_server, mut to_client) = (server.try_clone().unwrap(), client.try_clone().unwrap());
+        thread::spawn(move || {
+            let _ = std::io::copy(&mut from_server, &mut to_client);
+            let _ = to_client.shutdown(Shutdown::Both);
+        });
+        let mut buffer = [0u8; 65536];
+        loop {
+            let n = match client.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            thread_seen.lock().extend_from_slice(&buffer[..n]);
+            let chunk = &mut buffer[..n];
+            let result = match thread_mode.swap(PASS, Ordering::Relaxed) {
+                FLIP => {
+                    // the last byte of a frame belongs to its authentication tag
+                    chunk[n - 1] ^= 1;
+                    server.write_all(chunk)
+                }
+                REPLAY => server.write_all(chunk).and_then(|_| server.write_all(chunk)),
+                _ => server.write_all(chunk),
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        let _ = server.shutdown(Shutdown::Both);
+    });
+    Proxy { addr, mode, seen }
+}
+
+fn security() {
+    // fixed keys, so the test does not depend on randomness
+    const SECRET: secure::Key = [7; 32];
+    let secure_server = || ConnectionConfig {
+        server_secret: Some(SECRET),
+        ..ConnectionConfig::default()
+    };
+    let secure_client = |key: secure::Key| ConnectionConfig {
+        server_key: Some(key),
+        ..ConnectionConfig::default()
+    };
+    let mut server = Server::<String, String>::with_config(secure_server());
+    let handler = server.listen::<RecordingServer>("127.0.0.1:0").expect("Could not listen on TCP");
+    let addr = server.local_addrs()[0];
+
+    // the hello and all packets are encrypted on the wire
+    let spy = proxy(addr);
+    let config = ConnectionConfig {
+        hello: b"top secret token".to_vec(),
+        ..secure_client(secure::public_key(&SECRET))
+    };
+    let client_handler = recording_client();
+    let mut client = Client::<String, String>::connect_with(spy.addr, client_handler.clone(), config)
+        .expect("Could not connect securely");
+    assert!(wait_until(|| handler.lock().connected == 1));
+    assert_eq!(handler.lock().hellos, [b"top secret token".to_vec()]);
+    client.send("attack at dawn".to_string());
+    assert!(wait_until(|| handler.lock().packets.len() == 1), "Secure packet did not arrive");
+    assert_eq!(handler.lock().packets[0], "attack at dawn");
+    handler.lock().clients[0].send("retreat".to_string());
+    assert!(wait_until(|| client_handler.read().packets.len() == 1), "Secure packet did not arrive at the client");
+    assert_eq!(client_handler.read().packets[0], "retreat");
+    let seen = spy.seen.lock().clone();
+    for plaintext in [&b"top secret token"[..], b"attack at dawn"] {
+        assert!(!seen.windows(plaintext.len()).any(|w| w == plaintext), "Plaintext is visible on the wire");
+    }
+
+    // altered frames end the connection
+    spy.mode.store(FLIP, Ordering::Relaxed);
+    client.send("altered".to_string());
+    assert!(wait_until(|| handler.lock().disconnected == 1), "Altered frame was not detected");
+    assert!(matches!(handler.lock().reasons[..], [DisconnectReason::Tampered]));
+    assert_eq!(handler.lock().packets.len(), 1, "Altered packet was delivered");
+
+    // as do replayed ones
+    let spy = proxy(addr);
+    let mut client = Client::<String, String>::connect_with(spy.addr, recording_client(), secure_client(secure::public_key(&SECRET)))
+        .expect("Could not connect securely");
+    assert!(wait_until(|| handler.lock().connected == 2));
+    spy.mode.store(REPLAY, Ordering::Relaxed);
+    client.send("once".to_string());
+    assert!(wait_until(|| handler.lock().disconnected == 2), "Replayed frame was not detected");
+    assert!(matches!(handler.lock().reasons[1], DisconnectReason::Tampered));
+    assert_eq!(handler.lock().packets.iter().filter(|p| *p == "once").count(), 1, "Replayed packet was delivered twice");
+
+    // a client expecting another server key, e.g. because it talks to an impostor, cannot connect
+    let rejected = recording_client();
+    let wrong_key = secure_client(secure::public_key(&[8; 32]));
+    assert!(Client::<String, String>::connect_with(addr, rejected.clone(), wrong_key).is_none());
+    assert!(matches!(rejected.read().reasons[..], [DisconnectReason::Rejected(RejectReason::Insecure(_))]));
+
+    // both sides have to agree on using a secure connection
+    let rejected = recording_client();
+    assert!(Client::<String, String>::connect_with(addr, rejected.clone(), ConnectionConfig::default()).is_none());
+    assert!(matches!(rejected.read().reasons[..], [DisconnectReason::Rejected(RejectReason::Insecure(_))]));
+    assert_eq!(handler.lock().connected, 2);
+    server.stop("test finished");
+
+    let mut server = Server::<String, String>::new();
+    server.listen::<RecordingServer>("127.0.0.1:0").expect("Could not listen on TCP");
+    let rejected = recording_client();
+    let config = secure_client(secure::public_key(&SECRET));
+    assert!(Client::<String, String>::connect_with(server.local_addrs()[0], rejected.clone(), config).is_none());
+    assert!(matches!(rejected.read().reasons[..], [DisconnectReason::Rejected(RejectReason::Insecure(_))]));
+    server.stop("test finished");
+
+    let mut server = Server::<String, String>::with_config(secure_server());
+    assert!(matches!(
+        server.listen_udp::<RecordingServer>("127.0.0.1:0", UdpConfig::default()),
+        Err(ListenError::SecureUdp)
+    ));
+}
+
+/// Forwards datagrams between one UDP client and the server, recording what the server sends.
+/// Datagrams sent from `socket` reach the server as if the client sent them.
+struct Relay {
+    addr: SocketAddr,
+    socket: UdpSocket,
+    from_server: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+fn relay(to: SocketAddr) -> Relay {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let addr = socket.local_addr().unwrap();
+    let from_server = Arc::new(Mutex::new(Vec::new()));
+    let (thread_socket, thread_from_server) = (socket.try_clone().unwrap(), from_server.clone());
+    thread::spawn(move || {
+        let mut client = None;
+        let mut buffer = [0u8; 65536];
+        while let Ok((n, from)) = thread_socket.recv_from(&mut buffer) {
+            if from == to {
+                thread_from_server.lock().push(buffer[..n].to_vec());
+                if let Some(client) = client {
+                    let _ = thread_socket.send_to(&buffer[..n], client);
+                }
+            } else {
+                client = Some(from);
+                let _ = thread_socket.send_to(&buffer[..n], to);
+            }
+        }
+    });
+    Relay {
+        addr,
+        socket,
+        from_server,
+    }
+}
+
+impl Relay {
+    fn send_reliable(&self, to: SocketAddr, sequence: u32, index: u16, count: u16, payload: &[u8]) {
+        // magic, data, reliable ordered
+        let mut datagram = vec![b'M', b'V', 3, 2];
+        datagram.extend_from_slice(&sequence.to_le_bytes());
+        datagram.extend_from_slice(&index.to_le_bytes());
+        datagram.extend_from_slice(&count.to_le_bytes());
+        datagram.extend_from_slice(payload);
+        self.socket.send_to(&datagram, to).unwrap();
+    }
+
+    /// How often the server acknowledged a fragment.
+    fn acks(&self, sequence: u32, index: u16) -> usize {
+        let entry = [sequence.to_le_bytes().as_slice(), index.to_le_bytes().as_slice()].concat();
+        self.from_server
+            .lock()
+            .iter()
+            .filter_map(|d| d.strip_prefix(&[b'M', b'V', 4][..]))
+            .flat_map(|acks| acks.chunks_exact(6))
+            .filter(|ack| *ack == entry.as_slice())
+            .count()
+    }
+}
+
+fn reassembly() {
+    // a peer cannot make the server hold more than the limit, or packets far ahead of the next one
+    let mut server = Server::<String, String>::new();
+    let config = UdpConfig {
+        reassembly_limit: 4096,
+        ..UdpConfig::default()
+    };
+    let handler = server.listen_udp::<RecordingServer>("127.0.0.1:0", config).expect("Could not listen on UDP");
+    let addr = server.local_addrs()[0];
+    let relay = relay(addr);
+    let mut client = Client::<String, String>::connect_udp(relay.addr, recording_client(), quick(0), UdpConfig::default())
+        .expect("Could not connect over UDP");
+    client.send("before".to_string());
+    assert!(wait_until(|| handler.lock().packets.len() == 1), "Packet did not arrive");
+
+    // fragments of packets that never complete, a few sequences ahead
+    let fragment = [0u8; 1024];
+    for sequence in 100..104 {
+        relay.send_reliable(addr, sequence, 0, 2, &fragment);
+    }
+    assert!(wait_until(|| (100..104).all(|s| relay.acks(s, 0) == 1)), "Fragments within the limit were not acknowledged");
+    relay.send_reliable(addr, 104, 0, 2, &fragment);
+    relay.send_reliable(addr, 105, 0, u16::MAX, &fragment);
+    relay.send_reliable(addr, 100_000, 0, 1, &fragment[..10]);
+    relay.send_reliable(addr, u32::MAX - 10, 0, 2, &fragment[..10]);
+    // a repeated fragment is acknowledged again, once that arrived everything before it was handled
+    relay.send_reliable(addr, 100, 0, 2, &fragment);
+    assert!(wait_until(|| relay.acks(100, 0) == 2));
+    assert_eq!(relay.acks(104, 0), 0, "Fragment over the limit was acknowledged");
+    assert_eq!(relay.acks(105, 0), 0, "Fragment over the limit was acknowledged");
+    assert_eq!(relay.acks(100_000, 0), 0, "Fragment far ahead was acknowledged");
+    // sequences before the next one count as handed out, even across the wrap around
+    assert_eq!(relay.acks(u32::MAX - 10, 0), 1);
+
+    // the next packet in order still gets through
+    client.send("after".to_string());
+    assert!(wait_until(|| handler.lock().packets.len() == 2), "Packet after the limit did not arrive");
+    assert_eq!(handler.lock().packets[1], "after");
+    assert_eq!(handler.lock().disconnected, 0);
+    client.disconnect(DisconnectReason::Disconnected);
+    server.stop("test finished");
+}
+
+#[derive(Savable)]
+struct Login {
+    name: String,
+}
+
+impl Request for Login {
+    type Response = u64;
+    const NAME: &'static str = "login";
+}
+
+#[derive(Savable)]
+struct Divide {
+    a: i32,
+    b: i32,
+}
+
+impl Request for Divide {
+    type Response = i32;
+    const NAME: &'static str = "divide";
+}
+
+/// Answered after a delay.
+#[derive(Savable)]
+struct Slow;
+
+impl Request for Slow {
+    type Response = bool;
+    const NAME: &'static str = "slow";
+}
+
+/// Never registered on the server.
+#[derive(Savable)]
+struct Unknown;
+
+impl Request for Unknown {
+    type Response = bool;
+    const NAME: &'static str = "unknown";
+}
+
+fn rpc() {
+    let mut server = Server::<String, String>::new();
+    let handler = server.listen::<RecordingServer>("127.0.0.1:0").expect("Could not listen on TCP");
+    rpc_over(&server, &handler, |handler| {
+        Client::<String, String>::connect(server.local_addrs()[0], handler).expect("Could not connect")
+    });
+    server.stop("test finished");
+
+    let mut server = Server::<String, String>::new();
+    let handler = server
+        .listen_udp::<RecordingServer>("127.0.0.1:0", UdpConfig::default())
+        .expect("Could not listen on UDP");
+    rpc_over(&server, &handler, |handler| {
+        Client::<String, String>::connect_udp(server.local_addrs()[0], handler, ConnectionConfig::default(), UdpConfig::default())
+            .expect("Could not connect over UDP")
+    });
+    server.stop("test finished");
+}
+
+fn rpc_over(
+    server: &Server<String, String>,
+    handler: &Arc<Mutex<RecordingServer>>,
+    connect: impl Fn(Arc<RwLock<RecordingClient>>) -> Client<String, String>,
+) {
+    // handlers can lock the server handler, they do not run while it is locked
+    let logins = handler.clone();
+    server.on_request(move |client, login: Login| {
+        logins.lock().packets.push(format!("login {}", login.name));
+        Ok(client.id())
+    });
+    server.on_request(|_, divide: Divide| divide.a.checked_div(divide.b).ok_or_else(|| "Division by zero".to_string()));
+    server.on_request(|_, _: Slow| {
+        thread::sleep(Duration::from_millis(300));
+        Ok(true)
+    });
+
+    let client_handler = recording_client();
+    let mut client = connect(client_handler.clone());
+    assert!(wait_until(|| handler.lock().connected == 1));
+    let id = handler.lock().clients[0].id();
+
+    let token = client.call(Login { name: "alice".to_string() }).wait();
+    assert_eq!(token, Ok(id));
+    assert_eq!(handler.lock().packets, ["login alice"]);
+
+    // many calls in flight are matched to their own responses, packets keep working next to them
+    let calls: Vec<_> = (0..50).map(|a| client.call(Divide { a: a * 10, b: 10 })).collect();
+    client.send("packet".to_string());
+    for (a, call) in calls.into_iter().enumerate() {
+        assert_eq!(call.wait(), Ok(a as i32));
+    }
+    assert!(wait_until(|| handler.lock().packets.len() == 2));
+    assert_eq!(handler.lock().packets[1], "packet");
+
+    // errors of the handler, missing handlers and timeouts
+    assert_eq!(client.call(Divide { a: 1, b: 0 }).wait(), Err(RpcError::Failed("Division by zero".to_string())));
+    assert_eq!(client.call(Unknown).wait(), Err(RpcError::Unsupported("unknown".to_string())));
+    let start = Instant::now();
+    assert_eq!(client.call_with(Slow, Duration::from_millis(50)).wait(), Err(RpcError::TimedOut));
+    assert!(start.elapsed() < Duration::from_millis(250), "Call did not time out in time");
+
+    // polling does not block and reports the result once
+    let mut call = client.call(Divide { a: 9, b: 3 });
+    assert!(wait_until(|| match call.poll() {
+        Some(result) => {
+            assert_eq!(result, Ok(3));
+            true
+        }
+        None => false,
+    }));
+    assert!(call.poll().is_none());
+
+    // calls waiting when the connection closes fail right away
+    let call = client.call(Slow);
+    thread::sleep(Duration::from_millis(50));
+    client.disconnect(DisconnectReason::Disconnected);
+    let start = Instant::now();
+    assert_eq!(call.wait(), Err(RpcError::Disconnected));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(client.call(Slow).wait(), Err(RpcError::Disconnected));
+}
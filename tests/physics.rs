@@ -0,0 +1,255 @@
+use mvengine::game::ecs::entity::{Entity, EntityId};
+use mvengine::game::ecs::world::EcsWorld;
+use mvengine::game::ecs::{Ecs, EcsBackend, World};
+use mvengine::event::{EventQueue, EventReceiver};
+use mvengine::game::physics::components::{AABBCollider, CircleCollider, OBBCollider, PolygonCollider, RigidDynamic, Static, Transform, Trigger};
+use mvengine::game::physics::events::CollisionEvent;
+use mvengine::game::physics::sat;
+use mvengine::game::physics::sat::ColliderShape;
+use mvengine::game::physics::systems::PhysicsSystem;
+use mvengine::math::vec::Vec2;
+use mvengine::ui::geometry::polygon::Polygon;
+use std::sync::{Arc, Mutex};
+
+struct EventLog(Arc<Mutex<Vec<CollisionEvent>>>);
+
+impl EventReceiver<CollisionEvent, ()> for EventLog {
+    fn on_dispatch(&mut self, _: (), event: &mut CollisionEvent, _: &mut EventQueue<CollisionEvent>) {
+        self.0.lock().expect("Event log poisoned").push(*event);
+    }
+}
+
+fn body(world: &mut World, position: Vec2, extent: Vec2, velocity: Vec2, mass: f64) -> EntityId {
+    let id = Entity::<(Transform, AABBCollider, RigidDynamic)>::create(world);
+    if let Some(t) = world.get_component_mut::<Transform>(id) {
+        t.position = position;
+    }
+    if let Some(c) = world.get_component_mut::<AABBCollider>(id) {
+        c.extent = extent;
+    }
+    if let Some(r) = world.get_component_mut::<RigidDynamic>(id) {
+        r.velocity = velocity;
+        r.mass = mass;
+    }
+    id
+}
+
+fn floor(world: &mut World) -> EntityId {
+    let id = Entity::<(Transform, AABBCollider, Static)>::create(world);
+    if let Some(c) = world.get_component_mut::<AABBCollider>(id) {
+        c.extent = Vec2::new(10.0, 1.0);
+    }
+    id
+}
+
+fn rigid(world: &mut World, id: EntityId) -> &mut RigidDynamic {
+    world.get_component_mut::<RigidDynamic>(id).expect("Body has no RigidDynamic")
+}
+
+fn velocity(world: &World, id: EntityId) -> Vec2 {
+    world.get_component::<RigidDynamic>(id).expect("Body has no RigidDynamic").velocity
+}
+
+fn position(world: &World, id: EntityId) -> Vec2 {
+    world.get_component::<Transform>(id).expect("Body has no Transform").position
+}
+
+fn close(a: Vec2, b: Vec2) -> bool {
+    (a.x - b.x).abs() < 1e-5 && (a.y - b.y).abs() < 1e-5
+}
+
+fn new_ecs(archetype: bool) -> Ecs {
+    Ecs::new(if archetype { EcsBackend::Archetype } else { EcsBackend::SparseSet })
+}
+
+fn main() {
+    for archetype in [false, true] {
+        // head-on, elastic, equal mass: velocities are swapped and the bodies pushed apart
+        let mut ecs = new_ecs(archetype);
+        let world = ecs.world_mut();
+        let a = body(world, Vec2::new(0.0, 0.0), Vec2::splat(1.0), Vec2::new(1.0, 0.0), 1.0);
+        let b = body(world, Vec2::new(0.9, 0.0), Vec2::splat(1.0), Vec2::new(-1.0, 0.0), 1.0);
+        rigid(world, a).restitution = 1.0;
+        rigid(world, b).restitution = 1.0;
+        PhysicsSystem::new().iterate(world, 0.0);
+        assert!(close(velocity(world, a), Vec2::new(-1.0, 0.0)), "{:?}", velocity(world, a));
+        assert!(close(velocity(world, b), Vec2::new(1.0, 0.0)), "{:?}", velocity(world, b));
+        assert!(position(world, a).x < 0.0 && position(world, b).x > 0.9);
+
+        // bodies without an explicit mass weigh one and are pushed like any other
+        let mut ecs = new_ecs(archetype);
+        let world = ecs.world_mut();
+        assert_eq!(RigidDynamic::default().mass, 1.0);
+        let a = body(world, Vec2::new(0.0, 0.0), Vec2::splat(1.0), Vec2::new(2.0, 0.0), 1.0);
+        let b = Entity::<(Transform, AABBCollider, RigidDynamic)>::create(world);
+        world.set_component(b, Transform { position: Vec2::new(0.9, 0.0), ..Transform::default() });
+        world.set_component(b, AABBCollider { extent: Vec2::splat(1.0), ..AABBCollider::default() });
+        PhysicsSystem::new().iterate(world, 0.0);
+        assert!(close(velocity(world, a), Vec2::new(1.0, 0.0)), "{:?}", velocity(world, a));
+        assert!(close(velocity(world, b), Vec2::new(1.0, 0.0)), "{:?}", velocity(world, b));
+
+        // head-on, inelastic, different masses: both move on with the common momentum
+        let mut ecs = new_ecs(archetype);
+        let world = ecs.world_mut();
+        let a = body(world, Vec2::new(0.0, 0.0), Vec2::splat(1.0), Vec2::new(2.0, 0.0), 1.0);
+        let b = body(world, Vec2::new(0.9, 0.0), Vec2::splat(1.0), Vec2::new(0.0, 0.0), 3.0);
+        PhysicsSystem::new().iterate(world, 0.0);
+        assert!(close(velocity(world, a), Vec2::new(0.5, 0.0)), "{:?}", velocity(world, a));
+        assert!(close(velocity(world, b), Vec2::new(0.5, 0.0)), "{:?}", velocity(world, b));
+
+        // head-on into a static wall, the restitution of the wall counts too
+        let mut ecs = new_ecs(archetype);
+        let world = ecs.world_mut();
+        let a = body(world, Vec2::new(0.0, 0.0), Vec2::splat(1.0), Vec2::new(2.0, 0.0), 1.0);
+        let wall = body(world, Vec2::new(0.9, 0.0), Vec2::splat(1.0), Vec2::new(0.0, 0.0), 1.0);
+        world.set_component(wall, Static);
+        rigid(world, wall).restitution = 0.5;
+        PhysicsSystem::new().iterate(world, 0.0);
+        assert!(close(velocity(world, a), Vec2::new(-1.0, 0.0)), "{:?}", velocity(world, a));
+        assert!(close(velocity(world, wall), Vec2::default()));
+        assert!(close(position(world, wall), Vec2::new(0.9, 0.0)));
+
+        // glancing hit on the floor without friction keeps the tangential velocity
+        let mut ecs = new_ecs(archetype);
+        let world = ecs.world_mut();
+        floor(world);
+        let a = body(world, Vec2::new(0.0, 0.95), Vec2::splat(1.0), Vec2::new(3.0, -4.0), 1.0);
+        PhysicsSystem::new().iterate(world, 0.0);
+        assert!(close(velocity(world, a), Vec2::new(3.0, 0.0)), "{:?}", velocity(world, a));
+
+        // with friction, the tangential impulse is limited to friction * normal impulse
+        let mut ecs = new_ecs(archetype);
+        let world = ecs.world_mut();
+        floor(world);
+        let a = body(world, Vec2::new(0.0, 0.95), Vec2::splat(1.0), Vec2::new(3.0, -4.0), 1.0);
+        rigid(world, a).friction = 0.25;
+        PhysicsSystem::new().iterate(world, 0.0);
+        assert!(close(velocity(world, a), Vec2::new(2.0, 0.0)), "{:?}", velocity(world, a));
+
+        // resting on the floor under gravity, the body settles slightly inside the floor and stays there
+        let mut ecs = new_ecs(archetype);
+        let world = ecs.world_mut();
+        floor(world);
+        let a = body(world, Vec2::new(0.0, 1.0), Vec2::splat(1.0), Vec2::default(), 1.0);
+        rigid(world, a).gravity = Vec2::new(0.0, -1.0);
+        let mut physics = PhysicsSystem::new();
+        for _ in 0..120 {
+            physics.iterate(world, 1.0 / 60.0);
+        }
+        let settled = position(world, a);
+        physics.iterate(world, 1.0 / 60.0);
+        assert!(close(position(world, a), settled), "{:?} != {:?}", position(world, a), settled);
+        assert!(settled.y > 0.97 && settled.y < 1.0, "{:?}", settled);
+        assert!(close(velocity(world, a), Vec2::default()));
+    }
+
+    // separating axis tests
+    let circle = |x: f32, y: f32, radius: f32| ColliderShape::Circle { center: Vec2::new(x, y), radius };
+    let hit = sat::collide(&circle(0.0, 0.0, 1.0), &circle(1.5, 0.0, 1.0)).expect("Circles should overlap");
+    assert!(close(hit.normal, Vec2::new(1.0, 0.0)) && (hit.depth - 0.5).abs() < 1e-5, "{hit:?}");
+    assert!(close(hit.point, Vec2::new(0.75, 0.0)), "{hit:?}");
+    assert!(sat::collide(&circle(0.0, 0.0, 1.0), &circle(2.0, 0.0, 1.0)).is_none());
+
+    let square = ColliderShape::Polygon(Polygon {
+        vertices: vec![Vec2::new(-0.5, -0.5), Vec2::new(0.5, -0.5), Vec2::new(0.5, 0.5), Vec2::new(-0.5, 0.5)],
+    });
+    let hit = sat::collide(&square, &circle(0.0, 0.8, 0.5)).expect("Circle should touch the square");
+    assert!(close(hit.normal, Vec2::new(0.0, 1.0)) && (hit.depth - 0.2).abs() < 1e-5, "{hit:?}");
+    let hit = sat::collide(&circle(0.0, 0.8, 0.5), &square).expect("Circle should touch the square");
+    assert!(close(hit.normal, Vec2::new(0.0, -1.0)), "{hit:?}");
+    // next to the corner, only the circle axis separates them
+    assert!(sat::collide(&square, &circle(0.85, 0.85, 0.45)).is_none());
+
+    let triangle = PolygonCollider::new(vec![Vec2::new(0.0, 1.0), Vec2::new(1.0, 0.0), Vec2::new(0.0, 0.0)]);
+    assert_eq!(triangle.vertices.len(), 3);
+    let area: f32 = (0..3)
+        .map(|i| {
+            let (p, q) = (triangle.vertices[i], triangle.vertices[(i + 1) % 3]);
+            p.x * q.y - q.x * p.y
+        })
+        .sum();
+    assert!(area > 0.0, "Polygon vertices should be counter-clockwise");
+
+    for archetype in [false, true] {
+        // a box rotated by 45 degrees next to the corner of an aabb only overlaps its bounding box
+        let mut ecs = new_ecs(archetype);
+        let world = ecs.world_mut();
+        let aabb = Entity::<(Transform, AABBCollider)>::create(world);
+        if let Some(c) = world.get_component_mut::<AABBCollider>(aabb) {
+            c.extent = Vec2::splat(1.0);
+        }
+        let obb = Entity::<(Transform, OBBCollider)>::create(world);
+        if let Some(t) = world.get_component_mut::<Transform>(obb) {
+            t.position = Vec2::new(1.0, 1.0);
+            t.rotation = std::f64::consts::FRAC_PI_4;
+        }
+        if let Some(c) = world.get_component_mut::<OBBCollider>(obb) {
+            c.extent = Vec2::splat(1.0);
+        }
+        let mut physics = PhysicsSystem::new();
+        physics.iterate(world, 0.0);
+        assert!(physics.contacts().is_empty(), "{:?}", physics.contacts());
+
+        if let Some(t) = world.get_component_mut::<Transform>(obb) {
+            t.position = Vec2::new(0.8, 0.8);
+        }
+        physics.iterate(world, 0.0);
+        assert_eq!(physics.contacts().len(), 1);
+        let contact = physics.contacts()[0];
+        assert!(close(contact.normal, Vec2::new(std::f32::consts::FRAC_1_SQRT_2, std::f32::consts::FRAC_1_SQRT_2)), "{contact:?}");
+
+        // a ball hitting the floor stops and is pushed out
+        let mut ecs = new_ecs(archetype);
+        let world = ecs.world_mut();
+        let floor = floor(world);
+        let ball = body(world, Vec2::new(0.0, 0.95), Vec2::default(), Vec2::new(0.0, -4.0), 1.0);
+        world.remove_component::<AABBCollider>(ball);
+        world.set_component(ball, CircleCollider { radius: 0.5 });
+        let mut physics = PhysicsSystem::new();
+        physics.iterate(world, 0.0);
+        assert_eq!(physics.contacts().len(), 1);
+        let contact = physics.contacts()[0];
+        assert_eq!((contact.a, contact.b), (floor, ball));
+        assert!(close(contact.normal, Vec2::new(0.0, 1.0)) && (contact.depth - 0.05).abs() < 1e-5, "{contact:?}");
+        assert!(close(velocity(world, ball), Vec2::default()), "{:?}", velocity(world, ball));
+        assert!(position(world, ball).y > 0.95);
+    }
+
+    for archetype in [false, true] {
+        // a body moving through a trigger zone reports the transitions, but is not slowed down
+        let mut ecs = new_ecs(archetype);
+        let world = ecs.world_mut();
+        let zone = Entity::<(Transform, AABBCollider, Trigger, Static)>::create(world);
+        if let Some(c) = world.get_component_mut::<AABBCollider>(zone) {
+            c.extent = Vec2::splat(1.0);
+        }
+        let ball = body(world, Vec2::new(-1.5, 0.0), Vec2::default(), Vec2::new(1.0, 0.0), 1.0);
+        world.remove_component::<AABBCollider>(ball);
+        world.set_component(ball, CircleCollider { radius: 0.5 });
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut physics = PhysicsSystem::new();
+        physics.event_bus_mut().subscribe(EventLog(log.clone()));
+
+        let mut kinds = Vec::new();
+        for _ in 0..5 {
+            physics.iterate(world, 0.5);
+            for event in physics.events() {
+                assert_eq!(event.entities(), (zone, ball));
+                assert_eq!(event.other(ball), Some(zone));
+                kinds.push(match event {
+                    CollisionEvent::CollisionStarted(_) => "started",
+                    CollisionEvent::CollisionOngoing(_) => "ongoing",
+                    CollisionEvent::CollisionEnded(_) => "ended",
+                });
+            }
+        }
+        // the ball is centered at -1.0, -0.5, 0.0, 0.5 and 1.0, only touching the zone in the first and last frame
+        assert_eq!(kinds, ["started", "ongoing", "ongoing", "ended"]);
+        assert!(close(velocity(world, ball), Vec2::new(1.0, 0.0)));
+        assert!(close(position(world, ball), Vec2::new(1.0, 0.0)));
+        assert_eq!(log.lock().expect("Event log poisoned").len(), 4);
+    }
+
+    println!("end");
+}